@@ -44,6 +44,262 @@ struct ConsistentRoutingRingModeNum {
   1: i16 tasks_per_content;
 } (rust.exhaustive)
 
+// Limits on the rate of incoming requests for a group of clients.
+struct RequestLimit {
+  // Identities the limit applies to. A client matches if it has all of them.
+  // A limit with no identities is the default for clients that don't match
+  // any other limit.
+  1: list<string> identities;
+  // Maximum number of requests accepted per second. 0 means unlimited.
+  2: i32 requests_per_second;
+  // Maximum number of requests being served at once. 0 means unlimited.
+  3: i32 max_concurrent_requests;
+  // Maximum number of uploads being served at once. 0 means unlimited.
+  4: i32 max_concurrent_uploads;
+  // Maximum rate at which downloads are sent to these clients, shared by all
+  // of their requests. 0 means unlimited.
+  5: i64 max_egress_bytes_per_second;
+  // Maximum number of bytes each of these clients may upload to a repository
+  // today, and over the last 7 days. Each client, as identified by all of its
+  // identities, has its own quota. Days start at midnight UTC. 0 means
+  // unlimited.
+  6: i64 max_upload_bytes_per_day;
+  7: i64 max_upload_bytes_per_week;
+} (rust.exhaustive)
+
+// A feature enabled for a percentage of clients.
+struct RolloutFeature {
+  // Share of clients the feature is enabled for, from 0 to 100.
+  1: i32 percentage;
+  // Mixed into the hash of each client, so that features rolled out to the
+  // same percentage aren't enabled for the same clients. Changing it reshuffles
+  // which clients have the feature.
+  2: string salt;
+} (rust.exhaustive)
+
+// Throttling of clients that have no identities, by their IP address.
+struct IpThrottle {
+  // Requests each address may make per second. Addresses that were idle can
+  // make up to a second worth of requests at once. 0 disables throttling.
+  1: i32 requests_per_second;
+  // Addresses in these CIDR blocks (e.g. "10.0.0.0/8") are never throttled.
+  2: list<string> exempt_cidrs;
+} (rust.exhaustive)
+
+// Sampling of the server's logs, to keep high-volume logs down in production.
+struct LogSampleRate {
+  // Log 1 in N messages of each level, keyed by level: "error", "warning",
+  // "info", "debug" or "trace". Unlisted levels, 0 and 1 log every message.
+  1: map<string, i64> by_level;
+  // Log 1 in N requests to the access log for each route, keyed by route:
+  // "batch", "verify", "locks", "download", "download_sha256", "upload",
+  // "git_blob_upload", "health" or "config". Unlisted routes log every request.
+  // The access log is the line per request in the server's own log. This
+  // doesn't affect the request log in Scuba, which access_log_sample_rate
+  // samples.
+  2: map<string, i64> by_route;
+} (rust.exhaustive)
+
+// Cross-origin resource sharing, for browser-based clients that talk to the
+// server directly.
+struct CorsConfig {
+  // Origins allowed to make requests, e.g. "https://example.com". "*" allows
+  // any origin.
+  1: list<string> allowed_origins;
+  // Request headers that cross-origin requests may send, besides the ones
+  // browsers always allow and Content-Type, which Git LFS requests need.
+  2: list<string> allowed_headers;
+  // How long browsers may cache the response to a preflight request, in
+  // seconds. 0 leaves it up to the browser.
+  3: i64 max_age_secs;
+} (rust.exhaustive)
+
+// Timeouts for calls to one of the server's backends, in milliseconds. 0
+// disables a timeout.
+struct BackendTimeouts {
+  // Establishing a connection. Only applies to new connections, and changes
+  // apply to the next one.
+  1: i64 connect_ms;
+  // Until the backend starts responding, including sending the request.
+  2: i64 first_byte_ms;
+  // The whole call, including reading the response.
+  3: i64 total_ms;
+} (rust.exhaustive)
+
+// Faults injected into the requests to a route, to test how clients and the
+// rest of the deployment cope with a slow or failing server.
+struct RouteFaults {
+  // Share of requests, from 0 to 100, that are delayed by latency_ms before
+  // being served.
+  1: i32 latency_percentage;
+  2: i64 latency_ms;
+  // Share of requests, from 0 to 100, that fail with a 503 instead of being
+  // served.
+  3: i32 error_percentage;
+} (rust.exhaustive)
+
+// A backend that consistently routed downloads can be sent to.
+struct RoutingTarget {
+  // Sent as the routing key of the downloads routed to this target, so the
+  // proxy in front of the servers must route requests with that key to it.
+  1: string name;
+  // Share of objects routed to this target, relative to the other targets. 0
+  // routes nothing to it.
+  2: i32 weight;
+} (rust.exhaustive)
+
+// Consistent routing by rendezvous (highest random weight) hashing: each
+// object goes to the targets that score highest for it. Adding or removing a
+// target only moves the objects that target gains or loses.
+struct RendezvousRouting {
+  1: list<RoutingTarget> targets;
+} (rust.exhaustive)
+
+// Spreading of the downloads of objects that suddenly get popular, e.g. a new
+// release, across all hosts instead of routing them consistently. Unlike
+// object_popularity, downloads are counted by each server on its own, so this
+// needs no shared counters.
+struct HotObjectSpreading {
+  // Objects requested in more than this many batch requests within
+  // window_secs are hot.
+  1: i64 threshold;
+  2: i64 window_secs;
+  // Hot objects are spread for this many seconds after they were last over
+  // the threshold. 0 spreads them for window_secs.
+  3: i64 cooldown_secs;
+} (rust.exhaustive)
+
+// Enforcement of request_limits across all the servers that share a
+// category, using shared counters, so that the limits still protect the
+// backing store as the fleet scales out. The per-second request and egress
+// limits are checked against the total of the fleet, on top of each server's
+// own. Concurrency limits are only enforced by each server.
+struct DistributedRateLimits {
+  // Counter category shared by the servers enforcing the limits together.
+  1: string category;
+  // How long to wait for the shared counters, in milliseconds. Requests whose
+  // counters don't answer in time are only limited locally. 0 waits 10ms.
+  2: i64 timeout_ms;
+} (rust.exhaustive)
+
+// Falls back to uniform routing for the destinations of consistently routed
+// downloads whose error rates are too high. Servers count the routed downloads
+// they serve, and those that fail with a server error, in shared counters, and
+// each server checks the totals of the fleet. With rendezvous_routing, each
+// target is a destination. Otherwise, all routed downloads are counted
+// together, and consistent routing is disabled as a whole.
+struct RoutingErrorBudget {
+  // Counter category shared by the servers counting routed downloads.
+  1: string category;
+  // Share of routed downloads, in percent, that may fail before routing to
+  // their destination is disabled.
+  2: i64 max_error_percent;
+  // Destinations with fewer routed downloads per second than this are left
+  // alone, so that a few failures don't disable them. 0 judges all of them.
+  3: i64 min_requests_per_second;
+  // How long routing to a destination stays disabled, in seconds, before it
+  // is tried again.
+  4: i64 cool_down_secs;
+  // How often error rates are checked, in milliseconds. 0 checks every
+  // second.
+  5: i64 check_interval_ms;
+  // How long to wait for the shared counters, in milliseconds. 0 waits 10ms.
+  6: i64 timeout_ms;
+} (rust.exhaustive)
+
+// Export of spans for batch, upload and download requests, and for the
+// blobstore and upstream calls they make, to a trace collector. Requests that
+// carry a W3C traceparent header continue that trace, and pass it on to the
+// upstream server.
+struct TraceExport {
+  // Where spans are sent, e.g. http://collector:4318/v1/traces for OTLP or
+  // http://collector:9411/api/v2/spans for Zipkin.
+  1: string collector_url;
+  // "otlp" (OTLP over HTTP, JSON encoded) or "zipkin" (Zipkin v2, JSON
+  // encoded). Empty is "otlp".
+  2: string protocol;
+  // Traces 1 in this many requests that don't carry a sampling decision. 0
+  // traces all of them.
+  3: i64 sample_rate;
+  // Service name of the spans. Empty is "mononoke-lfs".
+  4: string service_name;
+  // How often spans are sent, in milliseconds. 0 sends them every second.
+  5: i64 flush_interval_ms;
+  // Spans waiting to be sent past this many are dropped. 0 keeps 10000.
+  6: i64 max_queued_spans;
+} (rust.exhaustive)
+
+// Rolling counts of the objects requested for download, served at /popular and
+// logged periodically.
+struct PopularityReport {
+  // How far back requests are counted, in seconds.
+  1: i64 window_secs;
+  // How many of the most requested objects are logged. 0 logs 10.
+  2: i32 log_top;
+  // How often they are logged, in seconds. 0 disables logging.
+  3: i64 log_interval_secs;
+} (rust.exhaustive)
+
+// Pushes uploaded objects to the LFS servers in other regions, so that reads
+// there don't miss objects that were only just uploaded here.
+struct Replication {
+  // Base URLs of the peer LFS servers, without the repository.
+  1: list<string> peers;
+  // How many times to try pushing an object to each peer. 0 tries 3 times.
+  2: i32 max_attempts;
+  // How long to wait before retrying a push, in milliseconds. This doubles
+  // after each attempt. 0 waits 1 second.
+  3: i64 retry_delay_ms;
+  // Maximum number of pushes waiting to be retried or in flight. Uploads past
+  // this aren't replicated. 0 allows 1000.
+  4: i64 max_backlog;
+} (rust.exhaustive)
+
+// An audit trail of completed uploads and who sent them, kept apart from the
+// debug logs for compliance investigations. Each upload is recorded as a line
+// of JSON. Exactly one of the destinations must be set.
+struct AuditLog {
+  // Scribe category to write records to. With --scribe-logging-directory,
+  // they are written to a file named after the category in that directory.
+  1: string scribe_category;
+  // Local file to append records to.
+  2: string file_path;
+} (rust.exhaustive)
+
+// An upstream LFS server that requests are proxied to.
+struct Upstream {
+  // Base URL of the upstream, e.g. https://lfs.example.com/repo. Batch
+  // requests are sent to objects/batch under it.
+  1: string url;
+  // File holding the value of the Authorization header sent with requests to
+  // the upstream's host, e.g. "Bearer <token>", so that the secret isn't part
+  // of the config. The file is read when the header is first needed, and again
+  // every minute so that rotated credentials are picked up. If it can't be read
+  // again, the last value read is still sent. Empty sends no Authorization
+  // header.
+  2: string authorization_file;
+} (rust.exhaustive)
+
+// Per-repository overrides for LfsServerConfig. Unset fields inherit the value
+// from the server-wide config.
+struct LfsRepoConfig {
+  1: optional bool track_bytes_sent;
+  2: optional bool enable_consistent_routing;
+  3: optional bool enforce_acl_check;
+  4: optional ObjectPopularity object_popularity;
+  5: optional bool disable_compression;
+  // Load shedding limits checked in addition to the server-wide ones for
+  // requests to this repository.
+  6: optional list<ratelimits.LoadShedLimit> loadshedding_limits;
+  7: optional string download_redirect_url;
+  8: optional bool enable_locks;
+  // Lets one server front a different upstream for each repository.
+  9: optional Upstream upstream;
+  // connect_ms must be 0: connections to all upstreams come from one pool,
+  // which uses the server-wide connect_ms.
+  10: optional BackendTimeouts upstream_timeouts;
+} (rust.exhaustive)
+
 struct LfsServerConfig {
   // Whether or not to increment counters when sending bytes as opposed to when
   // accepting an upload.
@@ -87,4 +343,142 @@ struct LfsServerConfig {
 
   // Load shedding config
   16: list<ratelimits.LoadShedLimit> loadshedding_limits;
+
+  // Overrides keyed by repository name.
+  17: map<string, LfsRepoConfig> repos;
+
+  // Per-client request rate limits. The first limit with identities matching
+  // the client is used, falling back to a limit without identities.
+  18: list<RequestLimit> request_limits;
+
+  // Identities allowed to upload. A client may upload if it has all the
+  // identities in one of the lists. Uploads are allowed for everyone if
+  // this is unset.
+  19: optional list<list<string>> upload_acl;
+
+  // Maximum number of uploads being served at once across all clients. 0
+  // means unlimited. Uploads past the limit are rejected, unless they fit in
+  // the queue of max_queued_uploads.
+  20: i32 max_concurrent_uploads;
+
+  // Largest object that can be uploaded, in bytes. This applies on top of the
+  // server's --max-upload-size. 0 means no limit.
+  21: i64 max_object_size_bytes;
+
+  // Log 1 in N repository requests to the request log (Scuba). 0 or 1 logs
+  // every request. Verbose requests are always logged. This doesn't affect
+  // the access log in the server's own log, which log_sample_rate.by_route
+  // samples.
+  22: i64 access_log_sample_rate;
+
+  // Report this server as unhealthy so load balancers take it out of rotation,
+  // while still serving the requests that reach it.
+  23: bool drain;
+
+  // Whether to return verify actions alongside upload actions in batch
+  // responses.
+  24: bool enable_verify_action;
+
+  // Downloads of objects smaller than this many bytes are never compressed,
+  // since the savings are not worth the CPU. 0 allows compressing any object.
+  25: i64 min_compression_size_bytes;
+
+  // When set, downloads are answered with a redirect to this URL instead of
+  // the object itself, to serve them from a CDN. "{oid}" is replaced by the
+  // object's SHA256, and "{content_id}" by its Mononoke content ID. This is
+  // usually set per repository, in `repos`.
+  26: optional string download_redirect_url;
+
+  // SHA256 hashes of objects that may not be uploaded or downloaded, e.g. in
+  // response to takedown requests. Attempts are rejected and logged.
+  27: list<string> denied_oids;
+
+  // Thresholds past which the host is considered overloaded, and starts
+  // rejecting batch requests so that transfers already underway can finish.
+  // 0 disables a threshold.
+  28: i64 max_in_flight_requests;
+  // Bytes that downloads in progress have yet to send.
+  29: i64 max_egress_backlog_bytes;
+  30: i64 max_event_loop_lag_ms;
+  // Features being rolled out gradually, keyed by name. A feature that also
+  // has a boolean field above is enabled for everyone when that field is set.
+  // Supported features: "consistent_routing".
+  31: map<string, RolloutFeature> rollout;
+  // Whether to serve the Git LFS file locking API. This is usually set per
+  // repository, in `repos`.
+  32: bool enable_locks;
+  // Downloads retry reads from the blobstore that haven't completed after
+  // this many milliseconds, and use whichever attempt completes first. The
+  // retry goes to the same blobstore, so it only helps when the first attempt
+  // was unlucky, e.g. it hit a slow host or a stalled connection. 0 disables
+  // this.
+  33: i64 slow_read_retry_delay_ms;
+  // Throttles clients without identities by source IP, for deployments that
+  // don't get client identities from proxies or certificates.
+  34: optional IpThrottle ip_throttle;
+  // Maximum number of downloads being transferred at once, so that a burst of
+  // them can't starve uploads, which are limited by max_concurrent_uploads.
+  // Requests past the limit wait for a slot. 0 means unlimited.
+  36: i64 max_concurrent_download_transfers;
+  // How long downloads, and uploads queued by max_queued_uploads, wait for a
+  // slot before being rejected. 0 lets them wait until they get one.
+  37: i64 transfer_queue_timeout_ms;
+  38: optional LogSampleRate log_sample_rate;
+  // Unset disables CORS, so browsers block cross-origin requests.
+  39: optional CorsConfig cors;
+  // Maximum size of the body of batch requests, and of other requests that
+  // don't upload objects (e.g. verify and locks requests). Larger requests are
+  // rejected with a 413 instead of being buffered. 0 means unlimited.
+  40: i64 max_batch_request_bytes;
+  41: i64 max_request_body_bytes;
+  // Blobstore gets and puts return all at once, so they are bounded by the
+  // smaller of first_byte_ms and total_ms. connect_ms must be 0, as the
+  // blobstore manages its own connections.
+  42: optional BackendTimeouts blobstore_timeouts;
+  // Requests to the upstream LFS server this one proxies to.
+  43: optional BackendTimeouts upstream_timeouts;
+  // Throttled (429) and unavailable (503) responses tell clients to wait this
+  // many seconds before retrying, in a Retry-After header. 0 tells them to wait
+  // 1 second.
+  44: i64 retry_after_secs;
+  // Faults to inject into requests, keyed by route (see log_sample_rate). This
+  // is ignored unless the server was started with --unsafe-allow-fault-injection,
+  // so that a config change alone can't break a production deployment.
+  45: map<string, RouteFaults> fault_injection;
+  // Picks the targets of consistently routed downloads with rendezvous
+  // hashing, instead of leaving it to the proxy to hash their routing keys.
+  // Unset uses the proxy's hashing.
+  46: optional RendezvousRouting rendezvous_routing;
+  // Unset routes all objects consistently, subject to object_popularity.
+  47: optional HotObjectSpreading hot_object_spreading;
+  // Identities allowed to use the admin routes, e.g. to reload this config. A
+  // client may use them if it has all the identities in one of the lists.
+  // Unlike upload_acl, nobody may use them if this is empty.
+  48: list<list<string>> admin_acl;
+  // Unset enforces request_limits on each server on its own.
+  49: optional DistributedRateLimits distributed_rate_limits;
+  // Unset doesn't count downloads, and /popular returns a 404.
+  50: optional PopularityReport popularity_report;
+  // Unset doesn't replicate uploads.
+  51: optional Replication replication;
+  // The optional middlewares that requests go through, in order. Leaving one
+  // out disables it. Empty runs all of them, in this order: "load_shedding",
+  // "request_limits", "ip_throttle", "host_pressure", "qps",
+  // "fault_injection", "access_log". The access log is written after the
+  // response, so its position doesn't matter. Requests in flight are counted
+  // after the last of these whatever this says, so shutdown always waits for
+  // them.
+  52: list<string> middleware_pipeline;
+  // Unset doesn't keep an audit trail of uploads.
+  53: optional AuditLog audit_log;
+  // Unset never disables consistent routing on its own.
+  54: optional RoutingErrorBudget routing_error_budget;
+  // Unset doesn't export traces.
+  55: optional TraceExport trace_export;
+  // Replaces the server's --upstream-url. Unset uses --upstream-url, if any.
+  56: optional Upstream upstream;
+  // Uploads past max_concurrent_uploads wait for a slot, up to
+  // transfer_queue_timeout_ms, while fewer than this many are waiting. 0
+  // rejects them right away. Requires max_concurrent_uploads.
+  57: i64 max_queued_uploads;
 } (rust.exhaustive)
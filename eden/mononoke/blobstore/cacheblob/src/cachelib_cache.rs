@@ -7,7 +7,9 @@
 
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
+use anyhow::Result;
 use async_trait::async_trait;
 use blobstore::Blobstore;
 use blobstore::BlobstoreBytes;
@@ -21,6 +23,11 @@ use crate::dummy::DummyLease;
 use crate::in_process_lease::InProcessLease;
 use crate::locking_cache::CacheBlobstore;
 use crate::locking_cache::CacheOps;
+use crate::locking_cache::CacheWritePolicy;
+use crate::locking_cache::LeaseOps;
+use crate::missing_keys::MissingKeys;
+use crate::missing_keys::DEFAULT_MAX_MISSING_KEYS;
+use crate::single_flight::SingleFlight;
 
 const MAX_CACHELIB_VALUE_SIZE: u64 = 4 * 1024 * 1024;
 
@@ -28,21 +35,43 @@ const MAX_CACHELIB_VALUE_SIZE: u64 = 4 * 1024 * 1024;
 pub struct CachelibBlobstoreOptions {
     // Whether to attempt zstd compressing data so it will fit inside cachelibs threshold
     pub attempt_zstd: bool,
-    // Whether to wait for cache write before returning. Usually false apart from tests.
-    pub lazy_cache_put: bool,
+    // When puts write to the cache. Usually write-back apart from tests.
+    pub write_policy: CacheWritePolicy,
+    // If set, remember keys the blobstore doesn't have in process for this long.
+    pub missing_keys_ttl: Option<Duration>,
+    // If set, concurrent misses on a key share one blobstore fetch, waiting this long for it.
+    pub single_flight_timeout: Option<Duration>,
 }
 
 impl CachelibBlobstoreOptions {
     pub fn new_lazy(attempt_zstd: Option<bool>) -> Self {
         Self {
             attempt_zstd: attempt_zstd.unwrap_or(true),
-            lazy_cache_put: true,
+            write_policy: CacheWritePolicy::WriteBack,
+            missing_keys_ttl: None,
+            single_flight_timeout: None,
         }
     }
     pub fn new_eager(attempt_zstd: Option<bool>) -> Self {
         Self {
             attempt_zstd: attempt_zstd.unwrap_or(true),
-            lazy_cache_put: false,
+            write_policy: CacheWritePolicy::WriteThrough,
+            missing_keys_ttl: None,
+            single_flight_timeout: None,
+        }
+    }
+
+    pub fn with_missing_keys_ttl(self, missing_keys_ttl: Option<Duration>) -> Self {
+        Self {
+            missing_keys_ttl,
+            ..self
+        }
+    }
+
+    pub fn with_single_flight_timeout(self, single_flight_timeout: Option<Duration>) -> Self {
+        Self {
+            single_flight_timeout,
+            ..self
         }
     }
 }
@@ -93,7 +122,10 @@ where
     let cache_ops = CachelibOps::new(blob_pool, presence_pool, options);
     CountedBlobstore::new(
         "cachelib".to_string(),
-        CacheBlobstore::new(cache_ops, DummyLease {}, blobstore, options.lazy_cache_put),
+        with_in_process_options(
+            CacheBlobstore::new(cache_ops, DummyLease {}, blobstore, options.write_policy),
+            options,
+        ),
     )
 }
 
@@ -109,15 +141,36 @@ where
     let cache_ops = CachelibOps::new(blob_pool, presence_pool, options);
     CountedBlobstore::new(
         "cachelib".to_string(),
-        CacheBlobstore::new(
-            cache_ops,
-            InProcessLease::new(),
-            blobstore,
-            options.lazy_cache_put,
+        with_in_process_options(
+            CacheBlobstore::new(
+                cache_ops,
+                InProcessLease::new(),
+                blobstore,
+                options.write_policy,
+            ),
+            options,
         ),
     )
 }
 
+fn with_in_process_options<L, T>(
+    blobstore: CacheBlobstore<CachelibOps, L, T>,
+    options: CachelibBlobstoreOptions,
+) -> CacheBlobstore<CachelibOps, L, T>
+where
+    L: LeaseOps + Clone,
+    T: Blobstore,
+{
+    let blobstore = match options.missing_keys_ttl {
+        Some(ttl) => blobstore.with_missing_keys(MissingKeys::new(ttl, DEFAULT_MAX_MISSING_KEYS)),
+        None => blobstore,
+    };
+    match options.single_flight_timeout {
+        Some(timeout) => blobstore.with_single_flight(SingleFlight::new(timeout)),
+        None => blobstore,
+    }
+}
+
 #[async_trait]
 impl CacheOps for CachelibOps {
     const HIT_COUNTER: Option<PerfCounterType> = Some(PerfCounterType::CachelibHits);
@@ -131,7 +184,7 @@ impl CacheOps for CachelibOps {
         Some(blob.into())
     }
 
-    async fn put(&self, key: &str, value: BlobstoreGetData) {
+    async fn put(&self, key: &str, value: BlobstoreGetData) -> Result<()> {
         // A failure to set presence is considered fine, here.
         let _ = self.presence_pool.set(key, Bytes::from(b"P".as_ref()));
 
@@ -141,8 +194,9 @@ impl CacheOps for CachelibOps {
             None
         };
         if let Some(bytes) = value.into_bytes().encode(encode_limit) {
-            let _ = self.blob_pool.set(key, bytes);
+            self.blob_pool.set(key, bytes)?;
         }
+        Ok(())
     }
 
     /// Ask the cache if it knows whether the backing store has a value for this key. Returns
@@ -162,6 +216,11 @@ impl CacheOps for CachelibOps {
 
         presence_pool || blob_pool
     }
+
+    async fn put_present(&self, key: &str) -> Result<()> {
+        self.presence_pool.set(key, Bytes::from(b"P".as_ref()))?;
+        Ok(())
+    }
 }
 
 impl fmt::Debug for CachelibOps {
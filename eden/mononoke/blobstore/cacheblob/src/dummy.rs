@@ -53,7 +53,9 @@ impl CacheOps for DummyCache {
         None
     }
 
-    async fn put(&self, _key: &str, _value: BlobstoreGetData) {}
+    async fn put(&self, _key: &str, _value: BlobstoreGetData) -> Result<()> {
+        Ok(())
+    }
 
     async fn check_present(&self, _key: &str) -> bool {
         false
@@ -19,6 +19,7 @@ mod locking_cache;
 pub use crate::locking_cache::CacheBlobstore;
 pub use crate::locking_cache::CacheBlobstoreExt;
 pub use crate::locking_cache::CacheOps;
+pub use crate::locking_cache::CacheWritePolicy;
 pub use crate::locking_cache::LeaseOps;
 
 mod memcache_cache_lease;
@@ -27,3 +28,10 @@ pub use crate::memcache_cache_lease::MemcacheOps;
 
 mod mem_writes;
 pub use crate::mem_writes::MemWritesBlobstore;
+
+mod missing_keys;
+pub use crate::missing_keys::MissingKeys;
+pub use crate::missing_keys::DEFAULT_MAX_MISSING_KEYS;
+
+mod single_flight;
+pub use crate::single_flight::SingleFlight;
@@ -6,6 +6,7 @@
  */
 
 use std::fmt;
+use std::time::Instant;
 
 use anyhow::Result;
 use async_trait::async_trait;
@@ -24,12 +25,20 @@ use redactedblobstore::config::GET_OPERATION;
 use redactedblobstore::RedactedBlobstore;
 use stats::prelude::*;
 
+use crate::missing_keys::MissingKeys;
+use crate::single_flight::SingleFlight;
+
 define_stats! {
     prefix = "mononoke.blobstore.cacheblob";
     get_miss: dynamic_timeseries("{}.get_miss", (cache_name: &'static str); Rate, Sum),
     get_hit: dynamic_timeseries("{}.get_hit", (cache_name: &'static str); Rate, Sum),
     presence_hit: dynamic_timeseries("{}.presence_hit", (cache_name: &'static str); Rate, Sum),
     presence_miss: dynamic_timeseries("{}.presence_miss", (cache_name: &'static str); Rate, Sum),
+    get_known_missing: dynamic_timeseries("{}.get_known_missing", (cache_name: &'static str); Rate, Sum),
+    presence_known_missing: dynamic_timeseries("{}.presence_known_missing", (cache_name: &'static str); Rate, Sum),
+    cache_put_err: dynamic_timeseries("{}.cache_put_err", (cache_name: &'static str); Rate, Sum),
+    cache_put_skipped: dynamic_timeseries("{}.cache_put_skipped", (cache_name: &'static str); Rate, Sum),
+    cache_put_time_ms: dynamic_histogram("{}.cache_put_time_ms", (cache_name: &'static str); 10, 0, 1_000, Average, Sum, Count; P 50; P 95; P 99),
 }
 
 /// Extra operations that can be performed on a cache. Other wrappers can implement this trait for
@@ -54,7 +63,7 @@ pub trait CacheBlobstoreExt: Blobstore {
 /// caching blobstore that caches blob contents and blob presence.
 /// For caches that do no I/O (e.g. in-memory caches), use Result::into_future() to create the
 /// return types - it is up to CacheBlobstore to use future::lazy where this would be unacceptable
-/// Errors returned by the cache are always ignored.
+/// Errors returned by the cache are counted, but otherwise ignored.
 ///
 /// The cache is expected to act as-if each entry is in one of four states:
 /// 1. Empty, implying that the cache has no knowledge of the backing store state for this key.
@@ -81,12 +90,20 @@ pub trait CacheOps: fmt::Display + fmt::Debug + Send + Sync {
 
     /// Tell the cache that the backing store value for this `key` is `value`. This should put the
     /// cache entry for this `key` into Known state or a demotion of Known state (Present, Empty).
-    async fn put(&self, key: &str, value: BlobstoreGetData);
+    /// Values that the cache chooses not to store are not errors.
+    async fn put(&self, key: &str, value: BlobstoreGetData) -> Result<()>;
 
     /// Ask the cache if it knows whether the backing store has a value for this key. Returns
     /// `true` if there is definitely a value (i.e. cache entry in Present or Known state), `false`
     /// otherwise (Empty or Leased states).
     async fn check_present(&self, key: &str) -> bool;
+
+    /// Tell the cache that the backing store has a value for this `key`, without providing the
+    /// value. This should put the cache entry for this `key` into Present state (or leave it in
+    /// Known state). Caches that can't track presence separately from blobs needn't do anything.
+    async fn put_present(&self, _key: &str) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// The operations a cache must provide to take part in the update lease protocol. This reduces the
@@ -118,6 +135,28 @@ pub trait LeaseOps: fmt::Display + fmt::Debug + Send + Sync {
     async fn release_lease(&self, key: &str);
 }
 
+/// When a `put` to a caching blobstore writes the blob to the cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheWritePolicy {
+    /// Return from `put` once the blob is in both the blobstore and the cache.
+    WriteThrough,
+    /// Return from `put` once the blob is in the blobstore, and write it to the cache in the
+    /// background.
+    WriteBack,
+    /// Don't write the blob to the cache on `put`. It is cached once it is read.
+    Disabled,
+}
+
+/// Writes `value` to `cache`, recording how long that took and whether it failed.
+async fn put_to_cache<C: CacheOps>(cache: &C, key: &str, value: BlobstoreGetData) {
+    let start = Instant::now();
+    let res = cache.put(key, value).await;
+    STATS::cache_put_time_ms.add_value(start.elapsed().as_millis() as i64, (C::CACHE_NAME,));
+    if res.is_err() {
+        STATS::cache_put_err.add_value(1, (C::CACHE_NAME,));
+    }
+}
+
 /// A caching layer over a blobstore, using a cache defined by its CacheOps. The idea is that
 /// generic code that any caching layer needs is defined here, while code that's cache-specific
 /// goes into CacheOps
@@ -131,7 +170,9 @@ where
     blobstore: T,
     cache: C,
     lease: L,
-    lazy_cache_put: bool,
+    write_policy: CacheWritePolicy,
+    missing_keys: Option<MissingKeys>,
+    single_flight: Option<SingleFlight>,
 }
 
 impl<C, L, T> fmt::Display for CacheBlobstore<C, L, T>
@@ -155,15 +196,40 @@ where
     L: LeaseOps + Clone,
     T: Blobstore,
 {
-    pub fn new(cache: C, lease: L, blobstore: T, lazy_cache_put: bool) -> Self {
+    pub fn new(cache: C, lease: L, blobstore: T, write_policy: CacheWritePolicy) -> Self {
         Self {
             blobstore,
             cache,
             lease,
-            lazy_cache_put,
+            write_policy,
+            missing_keys: None,
+            single_flight: None,
+        }
+    }
+
+    /// Also remember which keys the blobstore doesn't have, so that lookups of those keys
+    /// don't reach it again until the entry expires or the key is put.
+    pub fn with_missing_keys(self, missing_keys: MissingKeys) -> Self {
+        Self {
+            missing_keys: Some(missing_keys),
+            ..self
+        }
+    }
+
+    /// Also let concurrent misses on the same key share a single fetch from the blobstore.
+    pub fn with_single_flight(self, single_flight: SingleFlight) -> Self {
+        Self {
+            single_flight: Some(single_flight),
+            ..self
         }
     }
 
+    fn is_known_missing(&self, key: &str) -> bool {
+        self.missing_keys
+            .as_ref()
+            .map_or(false, |missing_keys| missing_keys.is_missing(key))
+    }
+
     fn take_put_lease<'a>(&'a self, key: &'a str) -> BoxFuture<'a, bool> {
         async move {
             if self.lease.try_add_put_lease(key).await.map_err(|_| ()) == Ok(true) {
@@ -198,16 +264,26 @@ where
             }
             STATS::get_hit.add_value(1, (C::CACHE_NAME,));
             Ok(blob)
+        } else if self.is_known_missing(key) {
+            STATS::get_known_missing.add_value(1, (C::CACHE_NAME,));
+            Ok(None)
         } else {
             if let Some(counter) = C::MISS_COUNTER {
                 ctx.perf_counters().increment_counter(counter);
             }
             STATS::get_miss.add_value(1, (C::CACHE_NAME,));
-            let blob = self.blobstore.get(ctx, key).await?;
+            let epoch = self.missing_keys.as_ref().map(MissingKeys::epoch);
+            let fetch = self.blobstore.get(ctx, key);
+            let blob = match &self.single_flight {
+                Some(single_flight) => single_flight.get(key, fetch).await?,
+                None => fetch.await?,
+            };
             if let Some(ref blob) = blob {
                 let key = key.to_owned();
                 cloned!(self.cache, blob);
-                tokio::spawn(async move { cache.put(&key, blob).await });
+                tokio::spawn(async move { put_to_cache(&cache, &key, blob).await });
+            } else if let (Some(missing_keys), Some(epoch)) = (&self.missing_keys, epoch) {
+                missing_keys.insert(key, epoch);
             }
             Ok(blob)
         }
@@ -222,16 +298,25 @@ where
         let can_put = self.take_put_lease(&key).await;
         if can_put {
             self.blobstore.put(ctx, key.clone(), value.clone()).await?;
+            if let Some(missing_keys) = &self.missing_keys {
+                missing_keys.invalidate(&key);
+            }
 
             cloned!(self.cache, self.lease);
+            let write_policy = self.write_policy;
             let cache_put = async move {
-                cache.put(&key, value.into()).await;
+                if write_policy == CacheWritePolicy::Disabled {
+                    STATS::cache_put_skipped.add_value(1, (C::CACHE_NAME,));
+                } else {
+                    put_to_cache(&cache, &key, value.into()).await;
+                }
                 lease.release_lease(&key).await
             };
-            if self.lazy_cache_put {
-                tokio::spawn(cache_put);
-            } else {
-                let _ = cache_put.await;
+            match write_policy {
+                CacheWritePolicy::WriteBack => {
+                    tokio::spawn(cache_put);
+                }
+                CacheWritePolicy::WriteThrough | CacheWritePolicy::Disabled => cache_put.await,
             }
         }
         Ok(())
@@ -246,9 +331,29 @@ where
         if present {
             STATS::presence_hit.add_value(1, (C::CACHE_NAME,));
             Ok(BlobstoreIsPresent::Present)
+        } else if self.is_known_missing(key) {
+            STATS::presence_known_missing.add_value(1, (C::CACHE_NAME,));
+            Ok(BlobstoreIsPresent::Absent)
         } else {
             STATS::presence_miss.add_value(1, (C::CACHE_NAME,));
-            self.blobstore.is_present(ctx, key).await
+            let epoch = self.missing_keys.as_ref().map(MissingKeys::epoch);
+            let present = self.blobstore.is_present(ctx, key).await?;
+            match (&present, &self.missing_keys, epoch) {
+                (BlobstoreIsPresent::Present, _, _) => {
+                    let key = key.to_owned();
+                    cloned!(self.cache);
+                    tokio::spawn(async move {
+                        if cache.put_present(&key).await.is_err() {
+                            STATS::cache_put_err.add_value(1, (C::CACHE_NAME,));
+                        }
+                    });
+                }
+                (BlobstoreIsPresent::Absent, Some(missing_keys), Some(epoch)) => {
+                    missing_keys.insert(key, epoch);
+                }
+                _ => {}
+            }
+            Ok(present)
         }
     }
 }
@@ -31,6 +31,7 @@ use tunables::tunables;
 use crate::dummy::DummyLease;
 use crate::CacheBlobstore;
 use crate::CacheOps;
+use crate::CacheWritePolicy;
 use crate::LeaseOps;
 
 define_stats! {
@@ -95,7 +96,7 @@ async fn mc_raw_put(
     key: String,
     value: BlobstoreGetData,
     presence_key: String,
-) {
+) -> Result<()> {
     let uploaded = compact_protocol::serialize(LockState::uploaded_key(orig_key));
 
     STATS::presence_put.add_value(1);
@@ -115,7 +116,9 @@ async fn mc_raw_put(
         if res.is_err() {
             STATS::blob_put_err.add_value(1);
         }
+        res?;
     }
+    Ok(())
 }
 
 impl MemcacheOps {
@@ -191,7 +194,12 @@ where
     let cache_ops = MemcacheOps::new(fb, backing_store_name, backing_store_params)?;
     Ok(CountedBlobstore::new(
         "memcache".to_string(),
-        CacheBlobstore::new(cache_ops, DummyLease {}, blobstore, true),
+        CacheBlobstore::new(
+            cache_ops,
+            DummyLease {},
+            blobstore,
+            CacheWritePolicy::WriteBack,
+        ),
     ))
 }
 
@@ -208,7 +216,7 @@ impl CacheOps for MemcacheOps {
         Some(BlobstoreGetData::from_bytes(buf))
     }
 
-    async fn put(&self, key: &str, value: BlobstoreGetData) {
+    async fn put(&self, key: &str, value: BlobstoreGetData) -> Result<()> {
         let mc_key = self.keygen.key(key);
         let presence_key = self.presence_keygen.key(key);
         let orig_key = key.to_string();
@@ -0,0 +1,179 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How many keys a `MissingKeys` remembers at most, unless configured otherwise.
+pub const DEFAULT_MAX_MISSING_KEYS: usize = 100_000;
+
+/// An in-process record of keys that the backing store was recently found not to have, so that
+/// repeated lookups of absent keys (common during discovery) don't all reach the backing store.
+///
+/// Entries expire after `ttl`. Puts through this process invalidate them straight away, but puts
+/// through other processes are only seen once the entry expires, so `ttl` should be short.
+#[derive(Clone, Debug)]
+pub struct MissingKeys {
+    ttl: Duration,
+    max_keys: usize,
+    /// Keys known to be missing, and when that knowledge expires.
+    keys: Arc<Mutex<HashMap<String, Instant>>>,
+    /// Bumped on every invalidation, so that lookups that raced with a put don't record the key
+    /// as missing after the put invalidated it.
+    epoch: Arc<AtomicU64>,
+}
+
+impl MissingKeys {
+    pub fn new(ttl: Duration, max_keys: usize) -> Self {
+        Self {
+            ttl,
+            max_keys,
+            keys: Arc::new(Mutex::new(HashMap::new())),
+            epoch: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns `true` if `key` was recently found to be missing.
+    pub fn is_missing(&self, key: &str) -> bool {
+        let mut keys = self.keys.lock().expect("poisoned lock");
+        match keys.get(key) {
+            Some(expiry) if *expiry > Instant::now() => true,
+            Some(_) => {
+                keys.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// The epoch to pass to `insert` for a lookup of the backing store that starts now.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    /// Records that a lookup of the backing store that started at `epoch` didn't find `key`.
+    /// Nothing is recorded if there was an invalidation since, or if there is no room left.
+    pub fn insert(&self, key: &str, epoch: u64) {
+        let now = Instant::now();
+        let mut keys = self.keys.lock().expect("poisoned lock");
+        if self.epoch() != epoch {
+            return;
+        }
+        if keys.len() >= self.max_keys {
+            keys.retain(|_, expiry| *expiry > now);
+            if keys.len() >= self.max_keys {
+                return;
+            }
+        }
+        keys.insert(key.to_string(), now + self.ttl);
+    }
+
+    /// Forgets that `key` was missing, e.g. because it was just put.
+    pub fn invalidate(&self, key: &str) {
+        let mut keys = self.keys.lock().expect("poisoned lock");
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        keys.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use blobstore::Blobstore;
+    use blobstore::BlobstoreBytes;
+    use borrowed::borrowed;
+    use context::CoreContext;
+    use fbinit::FacebookInit;
+    use memblob::Memblob;
+
+    use super::*;
+    use crate::dummy::DummyCache;
+    use crate::dummy::DummyLease;
+    use crate::CacheBlobstore;
+    use crate::CacheWritePolicy;
+
+    #[test]
+    fn test_missing_keys() {
+        let missing = MissingKeys::new(Duration::from_secs(60), 2);
+
+        let epoch = missing.epoch();
+        missing.insert("a", epoch);
+        assert!(missing.is_missing("a"));
+        assert!(!missing.is_missing("b"));
+
+        missing.invalidate("a");
+        assert!(!missing.is_missing("a"));
+
+        // A lookup that started before the invalidation doesn't record anything.
+        missing.insert("b", epoch);
+        assert!(!missing.is_missing("b"));
+
+        // No room for a third key.
+        let epoch = missing.epoch();
+        missing.insert("a", epoch);
+        missing.insert("b", epoch);
+        missing.insert("c", epoch);
+        assert!(missing.is_missing("a"));
+        assert!(missing.is_missing("b"));
+        assert!(!missing.is_missing("c"));
+    }
+
+    #[test]
+    fn test_missing_keys_expire() {
+        let missing = MissingKeys::new(Duration::ZERO, 1);
+
+        // Expired keys make room for new ones.
+        missing.insert("a", missing.epoch());
+        missing.insert("b", missing.epoch());
+        {
+            let keys = missing.keys.lock().unwrap();
+            assert!(!keys.contains_key("a"));
+            assert!(keys.contains_key("b"));
+        }
+
+        assert!(!missing.is_missing("b"));
+    }
+
+    #[fbinit::test]
+    async fn test_cache_blobstore_missing_keys(fb: FacebookInit) -> anyhow::Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        borrowed!(ctx);
+        let inner = Memblob::default();
+        let blobstore = CacheBlobstore::new(
+            DummyCache {},
+            DummyLease {},
+            inner.clone(),
+            CacheWritePolicy::WriteThrough,
+        )
+        .with_missing_keys(MissingKeys::new(Duration::from_secs(60), 10));
+        let value = BlobstoreBytes::from_bytes("foobar");
+
+        assert!(blobstore.get(ctx, "foo").await?.is_none());
+
+        // A put that bypasses the cache isn't seen until the entry expires
+        inner.put(ctx, "foo".to_owned(), value.clone()).await?;
+        assert!(blobstore.get(ctx, "foo").await?.is_none());
+        assert!(
+            !blobstore
+                .is_present(ctx, "foo")
+                .await?
+                .assume_not_found_if_unsure()
+        );
+
+        // A put through the cache is seen straight away
+        blobstore.put(ctx, "foo".to_owned(), value.clone()).await?;
+        assert_eq!(
+            blobstore.get(ctx, "foo").await?.map(|v| v.into_bytes()),
+            Some(value)
+        );
+        Ok(())
+    }
+}
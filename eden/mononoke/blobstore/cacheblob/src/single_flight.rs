@@ -0,0 +1,200 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use blobstore::BlobstoreGetData;
+use futures::channel::oneshot::channel;
+use futures::channel::oneshot::Receiver;
+use futures::channel::oneshot::Sender;
+use futures::future::FutureExt;
+use futures::future::Shared;
+use stats::prelude::*;
+
+define_stats! {
+    prefix = "mononoke.blobstore.cacheblob.single_flight";
+    fill: timeseries(Rate, Sum),
+    shared: timeseries(Rate, Sum),
+    timeout: timeseries(Rate, Sum),
+    failed: timeseries(Rate, Sum),
+}
+
+type Fill = Shared<Receiver<Option<BlobstoreGetData>>>;
+
+/// Lets concurrent cache misses on the same key share a single fetch from the blobstore. The
+/// first miss fetches, and the others wait for its result for up to `timeout`, after which they
+/// fetch for themselves. They also fetch for themselves if the first fetch fails.
+#[derive(Clone, Debug)]
+pub struct SingleFlight {
+    timeout: Duration,
+    fills: Arc<Mutex<HashMap<String, Fill>>>,
+}
+
+/// Removes a fill when its fetch is done or abandoned. Dropping the sender without sending
+/// tells the waiters to fetch for themselves.
+struct FillGuard<'a> {
+    fills: &'a Mutex<HashMap<String, Fill>>,
+    key: &'a str,
+    sender: Option<Sender<Option<BlobstoreGetData>>>,
+}
+
+impl FillGuard<'_> {
+    fn complete(mut self, blob: Option<BlobstoreGetData>) {
+        self.remove();
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send(blob);
+        }
+    }
+
+    fn remove(&self) {
+        self.fills.lock().expect("poisoned lock").remove(self.key);
+    }
+}
+
+impl Drop for FillGuard<'_> {
+    fn drop(&mut self) {
+        if self.sender.is_some() {
+            self.remove();
+        }
+    }
+}
+
+impl SingleFlight {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            fills: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Fetches `key` with `fetch`, unless a fetch of `key` is already in flight, in which case
+    /// this waits for that one instead.
+    pub async fn get<Fut>(&self, key: &str, fetch: Fut) -> Result<Option<BlobstoreGetData>>
+    where
+        Fut: Future<Output = Result<Option<BlobstoreGetData>>>,
+    {
+        let in_flight = {
+            let mut fills = self.fills.lock().expect("poisoned lock");
+            match fills.get(key) {
+                Some(fill) => Err(fill.clone()),
+                None => {
+                    let (sender, receiver) = channel();
+                    fills.insert(key.to_string(), receiver.shared());
+                    Ok(FillGuard {
+                        fills: &self.fills,
+                        key,
+                        sender: Some(sender),
+                    })
+                }
+            }
+        };
+
+        match in_flight {
+            Ok(guard) => {
+                STATS::fill.add_value(1);
+                let blob = fetch.await?;
+                guard.complete(blob.clone());
+                Ok(blob)
+            }
+            Err(fill) => match tokio::time::timeout(self.timeout, fill).await {
+                Ok(Ok(blob)) => {
+                    STATS::shared.add_value(1);
+                    Ok(blob)
+                }
+                Ok(Err(_)) => {
+                    STATS::failed.add_value(1);
+                    fetch.await
+                }
+                Err(_) => {
+                    STATS::timeout.add_value(1);
+                    fetch.await
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use anyhow::anyhow;
+    use blobstore::BlobstoreBytes;
+    use tokio::sync::Barrier;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_single_flight() -> Result<()> {
+        let single_flight = SingleFlight::new(Duration::from_secs(60));
+        let fetches = AtomicUsize::new(0);
+        let release = Barrier::new(2);
+        let blob: BlobstoreGetData = BlobstoreBytes::from_bytes("foobar").into();
+
+        let fetch = || async {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            release.wait().await;
+            Ok(Some(blob.clone()))
+        };
+
+        // The second get waits for the first one's fetch, and doesn't fetch itself.
+        let (first, second, ()) = futures::join!(
+            single_flight.get("key", fetch()),
+            single_flight.get("key", fetch()),
+            async {
+                release.wait().await;
+            }
+        );
+        assert_eq!(first?, Some(blob.clone()));
+        assert_eq!(second?, Some(blob.clone()));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert!(single_flight.fills.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_failure() -> Result<()> {
+        let single_flight = SingleFlight::new(Duration::from_secs(60));
+        let blob: BlobstoreGetData = BlobstoreBytes::from_bytes("foobar").into();
+
+        // If the first fetch fails, the second get fetches for itself.
+        let (first, second) = futures::join!(
+            single_flight.get("key", async {
+                tokio::task::yield_now().await;
+                Err(anyhow!("failed"))
+            }),
+            single_flight.get("key", async { Ok(Some(blob.clone())) }),
+        );
+        assert!(first.is_err());
+        assert_eq!(second?, Some(blob));
+        assert!(single_flight.fills.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_timeout() -> Result<()> {
+        let single_flight = SingleFlight::new(Duration::ZERO);
+        let blob: BlobstoreGetData = BlobstoreBytes::from_bytes("foobar").into();
+
+        // The first fetch never completes, so the second get gives up waiting on it.
+        let first = single_flight.get("key", futures::future::pending());
+        futures::pin_mut!(first);
+        assert!(futures::poll!(first.as_mut()).is_pending());
+
+        let second = single_flight
+            .get("key", async { Ok(Some(blob.clone())) })
+            .await?;
+        assert_eq!(second, Some(blob));
+        Ok(())
+    }
+}
@@ -97,9 +97,32 @@ pub struct BlobstoreArgs {
     #[clap(long, default_value_t = false, value_name = "BOOL", action = ArgAction::Set)]
     pub blobstore_cachelib_attempt_zstd: bool,
 
+    /// Remember in process for this many milliseconds that the blobstore
+    /// doesn't have a key, so that repeated lookups of missing keys don't
+    /// all reach the blobstore.
+    #[clap(long)]
+    pub blobstore_cachelib_missing_keys_ttl_ms: Option<u64>,
+
+    /// Let concurrent cache misses on the same key share a single
+    /// blobstore fetch, waiting up to this many milliseconds for it before
+    /// fetching themselves.
+    #[clap(long)]
+    pub blobstore_cachelib_single_flight_timeout_ms: Option<u64>,
+
     /// Desired blobstore behaviour when a put is made to an existing key.
     #[clap(long)]
     pub blobstore_put_behaviour: Option<PutBehaviour>,
+
+    /// Size in bytes of the chunks that SQL blobstores split new values
+    /// into. Values already written keep the chunk size they were
+    /// written with.
+    #[clap(long)]
+    pub blobstore_sqlblob_chunk_size: Option<NonZeroUsize>,
+
+    /// Maximum number of chunks of a single value that SQL blobstores
+    /// fetch at once.
+    #[clap(long)]
+    pub blobstore_sqlblob_get_concurrency: Option<NonZeroUsize>,
 }
 
 impl BlobstoreArgs {
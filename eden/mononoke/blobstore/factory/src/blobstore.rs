@@ -85,6 +85,8 @@ pub struct BlobstoreOptions {
     pub put_behaviour: PutBehaviour,
     pub scrub_options: Option<ScrubOptions>,
     pub sqlblob_mysql_options: MysqlOptions,
+    pub sqlblob_chunk_size: NonZeroUsize,
+    pub sqlblob_get_concurrency: NonZeroUsize,
 }
 
 impl BlobstoreOptions {
@@ -111,6 +113,8 @@ impl BlobstoreOptions {
             // These are added via the builder methods
             scrub_options: None,
             sqlblob_mysql_options,
+            sqlblob_chunk_size: sqlblob::DEFAULT_CHUNK_SIZE,
+            sqlblob_get_concurrency: sqlblob::DEFAULT_GET_CONCURRENCY,
         }
     }
 
@@ -129,6 +133,8 @@ impl BlobstoreOptions {
             cachelib_options: Default::default(),
             scrub_options: None,
             sqlblob_mysql_options: Default::default(),
+            sqlblob_chunk_size: sqlblob::DEFAULT_CHUNK_SIZE,
+            sqlblob_get_concurrency: sqlblob::DEFAULT_GET_CONCURRENCY,
         }
     }
 
@@ -173,6 +179,31 @@ impl BlobstoreOptions {
         }
     }
 
+    pub fn with_sqlblob_chunk_size(self, sqlblob_chunk_size: Option<NonZeroUsize>) -> Self {
+        if let Some(sqlblob_chunk_size) = sqlblob_chunk_size {
+            Self {
+                sqlblob_chunk_size,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    pub fn with_sqlblob_get_concurrency(
+        self,
+        sqlblob_get_concurrency: Option<NonZeroUsize>,
+    ) -> Self {
+        if let Some(sqlblob_get_concurrency) = sqlblob_get_concurrency {
+            Self {
+                sqlblob_get_concurrency,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
     pub fn with_scrub_queue_peek_bound(self, queue_peek_bound_secs: u64) -> Self {
         if let Some(mut scrub_options) = self.scrub_options {
             scrub_options.queue_peek_bound = Duration::from_secs(queue_peek_bound_secs);
@@ -269,6 +300,8 @@ pub async fn make_sql_blobstore<'a>(
             readonly_storage.0,
             blobstore_options.put_behaviour,
             config_store,
+            blobstore_options.sqlblob_chunk_size,
+            blobstore_options.sqlblob_get_concurrency,
         )
         .context(ErrorKind::StateOpen),
         Mysql { remote } => {
@@ -313,6 +346,8 @@ pub async fn make_sql_blobstore_xdb<'a>(
                 readonly_storage.0,
                 put_behaviour,
                 config_store,
+                blobstore_options.sqlblob_chunk_size,
+                blobstore_options.sqlblob_get_concurrency,
             )
             .await
         }
@@ -325,6 +360,8 @@ pub async fn make_sql_blobstore_xdb<'a>(
                 readonly_storage.0,
                 put_behaviour,
                 config_store,
+                blobstore_options.sqlblob_chunk_size,
+                blobstore_options.sqlblob_get_concurrency,
             )
             .await
         }
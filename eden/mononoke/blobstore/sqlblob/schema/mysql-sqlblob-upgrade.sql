@@ -0,0 +1,16 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+-- Brings MySQL shards created with an older schema up to date. Apply each
+-- statement that hasn't been applied yet before deploying code that needs it.
+-- Existing rows get the default, which is what they were written with.
+
+-- Chunks written before chunk formats were recorded are uncompressed (0)
+ALTER TABLE `chunk` ADD COLUMN `chunk_format` INT UNSIGNED NOT NULL DEFAULT 0;
+
+-- Values written before chunk sizes were recorded used the default size (NULL)
+ALTER TABLE `data` ADD COLUMN `chunk_size` INT UNSIGNED;
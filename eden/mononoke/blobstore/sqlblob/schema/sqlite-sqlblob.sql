@@ -11,6 +11,7 @@ CREATE TABLE IF NOT EXISTS `data` (
   `chunk_id` VARCHAR(255) NOT NULL,
   `chunk_count` INT UNSIGNED NOT NULL,
   `chunking_method` INT UNSIGNED NOT NULL,
+  `chunk_size` INT UNSIGNED,
   PRIMARY KEY (`id`)
 );
 
@@ -19,6 +20,7 @@ CREATE TABLE IF NOT EXISTS `chunk` (
   `creation_time` TIMESTAMP DEFAULT CURRENT NOT NULL,
   `chunk_num` INT UNSIGNED NOT NULL,
   `value` BLOB NOT NULL,
+  `chunk_format` INT UNSIGNED NOT NULL DEFAULT 0,
   PRIMARY KEY (`id`, `chunk_num`)
 );
 
@@ -16,6 +16,7 @@ mod store;
 #[cfg(test)]
 mod tests;
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
 use std::future::Future;
@@ -47,9 +48,11 @@ use cached_config::ModificationTime;
 use cached_config::TestSource;
 use context::CoreContext;
 use fbinit::FacebookInit;
+use futures::stream;
 use futures::stream::FuturesOrdered;
 use futures::stream::FuturesUnordered;
 use futures::stream::Stream;
+use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
 use futures::TryFutureExt;
 use mononoke_types::hash::Context as HashContext;
@@ -73,6 +76,7 @@ use crate::delay::BlobDelay;
 use crate::facebook::myadmin_delay;
 #[cfg(not(fbcode_build))]
 use crate::myadmin_delay_dummy as myadmin_delay;
+use crate::store::ChunkFormat;
 use crate::store::ChunkGenerationState;
 use crate::store::ChunkSqlStore;
 use crate::store::ChunkingMethod;
@@ -81,7 +85,9 @@ use crate::store::DataSqlStore;
 // Leaving some space for metadata
 const MAX_KEY_SIZE: usize = 200;
 // MySQL wants multiple chunks, each around 1 MiB, as a tradeoff between query latency and replication lag
-const CHUNK_SIZE: usize = 1024 * 1024;
+pub const DEFAULT_CHUNK_SIZE: NonZeroUsize = nonzero!(1_048_576_usize);
+// How many chunks of a value to fetch at once, so that large values don't flood the database
+pub const DEFAULT_GET_CONCURRENCY: NonZeroUsize = nonzero!(16_usize);
 const SQLITE_SHARD_NUM: NonZeroUsize = nonzero!(2_usize);
 const SINGLE_SHARD_NUM: NonZeroUsize = nonzero!(1_usize);
 const GC_GENERATION_PATH: &str = "scm/mononoke/xdb_gc/default";
@@ -101,6 +107,9 @@ pub struct Sqlblob {
     put_behaviour: PutBehaviour,
     allow_inline_put: bool,
     ctime_inline_grace: i64,
+    compress_min_size: Option<u64>,
+    chunk_size: NonZeroUsize,
+    get_concurrency: NonZeroUsize,
 }
 
 impl std::fmt::Display for Sqlblob {
@@ -121,10 +130,60 @@ const DEFAULT_CTIME_INLINE_GRACE: i64 = 86400;
 // base64 encoding for inline hash has an overhead
 pub const MAX_INLINE_LEN: u64 = 255 * 3 / 4;
 
+// Smaller values don't compress well enough to be worth the CPU
+const DEFAULT_COMPRESS_MIN_SIZE: Option<u64> = Some(4096);
+
+// Columns added since the schema was first deployed, and how to add each to an existing table.
+// CREATE TABLE IF NOT EXISTS leaves existing tables alone, so SQLite databases are upgraded when
+// opened. MySQL shards are upgraded with schema/mysql-sqlblob-upgrade.sql before deploying.
+const SCHEMA_UPGRADES: &[(&str, &str, &str)] = &[
+    (
+        "chunk",
+        "chunk_format",
+        "ALTER TABLE chunk ADD COLUMN chunk_format INT UNSIGNED NOT NULL DEFAULT 0",
+    ),
+    (
+        "data",
+        "chunk_size",
+        "ALTER TABLE data ADD COLUMN chunk_size INT UNSIGNED",
+    ),
+];
+
+fn upgrade_sqlite_schema(con: &SqliteConnection) -> Result<()> {
+    for (table, column, upgrade) in SCHEMA_UPGRADES {
+        // Preparing a query fails if the column doesn't exist
+        let has_column = con
+            .prepare(&format!("SELECT {} FROM {} LIMIT 0", column, table))
+            .is_ok();
+        if !has_column {
+            con.execute_batch(upgrade)?;
+        }
+    }
+    Ok(())
+}
+
 fn encode_small_value(raw: &[u8]) -> String {
     base64::encode_config(raw, base64::STANDARD_NO_PAD)
 }
 
+/// Compress a chunk if requested, keeping it raw if compression doesn't make it smaller
+fn encode_chunk(raw: &[u8], compress: bool) -> Result<(Cow<'_, [u8]>, ChunkFormat)> {
+    if compress {
+        let compressed = zstd::bulk::compress(raw, 0 /* use default */)?;
+        if compressed.len() < raw.len() {
+            return Ok((Cow::Owned(compressed), ChunkFormat::Zstd));
+        }
+    }
+    Ok((Cow::Borrowed(raw), ChunkFormat::Raw))
+}
+
+fn decode_chunk(stored: BytesMut, chunk_format: ChunkFormat) -> Result<Bytes> {
+    match chunk_format {
+        ChunkFormat::Raw => Ok(stored.freeze()),
+        ChunkFormat::Zstd => Ok(Bytes::from(zstd::decode_all(&*stored)?)),
+    }
+}
+
 impl Sqlblob {
     pub async fn with_mysql(
         fb: FacebookInit,
@@ -134,6 +193,8 @@ impl Sqlblob {
         readonly: bool,
         put_behaviour: PutBehaviour,
         config_store: &ConfigStore,
+        chunk_size: NonZeroUsize,
+        get_concurrency: NonZeroUsize,
     ) -> Result<CountedSqlblob, Error> {
         let delay = if readonly {
             BlobDelay::dummy(shard_num)
@@ -185,6 +246,9 @@ impl Sqlblob {
                 put_behaviour,
                 allow_inline_put: DEFAULT_ALLOW_INLINE_PUT,
                 ctime_inline_grace: DEFAULT_CTIME_INLINE_GRACE,
+                compress_min_size: DEFAULT_COMPRESS_MIN_SIZE,
+                chunk_size,
+                get_concurrency,
             },
             shardmap,
         ))
@@ -197,6 +261,8 @@ impl Sqlblob {
         readonly: bool,
         put_behaviour: PutBehaviour,
         config_store: &ConfigStore,
+        chunk_size: NonZeroUsize,
+        get_concurrency: NonZeroUsize,
     ) -> Result<CountedSqlblob, Error> {
         let delay = if readonly {
             BlobDelay::dummy(SINGLE_SHARD_NUM)
@@ -221,6 +287,9 @@ impl Sqlblob {
             config_store,
             DEFAULT_ALLOW_INLINE_PUT,
             DEFAULT_CTIME_INLINE_GRACE,
+            DEFAULT_COMPRESS_MIN_SIZE,
+            chunk_size,
+            get_concurrency,
         )
         .await
     }
@@ -234,6 +303,9 @@ impl Sqlblob {
         config_store: &ConfigStore,
         allow_inline_put: bool,
         ctime_inline_grace: i64,
+        compress_min_size: Option<u64>,
+        chunk_size: NonZeroUsize,
+        get_concurrency: NonZeroUsize,
     ) -> Result<CountedSqlblob, Error>
     where
         CF: Fn(usize) -> SF,
@@ -281,6 +353,9 @@ impl Sqlblob {
                 put_behaviour,
                 allow_inline_put,
                 ctime_inline_grace,
+                compress_min_size,
+                chunk_size,
+                get_concurrency,
             },
             label,
         ))
@@ -291,6 +366,9 @@ impl Sqlblob {
         config_store: &ConfigStore,
         allow_inline_put: bool,
         ctime_inline_grace: i64,
+        compress_min_size: Option<u64>,
+        chunk_size: NonZeroUsize,
+        get_concurrency: NonZeroUsize,
     ) -> Result<CountedSqlblob> {
         Self::with_sqlite(
             put_behaviour,
@@ -302,6 +380,9 @@ impl Sqlblob {
             config_store,
             allow_inline_put,
             ctime_inline_grace,
+            compress_min_size,
+            chunk_size,
+            get_concurrency,
         )
     }
 
@@ -310,6 +391,8 @@ impl Sqlblob {
         readonly_storage: bool,
         put_behaviour: PutBehaviour,
         config_store: &ConfigStore,
+        chunk_size: NonZeroUsize,
+        get_concurrency: NonZeroUsize,
     ) -> Result<CountedSqlblob> {
         let pathbuf = path.into();
         Self::with_sqlite(
@@ -325,6 +408,9 @@ impl Sqlblob {
             config_store,
             DEFAULT_ALLOW_INLINE_PUT,
             DEFAULT_CTIME_INLINE_GRACE,
+            DEFAULT_COMPRESS_MIN_SIZE,
+            chunk_size,
+            get_concurrency,
         )
     }
 
@@ -334,6 +420,9 @@ impl Sqlblob {
         config_store: &ConfigStore,
         allow_inline_put: bool,
         ctime_inline_grace: i64,
+        compress_min_size: Option<u64>,
+        chunk_size: NonZeroUsize,
+        get_concurrency: NonZeroUsize,
     ) -> Result<CountedSqlblob>
     where
         F: FnMut(usize) -> Result<SqliteConnection>,
@@ -341,7 +430,9 @@ impl Sqlblob {
         let mut cons = Vec::with_capacity(SQLITE_SHARD_NUM.get());
 
         for i in 0..SQLITE_SHARD_NUM.get() {
-            cons.push(Connection::with_sqlite(constructor(i)?));
+            let con = constructor(i)?;
+            upgrade_sqlite_schema(&con)?;
+            cons.push(Connection::with_sqlite(con));
         }
 
         let cons: Arc<Vec1<Connection>> = Arc::new(cons.try_into()?);
@@ -371,6 +462,9 @@ impl Sqlblob {
                 put_behaviour,
                 allow_inline_put,
                 ctime_inline_grace,
+                compress_min_size,
+                chunk_size,
+                get_concurrency,
             },
             "sqlite".into(),
         ))
@@ -421,10 +515,46 @@ impl Sqlblob {
         }
     }
 
+    #[cfg(test)]
+    pub(crate) async fn get_chunk_formats(&self, key: &str) -> Result<Vec<ChunkFormat>> {
+        let chunked = self.data_store.get(key).await?;
+        if let Some(chunked) = chunked {
+            let fetch_chunk_formats: FuturesOrdered<_> = (0..chunked.count)
+                .map(|chunk_num| {
+                    self.chunk_store
+                        .get(&chunked.id, chunk_num, chunked.chunking_method)
+                        .map_ok(|(_, chunk_format)| chunk_format)
+                })
+                .collect();
+            fetch_chunk_formats.try_collect().await
+        } else {
+            bail!("key does not exist");
+        }
+    }
+
     pub fn get_mark_generation(&self) -> u64 {
         self.chunk_store.get_mark_generation()
     }
 
+    pub fn get_delete_generation(&self) -> u64 {
+        self.chunk_store.get_delete_generation()
+    }
+
+    /// Delete up to `limit` chunk ids from a shard whose chunks were last marked or put at or
+    /// before `delete_generation`, i.e. that no key referenced when they were last marked.
+    /// Returns how many chunk ids were considered; once it returns 0 the shard is swept.
+    pub async fn sweep_shard(
+        &self,
+        shard_num: usize,
+        // Take the delete generation as param, so that sweeping for an entire run is consistent
+        delete_generation: u64,
+        limit: u64,
+    ) -> Result<u64> {
+        self.chunk_store
+            .sweep(shard_num, delete_generation, limit)
+            .await
+    }
+
     /// Mark the generation for a key
     /// If its value was small enough to inline, then also inline it if requested
     pub async fn set_generation(
@@ -485,6 +615,7 @@ impl Sqlblob {
                                         &small_value,
                                         0,
                                         ChunkingMethod::InlineBase64,
+                                        None,
                                         old_ctime,
                                     )
                                     .await?;
@@ -508,15 +639,39 @@ impl Sqlblob {
                     Bytes::copy_from_slice(decoded.as_ref())
                 }
                 ChunkingMethod::ByContentHashBlake2 => {
-                    let chunks = (0..chunked.count)
+                    // Values are read back in the chunks they were written in, whatever the
+                    // chunk size is now
+                    let chunk_size: usize = match chunked.chunk_size {
+                        Some(chunk_size) => chunk_size.try_into()?,
+                        None => DEFAULT_CHUNK_SIZE.get(),
+                    };
+                    let chunks = stream::iter(0..chunked.count)
                         .map(|chunk_num| {
                             self.chunk_store
                                 .get(&chunked.id, chunk_num, chunked.chunking_method)
+                                .and_then(|(stored, chunk_format)| async move {
+                                    decode_chunk(stored, chunk_format)
+                                })
                         })
-                        .collect::<FuturesOrdered<_>>()
+                        .buffered(self.get_concurrency.get())
                         .try_collect::<Vec<_>>()
                         .await?;
 
+                    // All but the last chunk are full
+                    if let Some((_, full_chunks)) = chunks.split_last() {
+                        for (chunk_num, chunk) in full_chunks.iter().enumerate() {
+                            if chunk.len() != chunk_size {
+                                bail!(
+                                    "Chunk {} of {} has size {}, expected {}",
+                                    chunk_num,
+                                    key,
+                                    chunk.len(),
+                                    chunk_size
+                                );
+                            }
+                        }
+                    }
+
                     let size = chunks.iter().map(|chunk| chunk.len()).sum();
                     let mut blob = BytesMut::with_capacity(size);
                     for chunk in chunks {
@@ -594,6 +749,7 @@ impl Blobstore for Sqlblob {
                 &existing_data.id,
                 existing_data.count,
                 existing_data.chunking_method,
+                existing_data.chunk_size,
             )
             .await
     }
@@ -629,6 +785,11 @@ impl BlobstorePutOps for Sqlblob {
             ChunkingMethod::ByContentHashBlake2
         };
 
+        let chunk_size: Option<u32> = match chunking_method {
+            ChunkingMethod::ByContentHashBlake2 => Some(self.chunk_size.get().try_into()?),
+            ChunkingMethod::InlineBase64 => None,
+        };
+
         let put_fut = async {
             let ctime = {
                 match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
@@ -641,20 +802,31 @@ impl BlobstorePutOps for Sqlblob {
                     let chunk_key = {
                         let mut hash_context = HashContext::new(b"sqlblob");
                         hash_context.update(value.as_bytes());
+                        // Chunks of the same value split at another size must not share chunk
+                        // rows. Values split at the default size keep the keys they had before
+                        // the chunk size was configurable, so they still dedup with old data.
+                        if self.chunk_size != DEFAULT_CHUNK_SIZE {
+                            hash_context.update((self.chunk_size.get() as u64).to_le_bytes());
+                        }
                         hash_context.finish().to_hex().to_string()
                     };
-                    let chunks = value.as_bytes().chunks(CHUNK_SIZE);
+                    let compress = self
+                        .compress_min_size
+                        .map_or(false, |min_size| value_len >= min_size);
+                    let chunks = value.as_bytes().chunks(self.chunk_size.get());
                     let chunk_count = chunks.len().try_into()?;
                     let mut updated_gen = false;
                     let mut chunk_gen_insert_shard_id = None;
                     for (chunk_num, chunk_value) in chunks.enumerate() {
+                        let (chunk_value, chunk_format) = encode_chunk(chunk_value, compress)?;
                         let chunk_gen_state = self
                             .chunk_store
                             .put(
                                 chunk_key.as_str(),
                                 chunk_num.try_into()?,
                                 chunking_method,
-                                chunk_value,
+                                &chunk_value,
+                                chunk_format,
                                 value_len,
                             )
                             .await?;
@@ -686,6 +858,7 @@ impl BlobstorePutOps for Sqlblob {
                     chunk_key.as_str(),
                     chunk_count,
                     chunking_method,
+                    chunk_size,
                 )
                 .await?;
 
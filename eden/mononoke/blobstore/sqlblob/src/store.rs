@@ -89,12 +89,66 @@ mod types {
     impl FromValue for ChunkingMethod {
         type Intermediate = ChunkingMethod;
     }
+
+    /// How the value of a single chunk is stored. Chunks written before compression existed
+    /// have the column default, which is `Raw`.
+    #[derive(Clone, Copy, Debug, PartialEq, mysql::OptTryFromRowField)]
+    pub enum ChunkFormat {
+        Raw,
+        Zstd,
+    }
+
+    impl From<ChunkFormat> for Value {
+        fn from(format: ChunkFormat) -> Self {
+            match format {
+                // When you add here, please add the reverse transform
+                // to impl ConvIr<ChunkFormat> below
+                ChunkFormat::Raw => Value::UInt(0),
+                ChunkFormat::Zstd => Value::UInt(1),
+            }
+        }
+    }
+
+    impl ConvIr<ChunkFormat> for ChunkFormat {
+        fn new(v: Value) -> FromValueResult<Self> {
+            match v {
+                // As for ChunkingMethod, accept integer, unsigned and string forms of each value
+                Value::Int(0) => Ok(ChunkFormat::Raw),
+                Value::UInt(0) => Ok(ChunkFormat::Raw),
+                Value::Bytes(ref b) if b == b"0" => Ok(ChunkFormat::Raw),
+                Value::Int(1) => Ok(ChunkFormat::Zstd),
+                Value::UInt(1) => Ok(ChunkFormat::Zstd),
+                Value::Bytes(ref b) if b == b"1" => Ok(ChunkFormat::Zstd),
+                v @ Value::NULL
+                | v @ Value::Bytes(..)
+                | v @ Value::Float(..)
+                | v @ Value::Double(..)
+                | v @ Value::Date(..)
+                | v @ Value::Time(..)
+                | v @ Value::Int(..)
+                | v @ Value::UInt(..) => Err(FromValueError(v)),
+            }
+        }
+
+        fn commit(self) -> ChunkFormat {
+            self
+        }
+
+        fn rollback(self) -> Value {
+            self.into()
+        }
+    }
+
+    impl FromValue for ChunkFormat {
+        type Intermediate = ChunkFormat;
+    }
 }
 
+pub use self::types::ChunkFormat;
 pub use self::types::ChunkingMethod;
 
 mononoke_queries! {
-    write InsertData(values: (id: &str, ctime: i64, chunk_id: &str, chunk_count: u32, chunking_method: ChunkingMethod)) {
+    write InsertData(values: (id: &str, ctime: i64, chunk_id: &str, chunk_count: u32, chunking_method: ChunkingMethod, chunk_size: Option<u32>)) {
         insert_or_ignore,
         "{insert_or_ignore} INTO data (
             id
@@ -102,6 +156,7 @@ mononoke_queries! {
             , chunk_id
             , chunk_count
             , chunking_method
+            , chunk_size
         ) VALUES {values}"
     }
 
@@ -110,33 +165,36 @@ mononoke_queries! {
         "DELETE FROM data WHERE id = {id}"
     }
 
-    write UpdateData(id: &str, ctime: i64, chunk_id: &str, chunk_count: u32, chunking_method: ChunkingMethod) {
+    write UpdateData(id: &str, ctime: i64, chunk_id: &str, chunk_count: u32, chunking_method: ChunkingMethod, chunk_size: Option<u32>) {
         none,
         "UPDATE data SET
             creation_time = {ctime}
             , chunk_id = {chunk_id}
             , chunk_count = {chunk_count}
             , chunking_method = {chunking_method}
+            , chunk_size = {chunk_size}
         WHERE id = {id}"
     }
 
 
-    write UpdateDataOptimistic(id: &str, ctime: i64, chunk_id: &str, chunk_count: u32, chunking_method: ChunkingMethod, old_ctime: i64) {
+    write UpdateDataOptimistic(id: &str, ctime: i64, chunk_id: &str, chunk_count: u32, chunking_method: ChunkingMethod, chunk_size: Option<u32>, old_ctime: i64) {
         none,
         "UPDATE data SET
             creation_time = {ctime}
             , chunk_id = {chunk_id}
             , chunk_count = {chunk_count}
             , chunking_method = {chunking_method}
+            , chunk_size = {chunk_size}
         WHERE id = {id} AND creation_time = {old_ctime}"
     }
 
-    write InsertChunk(values: (id: &str, chunk_num: u32, value: &[u8])) {
+    write InsertChunk(values: (id: &str, chunk_num: u32, value: &[u8], chunk_format: ChunkFormat)) {
         insert_or_ignore,
         "{insert_or_ignore} INTO chunk (
             id
             , chunk_num
             , value
+            , chunk_format
         ) VALUES {values}"
     }
 
@@ -147,8 +205,8 @@ mononoke_queries! {
             WHERE id = {id} AND last_seen_generation < {generation}"
     }
 
-    read SelectData(id: &str) -> (i64, Vec<u8>, u32, ChunkingMethod) {
-        "SELECT creation_time, chunk_id, chunk_count, chunking_method
+    read SelectData(id: &str) -> (i64, Vec<u8>, u32, ChunkingMethod, Option<u32>) {
+        "SELECT creation_time, chunk_id, chunk_count, chunking_method, chunk_size
          FROM data
          WHERE id = {id}"
     }
@@ -159,8 +217,8 @@ mononoke_queries! {
          WHERE id = {id}"
     }
 
-    read SelectChunk(id: &str, chunk_num: u32) -> (Vec<u8>) {
-        "SELECT value
+    read SelectChunk(id: &str, chunk_num: u32) -> (Vec<u8>, ChunkFormat) {
+        "SELECT value, chunk_format
          FROM chunk
          WHERE id = {id}
            AND chunk_num = {chunk_num}"
@@ -190,6 +248,28 @@ mononoke_queries! {
         LIMIT {limit}"
     }
 
+    read GetSweepableChunkIds(delete_generation: u64, limit: u64) -> (Vec<u8>) {
+        "SELECT id
+        FROM chunk_generation
+        WHERE last_seen_generation <= {delete_generation}
+        LIMIT {limit}"
+    }
+
+    // Rechecks the generation, so that chunks a put has revived since they were selected survive
+    write DeleteSweepableChunks(delete_generation: u64, >list ids: String) {
+        none,
+        "DELETE FROM chunk
+        WHERE id IN {ids} AND id IN (
+            SELECT id FROM chunk_generation WHERE last_seen_generation <= {delete_generation}
+        )"
+    }
+
+    write DeleteSweepableGenerations(delete_generation: u64, >list ids: String) {
+        none,
+        "DELETE FROM chunk_generation
+        WHERE id IN {ids} AND last_seen_generation <= {delete_generation}"
+    }
+
     read GetAllKeys() -> (Vec<u8>) {
         "SELECT id FROM data"
     }
@@ -206,6 +286,9 @@ pub struct Chunked {
     pub count: u32,
     pub ctime: i64,
     pub chunking_method: ChunkingMethod,
+    /// The size the value was split into chunks at, if it is chunked and was written after
+    /// the chunk size became configurable
+    pub chunk_size: Option<u32>,
 }
 
 #[derive(Clone)]
@@ -246,15 +329,15 @@ impl DataSqlStore {
             }
         };
 
-        Ok(rows
-            .into_iter()
-            .next()
-            .map(|(ctime, chunk_id, chunk_count, chunking_method)| Chunked {
+        Ok(rows.into_iter().next().map(
+            |(ctime, chunk_id, chunk_count, chunking_method, chunk_size)| Chunked {
                 id: String::from_utf8_lossy(&chunk_id).to_string(),
                 count: chunk_count,
                 ctime,
                 chunking_method,
-            }))
+                chunk_size,
+            },
+        ))
     }
 
     pub(crate) async fn put(
@@ -264,6 +347,7 @@ impl DataSqlStore {
         chunk_id: &str,
         chunk_count: u32,
         chunking_method: ChunkingMethod,
+        chunk_size: Option<u32>,
     ) -> Result<(), Error> {
         let shard_id = self.shard(key);
 
@@ -271,7 +355,14 @@ impl DataSqlStore {
 
         let res = InsertData::query(
             &self.write_connection[shard_id],
-            &[(&key, &ctime, &chunk_id, &chunk_count, &chunking_method)],
+            &[(
+                &key,
+                &ctime,
+                &chunk_id,
+                &chunk_count,
+                &chunking_method,
+                &chunk_size,
+            )],
         )
         .await?;
         if res.affected_rows() == 0 {
@@ -282,6 +373,7 @@ impl DataSqlStore {
                 &chunk_id,
                 &chunk_count,
                 &chunking_method,
+                &chunk_size,
             )
             .await?;
         }
@@ -297,6 +389,7 @@ impl DataSqlStore {
         chunk_id: &str,
         chunk_count: u32,
         chunking_method: ChunkingMethod,
+        chunk_size: Option<u32>,
         old_ctime: i64,
     ) -> Result<(), Error> {
         let shard_id = self.shard(key);
@@ -309,6 +402,7 @@ impl DataSqlStore {
             &chunk_id,
             &chunk_count,
             &chunking_method,
+            &chunk_size,
             &old_ctime,
         )
         .await?;
@@ -407,7 +501,7 @@ impl ChunkSqlStore {
         id: &str,
         chunk_num: u32,
         chunking_method: ChunkingMethod,
-    ) -> Result<BytesMut, Error> {
+    ) -> Result<(BytesMut, ChunkFormat), Error> {
         if let Some(shard_id) = self.shard(id, chunk_num, chunking_method) {
             let rows = {
                 let rows =
@@ -421,7 +515,7 @@ impl ChunkSqlStore {
             };
             rows.into_iter()
                 .next()
-                .map(|(value,)| (&*value).into())
+                .map(|(value, chunk_format)| ((&*value).into(), chunk_format))
                 .ok_or_else(|| {
                     format_err!("Missing chunk with id {} shard {}", chunk_num, shard_id)
                 })
@@ -440,6 +534,7 @@ impl ChunkSqlStore {
         chunk_num: u32,
         chunking_method: ChunkingMethod,
         value: &[u8],
+        chunk_format: ChunkFormat,
         full_value_len: u64,
     ) -> Result<Option<ChunkGenerationState>, Error> {
         if let Some(shard_id) = self.shard(key, chunk_num, chunking_method) {
@@ -448,7 +543,7 @@ impl ChunkSqlStore {
             let conn = &self.write_connection[shard_id];
             // Update generation incase it already exists
             let updated = UpdateGeneration::query(conn, &key, &generation, &full_value_len).await?;
-            InsertChunk::query(conn, &[(&key, &chunk_num, &value)]).await?;
+            InsertChunk::query(conn, &[(&key, &chunk_num, &value, &chunk_format)]).await?;
             if updated.affected_rows() > 0 {
                 Ok(Some(ChunkGenerationState::Updated))
             } else {
@@ -463,6 +558,10 @@ impl ChunkSqlStore {
         self.gc_generations.get().mark_generation as u64
     }
 
+    pub(crate) fn get_delete_generation(&self) -> u64 {
+        self.gc_generations.get().delete_generation as u64
+    }
+
     // Store an entry for value_len eagerly if it was missing on ChunkSqlStore::put()'s UpdateGeneration
     // Saves lazy computing it with associated MySQL read bandwidth from length(chunk.value) later.
     pub(crate) async fn put_chunk_generation(
@@ -519,6 +618,7 @@ impl ChunkSqlStore {
         }
     }
 
+    // Note that this is the stored length, which is less than the value length for compressed chunks
     async fn get_len(&self, shard_id: usize, key: &str) -> Result<u64, Error> {
         let rows = {
             let rows = SelectChunkLen::query(&self.read_connection[shard_id], &key).await?;
@@ -609,7 +709,7 @@ impl ChunkSqlStore {
     }
 
     // Returns a HashMap from generation->(size, chunk_id_count)
-    // Its a chunk id count as some chunk ids have multiple chunks of CHUNK_SIZE
+    // Its a chunk id count as some chunk ids have multiple chunks of the chunk size
     // but chunk_generation doesn't record that (it doesn't need to)
     pub(crate) async fn get_chunk_sizes_by_generation(
         &self,
@@ -648,6 +748,35 @@ impl ChunkSqlStore {
         }
     }
 
+    // Deletes up to limit chunk ids last seen at or before delete_generation from a shard, along
+    // with their generations. Returns how many chunk ids were considered, so 0 means done.
+    pub(crate) async fn sweep(
+        &self,
+        shard_num: usize,
+        delete_generation: u64,
+        limit: u64,
+    ) -> Result<u64, Error> {
+        let ids: Vec<String> = GetSweepableChunkIds::query(
+            &self.read_master_connection[shard_num],
+            &delete_generation,
+            &limit,
+        )
+        .await?
+        .into_iter()
+        .map(|(id,)| String::from_utf8_lossy(&id).into_owned())
+        .collect();
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        self.delay.delay(shard_num).await;
+        let conn = &self.write_connection[shard_num];
+        // Chunks first, so that an interrupted sweep never leaves chunks without a generation
+        DeleteSweepableChunks::query(conn, &delete_generation, &ids[..]).await?;
+        DeleteSweepableGenerations::query(conn, &delete_generation, &ids[..]).await?;
+        Ok(ids.len() as u64)
+    }
+
     // Returns None if the value is stored inline without needing chunk table lookup
     fn shard(&self, key: &str, chunk_id: u32, chunking_method: ChunkingMethod) -> Option<usize> {
         match chunking_method {
@@ -29,13 +29,28 @@ where
     Fut: Future<Output = Result<()>>,
 {
     for allow_inline in [true, false] {
-        let (test_source, config_store) = get_test_config_store();
-        let blobstore =
-            Sqlblob::with_sqlite_in_memory(put_behaviour, &config_store, allow_inline, 0)?;
-        let ctx = CoreContext::test_mock(fb);
-        do_test(ctx, blobstore, test_source)
-            .await
-            .with_context(|| format_err!("while testing allow_inline {}", allow_inline))?;
+        for compress_min_size in [None, Some(0)] {
+            let (test_source, config_store) = get_test_config_store();
+            let blobstore = Sqlblob::with_sqlite_in_memory(
+                put_behaviour,
+                &config_store,
+                allow_inline,
+                0,
+                compress_min_size,
+                DEFAULT_CHUNK_SIZE,
+                DEFAULT_GET_CONCURRENCY,
+            )?;
+            let ctx = CoreContext::test_mock(fb);
+            do_test(ctx, blobstore, test_source)
+                .await
+                .with_context(|| {
+                    format_err!(
+                        "while testing allow_inline {} compress_min_size {:?}",
+                        allow_inline,
+                        compress_min_size
+                    )
+                })?;
+        }
     }
     Ok(())
 }
@@ -322,6 +337,9 @@ async fn generations(fb: FacebookInit) -> Result<(), Error> {
                 &config_store,
                 auto_inline_puts,
                 0, // no grace period for ctime updates,
+                None,
+                DEFAULT_CHUNK_SIZE,
+                DEFAULT_GET_CONCURRENCY,
             )?;
             let ctx = CoreContext::test_mock(fb);
             borrowed!(ctx);
@@ -386,3 +404,235 @@ async fn generations(fb: FacebookInit) -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[fbinit::test]
+async fn sweep(fb: FacebookInit) -> Result<(), Error> {
+    let (test_source, config_store) = get_test_config_store();
+    let bs = Sqlblob::with_sqlite_in_memory(
+        DEFAULT_PUT_BEHAVIOUR,
+        &config_store,
+        false,
+        0,
+        None,
+        DEFAULT_CHUNK_SIZE,
+        DEFAULT_GET_CONCURRENCY,
+    )?;
+    let ctx = CoreContext::test_mock(fb);
+    borrowed!(ctx);
+
+    let mut bytes_in = [0u8; 1024];
+    thread_rng().fill_bytes(&mut bytes_in);
+    let live = BlobstoreBytes::from_bytes(Bytes::copy_from_slice(&bytes_in));
+    thread_rng().fill_bytes(&mut bytes_in);
+    let orphan = BlobstoreBytes::from_bytes(Bytes::copy_from_slice(&bytes_in));
+
+    // Both written in generation 2, then one is no longer referenced
+    bs.put(ctx, "live".to_string(), live.clone()).await?;
+    bs.put(ctx, "orphan".to_string(), orphan.clone()).await?;
+    bs.unlink(ctx, "orphan").await?;
+
+    // Only the referenced one is marked
+    let mark_gen = set_test_generations(test_source.as_ref(), 4, 3, 0, INITIAL_VERSION + 1);
+    config_store.force_update_configs();
+    bs.set_generation("live", false, mark_gen).await?;
+
+    set_test_generations(test_source.as_ref(), 5, 4, 2, INITIAL_VERSION + 2);
+    config_store.force_update_configs();
+    let delete_gen = bs.get_delete_generation();
+    assert_eq!(delete_gen, 2);
+    while bs.sweep_shard(0, delete_gen, 1).await? > 0 {}
+
+    let sizes = bs.get_chunk_sizes_by_generation(0).await?;
+    assert_eq!(sizes, HashMap::from([(Some(3), (1024, 1))]));
+    assert_eq!(
+        bs.get(ctx, "live").await?.map(|v| v.into_bytes()),
+        Some(live)
+    );
+
+    // Putting the value again writes a new chunk
+    bs.put(ctx, "orphan".to_string(), orphan.clone()).await?;
+    assert_eq!(
+        bs.get(ctx, "orphan").await?.map(|v| v.into_bytes()),
+        Some(orphan)
+    );
+    Ok(())
+}
+
+#[fbinit::test]
+async fn compression(fb: FacebookInit) -> Result<(), Error> {
+    let (_, config_store) = get_test_config_store();
+    let bs = Sqlblob::with_sqlite_in_memory(
+        DEFAULT_PUT_BEHAVIOUR,
+        &config_store,
+        true,
+        0,
+        Some(1024),
+        DEFAULT_CHUNK_SIZE,
+        DEFAULT_GET_CONCURRENCY,
+    )?;
+    let ctx = CoreContext::test_mock(fb);
+    borrowed!(ctx);
+
+    let compressible = BlobstoreBytes::from_bytes(Bytes::from(
+        b"manifest ".repeat(DEFAULT_CHUNK_SIZE.get() / 4),
+    ));
+    let incompressible = {
+        let mut bytes_in = vec![0u8; 2048];
+        thread_rng().fill_bytes(&mut bytes_in);
+        BlobstoreBytes::from_bytes(Bytes::from(bytes_in))
+    };
+    let below_min_size = BlobstoreBytes::from_bytes(Bytes::from(b"manifest ".repeat(64)));
+
+    for (key, value, expected_formats) in [
+        (
+            "compressible",
+            compressible,
+            vec![ChunkFormat::Zstd, ChunkFormat::Zstd, ChunkFormat::Zstd],
+        ),
+        ("incompressible", incompressible, vec![ChunkFormat::Raw]),
+        ("below_min_size", below_min_size, vec![ChunkFormat::Raw]),
+    ] {
+        bs.put(ctx, key.to_string(), value.clone()).await?;
+        assert_eq!(
+            bs.get_chunk_formats(key).await?,
+            expected_formats,
+            "Unexpected chunk formats for {}",
+            key
+        );
+        let value_out = bs.get(ctx, key).await?;
+        assert_eq!(
+            value.as_bytes(),
+            value_out.unwrap().as_raw_bytes(),
+            "Value mismatch for {}",
+            key
+        );
+    }
+    Ok(())
+}
+
+#[fbinit::test]
+async fn upgrade_schema(fb: FacebookInit) -> Result<(), Error> {
+    // The schema before chunk formats and sizes were recorded
+    const OLD_SCHEMA: &str = "
+        CREATE TABLE data (
+          id VARCHAR(255) NOT NULL,
+          creation_time BIGINT NOT NULL,
+          chunk_id VARCHAR(255) NOT NULL,
+          chunk_count INT UNSIGNED NOT NULL,
+          chunking_method INT UNSIGNED NOT NULL,
+          PRIMARY KEY (id)
+        );
+        CREATE TABLE chunk (
+          id VARCHAR(255) NOT NULL,
+          creation_time TIMESTAMP DEFAULT CURRENT NOT NULL,
+          chunk_num INT UNSIGNED NOT NULL,
+          value BLOB NOT NULL,
+          PRIMARY KEY (id, chunk_num)
+        );
+        CREATE TABLE chunk_generation (
+          id VARCHAR(255) NOT NULL,
+          last_seen_generation BIGINT UNSIGNED NOT NULL,
+          value_len INT UNSIGNED NOT NULL,
+          PRIMARY KEY (id)
+        );
+    ";
+    // A value written with the old schema, in every shard so it's found whichever one is used
+    const OLD_VALUE: &str = "
+        INSERT INTO data (id, creation_time, chunk_id, chunk_count, chunking_method)
+          VALUES ('old', 0, 'old_chunk', 1, 1);
+        INSERT INTO chunk (id, chunk_num, value) VALUES ('old_chunk', 0, X'6F6C64');
+    ";
+
+    let (_, config_store) = get_test_config_store();
+    let bs = Sqlblob::with_sqlite(
+        DEFAULT_PUT_BEHAVIOUR,
+        |_| {
+            let con = open_sqlite_in_memory()?;
+            con.execute_batch(OLD_SCHEMA)?;
+            con.execute_batch(OLD_VALUE)?;
+            Ok(con)
+        },
+        &config_store,
+        false,
+        0,
+        Some(0),
+        DEFAULT_CHUNK_SIZE,
+        DEFAULT_GET_CONCURRENCY,
+    )?;
+    let ctx = CoreContext::test_mock(fb);
+    borrowed!(ctx);
+
+    assert_eq!(bs.get_chunk_formats("old").await?, vec![ChunkFormat::Raw]);
+    let old = bs.get_data_store().get("old").await?.unwrap();
+    assert_eq!(old.chunk_size, None);
+    assert_eq!(
+        bs.get(ctx, "old").await?.map(|v| v.into_bytes()),
+        Some(BlobstoreBytes::from_bytes("old"))
+    );
+
+    // New values can be written with the upgraded schema
+    let new = BlobstoreBytes::from_bytes(Bytes::from(b"manifest ".repeat(64)));
+    bs.put(ctx, "new".to_string(), new.clone()).await?;
+    assert_eq!(bs.get_chunk_formats("new").await?, vec![ChunkFormat::Zstd]);
+    let new_data = bs.get_data_store().get("new").await?.unwrap();
+    assert_eq!(
+        new_data.chunk_size,
+        Some(DEFAULT_CHUNK_SIZE.get().try_into()?)
+    );
+    assert_eq!(bs.get(ctx, "new").await?.map(|v| v.into_bytes()), Some(new));
+    Ok(())
+}
+
+#[fbinit::test]
+async fn chunk_size(fb: FacebookInit) -> Result<(), Error> {
+    let (_, config_store) = get_test_config_store();
+    let bs = Sqlblob::with_sqlite_in_memory(
+        DEFAULT_PUT_BEHAVIOUR,
+        &config_store,
+        true,
+        0,
+        None,
+        nonzero!(100_usize),
+        DEFAULT_GET_CONCURRENCY,
+    )?
+    .into_inner();
+    // Same database, but splitting values at a different size and fetching one chunk at a time
+    let resized = Sqlblob {
+        data_store: bs.data_store.clone(),
+        chunk_store: bs.chunk_store.clone(),
+        put_behaviour: bs.put_behaviour,
+        allow_inline_put: bs.allow_inline_put,
+        ctime_inline_grace: bs.ctime_inline_grace,
+        compress_min_size: bs.compress_min_size,
+        chunk_size: nonzero!(64_usize),
+        get_concurrency: nonzero!(1_usize),
+    };
+    let ctx = CoreContext::test_mock(fb);
+    borrowed!(ctx);
+
+    let mut bytes_in = [0u8; 1000];
+    thread_rng().fill_bytes(&mut bytes_in);
+    let blobstore_bytes = BlobstoreBytes::from_bytes(Bytes::copy_from_slice(&bytes_in));
+
+    bs.put(ctx, "old_size".to_string(), blobstore_bytes.clone())
+        .await?;
+    resized
+        .put(ctx, "new_size".to_string(), blobstore_bytes.clone())
+        .await?;
+
+    let data_store = bs.get_data_store();
+    let old_size = data_store.get("old_size").await?.unwrap();
+    assert_eq!((old_size.chunk_size, old_size.count), (Some(100), 10));
+    let new_size = data_store.get("new_size").await?.unwrap();
+    assert_eq!((new_size.chunk_size, new_size.count), (Some(64), 16));
+    assert_ne!(old_size.id, new_size.id, "Chunks must not be shared");
+
+    // Both are readable whatever the chunk size used for reading
+    for blobstore in [&bs, &resized] {
+        for key in ["old_size", "new_size"] {
+            let bytes_out = blobstore.get(ctx, key).await?;
+            assert_eq!(&bytes_in.to_vec(), bytes_out.unwrap().as_raw_bytes());
+        }
+    }
+    Ok(())
+}
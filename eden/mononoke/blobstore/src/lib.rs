@@ -12,7 +12,6 @@ pub mod macros;
 
 use std::collections::HashSet;
 use std::fmt;
-use std::io::Cursor;
 use std::ops::Bound;
 use std::ops::RangeBounds;
 use std::ops::RangeFrom;
@@ -68,8 +67,14 @@ pub struct BlobstoreGetData {
     bytes: BlobstoreBytes,
 }
 
+// Cache encodings of blobs are a version byte followed by a payload in that version's format.
+// Anything with another version decodes as a cache miss. This includes versions b'0' and b'1',
+// the unversioned formats that came before.
+const CACHE_ENCODING_VERSION: u8 = b'2';
+// In this version, the payload is one of these compression flags followed by the blob.
 const UNCOMPRESSED: u8 = b'0';
 const COMPRESSED: u8 = b'1';
+const CACHE_ENCODING_HEADER_LEN: usize = 2;
 
 impl BlobstoreGetData {
     #[inline]
@@ -229,66 +234,45 @@ impl BlobstoreBytes {
         &self.0
     }
 
+    /// Encode for storing in a cache, compressing if the encoding would be at least
+    /// `encode_limit` bytes otherwise.
     pub fn encode(self, encode_limit: Option<u64>) -> Option<Bytes> {
-        let mut bytes = vec![UNCOMPRESSED];
-        let prepared = BlobstoreBytesSerialisable::from(self);
-        unsafe { abomonation::encode(&prepared, &mut bytes).ok()? };
+        let encoded_len = CACHE_ENCODING_HEADER_LEN + self.len();
+        let mut encoded = Vec::with_capacity(encoded_len);
+        encoded.push(CACHE_ENCODING_VERSION);
 
         match encode_limit {
-            Some(encode_limit) if bytes.len() as u64 >= encode_limit => {
-                let mut compressed = Vec::with_capacity(bytes.len());
-                compressed.push(COMPRESSED);
-
-                let mut cursor = Cursor::new(bytes);
-                cursor.set_position(1);
-                zstd::stream::copy_encode(cursor, &mut compressed, 0 /* use default */).ok()?;
-                Some(Bytes::from(compressed))
+            Some(encode_limit) if encoded_len as u64 >= encode_limit => {
+                encoded.push(COMPRESSED);
+                zstd::stream::copy_encode(self.0.as_ref(), &mut encoded, 0 /* use default */)
+                    .ok()?;
+            }
+            _ => {
+                encoded.push(UNCOMPRESSED);
+                encoded.extend_from_slice(&self.0);
             }
-            _ => Some(Bytes::from(bytes)),
         }
+        Some(Bytes::from(encoded))
     }
 
+    /// Decode what `encode` stored in a cache. Returns `None` for anything that isn't in the
+    /// current cache encoding, which callers should treat as a cache miss.
     pub fn decode(mut bytes: Bytes) -> Option<Self> {
-        let prefix_size = 1;
-        if bytes.len() < prefix_size {
+        if bytes.len() < CACHE_ENCODING_HEADER_LEN {
             return None;
         }
 
-        let is_compressed = bytes.split_to(prefix_size);
-        let mut bytes: Vec<u8> = if is_compressed[0] == COMPRESSED {
-            let cursor = Cursor::new(bytes);
-            zstd::decode_all(cursor).ok()?
-        } else {
-            bytes.as_ref().into()
-        };
-
-        let get_data_serialisable =
-            unsafe { abomonation::decode::<BlobstoreBytesSerialisable>(&mut bytes) };
-
-        get_data_serialisable.and_then(|(get_data_serialisable, tail)| {
-            if tail.is_empty() {
-                Some(get_data_serialisable.clone().into())
-            } else {
-                None
-            }
-        })
-    }
-}
-
-/// Serialisable counterpart of BlobstoreBytes fields mimic exactly its original
-/// struct except in types that cannot be serialised
-#[derive(Abomonation, Clone)]
-struct BlobstoreBytesSerialisable(Vec<u8>);
-
-impl From<BlobstoreBytes> for BlobstoreBytesSerialisable {
-    fn from(blob_bytes: BlobstoreBytes) -> Self {
-        BlobstoreBytesSerialisable(blob_bytes.into_bytes().as_ref().into())
-    }
-}
-
-impl From<BlobstoreBytesSerialisable> for BlobstoreBytes {
-    fn from(blob_bytes: BlobstoreBytesSerialisable) -> Self {
-        BlobstoreBytes(blob_bytes.0.into())
+        let header = bytes.split_to(CACHE_ENCODING_HEADER_LEN);
+        if header[0] != CACHE_ENCODING_VERSION {
+            return None;
+        }
+        match header[1] {
+            UNCOMPRESSED => Some(BlobstoreBytes(bytes)),
+            COMPRESSED => zstd::decode_all(bytes.as_ref())
+                .ok()
+                .map(BlobstoreBytes::from_bytes),
+            _ => None,
+        }
     }
 }
 
@@ -681,3 +665,36 @@ impl<'a, A: Blobstore, B: Blobstore> BlobCopier for GenericBlobstoreCopier<'a, A
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache_encoding() {
+        let value = BlobstoreBytes::from_bytes(b"cached ".repeat(100));
+
+        for encode_limit in [None, Some(0), Some(1024)] {
+            let encoded = value.clone().encode(encode_limit).unwrap();
+            assert_eq!(encoded[0], CACHE_ENCODING_VERSION);
+            assert_eq!(
+                encoded[1] == COMPRESSED,
+                encode_limit == Some(0),
+                "Unexpected compression with limit {:?}",
+                encode_limit
+            );
+            assert_eq!(BlobstoreBytes::decode(encoded), Some(value.clone()));
+        }
+
+        // Other versions, truncated and corrupt encodings are all misses
+        for encoded in [
+            &b"0abc"[..],
+            &b"9abc"[..],
+            &b"2"[..],
+            &b"21not zstd"[..],
+            &b"2xabc"[..],
+        ] {
+            assert_eq!(BlobstoreBytes::decode(Bytes::from_static(encoded)), None);
+        }
+    }
+}
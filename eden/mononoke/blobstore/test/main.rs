@@ -27,6 +27,8 @@ use memblob::Memblob;
 use mononoke_types::BlobstoreBytes;
 use sqlblob::get_test_config_store;
 use sqlblob::Sqlblob;
+use sqlblob::DEFAULT_CHUNK_SIZE;
+use sqlblob::DEFAULT_GET_CONCURRENCY;
 use strum::IntoEnumIterator;
 use tempdir::TempDir;
 
@@ -249,7 +251,7 @@ blobstore_test_impl! {
 blobstore_test_impl! {
     sqlblob_test_no_inline => {
         state: (),
-        new: move |_, put_behaviour,| Sqlblob::with_sqlite_in_memory(put_behaviour, &(get_test_config_store().1), false, 0),
+        new: move |_, put_behaviour,| Sqlblob::with_sqlite_in_memory(put_behaviour, &(get_test_config_store().1), false, 0, None, DEFAULT_CHUNK_SIZE, DEFAULT_GET_CONCURRENCY),
         persistent: true,
         has_ctime: true,
     }
@@ -258,7 +260,16 @@ blobstore_test_impl! {
 blobstore_test_impl! {
     sqlblob_test_allow_inline => {
         state: (),
-        new: move |_, put_behaviour,| Sqlblob::with_sqlite_in_memory(put_behaviour, &(get_test_config_store().1), true, 0),
+        new: move |_, put_behaviour,| Sqlblob::with_sqlite_in_memory(put_behaviour, &(get_test_config_store().1), true, 0, None, DEFAULT_CHUNK_SIZE, DEFAULT_GET_CONCURRENCY),
+        persistent: true,
+        has_ctime: true,
+    }
+}
+
+blobstore_test_impl! {
+    sqlblob_test_compressed => {
+        state: (),
+        new: move |_, put_behaviour,| Sqlblob::with_sqlite_in_memory(put_behaviour, &(get_test_config_store().1), true, 0, Some(0), DEFAULT_CHUNK_SIZE, DEFAULT_GET_CONCURRENCY),
         persistent: true,
         has_ctime: true,
     }
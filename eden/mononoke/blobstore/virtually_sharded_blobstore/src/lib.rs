@@ -91,18 +91,16 @@ enum CacheData {
 }
 
 impl CacheData {
-    fn deserialize(mut val: Bytes) -> Result<Self> {
+    /// Returns `None` for stored data in a cache encoding we don't understand, which is a miss.
+    fn deserialize(mut val: Bytes) -> Result<Option<Self>> {
         let prefix = val.split_to(1);
 
         if prefix.as_ref() == NOT_STORABLE {
-            return Ok(Self::NotStorable);
+            return Ok(Some(Self::NotStorable));
         }
 
         if prefix.as_ref() == STORED {
-            let val = BlobstoreBytes::decode(val)
-                .ok_or_else(|| anyhow!("Invalid data in blob cache"))?
-                .into();
-            return Ok(Self::Stored(val));
+            return Ok(BlobstoreBytes::decode(val).map(|val| Self::Stored(val.into())));
         }
 
         Err(anyhow!("Invalid prefix: {:?}", prefix))
@@ -235,7 +233,7 @@ impl Cache {
             None => return Ok(None),
         };
 
-        Ok(Some(CacheData::deserialize(val)?))
+        CacheData::deserialize(val)
     }
 
     /// Set presence for this cache key.
@@ -501,7 +501,17 @@ fn create_blobstore_options(
     let pack_options = PackOptions::new(blobstore_args.put_format_override()?);
 
     let cachelib_blobstore_options =
-        CachelibBlobstoreOptions::new_lazy(Some(blobstore_args.blobstore_cachelib_attempt_zstd));
+        CachelibBlobstoreOptions::new_lazy(Some(blobstore_args.blobstore_cachelib_attempt_zstd))
+            .with_missing_keys_ttl(
+                blobstore_args
+                    .blobstore_cachelib_missing_keys_ttl_ms
+                    .map(Duration::from_millis),
+            )
+            .with_single_flight_timeout(
+                blobstore_args
+                    .blobstore_cachelib_single_flight_timeout_ms
+                    .map(Duration::from_millis),
+            );
 
     let blobstore_put_behaviour = blobstore_args.blobstore_put_behaviour;
 
@@ -518,7 +528,9 @@ fn create_blobstore_options(
         cachelib_blobstore_options,
         blobstore_put_behaviour,
         mysql_sqlblob_options,
-    );
+    )
+    .with_sqlblob_chunk_size(blobstore_args.blobstore_sqlblob_chunk_size)
+    .with_sqlblob_get_concurrency(blobstore_args.blobstore_sqlblob_get_concurrency);
 
     Ok(blobstore_options)
 }
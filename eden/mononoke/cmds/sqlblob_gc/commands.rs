@@ -8,4 +8,5 @@
 mononoke_app::subcommands! {
     mod generation_size;
     mod mark;
+    mod sweep;
 }
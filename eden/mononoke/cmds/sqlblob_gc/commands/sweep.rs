@@ -0,0 +1,105 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use clap::Parser;
+use futures::stream;
+use futures::stream::TryStreamExt;
+use mononoke_app::MononokeApp;
+use retry::retry_always;
+use slog::info;
+use slog::Logger;
+use sqlblob::Sqlblob;
+
+use crate::utils;
+use crate::MononokeSQLBlobGCArgs;
+
+const BASE_RETRY_DELAY_MS: u64 = 1000;
+const RETRIES: usize = 3;
+
+/// delete chunks that the last completed mark found unreferenced
+#[derive(Parser)]
+pub struct CommandArgs {
+    /// Maximum number of chunk ids to delete per batch.
+    #[clap(long, default_value_t = 1000)]
+    batch_size: u64,
+    /// Milliseconds to wait between batches on each shard, to limit the delete rate.
+    #[clap(long, default_value_t = 1000)]
+    batch_delay_ms: u64,
+}
+
+async fn sweep_shard(
+    store: &Sqlblob,
+    shard: usize,
+    delete_generation: u64,
+    args: &CommandArgs,
+    logger: &Logger,
+) -> Result<()> {
+    let mut swept: u64 = 0;
+    loop {
+        let count = retry_always(
+            logger,
+            |_| store.sweep_shard(shard, delete_generation, args.batch_size),
+            BASE_RETRY_DELAY_MS,
+            RETRIES,
+        )
+        .await
+        .with_context(|| anyhow!("Failed to sweep shard {} after {} retries", &shard, RETRIES))?
+        .0;
+        if count == 0 {
+            break;
+        }
+        swept += count;
+        tokio::time::sleep(Duration::from_millis(args.batch_delay_ms)).await;
+    }
+    info!(
+        logger,
+        "Completed sweep on shard {}, {} chunk ids considered", shard, swept
+    );
+    Ok(())
+}
+
+pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
+    let common_args: MononokeSQLBlobGCArgs = app.args()?;
+
+    let logger: Logger = app.logger().clone();
+
+    let max_parallelism: usize = common_args.scheduled_max;
+
+    let (sqlblob, shard_range) = utils::get_sqlblob_and_shard_range(&app).await?;
+
+    // Hold delete generation constant for run
+    let delete_generation = sqlblob.get_delete_generation();
+    let mark_generation = sqlblob.get_mark_generation();
+    if delete_generation >= mark_generation {
+        bail!(
+            "Delete generation {} must be below mark generation {}",
+            delete_generation,
+            mark_generation
+        );
+    }
+
+    info!(
+        logger,
+        "Starting sweep of generation {} and older", delete_generation
+    );
+    stream::iter(shard_range.map(Ok))
+        .try_for_each_concurrent(max_parallelism, |shard| {
+            sweep_shard(&sqlblob, shard, delete_generation, &args, &logger)
+        })
+        .await?;
+    info!(
+        logger,
+        "Completed sweep of generation {} and older", delete_generation
+    );
+    Ok(())
+}
@@ -49,6 +49,7 @@ pub use alias::add_aliases_to_multiplexer;
 pub use chunk::make_chunks;
 pub use chunk::Chunks;
 pub use copy::copy;
+pub use errors::ErrorKind;
 pub use expected_size::ExpectedSize;
 pub use fetch::Range;
 pub use fetch_key::Alias;
@@ -62,6 +62,20 @@ impl HttpError {
         }
     }
 
+    pub fn e413<E: Into<Error>>(err: E) -> Self {
+        Self {
+            error: err.into(),
+            status_code: StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+
+    pub fn e416<E: Into<Error>>(err: E) -> Self {
+        Self {
+            error: err.into(),
+            status_code: StatusCode::RANGE_NOT_SATISFIABLE,
+        }
+    }
+
     pub fn e429<E: Into<Error>>(err: E) -> Self {
         Self {
             error: err.into(),
@@ -10,7 +10,10 @@ use std::env;
 
 use anyhow::Error;
 use gotham::helpers::http::header::X_REQUEST_ID;
+use gotham::state::request_id;
+use gotham::state::FromState;
 use gotham::state::State;
+use hyper::header::HeaderMap;
 use hyper::header::HeaderValue;
 use hyper::Body;
 use hyper::Response;
@@ -75,7 +78,17 @@ impl Middleware for ServerIdentityMiddleware {
             headers.insert(*header, value.clone());
         }
 
-        if let Ok(id) = HeaderValue::from_str(state.short_request_id()) {
+        // Clients that sent their own request ID get it back in full, so they can correlate it
+        // with their own logs.
+        let client_request_id = HeaderMap::try_borrow_from(state)
+            .map_or(false, |headers| headers.contains_key(X_REQUEST_ID));
+        let id = if client_request_id {
+            request_id(state)
+        } else {
+            state.short_request_id()
+        };
+
+        if let Ok(id) = HeaderValue::from_str(id) {
             headers.insert(X_REQUEST_ID, id);
         }
     }
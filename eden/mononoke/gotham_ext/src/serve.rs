@@ -5,6 +5,9 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
 use std::panic::RefUnwindSafe;
 use std::sync::Arc;
 
@@ -17,10 +20,12 @@ use gotham::handler::Handler;
 use hyper::server::conn::Http;
 use openssl::ssl::Ssl;
 use openssl::ssl::SslAcceptor;
+use permission_checker::MononokeIdentitySet;
 use quiet_stream::QuietShutdownStream;
 use slog::warn;
 use slog::Logger;
 use tokio::net::TcpListener;
+use tokio::net::UnixListener;
 use tokio_openssl::SslStream;
 
 use crate::handler::MononokeHttpHandler;
@@ -120,3 +125,58 @@ where
         }));
     }
 }
+
+/// Serves plain HTTP on a unix domain socket, for deployments where a local proxy terminates TLS
+/// and authenticates clients. Peers running as one of `trusted_uids` are trusted the way a proxy
+/// presenting a trusted certificate would be: the client identities and IP they send in headers
+/// are used as is, so anyone who can run as one of those users can act as any client. Other peers
+/// are served like plain HTTP clients, without identities.
+///
+/// Unix sockets have no peer address, so peers are given the unspecified address 0.0.0.0:0, which
+/// unlike a loopback address won't match the addresses of local TCP clients, e.g. in allowlists.
+pub async fn unix<H>(
+    logger: Logger,
+    listener: UnixListener,
+    trusted_uids: HashSet<u32>,
+    handler: MononokeHttpHandler<H>,
+) -> Result<(), Error>
+where
+    H: Handler + Clone + Send + Sync + 'static + RefUnwindSafe,
+{
+    let peer_addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+    let trusted_uids = Arc::new(trusted_uids);
+
+    loop {
+        let (socket, _) = listener
+            .accept()
+            .await
+            .context("Error accepting connections")?;
+
+        cloned!(logger, handler, trusted_uids);
+
+        let task = async move {
+            let peer_cred = socket
+                .peer_cred()
+                .context("Error getting peer credentials")?;
+            let socket_data = if trusted_uids.contains(&peer_cred.uid()) {
+                Some(TlsSocketData::trusted_proxy(MononokeIdentitySet::new()))
+            } else {
+                None
+            };
+            let service = handler.clone().into_service(peer_addr, socket_data);
+
+            let socket = QuietShutdownStream::new(socket);
+
+            Http::new()
+                .serve_connection(socket, service)
+                .await
+                .context("Error serving connection")?;
+
+            Result::<_, Error>::Ok(())
+        };
+
+        tokio::spawn(task.map_err(move |e| {
+            warn!(&logger, "Unix socket server error: {:?}", e);
+        }));
+    }
+}
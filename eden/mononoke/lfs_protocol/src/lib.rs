@@ -5,12 +5,24 @@
  * GNU General Public License version 2.
  */
 
+mod locking;
 mod protocol;
 mod str_serialized;
 
+pub use locking::Lock;
+pub use locking::LockOwner;
+pub use locking::RequestCreateLock;
+pub use locking::RequestUnlock;
+pub use locking::RequestVerifyLocks;
+pub use locking::ResponseCreateLock;
+pub use locking::ResponseListLocks;
+pub use locking::ResponseLockConflict;
+pub use locking::ResponseUnlock;
+pub use locking::ResponseVerifyLocks;
 pub use protocol::git_lfs_mime;
 pub use protocol::ObjectAction;
 pub use protocol::ObjectError;
+pub use protocol::ObjectErrorCode;
 pub use protocol::ObjectStatus;
 pub use protocol::Operation;
 pub use protocol::RequestBatch;
@@ -0,0 +1,143 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use serde::Deserialize;
+use serde::Serialize;
+
+// This module provides types conforming to the Git-LFS file locking API:
+// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md
+
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct LockOwner {
+    pub name: String,
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct Lock {
+    pub id: String,
+    pub path: String,
+    /// When the lock was created, as an RFC 3339 timestamp.
+    pub locked_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<LockOwner>,
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct RequestCreateLock {
+    pub path: String,
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct ResponseCreateLock {
+    pub lock: Lock,
+}
+
+/// Returned with a 409 when the path is already locked.
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct ResponseLockConflict {
+    pub lock: Lock,
+    pub message: String,
+    pub request_id: Option<String>,
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct ResponseListLocks {
+    pub locks: Vec<Lock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct RequestVerifyLocks {
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct ResponseVerifyLocks {
+    /// Locks held by the client making the request.
+    pub ours: Vec<Lock>,
+    pub theirs: Vec<Lock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct RequestUnlock {
+    /// Release the lock even if it belongs to somebody else.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq, Eq)]
+pub struct ResponseUnlock {
+    pub lock: Lock,
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_deserialize_requests() {
+        // Clients send a ref along with most requests. This server doesn't scope locks to refs,
+        // so it is ignored.
+        let j = json!({"path": "foo/bar.zip", "ref": {"name": "refs/heads/main"}});
+        assert_eq!(
+            serde_json::from_value::<RequestCreateLock>(j).unwrap(),
+            RequestCreateLock {
+                path: "foo/bar.zip".to_string()
+            }
+        );
+
+        let j = json!({"ref": {"name": "refs/heads/main"}});
+        assert_eq!(
+            serde_json::from_value::<RequestVerifyLocks>(j).unwrap(),
+            RequestVerifyLocks {
+                cursor: None,
+                limit: None
+            }
+        );
+
+        assert!(
+            !serde_json::from_value::<RequestUnlock>(json!({}))
+                .unwrap()
+                .force
+        );
+    }
+
+    #[test]
+    fn test_serialize_list() {
+        let list = ResponseListLocks {
+            locks: vec![Lock {
+                id: "1".to_string(),
+                path: "foo/bar.zip".to_string(),
+                locked_at: "2016-05-17T15:49:06+00:00".to_string(),
+                owner: Some(LockOwner {
+                    name: "alice".to_string(),
+                }),
+            }],
+            next_cursor: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(list).unwrap(),
+            json!({
+                "locks": [{
+                    "id": "1",
+                    "path": "foo/bar.zip",
+                    "locked_at": "2016-05-17T15:49:06+00:00",
+                    "owner": {"name": "alice"},
+                }]
+            })
+        );
+    }
+}
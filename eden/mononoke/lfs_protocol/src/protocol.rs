@@ -42,6 +42,9 @@ pub enum Operation {
     Download,
     #[serde(rename = "upload")]
     Upload,
+    /// Only valid as an action, telling the client where to confirm an upload.
+    #[serde(rename = "verify")]
+    Verify,
 }
 
 impl Display for Operation {
@@ -49,6 +52,7 @@ impl Display for Operation {
         match self {
             Self::Download => write!(f, "download"),
             Self::Upload => write!(f, "upload"),
+            Self::Verify => write!(f, "verify"),
         }
     }
 }
@@ -223,10 +227,38 @@ impl ObjectAction {
     }
 }
 
+/// A machine-readable reason for an object error. This is an extension to the protocol, so other
+/// servers don't send it.
+#[derive(Copy, Clone, Serialize, Debug, Deserialize, Hash, PartialEq, Eq)]
+pub enum ObjectErrorCode {
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "too_large")]
+    TooLarge,
+    #[serde(other)]
+    Unknown,
+}
+
+impl Arbitrary for ObjectErrorCode {
+    fn arbitrary(g: &mut Gen) -> Self {
+        // We don't generate unknown codes, since they don't roundtrip.
+        if bool::arbitrary(g) {
+            Self::NotFound
+        } else {
+            Self::TooLarge
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Debug, Deserialize, Hash, PartialEq, Eq)]
 pub struct ObjectError {
     pub code: u16,
     pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ObjectErrorCode>,
+    /// Whether the same request might succeed if the client retries it.
+    #[serde(default)]
+    pub retryable: bool,
 }
 
 impl Arbitrary for ObjectError {
@@ -234,6 +266,8 @@ impl Arbitrary for ObjectError {
         Self {
             code: u16::arbitrary(g),
             message: String::arbitrary(g),
+            error_code: Option::<ObjectErrorCode>::arbitrary(g),
+            retryable: bool::arbitrary(g),
         }
     }
 }
@@ -386,12 +420,50 @@ mod test {
                     error: ObjectError {
                         code: 404,
                         message: _,
+                        error_code: None,
+                        retryable: false,
                     },
                 },
             })
         )
     }
 
+    #[test]
+    pub fn test_serialize_err_object() {
+        let error = ObjectError {
+            code: 404,
+            message: "Object does not exist".to_string(),
+            error_code: Some(ObjectErrorCode::NotFound),
+            retryable: false,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "code": 404,
+                "message": "Object does not exist",
+                "error_code": "not_found",
+                "retryable": false,
+            })
+        );
+
+        // Codes added after this version are still understood to be errors.
+        let j = json!({
+            "code": 503,
+            "message": "Try later",
+            "error_code": "overloaded",
+            "retryable": true,
+        });
+        assert_matches!(
+            serde_json::from_value::<ObjectError>(j),
+            Ok(ObjectError {
+                error_code: Some(ObjectErrorCode::Unknown),
+                retryable: true,
+                ..
+            })
+        );
+    }
+
     #[test]
     pub fn test_deserialize_action() {
         let j = json!({
@@ -0,0 +1,247 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! An audit trail of who uploaded which objects, written to the destination in the `audit_log`
+//! config. Unlike the debug logs, it is never sampled, and records are written as they happen, so
+//! it can be used for compliance investigations. Files are written and synced on a thread of
+//! their own, so that uploads don't block the runtime on disk.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Error;
+use futures::channel::mpsc;
+use futures::channel::oneshot;
+use mononoke_types::hash::Sha256;
+use scribe_ext::Scribe;
+use serde::Serialize;
+use slog::error;
+use slog::Logger;
+use stats::prelude::*;
+
+use crate::config::AuditLogDestination;
+use crate::lfs_server_context::RepositoryRequestContext;
+
+define_stats! {
+    prefix = "mononoke.lfs.audit_log";
+    recorded: timeseries(Rate, Sum),
+    failed: timeseries(Rate, Sum),
+}
+
+/// A completed upload, and who sent it.
+#[derive(Debug, Serialize)]
+struct UploadRecord<'a> {
+    timestamp: u64,
+    repository: &'a str,
+    oid: String,
+    size: u64,
+    identities: Vec<String>,
+    client_ip: Option<String>,
+    client_hostname: Option<&'a str>,
+    unix_name: Option<&'a str>,
+    request_id: Option<&'a str>,
+    /// Whether this was pushed by a peer replicating its own upload.
+    replicated: bool,
+}
+
+/// Work for the thread that appends to file destinations.
+enum FileWrite {
+    Append {
+        path: PathBuf,
+        line: String,
+        oid: Sha256,
+        logger: Logger,
+    },
+    /// Answered once everything sent before it was written.
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Clone)]
+pub struct AuditLog {
+    scribe: Scribe,
+    file_writer: mpsc::UnboundedSender<FileWrite>,
+}
+
+impl AuditLog {
+    /// Starts the thread that writes to file destinations. It exits once all copies of this are
+    /// dropped, after writing what they sent.
+    pub fn new(scribe: Scribe) -> Result<Self, Error> {
+        let (file_writer, writes) = mpsc::unbounded();
+        thread::Builder::new()
+            .name("lfs-audit-log".to_string())
+            .spawn(move || write_files(writes))?;
+
+        Ok(Self {
+            scribe,
+            file_writer,
+        })
+    }
+
+    /// Records that `oid` was uploaded, if the config keeps an audit trail. Failing to record it
+    /// doesn't fail the upload, which has already completed.
+    pub fn record_upload(
+        &self,
+        ctx: &RepositoryRequestContext,
+        oid: Sha256,
+        size: u64,
+        replicated: bool,
+    ) {
+        let destination = match ctx.config.audit_log() {
+            Some(destination) => destination,
+            None => return,
+        };
+
+        let metadata = ctx.ctx.metadata();
+        let record = UploadRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |t| t.as_secs()),
+            repository: &ctx.uri_builder.repository,
+            oid: oid.to_string(),
+            size,
+            identities: metadata
+                .identities()
+                .iter()
+                .map(|i| i.to_string())
+                .collect(),
+            client_ip: metadata.client_ip().map(|ip| ip.to_string()),
+            client_hostname: metadata.client_hostname(),
+            unix_name: metadata.unix_name(),
+            request_id: ctx.request_id(),
+            replicated,
+        };
+
+        let res = serde_json::to_string(&record)
+            .map_err(Error::from)
+            .and_then(|line| match destination {
+                AuditLogDestination::Scribe(category) => {
+                    self.scribe.offer(category, &line)?;
+                    STATS::recorded.add_value(1);
+                    Ok(())
+                }
+                AuditLogDestination::File(path) => {
+                    let write = FileWrite::Append {
+                        path: path.clone(),
+                        line,
+                        oid,
+                        logger: ctx.logger().clone(),
+                    };
+                    self.file_writer
+                        .unbounded_send(write)
+                        .map_err(|_| Error::msg("audit log writer exited"))
+                }
+            });
+
+        if let Err(err) = res {
+            report_failure(ctx.logger(), &oid, &err);
+        }
+    }
+
+    /// Waits for the records sent so far to be written, e.g. before exiting.
+    pub async fn flush(&self) {
+        let (sender, receiver) = oneshot::channel();
+        if self
+            .file_writer
+            .unbounded_send(FileWrite::Flush(sender))
+            .is_ok()
+        {
+            let _ = receiver.await;
+        }
+    }
+}
+
+fn report_failure(logger: &Logger, oid: &Sha256, err: &Error) {
+    STATS::failed.add_value(1);
+    error!(
+        logger,
+        "Failed to record upload of {} in the audit log: {:#}", oid, err
+    );
+}
+
+/// Appends records to their files one at a time, so that concurrent records don't interleave.
+fn write_files(writes: mpsc::UnboundedReceiver<FileWrite>) {
+    for write in futures::executor::block_on_stream(writes) {
+        match write {
+            FileWrite::Append {
+                path,
+                line,
+                oid,
+                logger,
+            } => match append(&path, &line) {
+                Ok(()) => STATS::recorded.add_value(1),
+                Err(err) => report_failure(&logger, &oid, &err),
+            },
+            FileWrite::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Appends `line` to the file at `path`, and waits for it to be on disk.
+fn append(path: &Path, line: &str) -> Result<(), Error> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use fbinit::FacebookInit;
+
+    use super::*;
+    use crate::config::ServerConfig;
+
+    #[fbinit::test]
+    async fn test_record_upload(fb: FacebookInit) -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("uploads");
+
+        let audit_log = AuditLog::new(Scribe::new(fb))?;
+        let oid = Sha256::from_byte_array([1; 32]);
+
+        // Nothing is recorded without an audit_log config.
+        let ctx = RepositoryRequestContext::test_builder(fb).await?.build()?;
+        audit_log.record_upload(&ctx, oid, 10, false);
+        audit_log.flush().await;
+        assert!(!path.exists());
+
+        let mut config = ServerConfig::default();
+        *config.audit_log_mut() = Some(AuditLogDestination::File(path.clone()));
+        let ctx = RepositoryRequestContext::test_builder(fb)
+            .await?
+            .config(config)
+            .build()?;
+
+        audit_log.record_upload(&ctx, oid, 10, false);
+        audit_log.record_upload(&ctx, oid, 20, true);
+        audit_log.flush().await;
+
+        let contents = fs::read_to_string(&path)?;
+        let records = contents
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["oid"], oid.to_string());
+        assert_eq!(records[0]["size"], 10);
+        assert_eq!(records[0]["replicated"], false);
+        assert_eq!(records[1]["size"], 20);
+        assert_eq!(records[1]["replicated"], true);
+
+        Ok(())
+    }
+}
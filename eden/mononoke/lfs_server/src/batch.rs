@@ -15,26 +15,25 @@ use blobstore::Blobstore;
 use blobstore::Loadable;
 use blobstore::LoadableError;
 use filestore::Alias;
+use filestore::FetchKey;
 use futures::future;
 use futures::future::FutureExt;
 use futures::pin_mut;
 use futures::select;
-use gotham::state::FromState;
 use gotham::state::State;
 use gotham_derive::StateData;
 use gotham_derive::StaticResponseExtender;
-use gotham_ext::body_ext::BodyExt;
 use gotham_ext::error::HttpError;
 use gotham_ext::middleware::RequestStartTime;
 use gotham_ext::middleware::ScubaMiddlewareState;
 use gotham_ext::response::BytesBody;
+use gotham_ext::response::EmptyBody;
 use gotham_ext::response::TryIntoResponse;
-use http::header::HeaderMap;
-use hyper::Body;
 use hyper::StatusCode;
 use lfs_protocol::git_lfs_mime;
 use lfs_protocol::ObjectAction;
 use lfs_protocol::ObjectError;
+use lfs_protocol::ObjectErrorCode;
 use lfs_protocol::ObjectStatus;
 use lfs_protocol::Operation;
 use lfs_protocol::RequestBatch;
@@ -48,19 +47,20 @@ use mononoke_types::typed_hash::ContentId;
 use mononoke_types::BlobstoreKey;
 use rand::Rng;
 use redactedblobstore::has_redaction_root_cause;
-use repo_blobstore::RepoBlobstoreRef;
 use serde::Deserialize;
 use slog::debug;
 use stats::prelude::*;
 use time_ext::DurationExt;
 use time_window_counter::GlobalTimeWindowCounterBuilder;
 
+use crate::config::RendezvousRouting;
 use crate::errors::ErrorKind;
 use crate::lfs_server_context::RepositoryRequestContext;
 use crate::lfs_server_context::UriBuilder;
 use crate::middleware::LfsMethod;
 use crate::popularity::consistent_routing;
 use crate::scuba::LfsScubaKey;
+use crate::util::read_request_body;
 
 define_stats! {
     prefix ="mononoke.lfs.batch";
@@ -227,18 +227,27 @@ impl InternalObject {
         self.id
     }
 
+    pub fn oid(&self) -> Sha256 {
+        self.oid
+    }
+
     pub fn download_size(&self) -> u64 {
         self.size.unwrap_or(0)
     }
+
+    /// The size of the object, unless it is redacted.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
 }
 
-async fn resolve_internal_object(
+pub async fn resolve_internal_object(
     ctx: &RepositoryRequestContext,
     oid: Sha256,
 ) -> Result<Option<InternalObject>, Error> {
-    let blobstore = ctx.repo.repo_blobstore();
+    let blobstore = ctx.blobstore();
 
-    let content_id = Alias::Sha256(oid).load(&ctx.ctx, blobstore).await;
+    let content_id = Alias::Sha256(oid).load(&ctx.ctx, &blobstore).await;
 
     let content_id = match content_id {
         Ok(content_id) => content_id,
@@ -292,11 +301,20 @@ async fn resolve_internal_object(
     Ok(None)
 }
 
-fn generate_routing_key(tasks_per_content: NonZeroU16, oid: Sha256) -> String {
+fn generate_routing_key(
+    rendezvous: Option<&RendezvousRouting>,
+    tasks_per_content: NonZeroU16,
+    oid: Sha256,
+) -> String {
     // Randomly generate task number to send to.
     let task_n = rand::thread_rng().gen_range(0..tasks_per_content.get());
+    // With rendezvous routing, the routing key is the target picked for the task.
+    let oid = format!("{}", oid);
+    if let Some(target) = rendezvous.and_then(|r| r.target(&oid, task_n.into())) {
+        return target.to_string();
+    }
     // For the base task, no extension is added to routing key.
-    let mut routing_key = format!("{}", oid);
+    let mut routing_key = oid;
     if task_n > 0 {
         // All other tasks have tailing number in routing key.
         routing_key = format!("{}-{}", routing_key, task_n);
@@ -304,6 +322,24 @@ fn generate_routing_key(tasks_per_content: NonZeroU16, oid: Sha256) -> String {
     routing_key
 }
 
+/// Consistent routing is either enabled for everyone, or rolled out to some clients, keyed by their
+/// identities.
+fn consistent_routing_enabled(ctx: &RepositoryRequestContext) -> bool {
+    if ctx.config.enable_consistent_routing() {
+        return true;
+    }
+
+    let key = ctx
+        .ctx
+        .metadata()
+        .identities()
+        .iter()
+        .map(|identity| identity.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    ctx.config.is_enabled("consistent_routing", &key)
+}
+
 async fn internal_objects(
     ctx: &RepositoryRequestContext,
     objects: &[RequestObject],
@@ -322,15 +358,25 @@ async fn internal_objects(
     });
 
     let objs = future::try_join_all(futs).await?;
+    let enable_consistent_routing = consistent_routing_enabled(ctx);
 
     objs.into_iter()
         .filter_map(|(maybe_obj, consistent_routing)| match maybe_obj {
             // Map the objects we have locally into an action routing to a Mononoke LFS server.
             Some(obj) => {
-                let uri = if let Some(consistent_routing) = consistent_routing && ctx.config.enable_consistent_routing() {
-                    let routing_key = generate_routing_key(consistent_routing, obj.oid);
-                    ctx.uri_builder
-                        .consistent_download_uri(&obj.id, routing_key, consistent_routing)
+                let uri = if let Some(consistent_routing) = consistent_routing && enable_consistent_routing {
+                    let routing_key = generate_routing_key(
+                        ctx.config.rendezvous_routing(),
+                        consistent_routing,
+                        obj.oid,
+                    );
+                    if ctx.routing_health().is_disabled(&ctx.config, &routing_key) {
+                        // Its destination is failing too many downloads, so don't pin it there.
+                        ctx.uri_builder.download_uri(&obj.id)
+                    } else {
+                        ctx.uri_builder
+                            .consistent_download_uri(&obj.id, routing_key, consistent_routing)
+                    }
                 } else {
                     ctx.uri_builder.download_uri(&obj.id)
                 };
@@ -377,6 +423,8 @@ fn batch_upload_response_objects(
                             code: StatusCode::BAD_REQUEST.as_u16(),
                             message: ErrorKind::UploadTooLarge(object.size, max_upload_size)
                                 .to_string(),
+                            error_code: Some(ObjectErrorCode::TooLarge),
+                            retryable: false,
                         },
                     }
                 }
@@ -412,7 +460,7 @@ async fn batch_upload(
     )
     .await?;
 
-    let objects = batch_upload_response_objects(
+    let mut objects = batch_upload_response_objects(
         &ctx.uri_builder,
         ctx.max_upload_size(),
         &batch.objects,
@@ -420,6 +468,17 @@ async fn batch_upload(
         &internal,
     )?;
 
+    if ctx.config.enable_verify_action() {
+        let verify = ObjectAction::new(ctx.uri_builder.verify_uri()?);
+        for object in objects.iter_mut() {
+            if let ObjectStatus::Ok { actions, .. } = &mut object.status {
+                if actions.contains_key(&Operation::Upload) {
+                    actions.insert(Operation::Verify, verify.clone());
+                }
+            }
+        }
+    }
+
     Ok(ResponseBatch {
         transfer: Transfer::Basic,
         objects,
@@ -499,6 +558,8 @@ fn batch_download_response_objects(
                         error: ObjectError {
                             code: StatusCode::NOT_FOUND.as_u16(),
                             message: "Object does not exist".to_string(),
+                            error_code: Some(ObjectErrorCode::NotFound),
+                            retryable: false,
                         },
                     };
 
@@ -550,13 +611,33 @@ fn batch_download_internal_only_response_objects(
     Some(res)
 }
 
+/// Counts the objects we serve towards the `popularity_report`.
+fn record_popular_objects(ctx: &RepositoryRequestContext, objects: &ServerObjects) {
+    let report = match ctx.config.popularity_report() {
+        Some(report) => report,
+        None => return,
+    };
+
+    let now = Instant::now();
+    for (oid, (size, _)) in objects.objects.iter() {
+        ctx.popular_objects()
+            .record(&ctx.repo.name, (*oid).into(), *size, report, now);
+    }
+}
+
 async fn batch_download(
     ctx: &RepositoryRequestContext,
     batch: RequestBatch,
     scuba: &mut Option<&mut ScubaMiddlewareState>,
 ) -> Result<ResponseBatch, ErrorKind> {
     let upstream = upstream_objects(ctx, &batch.objects).fuse();
-    let internal = internal_objects(ctx, &batch.objects).fuse();
+    let internal = internal_objects(ctx, &batch.objects)
+        .inspect(|res| {
+            if let Ok(objects) = res {
+                record_popular_objects(ctx, objects);
+            }
+        })
+        .fuse();
     pin_mut!(upstream, internal);
 
     let mut update_batch_order = |status| {
@@ -620,15 +701,7 @@ pub async fn batch(state: &mut State) -> Result<impl TryIntoResponse, HttpError>
         start_time.elapsed().as_micros_unchecked(),
     );
 
-    let body = Body::take_from(state);
-    let headers = HeaderMap::try_borrow_from(state);
-
-    let body = body
-        .try_concat_body_opt(headers)
-        .map_err(HttpError::e400)?
-        .await
-        .context(ErrorKind::ClientCancelled)
-        .map_err(HttpError::e400)?;
+    let body = read_request_body(state, ctx.config.max_batch_request_bytes()).await?;
 
     let mut scuba = state.try_borrow_mut::<ScubaMiddlewareState>();
 
@@ -657,6 +730,7 @@ pub async fn batch(state: &mut State) -> Result<impl TryIntoResponse, HttpError>
     let res = match request_batch.operation {
         Operation::Upload => batch_upload(&ctx, request_batch).await,
         Operation::Download => batch_download(&ctx, request_batch, &mut scuba).await,
+        Operation::Verify => Err(ErrorKind::InvalidBatchOperation(Operation::Verify)),
     };
 
     ScubaMiddlewareState::maybe_add(
@@ -666,7 +740,9 @@ pub async fn batch(state: &mut State) -> Result<impl TryIntoResponse, HttpError>
     );
 
     let res = res.map_err(|e| match e {
-        ErrorKind::HostNotAllowlisted(_) => HttpError::e400(e),
+        ErrorKind::HostNotAllowlisted(_) | ErrorKind::InvalidBatchOperation(_) => {
+            HttpError::e400(e)
+        }
         _ => HttpError::e500(e),
     })?;
     let body = serde_json::to_string(&res).map_err(HttpError::e500)?;
@@ -674,6 +750,43 @@ pub async fn batch(state: &mut State) -> Result<impl TryIntoResponse, HttpError>
     Ok(BytesBody::new(body, git_lfs_mime()))
 }
 
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct VerifyParams {
+    repository: String,
+}
+
+/// Handles the verify actions handed out with upload actions. Clients call this after uploading to
+/// confirm the object is now available here with the size they uploaded.
+pub async fn verify(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let VerifyParams { repository } = state.take();
+
+    let ctx = RepositoryRequestContext::instantiate(state, repository, LfsMethod::Verify).await?;
+
+    let body = read_request_body(state, ctx.config.max_request_body_bytes()).await?;
+
+    let object = serde_json::from_slice::<RequestObject>(&body)
+        .context(ErrorKind::InvalidVerifyRequest)
+        .map_err(HttpError::e400)?;
+
+    let oid: Sha256 = object.oid.into();
+    let obj = resolve_internal_object(&ctx, oid)
+        .await
+        .map_err(HttpError::e500)?
+        .ok_or(ErrorKind::ObjectDoesNotExist(FetchKey::Aliased(
+            Alias::Sha256(oid),
+        )))
+        .map_err(HttpError::e404)?;
+
+    // The size is unknown for redacted objects, but they do exist.
+    match obj.size {
+        Some(size) if size != object.size => Err(HttpError::e400(ErrorKind::VerifySizeMismatch(
+            size,
+            object.size,
+        ))),
+        _ => Ok(EmptyBody::new()),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashSet;
@@ -700,9 +813,11 @@ mod test {
     use pretty_assertions::assert_eq;
     use redactedblobstore::RedactedBlobs;
     use redactedblobstore::RedactedMetadata;
+    use repo_blobstore::RepoBlobstoreRef;
     use test_repo_factory::TestRepoFactory;
 
     use super::*;
+    use crate::config::ServerConfig;
     use crate::lfs_server_context::ServerUris;
     use crate::Repo;
 
@@ -773,6 +888,8 @@ mod test {
                         error: ObjectError {
                             code: 404,
                             message: "Object does not exist".to_string(),
+                            error_code: Some(ObjectErrorCode::NotFound),
+                            retryable: false,
                         }
                     }
                 }
@@ -818,16 +935,30 @@ mod test {
         let allowed_routing_key_base: String = format!("{}", ONES_SHA256);
         let allowed_routing_key_one: String = format!("{}-1", allowed_routing_key_base);
         // base case
-        let routing_key_base = generate_routing_key(NonZeroU16::new(1).unwrap(), ONES_SHA256);
+        let routing_key_base = generate_routing_key(None, NonZeroU16::new(1).unwrap(), ONES_SHA256);
         assert_eq!(&routing_key_base, &allowed_routing_key_base);
 
         // random key case
         let allowed_routing_keys = vec![allowed_routing_key_base, allowed_routing_key_one];
         for _ in 0..5 {
-            let routing_key = generate_routing_key(NonZeroU16::new(2).unwrap(), ONES_SHA256);
+            let routing_key = generate_routing_key(None, NonZeroU16::new(2).unwrap(), ONES_SHA256);
             assert!(allowed_routing_keys.contains(&routing_key))
         }
 
+        // rendezvous routing case
+        let rendezvous: RendezvousRouting = lfs_server_config::RendezvousRouting {
+            targets: vec![lfs_server_config::RoutingTarget {
+                name: "task1".to_string(),
+                weight: 1,
+            }],
+        }
+        .try_into()?;
+        for _ in 0..5 {
+            let routing_key =
+                generate_routing_key(Some(&rendezvous), NonZeroU16::new(2).unwrap(), ONES_SHA256);
+            assert_eq!(&routing_key, "task1");
+        }
+
         Ok(())
     }
 
@@ -961,6 +1092,8 @@ mod test {
                             code: 400,
                             message: "Object size (1111) exceeds max allowed size (1000)"
                                 .to_string(),
+                            error_code: Some(ObjectErrorCode::TooLarge),
+                            retryable: false,
                         }
                     }
                 },
@@ -971,6 +1104,33 @@ mod test {
         Ok(())
     }
 
+    #[fbinit::test]
+    async fn test_consistent_routing_repo_overrides(fb: FacebookInit) -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.repos.insert(
+            "repo1".to_string(),
+            lfs_server_config::LfsRepoConfig {
+                enable_consistent_routing: Some(true),
+                ..Default::default()
+            },
+        );
+        let config = Arc::new(ServerConfig::try_from(raw)?);
+
+        let ctx = |repo: &str| {
+            let config = config.for_repo(repo);
+            async move {
+                RepositoryRequestContext::test_builder(fb)
+                    .await?
+                    .config(config.as_ref().clone())
+                    .build()
+            }
+        };
+        assert!(consistent_routing_enabled(&ctx("repo1").await?));
+        assert!(!consistent_routing_enabled(&ctx("repo2").await?));
+
+        Ok(())
+    }
+
     #[fbinit::test]
     async fn test_resolve_missing(fb: FacebookInit) -> Result<(), Error> {
         let ctx = RepositoryRequestContext::test_builder(fb).await?.build()?;
@@ -5,14 +5,29 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::iter;
+use std::net::IpAddr;
 use std::num::NonZeroU16;
+use std::num::NonZeroU32;
+use std::num::NonZeroU64;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
+use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Error;
 use gotham_ext::middleware::PostResponseConfig;
+use http::header::HeaderName;
+use http::Uri;
+use ipnetwork::IpNetwork;
+use mononoke_types::hash::Sha256;
 use permission_checker::MononokeIdentitySet;
 use rate_limiting::LoadShedLimit;
 use serde::de::Deserializer;
@@ -20,6 +35,10 @@ use serde::de::Error as _;
 use serde::ser::Serializer;
 use serde::Deserialize;
 use serde::Serialize;
+use slog::Level;
+
+use crate::util::is_identity_subset;
+use crate::util::ROUTE_NAMES;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ObjectPopularity {
@@ -45,6 +64,44 @@ pub enum ConsistentRoutingRingMode {
     All,
 }
 
+impl ObjectPopularity {
+    /// Checks the ordering rules for `thresholds` documented in the config schema.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut prev: Option<&ConsistentRoutingRing> = None;
+        for ring in self.thresholds.iter() {
+            if let Some(prev) = prev {
+                if ring.threshold <= prev.threshold {
+                    bail!(
+                        "Thresholds are not in ascending order: {} after {}",
+                        ring.threshold,
+                        prev.threshold
+                    );
+                }
+                match (&prev.mode, &ring.mode) {
+                    (ConsistentRoutingRingMode::All, _) => {
+                        bail!("Only the last threshold can route to all tasks")
+                    }
+                    (
+                        ConsistentRoutingRingMode::Num {
+                            tasks_per_content: prev_tasks,
+                        },
+                        ConsistentRoutingRingMode::Num { tasks_per_content },
+                    ) if tasks_per_content <= prev_tasks => {
+                        bail!(
+                            "tasks_per_content is not in ascending order: {} after {}",
+                            tasks_per_content,
+                            prev_tasks
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            prev = Some(ring);
+        }
+        Ok(())
+    }
+}
+
 impl TryFrom<lfs_server_config::ConsistentRoutingRingMode> for ConsistentRoutingRingMode {
     type Error = Error;
 
@@ -110,133 +167,2094 @@ impl TryFrom<lfs_server_config::ObjectPopularity> for ObjectPopularity {
 }
 
 #[derive(Debug, Clone)]
-pub struct ServerConfig {
-    pub raw_server_config: lfs_server_config::LfsServerConfig,
-    loadshedding_limits: Vec<LoadShedLimit>,
-    object_popularity: Option<ObjectPopularity>,
-    disable_compression_identities: Vec<MononokeIdentitySet>,
+pub struct RequestLimit {
+    /// Identities a client must have for this limit to apply. Empty for the default limit.
+    pub identities: MononokeIdentitySet,
+    pub requests_per_second: Option<NonZeroU32>,
+    pub max_concurrent_requests: Option<NonZeroU32>,
+    pub max_concurrent_uploads: Option<NonZeroU32>,
+    pub max_egress_bytes_per_second: Option<NonZeroU64>,
+    pub max_upload_bytes_per_day: Option<NonZeroU64>,
+    pub max_upload_bytes_per_week: Option<NonZeroU64>,
 }
 
-impl TryFrom<lfs_server_config::LfsServerConfig> for ServerConfig {
+impl TryFrom<lfs_server_config::RequestLimit> for RequestLimit {
     type Error = Error;
 
-    fn try_from(value: lfs_server_config::LfsServerConfig) -> Result<Self, Error> {
-        let loadshedding_limits = value
-            .loadshedding_limits
-            .clone()
-            .into_iter()
-            .map(|l| l.try_into())
-            .collect::<Result<Vec<_>, _>>()
-            .context("Invalid loadshedding config")?;
+    fn try_from(value: lfs_server_config::RequestLimit) -> Result<Self, Self::Error> {
+        let identities = value
+            .identities
+            .iter()
+            .map(|i| FromStr::from_str(i))
+            .collect::<Result<BTreeSet<_>, _>>()?;
 
-        let object_popularity = value
-            .object_popularity
-            .as_ref()
-            .map(|o| o.clone().try_into())
-            .transpose()
-            .with_context(|| "Invalid object popularity")?;
+        let requests_per_second: u32 = value.requests_per_second.try_into().with_context(|| {
+            format!(
+                "Invalid requests_per_second: {:?}",
+                value.requests_per_second
+            )
+        })?;
 
-        let mut disable_compression_identities: Vec<MononokeIdentitySet> = Vec::new();
-        for list in value.disable_compression_identities.iter() {
-            let idents = list
-                .iter()
-                .map(|i| FromStr::from_str(i))
-                .collect::<Result<BTreeSet<_>, _>>()?;
-            disable_compression_identities.push(idents);
-        }
+        let max_concurrent_requests: u32 =
+            value.max_concurrent_requests.try_into().with_context(|| {
+                format!(
+                    "Invalid max_concurrent_requests: {:?}",
+                    value.max_concurrent_requests
+                )
+            })?;
+
+        let max_concurrent_uploads: u32 =
+            value.max_concurrent_uploads.try_into().with_context(|| {
+                format!(
+                    "Invalid max_concurrent_uploads: {:?}",
+                    value.max_concurrent_uploads
+                )
+            })?;
+
+        let max_egress_bytes_per_second: u64 = value
+            .max_egress_bytes_per_second
+            .try_into()
+            .with_context(|| {
+                format!(
+                    "Invalid max_egress_bytes_per_second: {:?}",
+                    value.max_egress_bytes_per_second
+                )
+            })?;
+
+        let max_upload_bytes_per_day: u64 =
+            value.max_upload_bytes_per_day.try_into().with_context(|| {
+                format!(
+                    "Invalid max_upload_bytes_per_day: {:?}",
+                    value.max_upload_bytes_per_day
+                )
+            })?;
+
+        let max_upload_bytes_per_week: u64 = value
+            .max_upload_bytes_per_week
+            .try_into()
+            .with_context(|| {
+                format!(
+                    "Invalid max_upload_bytes_per_week: {:?}",
+                    value.max_upload_bytes_per_week
+                )
+            })?;
 
         Ok(Self {
-            raw_server_config: value,
-            loadshedding_limits,
-            object_popularity,
-            disable_compression_identities,
+            identities,
+            requests_per_second: NonZeroU32::new(requests_per_second),
+            max_concurrent_requests: NonZeroU32::new(max_concurrent_requests),
+            max_concurrent_uploads: NonZeroU32::new(max_concurrent_uploads),
+            max_egress_bytes_per_second: NonZeroU64::new(max_egress_bytes_per_second),
+            max_upload_bytes_per_day: NonZeroU64::new(max_upload_bytes_per_day),
+            max_upload_bytes_per_week: NonZeroU64::new(max_upload_bytes_per_week),
         })
     }
 }
 
-impl<'de> Deserialize<'de> for ServerConfig {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let raw = lfs_server_config::LfsServerConfig::deserialize(deserializer)?;
-        let config = Self::try_from(raw).map_err(|e| D::Error::custom(format!("{:?}", e)))?;
-        Ok(config)
+#[derive(Debug, Clone)]
+pub struct RolloutFeature {
+    pub percentage: u64,
+    pub salt: String,
+}
+
+impl RolloutFeature {
+    /// Whether the feature is enabled for `key`. The hash is stable across hosts and restarts, so
+    /// a client keeps the feature as the percentage grows.
+    fn is_enabled(&self, key: &str) -> bool {
+        stable_hash(&self.salt, key) % 100 < self.percentage
     }
 }
 
-impl Serialize for ServerConfig {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        lfs_server_config::LfsServerConfig::serialize(&self.raw_server_config, serializer)
+/// FNV-1a, which unlike std's hasher is guaranteed not to change between releases.
+fn stable_hash(salt: &str, key: &str) -> u64 {
+    salt.bytes()
+        .chain(iter::once(b':'))
+        .chain(key.bytes())
+        .fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// The splitmix64 finalizer. FNV-1a hashes of strings that only differ in their first bytes are
+/// correlated, which would skew the scores of the targets compared for a key.
+fn mix(hash: u64) -> u64 {
+    let hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+impl TryFrom<lfs_server_config::RolloutFeature> for RolloutFeature {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::RolloutFeature) -> Result<Self, Self::Error> {
+        Ok(Self {
+            percentage: parse_percentage(value.percentage)?,
+            salt: value.salt,
+        })
     }
 }
 
-impl Default for ServerConfig {
-    fn default() -> Self {
-        let raw_server_config = lfs_server_config::LfsServerConfig {
-            track_bytes_sent: false,
-            enable_consistent_routing: false,
-            disable_hostname_logging: false,
-            enforce_acl_check: false,
-            loadshedding_limits: vec![],
-            object_popularity: None,
-            disable_compression: false,
-            disable_compression_identities: vec![],
-            enforce_authentication: false,
-        };
+fn parse_percentage(percentage: i32) -> Result<u64, Error> {
+    u64::try_from(percentage)
+        .ok()
+        .filter(|p| *p <= 100)
+        .with_context(|| format!("Invalid percentage: {:?}", percentage))
+}
 
-        Self {
-            raw_server_config,
-            loadshedding_limits: vec![],
-            object_popularity: None,
-            disable_compression_identities: vec![],
+#[derive(Debug, Clone)]
+pub struct IpThrottle {
+    pub requests_per_second: Option<NonZeroU32>,
+    pub exempt_cidrs: Vec<IpNetwork>,
+}
+
+impl IpThrottle {
+    pub fn is_exempt(&self, ip: IpAddr) -> bool {
+        self.exempt_cidrs.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+impl TryFrom<lfs_server_config::IpThrottle> for IpThrottle {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::IpThrottle) -> Result<Self, Self::Error> {
+        let requests_per_second: u32 = value.requests_per_second.try_into().with_context(|| {
+            format!(
+                "Invalid requests_per_second: {:?}",
+                value.requests_per_second
+            )
+        })?;
+
+        let exempt_cidrs = value
+            .exempt_cidrs
+            .iter()
+            .map(|cidr| {
+                IpNetwork::from_str(cidr).with_context(|| format!("Invalid CIDR: {}", cidr))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            requests_per_second: NonZeroU32::new(requests_per_second),
+            exempt_cidrs,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogSampleRate {
+    by_level: HashMap<Level, NonZeroU64>,
+    by_route: HashMap<String, NonZeroU64>,
+}
+
+/// Rates of 0 and 1 mean that everything is logged, so they aren't kept.
+fn parse_sample_rate(rate: i64) -> Result<Option<NonZeroU64>, Error> {
+    let rate = u64::try_from(rate).with_context(|| format!("Invalid sample rate: {}", rate))?;
+    Ok(NonZeroU64::new(rate).filter(|rate| rate.get() > 1))
+}
+
+impl TryFrom<lfs_server_config::LogSampleRate> for LogSampleRate {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::LogSampleRate) -> Result<Self, Self::Error> {
+        let mut by_level = HashMap::new();
+        for (level, rate) in value.by_level.iter() {
+            let parsed =
+                Level::from_str(level).map_err(|_| anyhow!("Invalid log level: {}", level))?;
+            if let Some(rate) = parse_sample_rate(*rate)? {
+                by_level.insert(parsed, rate);
+            }
         }
+
+        let mut by_route = HashMap::new();
+        for (route, rate) in value.by_route.iter() {
+            if let Some(rate) = parse_sample_rate(*rate)? {
+                by_route.insert(route.clone(), rate);
+            }
+        }
+
+        Ok(Self { by_level, by_route })
     }
 }
 
-impl ServerConfig {
-    pub fn track_bytes_sent(&self) -> bool {
-        self.raw_server_config.track_bytes_sent
+#[derive(Debug, Clone)]
+pub struct Cors {
+    allow_any_origin: bool,
+    allowed_origins: HashSet<String>,
+    pub allowed_headers: Vec<String>,
+    pub max_age_secs: Option<u64>,
+}
+
+impl Cors {
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allow_any_origin || self.allowed_origins.contains(origin)
     }
-    pub fn enable_consistent_routing(&self) -> bool {
-        self.raw_server_config.enable_consistent_routing
+}
+
+impl TryFrom<lfs_server_config::CorsConfig> for Cors {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::CorsConfig) -> Result<Self, Self::Error> {
+        let max_age_secs = u64::try_from(value.max_age_secs)
+            .with_context(|| format!("Invalid max_age_secs: {}", value.max_age_secs))?;
+
+        for header in value.allowed_headers.iter() {
+            HeaderName::from_str(header).with_context(|| format!("Invalid header: {}", header))?;
+        }
+
+        // Browsers send origins without a trailing slash, so normalize them to match.
+        let allowed_origins = value
+            .allowed_origins
+            .iter()
+            .map(|origin| origin.trim_end_matches('/').to_string())
+            .collect::<HashSet<_>>();
+
+        Ok(Self {
+            allow_any_origin: allowed_origins.contains("*"),
+            allowed_origins,
+            allowed_headers: value.allowed_headers,
+            max_age_secs: Some(max_age_secs).filter(|age| *age > 0),
+        })
     }
-    pub fn disable_hostname_logging(&self) -> bool {
-        self.raw_server_config.disable_hostname_logging
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackendTimeouts {
+    pub connect: Option<Duration>,
+    pub first_byte: Option<Duration>,
+    pub total: Option<Duration>,
+}
+
+impl BackendTimeouts {
+    /// The first of the timeouts to expire for a call that returns all at once.
+    pub fn call(&self) -> Option<Duration> {
+        self.first_byte.into_iter().chain(self.total).min()
     }
-    pub fn loadshedding_limits(&self) -> Vec<LoadShedLimit> {
-        self.loadshedding_limits.clone()
+}
+
+fn parse_timeout(timeout_ms: i64) -> Result<Option<Duration>, Error> {
+    let timeout_ms =
+        u64::try_from(timeout_ms).with_context(|| format!("Invalid timeout: {}", timeout_ms))?;
+    Ok(Some(timeout_ms)
+        .filter(|t| *t > 0)
+        .map(Duration::from_millis))
+}
+
+impl TryFrom<lfs_server_config::BackendTimeouts> for BackendTimeouts {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::BackendTimeouts) -> Result<Self, Self::Error> {
+        Ok(Self {
+            connect: parse_timeout(value.connect_ms)?,
+            first_byte: parse_timeout(value.first_byte_ms)?,
+            total: parse_timeout(value.total_ms)?,
+        })
     }
-    pub fn enforce_acl_check(&self) -> bool {
-        self.raw_server_config.enforce_acl_check
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RouteFaults {
+    pub latency_percentage: u64,
+    pub latency: Duration,
+    pub error_percentage: u64,
+}
+
+impl TryFrom<lfs_server_config::RouteFaults> for RouteFaults {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::RouteFaults) -> Result<Self, Self::Error> {
+        let latency = parse_timeout(value.latency_ms)
+            .context("Invalid latency")?
+            .unwrap_or_default();
+
+        Ok(Self {
+            latency_percentage: parse_percentage(value.latency_percentage)?,
+            latency,
+            error_percentage: parse_percentage(value.error_percentage)?,
+        })
     }
-    pub fn enforce_authentication(&self) -> bool {
-        self.raw_server_config.enforce_authentication
+}
+
+#[derive(Debug, Clone)]
+pub struct RendezvousRouting {
+    /// Targets and their weights. Targets with a weight of 0 are left out.
+    targets: Vec<(String, f64)>,
+}
+
+impl RendezvousRouting {
+    /// The target that scores `rank`th highest for `key`, wrapping around if there are fewer
+    /// targets. Each target scores `weight / -ln(h)`, where `h` is the hash of the target and key
+    /// scaled to (0, 1), which gives each target a share of keys proportional to its weight.
+    pub fn target(&self, key: &str, rank: usize) -> Option<&str> {
+        if self.targets.is_empty() {
+            return None;
+        }
+
+        let mut scores = self
+            .targets
+            .iter()
+            .map(|(name, weight)| {
+                let h = ((mix(stable_hash(name, key)) >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+                (weight / -h.ln(), name.as_str())
+            })
+            .collect::<Vec<_>>();
+        scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Some(scores[rank % scores.len()].1)
     }
-    pub fn object_popularity(&self) -> Option<&ObjectPopularity> {
-        self.object_popularity.as_ref()
+
+    pub fn targets(&self) -> impl Iterator<Item = &str> {
+        self.targets.iter().map(|(name, _)| name.as_str())
     }
-    #[cfg(test)]
-    pub fn object_popularity_mut(&mut self) -> &mut Option<ObjectPopularity> {
-        &mut self.object_popularity
+}
+
+impl TryFrom<lfs_server_config::RendezvousRouting> for RendezvousRouting {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::RendezvousRouting) -> Result<Self, Self::Error> {
+        let mut names = HashSet::new();
+        let mut targets = vec![];
+        for target in value.targets {
+            if target.name.is_empty() || !names.insert(target.name.clone()) {
+                bail!("Invalid or duplicate target: {:?}", target.name);
+            }
+
+            let weight = u32::try_from(target.weight).with_context(|| {
+                format!("Invalid weight for {}: {}", target.name, target.weight)
+            })?;
+            if weight > 0 {
+                targets.push((target.name, weight as f64));
+            }
+        }
+
+        Ok(Self { targets })
     }
-    pub fn disable_compression(&self) -> bool {
-        self.raw_server_config.disable_compression
+}
+
+#[derive(Debug, Clone)]
+pub struct HotObjectSpreading {
+    pub threshold: u64,
+    pub window: Duration,
+    pub cooldown: Duration,
+}
+
+impl TryFrom<lfs_server_config::HotObjectSpreading> for HotObjectSpreading {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::HotObjectSpreading) -> Result<Self, Self::Error> {
+        let threshold = u64::try_from(value.threshold)
+            .ok()
+            .filter(|t| *t > 0)
+            .with_context(|| format!("Invalid threshold: {}", value.threshold))?;
+        let window = u64::try_from(value.window_secs)
+            .ok()
+            .filter(|w| *w > 0)
+            .map(Duration::from_secs)
+            .with_context(|| format!("Invalid window_secs: {}", value.window_secs))?;
+        let cooldown = u64::try_from(value.cooldown_secs)
+            .with_context(|| format!("Invalid cooldown_secs: {}", value.cooldown_secs))?;
+
+        Ok(Self {
+            threshold,
+            window,
+            cooldown: Some(Duration::from_secs(cooldown))
+                .filter(|c| !c.is_zero())
+                .unwrap_or(window),
+        })
     }
-    pub fn disable_compression_identities(&self) -> &Vec<MononokeIdentitySet> {
-        &self.disable_compression_identities
+}
+
+/// How long to wait for shared counters if the config doesn't say.
+const DEFAULT_SHARED_COUNTER_TIMEOUT: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone)]
+pub struct DistributedRateLimits {
+    pub category: String,
+    pub timeout: Duration,
+}
+
+impl TryFrom<lfs_server_config::DistributedRateLimits> for DistributedRateLimits {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::DistributedRateLimits) -> Result<Self, Self::Error> {
+        if value.category.is_empty() {
+            bail!("Missing category");
+        }
+
+        Ok(Self {
+            category: value.category,
+            timeout: parse_timeout(value.timeout_ms)?.unwrap_or(DEFAULT_SHARED_COUNTER_TIMEOUT),
+        })
     }
-    #[cfg(test)]
-    pub fn disable_compression_identities_mut(&mut self) -> &mut Vec<MononokeIdentitySet> {
-        &mut self.disable_compression_identities
+}
+
+/// How many objects are logged if the config doesn't say.
+const DEFAULT_POPULARITY_LOG_TOP: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct PopularityReport {
+    pub window: Duration,
+    pub log_top: usize,
+    pub log_interval: Option<Duration>,
+}
+
+impl TryFrom<lfs_server_config::PopularityReport> for PopularityReport {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::PopularityReport) -> Result<Self, Self::Error> {
+        let window = u64::try_from(value.window_secs)
+            .ok()
+            .filter(|w| *w > 0)
+            .map(Duration::from_secs)
+            .with_context(|| format!("Invalid window_secs: {}", value.window_secs))?;
+        let log_top = usize::try_from(value.log_top)
+            .with_context(|| format!("Invalid log_top: {}", value.log_top))?;
+        let log_interval = u64::try_from(value.log_interval_secs)
+            .with_context(|| format!("Invalid log_interval_secs: {}", value.log_interval_secs))?;
+
+        Ok(Self {
+            window,
+            log_top: Some(log_top)
+                .filter(|n| *n > 0)
+                .unwrap_or(DEFAULT_POPULARITY_LOG_TOP),
+            log_interval: Some(Duration::from_secs(log_interval)).filter(|i| !i.is_zero()),
+        })
     }
 }
 
-impl PostResponseConfig for ServerConfig {
-    fn resolve_hostname(&self) -> bool {
-        !self.disable_hostname_logging()
+/// How many times objects are pushed to each peer if the config doesn't say.
+const DEFAULT_REPLICATION_MAX_ATTEMPTS: u32 = 3;
+
+/// How long to wait before retrying a push if the config doesn't say.
+const DEFAULT_REPLICATION_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How many pushes may be pending if the config doesn't say.
+const DEFAULT_REPLICATION_MAX_BACKLOG: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct Replication {
+    /// Base URLs of the peers, without a trailing slash.
+    pub peers: Vec<String>,
+    pub max_attempts: u32,
+    pub retry_delay: Duration,
+    pub max_backlog: usize,
+}
+
+impl TryFrom<lfs_server_config::Replication> for Replication {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::Replication) -> Result<Self, Self::Error> {
+        if value.peers.is_empty() {
+            bail!("Missing peers");
+        }
+
+        let peers = value
+            .peers
+            .into_iter()
+            .map(|peer| {
+                let uri = peer
+                    .parse::<Uri>()
+                    .with_context(|| format!("Invalid peer: {}", peer))?;
+                if uri.scheme().is_none() || uri.authority().is_none() {
+                    bail!("Invalid peer: {}", peer);
+                }
+                Ok(peer.trim_end_matches('/').to_string())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let max_attempts = u32::try_from(value.max_attempts)
+            .with_context(|| format!("Invalid max_attempts: {}", value.max_attempts))?;
+        let max_backlog = usize::try_from(value.max_backlog)
+            .with_context(|| format!("Invalid max_backlog: {}", value.max_backlog))?;
+
+        Ok(Self {
+            peers,
+            max_attempts: Some(max_attempts)
+                .filter(|a| *a > 0)
+                .unwrap_or(DEFAULT_REPLICATION_MAX_ATTEMPTS),
+            retry_delay: parse_timeout(value.retry_delay_ms)?
+                .unwrap_or(DEFAULT_REPLICATION_RETRY_DELAY),
+            max_backlog: Some(max_backlog)
+                .filter(|b| *b > 0)
+                .unwrap_or(DEFAULT_REPLICATION_MAX_BACKLOG),
+        })
+    }
+}
+
+/// How often routing error rates are checked if the config doesn't say.
+const DEFAULT_ROUTING_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct RoutingErrorBudget {
+    pub category: String,
+    /// Highest share of routed downloads that may fail, between 0 and 1.
+    pub max_error_rate: f64,
+    pub min_requests_per_second: u64,
+    pub cool_down: Duration,
+    pub check_interval: Duration,
+    pub timeout: Duration,
+}
+
+impl TryFrom<lfs_server_config::RoutingErrorBudget> for RoutingErrorBudget {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::RoutingErrorBudget) -> Result<Self, Self::Error> {
+        if value.category.is_empty() {
+            bail!("Missing category");
+        }
+
+        let max_error_percent = u8::try_from(value.max_error_percent)
+            .ok()
+            .filter(|p| *p <= 100)
+            .with_context(|| format!("Invalid max_error_percent: {}", value.max_error_percent))?;
+        let min_requests_per_second =
+            u64::try_from(value.min_requests_per_second).with_context(|| {
+                format!(
+                    "Invalid min_requests_per_second: {}",
+                    value.min_requests_per_second
+                )
+            })?;
+        let cool_down = u64::try_from(value.cool_down_secs)
+            .ok()
+            .filter(|c| *c > 0)
+            .map(Duration::from_secs)
+            .with_context(|| format!("Invalid cool_down_secs: {}", value.cool_down_secs))?;
+
+        Ok(Self {
+            category: value.category,
+            max_error_rate: max_error_percent as f64 / 100.0,
+            min_requests_per_second,
+            cool_down,
+            check_interval: parse_timeout(value.check_interval_ms)?
+                .unwrap_or(DEFAULT_ROUTING_CHECK_INTERVAL),
+            timeout: parse_timeout(value.timeout_ms)?.unwrap_or(DEFAULT_SHARED_COUNTER_TIMEOUT),
+        })
+    }
+}
+
+const DEFAULT_TRACE_SERVICE_NAME: &str = "mononoke-lfs";
+const DEFAULT_TRACE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_TRACE_MAX_QUEUED_SPANS: usize = 10000;
+
+/// The format spans are sent to the trace collector in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceProtocol {
+    Otlp,
+    Zipkin,
+}
+
+impl FromStr for TraceProtocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "otlp" => Ok(Self::Otlp),
+            "zipkin" => Ok(Self::Zipkin),
+            _ => Err(anyhow!("Invalid protocol: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceExport {
+    pub collector_url: Uri,
+    pub protocol: TraceProtocol,
+    pub sample_rate: NonZeroU64,
+    pub service_name: String,
+    pub flush_interval: Duration,
+    pub max_queued_spans: usize,
+}
+
+impl TryFrom<lfs_server_config::TraceExport> for TraceExport {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::TraceExport) -> Result<Self, Self::Error> {
+        let collector_url = value
+            .collector_url
+            .parse::<Uri>()
+            .with_context(|| format!("Invalid collector_url: {}", value.collector_url))?;
+        if collector_url.scheme().is_none() || collector_url.authority().is_none() {
+            bail!("Invalid collector_url: {}", value.collector_url);
+        }
+
+        let sample_rate = u64::try_from(value.sample_rate)
+            .with_context(|| format!("Invalid sample_rate: {}", value.sample_rate))?;
+        let max_queued_spans = usize::try_from(value.max_queued_spans)
+            .with_context(|| format!("Invalid max_queued_spans: {}", value.max_queued_spans))?;
+
+        Ok(Self {
+            collector_url,
+            protocol: value.protocol.parse()?,
+            sample_rate: NonZeroU64::new(sample_rate).unwrap_or(NonZeroU64::MIN),
+            service_name: Some(value.service_name)
+                .filter(|n| !n.is_empty())
+                .unwrap_or_else(|| DEFAULT_TRACE_SERVICE_NAME.to_string()),
+            flush_interval: parse_timeout(value.flush_interval_ms)?
+                .unwrap_or(DEFAULT_TRACE_FLUSH_INTERVAL),
+            max_queued_spans: Some(max_queued_spans)
+                .filter(|m| *m > 0)
+                .unwrap_or(DEFAULT_TRACE_MAX_QUEUED_SPANS),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub url: Uri,
+    /// Holds the Authorization header sent with requests to the upstream's host. It is read by
+    /// `UpstreamAuthorization` when the header is needed, not when the config is loaded.
+    pub authorization_file: Option<PathBuf>,
+}
+
+impl TryFrom<lfs_server_config::Upstream> for Upstream {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::Upstream) -> Result<Self, Self::Error> {
+        let url = value
+            .url
+            .parse::<Uri>()
+            .with_context(|| format!("Invalid url: {}", value.url))?;
+        if url.scheme().is_none() || url.authority().is_none() {
+            bail!("Invalid url: {}", value.url);
+        }
+
+        let authorization_file = Some(value.authorization_file)
+            .filter(|f| !f.is_empty())
+            .map(PathBuf::from);
+
+        Ok(Self {
+            url,
+            authorization_file,
+        })
+    }
+}
+
+/// Where the audit trail of uploads is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditLogDestination {
+    Scribe(String),
+    File(PathBuf),
+}
+
+impl TryFrom<lfs_server_config::AuditLog> for AuditLogDestination {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::AuditLog) -> Result<Self, Self::Error> {
+        match (value.scribe_category.is_empty(), value.file_path.is_empty()) {
+            (false, true) => Ok(Self::Scribe(value.scribe_category)),
+            (true, false) => Ok(Self::File(PathBuf::from(value.file_path))),
+            _ => bail!("Exactly one of scribe_category and file_path must be set"),
+        }
+    }
+}
+
+/// The optional middlewares that requests go through, as named in `middleware_pipeline`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PipelineStage {
+    /// `loadshedding_limits`
+    LoadShedding,
+    /// `request_limits`
+    RequestLimits,
+    /// `ip_throttle`
+    IpThrottle,
+    /// Rejects batch requests while the host is overloaded.
+    HostPressure,
+    /// Counts requests per proxy region for CSLB (--cslb-config).
+    Qps,
+    /// `fault_injection`
+    FaultInjection,
+    /// The access log, sampled by `log_sample_rate`.
+    AccessLog,
+}
+
+/// The pipeline requests go through if the config doesn't say.
+const DEFAULT_MIDDLEWARE_PIPELINE: [PipelineStage; 7] = [
+    PipelineStage::LoadShedding,
+    PipelineStage::RequestLimits,
+    PipelineStage::IpThrottle,
+    PipelineStage::HostPressure,
+    PipelineStage::Qps,
+    PipelineStage::FaultInjection,
+    PipelineStage::AccessLog,
+];
+
+impl FromStr for PipelineStage {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let stage = match s {
+            "load_shedding" => Self::LoadShedding,
+            "request_limits" => Self::RequestLimits,
+            "ip_throttle" => Self::IpThrottle,
+            "host_pressure" => Self::HostPressure,
+            "qps" => Self::Qps,
+            "fault_injection" => Self::FaultInjection,
+            "access_log" => Self::AccessLog,
+            _ => bail!("Unknown middleware: {}", s),
+        };
+        Ok(stage)
+    }
+}
+
+fn parse_middleware_pipeline(stages: &[String]) -> Result<Vec<PipelineStage>, Error> {
+    if stages.is_empty() {
+        return Ok(DEFAULT_MIDDLEWARE_PIPELINE.to_vec());
+    }
+
+    let mut seen = HashSet::new();
+    stages
+        .iter()
+        .map(|stage| {
+            let stage = stage.parse::<PipelineStage>()?;
+            if !seen.insert(stage) {
+                bail!("Duplicate middleware: {:?}", stage);
+            }
+            Ok(stage)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub raw_server_config: lfs_server_config::LfsServerConfig,
+    loadshedding_limits: Vec<LoadShedLimit>,
+    object_popularity: Option<ObjectPopularity>,
+    disable_compression_identities: Vec<MononokeIdentitySet>,
+    request_limits: Vec<RequestLimit>,
+    upload_acl: Option<Vec<MononokeIdentitySet>>,
+    admin_acl: Vec<MononokeIdentitySet>,
+    max_concurrent_uploads: Option<NonZeroU32>,
+    denied_oids: HashSet<Sha256>,
+    rollout: HashMap<String, RolloutFeature>,
+    ip_throttle: Option<IpThrottle>,
+    log_sample_rate: LogSampleRate,
+    cors: Option<Cors>,
+    blobstore_timeouts: BackendTimeouts,
+    upstream_timeouts: BackendTimeouts,
+    fault_injection: HashMap<String, RouteFaults>,
+    rendezvous_routing: Option<RendezvousRouting>,
+    hot_object_spreading: Option<HotObjectSpreading>,
+    distributed_rate_limits: Option<DistributedRateLimits>,
+    popularity_report: Option<PopularityReport>,
+    replication: Option<Replication>,
+    middleware_pipeline: Vec<PipelineStage>,
+    audit_log: Option<AuditLogDestination>,
+    routing_error_budget: Option<RoutingErrorBudget>,
+    trace_export: Option<TraceExport>,
+    upstream: Option<Upstream>,
+    /// Load shedding limits that only apply to one repository. These are only set on the configs
+    /// returned by `for_repo`, and are checked on top of `loadshedding_limits`.
+    repo_loadshedding_limits: Vec<LoadShedLimit>,
+    /// Effective configs for repositories that have overrides, keyed by repository name.
+    repos: HashMap<String, Arc<ServerConfig>>,
+}
+
+fn apply_repo_overrides(
+    mut config: lfs_server_config::LfsServerConfig,
+    overrides: &lfs_server_config::LfsRepoConfig,
+) -> lfs_server_config::LfsServerConfig {
+    config.repos = BTreeMap::new();
+    if let Some(v) = overrides.track_bytes_sent {
+        config.track_bytes_sent = v;
+    }
+    if let Some(v) = overrides.enable_consistent_routing {
+        config.enable_consistent_routing = v;
+    }
+    if let Some(v) = overrides.enforce_acl_check {
+        config.enforce_acl_check = v;
+    }
+    if let Some(v) = &overrides.object_popularity {
+        config.object_popularity = Some(v.clone());
+    }
+    if let Some(v) = overrides.disable_compression {
+        config.disable_compression = v;
+    }
+    if let Some(v) = &overrides.download_redirect_url {
+        config.download_redirect_url = Some(v.clone());
+    }
+    if let Some(v) = overrides.enable_locks {
+        config.enable_locks = v;
+    }
+    if let Some(v) = &overrides.upstream {
+        config.upstream = Some(v.clone());
+    }
+    if let Some(v) = &overrides.upstream_timeouts {
+        // Connections to all upstreams come from one pool, so only the server-wide connect_ms
+        // applies.
+        let connect_ms = config.upstream_timeouts.as_ref().map_or(0, |t| t.connect_ms);
+        config.upstream_timeouts = Some(lfs_server_config::BackendTimeouts {
+            connect_ms,
+            ..v.clone()
+        });
+    }
+    config
+}
+
+/// Converts an optional part of the raw config, if it is set.
+fn convert_optional<T, U>(value: &Option<T>, context: &str) -> Result<Option<U>, Error>
+where
+    T: Clone,
+    U: TryFrom<T, Error = Error>,
+{
+    value
+        .clone()
+        .map(U::try_from)
+        .transpose()
+        .with_context(|| context.to_string())
+}
+
+/// Converts each element of a list in the raw config.
+fn convert_all<T, U>(values: &[T], context: &str) -> Result<Vec<U>, Error>
+where
+    T: Clone,
+    U: TryFrom<T, Error = Error>,
+{
+    values
+        .iter()
+        .cloned()
+        .map(U::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| context.to_string())
+}
+
+/// Converts each value of a map in the raw config, keeping their names.
+fn convert_map<T, U>(
+    values: &BTreeMap<String, T>,
+    context: &str,
+) -> Result<HashMap<String, U>, Error>
+where
+    T: Clone,
+    U: TryFrom<T, Error = Error>,
+{
+    values
+        .iter()
+        .map(|(name, value)| {
+            let value =
+                U::try_from(value.clone()).with_context(|| format!("{} for {}", context, name))?;
+            Ok((name.clone(), value))
+        })
+        .collect()
+}
+
+/// Parses lists of identities, e.g. for an ACL where each list is a set of identities a client
+/// must all have.
+fn parse_identity_lists(
+    lists: &[Vec<String>],
+    context: &str,
+) -> Result<Vec<MononokeIdentitySet>, Error> {
+    lists
+        .iter()
+        .map(|list| {
+            list.iter()
+                .map(|i| FromStr::from_str(i))
+                .collect::<Result<BTreeSet<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| context.to_string())
+}
+
+impl TryFrom<lfs_server_config::LfsServerConfig> for ServerConfig {
+    type Error = Error;
+
+    fn try_from(value: lfs_server_config::LfsServerConfig) -> Result<Self, Error> {
+        let upload_acl = value
+            .upload_acl
+            .as_ref()
+            .map(|acl| parse_identity_lists(acl, "Invalid upload ACL"))
+            .transpose()?;
+
+        let max_concurrent_uploads: u32 =
+            value.max_concurrent_uploads.try_into().with_context(|| {
+                format!(
+                    "Invalid max_concurrent_uploads: {:?}",
+                    value.max_concurrent_uploads
+                )
+            })?;
+
+        let denied_oids = value
+            .denied_oids
+            .iter()
+            .map(|oid| {
+                Sha256::from_str(oid).with_context(|| format!("Invalid denied oid: {}", oid))
+            })
+            .collect::<Result<HashSet<_>, _>>()?;
+
+        let middleware_pipeline = parse_middleware_pipeline(&value.middleware_pipeline)
+            .context("Invalid middleware pipeline")?;
+
+        let mut repos = HashMap::new();
+        for (name, overrides) in value.repos.iter() {
+            if let Some(timeouts) = &overrides.upstream_timeouts {
+                if timeouts.connect_ms != 0 {
+                    bail!(
+                        "Invalid overrides for repo {}: connect_ms can only be set server-wide",
+                        name
+                    );
+                }
+            }
+            let raw = apply_repo_overrides(value.clone(), overrides);
+            let mut config = Self::try_from(raw)
+                .with_context(|| format!("Invalid overrides for repo {}", name))?;
+            config.repo_loadshedding_limits = convert_all(
+                overrides.loadshedding_limits.as_deref().unwrap_or_default(),
+                &format!("Invalid loadshedding config for repo {}", name),
+            )?;
+            repos.insert(name.clone(), Arc::new(config));
+        }
+
+        Ok(Self {
+            loadshedding_limits: convert_all(
+                &value.loadshedding_limits,
+                "Invalid loadshedding config",
+            )?,
+            object_popularity: convert_optional(
+                &value.object_popularity,
+                "Invalid object popularity",
+            )?,
+            disable_compression_identities: parse_identity_lists(
+                &value.disable_compression_identities,
+                "Invalid disable_compression_identities",
+            )?,
+            request_limits: convert_all(&value.request_limits, "Invalid request limits")?,
+            upload_acl,
+            admin_acl: parse_identity_lists(&value.admin_acl, "Invalid admin ACL")?,
+            max_concurrent_uploads: NonZeroU32::new(max_concurrent_uploads),
+            denied_oids,
+            rollout: convert_map(&value.rollout, "Invalid rollout")?,
+            ip_throttle: convert_optional(&value.ip_throttle, "Invalid IP throttle")?,
+            log_sample_rate: convert_optional(&value.log_sample_rate, "Invalid log sample rate")?
+                .unwrap_or_default(),
+            cors: convert_optional(&value.cors, "Invalid CORS config")?,
+            blobstore_timeouts: convert_optional(
+                &value.blobstore_timeouts,
+                "Invalid blobstore timeouts",
+            )?
+            .unwrap_or_default(),
+            upstream_timeouts: convert_optional(
+                &value.upstream_timeouts,
+                "Invalid upstream timeouts",
+            )?
+            .unwrap_or_default(),
+            fault_injection: convert_map(&value.fault_injection, "Invalid fault injection")?,
+            rendezvous_routing: convert_optional(
+                &value.rendezvous_routing,
+                "Invalid rendezvous routing",
+            )?,
+            hot_object_spreading: convert_optional(
+                &value.hot_object_spreading,
+                "Invalid hot object spreading",
+            )?,
+            distributed_rate_limits: convert_optional(
+                &value.distributed_rate_limits,
+                "Invalid distributed rate limits",
+            )?,
+            popularity_report: convert_optional(
+                &value.popularity_report,
+                "Invalid popularity report",
+            )?,
+            replication: convert_optional(&value.replication, "Invalid replication")?,
+            middleware_pipeline,
+            audit_log: convert_optional(&value.audit_log, "Invalid audit log")?,
+            routing_error_budget: convert_optional(
+                &value.routing_error_budget,
+                "Invalid routing error budget",
+            )?,
+            trace_export: convert_optional(&value.trace_export, "Invalid trace export")?,
+            upstream: convert_optional(&value.upstream, "Invalid upstream")?,
+            repo_loadshedding_limits: vec![],
+            repos,
+            raw_server_config: value,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ServerConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = lfs_server_config::LfsServerConfig::deserialize(deserializer)?;
+        // Checking the config here means an invalid update is rejected like one that doesn't
+        // parse, and the config store keeps serving the last valid config.
+        let config = Self::try_from(raw)
+            .and_then(|config| {
+                config.validate()?;
+                Ok(config)
+            })
+            .map_err(|e| D::Error::custom(format!("{:?}", e)))?;
+        Ok(config)
+    }
+}
+
+impl Serialize for ServerConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        lfs_server_config::LfsServerConfig::serialize(&self.raw_server_config, serializer)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let raw_server_config = lfs_server_config::LfsServerConfig {
+            track_bytes_sent: false,
+            enable_consistent_routing: false,
+            disable_hostname_logging: false,
+            enforce_acl_check: false,
+            loadshedding_limits: vec![],
+            object_popularity: None,
+            disable_compression: false,
+            disable_compression_identities: vec![],
+            enforce_authentication: false,
+            repos: BTreeMap::new(),
+            request_limits: vec![],
+            upload_acl: None,
+            max_concurrent_uploads: 0,
+            max_object_size_bytes: 0,
+            access_log_sample_rate: 0,
+            drain: false,
+            enable_verify_action: false,
+            min_compression_size_bytes: 0,
+            download_redirect_url: None,
+            denied_oids: vec![],
+            max_in_flight_requests: 0,
+            max_egress_backlog_bytes: 0,
+            max_event_loop_lag_ms: 0,
+            rollout: BTreeMap::new(),
+            enable_locks: false,
+            slow_read_retry_delay_ms: 0,
+            ip_throttle: None,
+            max_concurrent_download_transfers: 0,
+            transfer_queue_timeout_ms: 0,
+            log_sample_rate: None,
+            cors: None,
+            max_batch_request_bytes: 0,
+            max_request_body_bytes: 0,
+            blobstore_timeouts: None,
+            upstream_timeouts: None,
+            retry_after_secs: 0,
+            fault_injection: BTreeMap::new(),
+            rendezvous_routing: None,
+            hot_object_spreading: None,
+            admin_acl: vec![],
+            distributed_rate_limits: None,
+            popularity_report: None,
+            replication: None,
+            middleware_pipeline: vec![],
+            audit_log: None,
+            routing_error_budget: None,
+            trace_export: None,
+            upstream: None,
+            max_queued_uploads: 0,
+        };
+
+        Self {
+            raw_server_config,
+            loadshedding_limits: vec![],
+            object_popularity: None,
+            disable_compression_identities: vec![],
+            request_limits: vec![],
+            upload_acl: None,
+            admin_acl: vec![],
+            max_concurrent_uploads: None,
+            denied_oids: HashSet::new(),
+            rollout: HashMap::new(),
+            ip_throttle: None,
+            log_sample_rate: LogSampleRate::default(),
+            cors: None,
+            blobstore_timeouts: BackendTimeouts::default(),
+            upstream_timeouts: BackendTimeouts::default(),
+            fault_injection: HashMap::new(),
+            rendezvous_routing: None,
+            hot_object_spreading: None,
+            distributed_rate_limits: None,
+            popularity_report: None,
+            replication: None,
+            middleware_pipeline: DEFAULT_MIDDLEWARE_PIPELINE.to_vec(),
+            audit_log: None,
+            routing_error_budget: None,
+            trace_export: None,
+            upstream: None,
+            repo_loadshedding_limits: vec![],
+            repos: HashMap::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// A handle serving the config parsed from `raw`, as the config store would serve it.
+    #[cfg(test)]
+    pub fn test_handle(
+        raw: lfs_server_config::LfsServerConfig,
+    ) -> Result<cached_config::ConfigHandle<Self>, Error> {
+        cached_config::ConfigHandle::from_json(&serde_json::to_string(&raw)?)
+    }
+    pub fn track_bytes_sent(&self) -> bool {
+        self.raw_server_config.track_bytes_sent
+    }
+    pub fn enable_consistent_routing(&self) -> bool {
+        self.raw_server_config.enable_consistent_routing
+    }
+    pub fn disable_hostname_logging(&self) -> bool {
+        self.raw_server_config.disable_hostname_logging
+    }
+    pub fn loadshedding_limits(&self) -> Vec<LoadShedLimit> {
+        self.loadshedding_limits.clone()
+    }
+    pub fn repo_loadshedding_limits(&self) -> &[LoadShedLimit] {
+        &self.repo_loadshedding_limits
+    }
+    pub fn enforce_acl_check(&self) -> bool {
+        self.raw_server_config.enforce_acl_check
+    }
+    pub fn enforce_authentication(&self) -> bool {
+        self.raw_server_config.enforce_authentication
+    }
+    pub fn object_popularity(&self) -> Option<&ObjectPopularity> {
+        self.object_popularity.as_ref()
+    }
+    #[cfg(test)]
+    pub fn object_popularity_mut(&mut self) -> &mut Option<ObjectPopularity> {
+        &mut self.object_popularity
+    }
+    pub fn disable_compression(&self) -> bool {
+        self.raw_server_config.disable_compression
+    }
+    pub fn disable_compression_identities(&self) -> &Vec<MononokeIdentitySet> {
+        &self.disable_compression_identities
+    }
+    #[cfg(test)]
+    pub fn disable_compression_identities_mut(&mut self) -> &mut Vec<MononokeIdentitySet> {
+        &mut self.disable_compression_identities
+    }
+    /// The request limit that applies to a client with these identities, if any.
+    pub fn request_limit(
+        &self,
+        client_idents: Option<&MononokeIdentitySet>,
+    ) -> Option<&RequestLimit> {
+        let matching = client_idents.and_then(|idents| {
+            self.request_limits
+                .iter()
+                .find(|l| !l.identities.is_empty() && l.identities.is_subset(idents))
+        });
+
+        matching.or_else(|| self.request_limits.iter().find(|l| l.identities.is_empty()))
+    }
+    /// Where to count `request_limits` across the fleet, if they are enforced fleet-wide.
+    pub fn distributed_rate_limits(&self) -> Option<&DistributedRateLimits> {
+        self.distributed_rate_limits.as_ref()
+    }
+    #[cfg(test)]
+    pub fn distributed_rate_limits_mut(&mut self) -> &mut Option<DistributedRateLimits> {
+        &mut self.distributed_rate_limits
+    }
+    /// How downloads are counted for `/popular`, if they are.
+    pub fn popularity_report(&self) -> Option<&PopularityReport> {
+        self.popularity_report.as_ref()
+    }
+    #[cfg(test)]
+    pub fn popularity_report_mut(&mut self) -> &mut Option<PopularityReport> {
+        &mut self.popularity_report
+    }
+    /// Where uploads are pushed to after they complete, if anywhere.
+    pub fn replication(&self) -> Option<&Replication> {
+        self.replication.as_ref()
+    }
+    /// The optional middlewares that requests go through, in order.
+    pub fn middleware_pipeline(&self) -> &[PipelineStage] {
+        &self.middleware_pipeline
+    }
+    pub fn has_middleware(&self, stage: PipelineStage) -> bool {
+        self.middleware_pipeline.contains(&stage)
+    }
+    /// Where uploads are recorded for auditing, if they are.
+    pub fn audit_log(&self) -> Option<&AuditLogDestination> {
+        self.audit_log.as_ref()
+    }
+    #[cfg(test)]
+    pub fn audit_log_mut(&mut self) -> &mut Option<AuditLogDestination> {
+        &mut self.audit_log
+    }
+    /// The error budget of consistent routing destinations, if routing falls back on errors.
+    pub fn routing_error_budget(&self) -> Option<&RoutingErrorBudget> {
+        self.routing_error_budget.as_ref()
+    }
+    /// Where spans of requests are exported to, if they are traced.
+    pub fn trace_export(&self) -> Option<&TraceExport> {
+        self.trace_export.as_ref()
+    }
+    #[cfg(test)]
+    pub fn trace_export_mut(&mut self) -> &mut Option<TraceExport> {
+        &mut self.trace_export
+    }
+    /// The upstream to proxy to, if the config replaces the server's.
+    pub fn upstream(&self) -> Option<&Upstream> {
+        self.upstream.as_ref()
+    }
+    #[cfg(test)]
+    pub fn upstream_mut(&mut self) -> &mut Option<Upstream> {
+        &mut self.upstream
+    }
+    #[cfg(test)]
+    pub fn request_limits_mut(&mut self) -> &mut Vec<RequestLimit> {
+        &mut self.request_limits
+    }
+    /// Whether a client with these identities may upload.
+    pub fn allows_upload(&self, client_idents: Option<&MononokeIdentitySet>) -> bool {
+        match &self.upload_acl {
+            Some(acl) => is_identity_subset(acl, client_idents),
+            None => true,
+        }
+    }
+    /// Whether a client with these identities may use the admin routes.
+    pub fn allows_admin(&self, client_idents: Option<&MononokeIdentitySet>) -> bool {
+        is_identity_subset(&self.admin_acl, client_idents)
+    }
+    #[cfg(test)]
+    pub fn upload_acl_mut(&mut self) -> &mut Option<Vec<MononokeIdentitySet>> {
+        &mut self.upload_acl
+    }
+    pub fn max_object_size_bytes(&self) -> Option<u64> {
+        u64::try_from(self.raw_server_config.max_object_size_bytes)
+            .ok()
+            .filter(|size| *size > 0)
+    }
+    pub fn max_in_flight_requests(&self) -> Option<u64> {
+        u64::try_from(self.raw_server_config.max_in_flight_requests)
+            .ok()
+            .filter(|max| *max > 0)
+    }
+    pub fn max_egress_backlog_bytes(&self) -> Option<u64> {
+        u64::try_from(self.raw_server_config.max_egress_backlog_bytes)
+            .ok()
+            .filter(|max| *max > 0)
+    }
+    pub fn max_event_loop_lag_ms(&self) -> Option<u64> {
+        u64::try_from(self.raw_server_config.max_event_loop_lag_ms)
+            .ok()
+            .filter(|max| *max > 0)
+    }
+    /// How many uploads may wait for a slot once `max_concurrent_uploads` are in flight.
+    pub fn max_queued_uploads(&self) -> Option<u64> {
+        u64::try_from(self.raw_server_config.max_queued_uploads)
+            .ok()
+            .filter(|max| *max > 0)
+    }
+    pub fn max_concurrent_download_transfers(&self) -> Option<u64> {
+        u64::try_from(self.raw_server_config.max_concurrent_download_transfers)
+            .ok()
+            .filter(|max| *max > 0)
+    }
+    pub fn transfer_queue_timeout(&self) -> Option<Duration> {
+        u64::try_from(self.raw_server_config.transfer_queue_timeout_ms)
+            .ok()
+            .filter(|timeout| *timeout > 0)
+            .map(Duration::from_millis)
+    }
+    pub fn has_denied_oids(&self) -> bool {
+        !self.denied_oids.is_empty()
+    }
+    pub fn is_denied(&self, oid: &Sha256) -> bool {
+        self.denied_oids.contains(oid)
+    }
+    /// How to pick the targets of consistently routed downloads, if the server picks them.
+    pub fn rendezvous_routing(&self) -> Option<&RendezvousRouting> {
+        self.rendezvous_routing.as_ref()
+    }
+    /// When to stop routing objects that get hot consistently, if ever.
+    pub fn hot_object_spreading(&self) -> Option<&HotObjectSpreading> {
+        self.hot_object_spreading.as_ref()
+    }
+    #[cfg(test)]
+    pub fn hot_object_spreading_mut(&mut self) -> &mut Option<HotObjectSpreading> {
+        &mut self.hot_object_spreading
+    }
+    /// Whether `feature` is enabled for `key` (e.g. a client's identities) by the rollout config.
+    /// Features that aren't being rolled out are disabled.
+    pub fn is_enabled(&self, feature: &str, key: &str) -> bool {
+        self.rollout
+            .get(feature)
+            .map_or(false, |rollout| rollout.is_enabled(key))
+    }
+    /// The throttle for clients without identities, if any.
+    pub fn ip_throttle(&self) -> Option<&IpThrottle> {
+        self.ip_throttle.as_ref()
+    }
+    /// Log 1 in this many messages of this level, if they are sampled.
+    pub fn log_sample_rate_for_level(&self, level: Level) -> Option<NonZeroU64> {
+        self.log_sample_rate.by_level.get(&level).copied()
+    }
+    /// Log 1 in this many requests to this route to the access log in the server's own log, if
+    /// they are sampled.
+    pub fn log_sample_rate_for_route(&self, route: &str) -> Option<NonZeroU64> {
+        self.log_sample_rate.by_route.get(route).copied()
+    }
+    /// How to answer cross-origin requests, if they are allowed.
+    pub fn cors(&self) -> Option<&Cors> {
+        self.cors.as_ref()
+    }
+    pub fn blobstore_timeouts(&self) -> BackendTimeouts {
+        self.blobstore_timeouts
+    }
+    pub fn upstream_timeouts(&self) -> BackendTimeouts {
+        self.upstream_timeouts
+    }
+    /// How long throttled or turned away clients should wait before retrying.
+    pub fn retry_after_secs(&self) -> u64 {
+        u64::try_from(self.raw_server_config.retry_after_secs)
+            .ok()
+            .filter(|secs| *secs > 0)
+            .unwrap_or(1)
+    }
+    /// The faults to inject into requests to this route, if any. These must only be injected if
+    /// the server was started with fault injection allowed.
+    pub fn route_faults(&self, route: &str) -> Option<&RouteFaults> {
+        self.fault_injection.get(route)
+    }
+    pub fn download_redirect_url(&self) -> Option<&str> {
+        self.raw_server_config.download_redirect_url.as_deref()
+    }
+    pub fn min_compression_size_bytes(&self) -> u64 {
+        u64::try_from(self.raw_server_config.min_compression_size_bytes).unwrap_or(0)
+    }
+    pub fn slow_read_retry_delay(&self) -> Option<Duration> {
+        u64::try_from(self.raw_server_config.slow_read_retry_delay_ms)
+            .ok()
+            .filter(|delay| *delay > 0)
+            .map(Duration::from_millis)
+    }
+    pub fn enable_locks(&self) -> bool {
+        self.raw_server_config.enable_locks
+    }
+    pub fn enable_verify_action(&self) -> bool {
+        self.raw_server_config.enable_verify_action
+    }
+    pub fn drain(&self) -> bool {
+        self.raw_server_config.drain
+    }
+    /// Log 1 in this many requests to the request log in Scuba, if they are sampled. Despite the
+    /// name, this doesn't sample the access log (see `log_sample_rate_for_route`).
+    pub fn access_log_sample_rate(&self) -> Option<NonZeroU64> {
+        u64::try_from(self.raw_server_config.access_log_sample_rate)
+            .ok()
+            .filter(|rate| *rate > 1)
+            .and_then(NonZeroU64::new)
+    }
+    pub fn max_batch_request_bytes(&self) -> Option<u64> {
+        u64::try_from(self.raw_server_config.max_batch_request_bytes)
+            .ok()
+            .filter(|size| *size > 0)
+    }
+    pub fn max_request_body_bytes(&self) -> Option<u64> {
+        u64::try_from(self.raw_server_config.max_request_body_bytes)
+            .ok()
+            .filter(|size| *size > 0)
+    }
+    pub fn max_concurrent_uploads(&self) -> Option<NonZeroU32> {
+        self.max_concurrent_uploads
+    }
+    /// Checks constraints that can't be expressed when parsing individual fields, for this config
+    /// and all of its per-repo configs.
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(object_popularity) = &self.object_popularity {
+            object_popularity
+                .validate()
+                .context("Invalid object popularity")?;
+        }
+        if self.max_queued_uploads().is_some() && self.max_concurrent_uploads.is_none() {
+            bail!("max_queued_uploads requires max_concurrent_uploads");
+        }
+        if self.blobstore_timeouts.connect.is_some() {
+            bail!("Invalid blobstore timeouts: connect_ms doesn't apply to the blobstore");
+        }
+        if let Some(routing) = &self.rendezvous_routing {
+            if routing.targets.is_empty() {
+                bail!("Invalid rendezvous routing: no target has a weight");
+            }
+        }
+        let raw = &self.raw_server_config;
+        let routes = raw.fault_injection.keys().chain(
+            raw.log_sample_rate
+                .iter()
+                .flat_map(|rate| rate.by_route.keys()),
+        );
+        for route in routes {
+            if !ROUTE_NAMES.contains(&route.as_str()) {
+                bail!("Unknown route: {}", route);
+            }
+        }
+        for (name, config) in self.repos.iter() {
+            config
+                .validate()
+                .with_context(|| format!("Invalid config for repo {}", name))?;
+        }
+        Ok(())
+    }
+    /// Config to use for requests to `repo`: this config with the repository's overrides applied,
+    /// or this config itself if the repository has none.
+    pub fn for_repo(self: &Arc<Self>, repo: &str) -> Arc<Self> {
+        self.repos
+            .get(repo)
+            .cloned()
+            .unwrap_or_else(|| self.clone())
+    }
+}
+
+impl PostResponseConfig for ServerConfig {
+    fn resolve_hostname(&self) -> bool {
+        !self.disable_hostname_logging()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use maplit::btreemap;
+    use permission_checker::MononokeIdentity;
+
+    use super::*;
+
+    #[test]
+    fn test_repo_overrides() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.upstream_timeouts = Some(lfs_server_config::BackendTimeouts {
+            connect_ms: 500,
+            first_byte_ms: 0,
+            total_ms: 0,
+        });
+        raw.repos.insert(
+            "repo1".to_string(),
+            lfs_server_config::LfsRepoConfig {
+                upstream_timeouts: Some(lfs_server_config::BackendTimeouts {
+                    connect_ms: 0,
+                    first_byte_ms: 0,
+                    total_ms: 1000,
+                }),
+                ..Default::default()
+            },
+        );
+
+        let config = Arc::new(ServerConfig::try_from(raw.clone())?);
+
+        // The server-wide connect timeout is kept, as that's the one connections use.
+        let repo1 = config.for_repo("repo1");
+        assert_eq!(
+            repo1.upstream_timeouts(),
+            BackendTimeouts {
+                connect: Some(Duration::from_millis(500)),
+                first_byte: None,
+                total: Some(Duration::from_secs(1)),
+            }
+        );
+
+        // Repositories without overrides use the server-wide config.
+        assert!(Arc::ptr_eq(&config, &config.for_repo("repo2")));
+
+        // Repositories can't have their own connect timeout.
+        if let Some(timeouts) = raw
+            .repos
+            .get_mut("repo1")
+            .and_then(|repo1| repo1.upstream_timeouts.as_mut())
+        {
+            timeouts.connect_ms = 100;
+        }
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_denied_oids() -> Result<(), Error> {
+        let oid = "c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2";
+
+        let config = ServerConfig::default();
+        assert!(!config.has_denied_oids());
+        assert!(!config.is_denied(&Sha256::from_str(oid)?));
+
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.denied_oids = vec![oid.to_string()];
+        let config = ServerConfig::try_from(raw)?;
+        assert!(config.has_denied_oids());
+        assert!(config.is_denied(&Sha256::from_str(oid)?));
+
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.denied_oids = vec!["not a hash".to_string()];
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollout() -> Result<(), Error> {
+        let feature = |percentage| lfs_server_config::RolloutFeature {
+            percentage,
+            salt: "salt".to_string(),
+        };
+
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.rollout.insert("none".to_string(), feature(0));
+        raw.rollout.insert("half".to_string(), feature(50));
+        raw.rollout.insert("all".to_string(), feature(100));
+        let config = ServerConfig::try_from(raw.clone())?;
+
+        let keys = (0..1000).map(|i| format!("user:{}", i)).collect::<Vec<_>>();
+        let enabled = |feature| {
+            keys.iter()
+                .filter(|key| config.is_enabled(feature, key))
+                .count()
+        };
+
+        assert_eq!(enabled("none"), 0);
+        assert_eq!(enabled("all"), 1000);
+        assert_eq!(enabled("unknown"), 0);
+        let half = enabled("half");
+        assert!((400..600).contains(&half), "{} keys enabled", half);
+
+        // The same key always gets the same answer.
+        assert_eq!(
+            config.is_enabled("half", "user:1"),
+            config.is_enabled("half", "user:1")
+        );
+
+        raw.rollout.insert("invalid".to_string(), feature(101));
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ip_throttle() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.ip_throttle = Some(lfs_server_config::IpThrottle {
+            requests_per_second: 10,
+            exempt_cidrs: vec!["10.0.0.0/8".to_string(), "2001:db8::/32".to_string()],
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+
+        let throttle = config.ip_throttle().unwrap();
+        assert_eq!(throttle.requests_per_second, NonZeroU32::new(10));
+        assert!(throttle.is_exempt("10.1.2.3".parse()?));
+        assert!(throttle.is_exempt("2001:db8::1".parse()?));
+        assert!(!throttle.is_exempt("192.168.0.1".parse()?));
+
+        raw.ip_throttle = Some(lfs_server_config::IpThrottle {
+            requests_per_second: 10,
+            exempt_cidrs: vec!["not a cidr".to_string()],
+        });
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_sample_rate() {
+        let mut raw = ServerConfig::default().raw_server_config;
+        for (by_level, by_route) in [
+            (btreemap! { "loud".to_string() => 10 }, BTreeMap::new()),
+            (BTreeMap::new(), btreemap! { "download".to_string() => -1 }),
+        ] {
+            raw.log_sample_rate = Some(lfs_server_config::LogSampleRate { by_level, by_route });
+            assert!(ServerConfig::try_from(raw.clone()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_cors() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.cors = Some(lfs_server_config::CorsConfig {
+            allowed_origins: vec!["https://example.com/".to_string()],
+            allowed_headers: vec!["x-client-info".to_string()],
+            max_age_secs: 0,
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+
+        let cors = config.cors().unwrap();
+        assert!(cors.allows_origin("https://example.com"));
+        assert!(!cors.allows_origin("https://example.org"));
+        assert_eq!(cors.max_age_secs, None);
+
+        raw.cors = Some(lfs_server_config::CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allowed_headers: vec![],
+            max_age_secs: 600,
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+
+        let cors = config.cors().unwrap();
+        assert!(cors.allows_origin("https://example.org"));
+        assert_eq!(cors.max_age_secs, Some(600));
+
+        raw.cors = Some(lfs_server_config::CorsConfig {
+            allowed_origins: vec![],
+            allowed_headers: vec!["not a header".to_string()],
+            max_age_secs: 0,
+        });
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backend_timeouts() {
+        let timeouts = BackendTimeouts {
+            connect: None,
+            first_byte: Some(Duration::from_millis(500)),
+            total: Some(Duration::from_millis(200)),
+        };
+        assert_eq!(timeouts.call(), Some(Duration::from_millis(200)));
+        assert_eq!(BackendTimeouts::default().call(), None);
+
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.upstream_timeouts = Some(lfs_server_config::BackendTimeouts {
+            connect_ms: -1,
+            first_byte_ms: 0,
+            total_ms: 0,
+        });
+        assert!(ServerConfig::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_fault_injection() -> Result<(), Error> {
+        let faults = |latency_percentage, error_percentage| lfs_server_config::RouteFaults {
+            latency_percentage,
+            latency_ms: 500,
+            error_percentage,
+        };
+
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.fault_injection
+            .insert("download".to_string(), faults(10, 5));
+        let config = ServerConfig::try_from(raw.clone())?;
+
+        let download = config.route_faults("download").expect("faults are set");
+        assert_eq!(download.latency_percentage, 10);
+        assert_eq!(download.latency, Duration::from_millis(500));
+        assert_eq!(download.error_percentage, 5);
+        assert!(config.route_faults("upload").is_none());
+
+        raw.fault_injection
+            .insert("upload".to_string(), faults(0, 101));
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rendezvous_routing() -> Result<(), Error> {
+        let routing = |targets: &[(&str, i32)]| -> Result<RendezvousRouting, Error> {
+            lfs_server_config::RendezvousRouting {
+                targets: targets
+                    .iter()
+                    .map(|(name, weight)| lfs_server_config::RoutingTarget {
+                        name: name.to_string(),
+                        weight: *weight,
+                    })
+                    .collect(),
+            }
+            .try_into()
+        };
+
+        let keys = (0..1000).map(|i| format!("oid{}", i)).collect::<Vec<_>>();
+        let three = routing(&[("a", 1), ("b", 1), ("c", 2)])?;
+        let two = routing(&[("a", 1), ("c", 2)])?;
+
+        let mut counts = HashMap::new();
+        for key in keys.iter() {
+            let target = three.target(key, 0).expect("targets are set");
+            *counts.entry(target).or_insert(0) += 1;
+
+            // Removing a target only moves the keys that were routed to it.
+            if target != "b" {
+                assert_eq!(two.target(key, 0), Some(target));
+            }
+
+            // Keys go to different targets for each rank.
+            assert_ne!(three.target(key, 1), Some(target));
+        }
+        assert!((150..350).contains(&counts["a"]), "{:?}", counts);
+        assert!((150..350).contains(&counts["b"]), "{:?}", counts);
+        assert!((400..600).contains(&counts["c"]), "{:?}", counts);
+
+        assert_eq!(routing(&[("a", 0)])?.target("oid", 0), None);
+        assert!(routing(&[("a", -1)]).is_err());
+        assert!(routing(&[("a", 1), ("a", 1)]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hot_object_spreading() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.hot_object_spreading = Some(lfs_server_config::HotObjectSpreading {
+            threshold: 100,
+            window_secs: 10,
+            cooldown_secs: 0,
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+        let spreading = config.hot_object_spreading().expect("spreading is set");
+        assert_eq!(spreading.threshold, 100);
+        assert_eq!(spreading.cooldown, Duration::from_secs(10));
+
+        raw.hot_object_spreading = Some(lfs_server_config::HotObjectSpreading {
+            threshold: 0,
+            window_secs: 10,
+            cooldown_secs: 60,
+        });
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_object_popularity() {
+        let ring = |threshold, mode| ConsistentRoutingRing { threshold, mode };
+        let num = |n| ConsistentRoutingRingMode::Num {
+            tasks_per_content: NonZeroU16::new(n).unwrap(),
+        };
+        let mut popularity = ObjectPopularity {
+            category: "foo".to_string(),
+            window: 10,
+            thresholds: vec![
+                ring(10, num(2)),
+                ring(20, num(4)),
+                ring(30, ConsistentRoutingRingMode::All),
+            ],
+        };
+        assert!(popularity.validate().is_ok());
+
+        popularity.thresholds[1].threshold = 5;
+        assert!(popularity.validate().is_err());
+
+        popularity.thresholds[1] = ring(20, num(1));
+        assert!(popularity.validate().is_err());
+
+        popularity.thresholds = vec![ring(10, ConsistentRoutingRingMode::All), ring(20, num(4))];
+        assert!(popularity.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate() -> Result<(), Error> {
+        let validate = |raw: &lfs_server_config::LfsServerConfig| -> Result<(), Error> {
+            ServerConfig::try_from(raw.clone())?.validate()
+        };
+
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.max_queued_uploads = 10;
+        assert!(validate(&raw).is_err());
+        raw.max_concurrent_uploads = 5;
+        validate(&raw)?;
+
+        raw.blobstore_timeouts = Some(lfs_server_config::BackendTimeouts {
+            connect_ms: 100,
+            first_byte_ms: 0,
+            total_ms: 0,
+        });
+        assert!(validate(&raw).is_err());
+        raw.blobstore_timeouts = None;
+
+        raw.rendezvous_routing = Some(lfs_server_config::RendezvousRouting {
+            targets: vec![lfs_server_config::RoutingTarget {
+                name: "a".to_string(),
+                weight: 0,
+            }],
+        });
+        assert!(validate(&raw).is_err());
+        raw.rendezvous_routing = None;
+
+        raw.log_sample_rate = Some(lfs_server_config::LogSampleRate {
+            by_level: BTreeMap::new(),
+            by_route: btreemap! { "downloads".to_string() => 1 },
+        });
+        assert!(validate(&raw).is_err());
+        raw.log_sample_rate = None;
+
+        raw.fault_injection.insert(
+            "uploads".to_string(),
+            lfs_server_config::RouteFaults {
+                latency_percentage: 0,
+                latency_ms: 0,
+                error_percentage: 0,
+            },
+        );
+        assert!(validate(&raw).is_err());
+        raw.fault_injection.clear();
+
+        // Repository overrides are checked too.
+        raw.repos.insert(
+            "repo1".to_string(),
+            lfs_server_config::LfsRepoConfig {
+                object_popularity: Some(lfs_server_config::ObjectPopularity {
+                    category: "foo".to_string(),
+                    window: 10,
+                    thresholds: vec![
+                        lfs_server_config::ConsistentRoutingRing {
+                            threshold: 20,
+                            mode: lfs_server_config::ConsistentRoutingRingMode::all(
+                                lfs_server_config::ConsistentRoutingRingModeAll {},
+                            ),
+                        },
+                        lfs_server_config::ConsistentRoutingRing {
+                            threshold: 10,
+                            mode: lfs_server_config::ConsistentRoutingRingMode::all(
+                                lfs_server_config::ConsistentRoutingRingModeAll {},
+                            ),
+                        },
+                    ],
+                }),
+                ..Default::default()
+            },
+        );
+        assert!(validate(&raw).is_err());
+
+        // Invalid configs are rejected when they are loaded, like ones that don't parse.
+        let json = serde_json::to_string(&raw)?;
+        assert!(serde_json::from_str::<ServerConfig>(&json).is_err());
+        raw.repos.clear();
+        let json = serde_json::to_string(&raw)?;
+        assert!(serde_json::from_str::<ServerConfig>(&json).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allows_upload() {
+        let client_idents = BTreeSet::from([MononokeIdentity::new("USER", "foo")]);
+        let mut config = ServerConfig::default();
+        assert!(config.allows_upload(None));
+        assert!(config.allows_upload(Some(&client_idents)));
+
+        *config.upload_acl_mut() = Some(vec![]);
+        assert!(!config.allows_upload(Some(&client_idents)));
+
+        *config.upload_acl_mut() =
+            Some(vec![BTreeSet::from([MononokeIdentity::new("USER", "foo")])]);
+        assert!(!config.allows_upload(None));
+        assert!(config.allows_upload(Some(&client_idents)));
+    }
+
+    #[test]
+    fn test_allows_admin() -> Result<(), Error> {
+        let client_idents = BTreeSet::from([MononokeIdentity::new("USER", "foo")]);
+        let mut raw = ServerConfig::default().raw_server_config;
+        let config = ServerConfig::try_from(raw.clone())?;
+        assert!(!config.allows_admin(Some(&client_idents)));
+
+        raw.admin_acl = vec![vec!["USER:foo".to_string()]];
+        let config = ServerConfig::try_from(raw)?;
+        assert!(!config.allows_admin(None));
+        assert!(config.allows_admin(Some(&client_idents)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distributed_rate_limits() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.distributed_rate_limits = Some(lfs_server_config::DistributedRateLimits {
+            category: "lfs".to_string(),
+            timeout_ms: 0,
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+        let limits = config.distributed_rate_limits().expect("limits are set");
+        assert_eq!(limits.category, "lfs");
+        assert_eq!(limits.timeout, DEFAULT_SHARED_COUNTER_TIMEOUT);
+
+        raw.distributed_rate_limits = Some(lfs_server_config::DistributedRateLimits {
+            category: "".to_string(),
+            timeout_ms: 100,
+        });
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_popularity_report() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.popularity_report = Some(lfs_server_config::PopularityReport {
+            window_secs: 600,
+            log_top: 0,
+            log_interval_secs: 0,
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+        let report = config.popularity_report().expect("report is set");
+        assert_eq!(report.window, Duration::from_secs(600));
+        assert_eq!(report.log_top, DEFAULT_POPULARITY_LOG_TOP);
+        assert_eq!(report.log_interval, None);
+
+        raw.popularity_report = Some(lfs_server_config::PopularityReport {
+            window_secs: 0,
+            log_top: 5,
+            log_interval_secs: 60,
+        });
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replication() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.replication = Some(lfs_server_config::Replication {
+            peers: vec!["https://lfs.region2.example.com/".to_string()],
+            max_attempts: 0,
+            retry_delay_ms: 0,
+            max_backlog: 0,
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+        let replication = config.replication().expect("replication is set");
+        assert_eq!(
+            replication.peers,
+            vec!["https://lfs.region2.example.com".to_string()]
+        );
+        assert_eq!(replication.max_attempts, DEFAULT_REPLICATION_MAX_ATTEMPTS);
+        assert_eq!(replication.retry_delay, DEFAULT_REPLICATION_RETRY_DELAY);
+        assert_eq!(replication.max_backlog, DEFAULT_REPLICATION_MAX_BACKLOG);
+
+        raw.replication = Some(lfs_server_config::Replication {
+            peers: vec!["lfs.region2.example.com".to_string()],
+            max_attempts: 1,
+            retry_delay_ms: 10,
+            max_backlog: 10,
+        });
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_middleware_pipeline() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        let config = ServerConfig::try_from(raw.clone())?;
+        assert_eq!(config.middleware_pipeline(), DEFAULT_MIDDLEWARE_PIPELINE);
+
+        for pipeline in [vec!["qps", "qps"], vec!["tracing"]] {
+            raw.middleware_pipeline = pipeline.into_iter().map(String::from).collect();
+            assert!(ServerConfig::try_from(raw.clone()).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit_log() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.audit_log = Some(lfs_server_config::AuditLog {
+            scribe_category: "lfs_uploads".to_string(),
+            file_path: "".to_string(),
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+        assert_eq!(
+            config.audit_log(),
+            Some(&AuditLogDestination::Scribe("lfs_uploads".to_string()))
+        );
+
+        raw.audit_log = Some(lfs_server_config::AuditLog {
+            scribe_category: "lfs_uploads".to_string(),
+            file_path: "/tmp/lfs_uploads".to_string(),
+        });
+        assert!(ServerConfig::try_from(raw.clone()).is_err());
+
+        raw.audit_log = Some(lfs_server_config::AuditLog {
+            scribe_category: "".to_string(),
+            file_path: "".to_string(),
+        });
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_routing_error_budget() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.routing_error_budget = Some(lfs_server_config::RoutingErrorBudget {
+            category: "routing".to_string(),
+            max_error_percent: 25,
+            min_requests_per_second: 10,
+            cool_down_secs: 30,
+            check_interval_ms: 0,
+            timeout_ms: 0,
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+        let budget = config.routing_error_budget().expect("budget is set");
+        assert_eq!(budget.max_error_rate, 0.25);
+        assert_eq!(budget.min_requests_per_second, 10);
+        assert_eq!(budget.cool_down, Duration::from_secs(30));
+        assert_eq!(budget.check_interval, DEFAULT_ROUTING_CHECK_INTERVAL);
+        assert_eq!(budget.timeout, DEFAULT_SHARED_COUNTER_TIMEOUT);
+
+        for (max_error_percent, cool_down_secs) in [(101, 30), (-1, 30), (25, 0)] {
+            raw.routing_error_budget = Some(lfs_server_config::RoutingErrorBudget {
+                category: "routing".to_string(),
+                max_error_percent,
+                min_requests_per_second: 10,
+                cool_down_secs,
+                check_interval_ms: 0,
+                timeout_ms: 0,
+            });
+            assert!(ServerConfig::try_from(raw.clone()).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trace_export() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.trace_export = Some(lfs_server_config::TraceExport {
+            collector_url: "http://collector:9411/api/v2/spans".to_string(),
+            protocol: "zipkin".to_string(),
+            sample_rate: 0,
+            service_name: "".to_string(),
+            flush_interval_ms: 0,
+            max_queued_spans: 0,
+        });
+        let config = ServerConfig::try_from(raw.clone())?;
+        let export = config.trace_export().expect("trace export is set");
+        assert_eq!(export.protocol, TraceProtocol::Zipkin);
+        assert_eq!(export.sample_rate.get(), 1);
+        assert_eq!(export.service_name, DEFAULT_TRACE_SERVICE_NAME);
+        assert_eq!(export.flush_interval, DEFAULT_TRACE_FLUSH_INTERVAL);
+        assert_eq!(export.max_queued_spans, DEFAULT_TRACE_MAX_QUEUED_SPANS);
+
+        for (collector_url, protocol, sample_rate) in [
+            ("collector:4318", "otlp", 1),
+            ("http://collector:4318/v1/traces", "jaeger", 1),
+            ("http://collector:4318/v1/traces", "", -1),
+        ] {
+            raw.trace_export = Some(lfs_server_config::TraceExport {
+                collector_url: collector_url.to_string(),
+                protocol: protocol.to_string(),
+                sample_rate,
+                service_name: "".to_string(),
+                flush_interval_ms: 0,
+                max_queued_spans: 0,
+            });
+            assert!(ServerConfig::try_from(raw.clone()).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_upstream() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.upstream = Some(lfs_server_config::Upstream {
+            url: "https://upstream.example.com".to_string(),
+            authorization_file: "/does/not/exist".to_string(),
+        });
+        // The authorization file is only read when it is needed.
+        let config = ServerConfig::try_from(raw.clone())?;
+        let upstream = config.upstream().expect("upstream is set");
+        assert_eq!(
+            upstream.authorization_file,
+            Some(PathBuf::from("/does/not/exist"))
+        );
+
+        raw.upstream = Some(lfs_server_config::Upstream {
+            url: "upstream.example.com".to_string(),
+            authorization_file: "".to_string(),
+        });
+        assert!(ServerConfig::try_from(raw).is_err());
+
+        Ok(())
     }
 }
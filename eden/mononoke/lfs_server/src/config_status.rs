@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use cached_config::ConfigHandle;
+use cached_config::ConfigStore;
+use fbinit::FacebookInit;
+use serde::Serialize;
+use stats::prelude::*;
+
+use crate::config::ServerConfig;
+
+define_stats! {
+    prefix = "mononoke.lfs.config";
+    age_secs: singleton_counter(),
+}
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the served config came from and how old it is, so alerting can catch hosts that stopped
+/// picking up changes. The config store polls internally and doesn't report when it last polled
+/// or why a poll failed, so the age is how long ago the served config last changed, and the last
+/// error is from the sources that failed to load at startup.
+#[derive(Clone, Default)]
+pub struct ConfigStatus {
+    inner: Arc<Mutex<ConfigStatusInner>>,
+}
+
+#[derive(Default)]
+struct ConfigStatusInner {
+    source: Option<String>,
+    updated_at: Option<SystemTime>,
+    last_error: Option<String>,
+    served: Option<Arc<ServerConfig>>,
+    /// The store the config is read from, if it can pick up changes.
+    config_store: Option<ConfigStore>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ConfigStatusReport {
+    pub source: Option<String>,
+    pub updated_at_secs: Option<u64>,
+    pub age_secs: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// The outcome of polling the config source on demand.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum ReloadOutcome {
+    Updated,
+    Unchanged,
+    Error { message: String },
+}
+
+impl ConfigStatus {
+    pub fn set_source(&self, source: &str) {
+        self.inner.lock().expect("poisoned lock").source = Some(source.to_string());
+    }
+
+    pub fn set_error(&self, error: String) {
+        self.inner.lock().expect("poisoned lock").last_error = Some(error);
+    }
+
+    pub fn set_config_store(&self, config_store: ConfigStore) {
+        self.inner.lock().expect("poisoned lock").config_store = Some(config_store);
+    }
+
+    /// Polls the config source right away instead of waiting for the config store's next poll,
+    /// and reports whether the served config changed. This blocks while the source is read. The
+    /// config store keeps serving the old config if the new one doesn't parse or is invalid, which
+    /// shows up as unchanged here (and in the config store's logs).
+    pub fn reload(&self, config_handle: &ConfigHandle<ServerConfig>) -> ReloadOutcome {
+        let (config_store, source) = {
+            let inner = self.inner.lock().expect("poisoned lock");
+            (inner.config_store.clone(), inner.source.clone())
+        };
+
+        let config_store = match config_store {
+            Some(config_store) => config_store,
+            None => {
+                return ReloadOutcome::Error {
+                    message: format!(
+                        "Config source {} does not pick up changes",
+                        source.as_deref().unwrap_or("default")
+                    ),
+                };
+            }
+        };
+
+        let before = config_handle.get();
+        config_store.force_update_configs();
+        let after = config_handle.get();
+
+        if Arc::ptr_eq(&before, &after) {
+            ReloadOutcome::Unchanged
+        } else {
+            self.observe(after, SystemTime::now());
+            ReloadOutcome::Updated
+        }
+    }
+
+    /// Records `config` as the one being served, and returns its age.
+    fn observe(&self, config: Arc<ServerConfig>, now: SystemTime) -> Duration {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+
+        let changed = match &inner.served {
+            Some(served) => !Arc::ptr_eq(served, &config),
+            None => true,
+        };
+
+        if changed {
+            inner.served = Some(config);
+            inner.updated_at = Some(now);
+        }
+
+        inner
+            .updated_at
+            .and_then(|updated_at| now.duration_since(updated_at).ok())
+            .unwrap_or_default()
+    }
+
+    /// Watches `config_handle` for changes, and publishes the age of the served config. The task
+    /// exits once all copies of this are dropped.
+    pub fn spawn_monitor(&self, fb: FacebookInit, config_handle: ConfigHandle<ServerConfig>) {
+        let inner = Arc::downgrade(&self.inner);
+
+        tokio::spawn(async move {
+            loop {
+                let status = match inner.upgrade() {
+                    Some(inner) => ConfigStatus { inner },
+                    None => break,
+                };
+
+                let age = status.observe(config_handle.get(), SystemTime::now());
+                STATS::age_secs.set_value(fb, age.as_secs() as i64);
+                drop(status);
+
+                tokio::time::sleep(CHECK_INTERVAL).await;
+            }
+        });
+    }
+
+    pub fn report(&self, now: SystemTime) -> ConfigStatusReport {
+        let inner = self.inner.lock().expect("poisoned lock");
+
+        ConfigStatusReport {
+            source: inner.source.clone(),
+            updated_at_secs: inner
+                .updated_at
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            age_secs: inner
+                .updated_at
+                .and_then(|t| now.duration_since(t).ok())
+                .map(|d| d.as_secs()),
+            last_error: inner.last_error.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_observe() {
+        let status = ConfigStatus::default();
+        status.set_source("default");
+
+        let start = UNIX_EPOCH + Duration::from_secs(100);
+        let config = Arc::new(ServerConfig::default());
+
+        assert_eq!(status.observe(config.clone(), start), Duration::ZERO);
+
+        // The same config keeps ageing.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(status.observe(config, later), Duration::from_secs(10));
+        assert_eq!(
+            status.report(later),
+            ConfigStatusReport {
+                source: Some("default".to_string()),
+                updated_at_secs: Some(100),
+                age_secs: Some(10),
+                last_error: None,
+            }
+        );
+
+        // A new config resets the age.
+        let updated = Arc::new(ServerConfig::default());
+        assert_eq!(status.observe(updated, later), Duration::ZERO);
+        assert_eq!(status.report(later).updated_at_secs, Some(110));
+    }
+
+    #[test]
+    fn test_reload_without_config_store() {
+        let status = ConfigStatus::default();
+        status.set_source("default");
+
+        assert_eq!(
+            status.reload(&ConfigHandle::default()),
+            ReloadOutcome::Error {
+                message: "Config source default does not pick up changes".to_string(),
+            }
+        );
+    }
+}
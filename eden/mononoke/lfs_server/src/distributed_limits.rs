@@ -0,0 +1,251 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Enforcement of `request_limits` across the fleet, according to the `distributed_rate_limits`
+//! config. Servers count requests and bytes in shared counters, and check the limits against the
+//! totals. Local limits still apply, so the shared counters being slow or down only means that
+//! each server falls back to limiting on its own.
+
+use std::num::NonZeroU64;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use fbinit::FacebookInit;
+use permission_checker::MononokeIdentitySet;
+use stats::prelude::*;
+use time_window_counter::GlobalTimeWindowCounterBuilder;
+
+use crate::config::DistributedRateLimits;
+use crate::config::RequestLimit;
+use crate::errors::ErrorKind;
+
+define_stats! {
+    prefix = "mononoke.lfs.distributed_limits";
+    rate_limited: timeseries(Rate, Sum),
+    egress_delayed: timeseries(Rate, Sum),
+    error: timeseries(Rate, Sum),
+    timeout: timeseries(Rate, Sum),
+}
+
+/// Egress is never delayed by more than this at once, so that a burst across the fleet doesn't
+/// stall downloads for long after it is over.
+const MAX_EGRESS_DELAY: Duration = Duration::from_secs(1);
+
+/// Counters shared by all the servers in a fleet.
+#[async_trait]
+pub trait SharedCounters: Send + Sync {
+    /// Adds `value` to the counter for `key` in `category`, and returns how much was added to it
+    /// across the fleet over the last second, including `value`.
+    async fn add(&self, category: &str, key: &str, value: u64) -> Result<f64, Error>;
+}
+
+/// Shared counters backed by global time window counters.
+pub struct TimeWindowCounters {
+    fb: FacebookInit,
+}
+
+impl TimeWindowCounters {
+    pub fn new(fb: FacebookInit) -> Self {
+        Self { fb }
+    }
+}
+
+#[async_trait]
+impl SharedCounters for TimeWindowCounters {
+    async fn add(&self, category: &str, key: &str, value: u64) -> Result<f64, Error> {
+        let counter = GlobalTimeWindowCounterBuilder::build(self.fb, category, key, 1, 1);
+        counter.bump(value as f64);
+        counter.get(1).await
+    }
+}
+
+fn limit_key(kind: &str, identities: &MononokeIdentitySet) -> String {
+    let identities = identities
+        .iter()
+        .map(|identity| identity.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{}/{}", kind, identities)
+}
+
+/// Checks `request_limits` against the totals of the fleet.
+#[derive(Clone)]
+pub struct DistributedLimiter {
+    counters: Arc<dyn SharedCounters>,
+}
+
+impl DistributedLimiter {
+    pub fn new(counters: Arc<dyn SharedCounters>) -> Self {
+        Self { counters }
+    }
+
+    /// The fleet's rate for `key` after adding `value`, or `None` if the shared counters didn't
+    /// answer in time.
+    async fn add(&self, config: &DistributedRateLimits, key: &str, value: u64) -> Option<f64> {
+        let add = self.counters.add(&config.category, key, value);
+
+        match tokio::time::timeout(config.timeout, add).await {
+            Ok(Ok(rate)) => Some(rate),
+            Ok(Err(_)) => {
+                STATS::error.add_value(1);
+                None
+            }
+            Err(_) => {
+                STATS::timeout.add_value(1);
+                None
+            }
+        }
+    }
+
+    /// Counts a request towards `limit` across the fleet.
+    pub async fn acquire(
+        &self,
+        config: &DistributedRateLimits,
+        limit: &RequestLimit,
+    ) -> Result<(), ErrorKind> {
+        let rps = match limit.requests_per_second {
+            Some(rps) => rps,
+            None => return Ok(()),
+        };
+
+        let rate = self
+            .add(config, &limit_key("requests", &limit.identities), 1)
+            .await;
+
+        if rate.map_or(false, |rate| rate > rps.get() as f64) {
+            STATS::rate_limited.add_value(1);
+            return Err(ErrorKind::RequestRateLimited(rps.get()));
+        }
+
+        Ok(())
+    }
+
+    /// Counts `bytes` sent to clients with `identities` across the fleet, and returns how long to
+    /// wait before sending them to keep the fleet under `bytes_per_second`.
+    pub async fn egress_delay(
+        &self,
+        config: &DistributedRateLimits,
+        identities: &MononokeIdentitySet,
+        bytes_per_second: NonZeroU64,
+        bytes: usize,
+    ) -> Duration {
+        let rate = self
+            .add(config, &limit_key("egress", identities), bytes as u64)
+            .await;
+        let limit = bytes_per_second.get() as f64;
+
+        match rate {
+            Some(rate) if rate > limit => {
+                STATS::egress_delayed.add_value(1);
+                Duration::from_secs_f64((rate - limit) / limit).min(MAX_EGRESS_DELAY)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use std::collections::HashMap;
+    use std::num::NonZeroU32;
+    use std::sync::Mutex;
+
+    use futures::future;
+
+    use super::*;
+
+    /// Shared counters that are only shared by the callers in this process, with all calls in the
+    /// same second.
+    #[derive(Default)]
+    struct LocalCounters {
+        counters: Mutex<HashMap<String, u64>>,
+    }
+
+    #[async_trait]
+    impl SharedCounters for LocalCounters {
+        async fn add(&self, category: &str, key: &str, value: u64) -> Result<f64, Error> {
+            let mut counters = self.counters.lock().expect("poisoned lock");
+            let counter = counters.entry(format!("{}/{}", category, key)).or_default();
+            *counter += value;
+            Ok(*counter as f64)
+        }
+    }
+
+    struct PendingCounters;
+
+    #[async_trait]
+    impl SharedCounters for PendingCounters {
+        async fn add(&self, _: &str, _: &str, _: u64) -> Result<f64, Error> {
+            future::pending().await
+        }
+    }
+
+    fn config() -> DistributedRateLimits {
+        DistributedRateLimits {
+            category: "test".to_string(),
+            timeout: Duration::from_millis(10),
+        }
+    }
+
+    fn limit(requests_per_second: u32) -> RequestLimit {
+        RequestLimit {
+            identities: BTreeSet::new(),
+            requests_per_second: NonZeroU32::new(requests_per_second),
+            max_concurrent_requests: None,
+            max_concurrent_uploads: None,
+            max_egress_bytes_per_second: None,
+            max_upload_bytes_per_day: None,
+            max_upload_bytes_per_week: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_acquire() {
+        // Two servers sharing the same counters.
+        let counters = Arc::new(LocalCounters::default());
+        let server1 = DistributedLimiter::new(counters.clone());
+        let server2 = DistributedLimiter::new(counters);
+
+        assert!(server1.acquire(&config(), &limit(2)).await.is_ok());
+        assert!(server2.acquire(&config(), &limit(2)).await.is_ok());
+        assert!(matches!(
+            server1.acquire(&config(), &limit(2)).await,
+            Err(ErrorKind::RequestRateLimited(2))
+        ));
+
+        // Requests go through if the counters don't answer.
+        let pending = DistributedLimiter::new(Arc::new(PendingCounters));
+        assert!(pending.acquire(&config(), &limit(1)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_egress_delay() {
+        let limiter = DistributedLimiter::new(Arc::new(LocalCounters::default()));
+        let identities = BTreeSet::new();
+        let rate = NonZeroU64::new(100).unwrap();
+
+        assert_eq!(
+            limiter
+                .egress_delay(&config(), &identities, rate, 100)
+                .await,
+            Duration::ZERO
+        );
+        assert_eq!(
+            limiter.egress_delay(&config(), &identities, rate, 50).await,
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            limiter
+                .egress_delay(&config(), &identities, rate, 1000)
+                .await,
+            MAX_EGRESS_DELAY
+        );
+    }
+}
@@ -6,14 +6,20 @@
  */
 
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::Context;
 use anyhow::Error;
+use bytes::Bytes;
 use filestore::Alias;
 use filestore::FetchKey;
 use filestore::Range;
+use futures::future;
+use futures::stream;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
+use futures::Stream;
 use gotham::state::FromState;
 use gotham::state::State;
 use gotham_derive::StateData;
@@ -22,25 +28,38 @@ use gotham_ext::content_encoding::ContentEncoding;
 use gotham_ext::error::HttpError;
 use gotham_ext::middleware::ScubaMiddlewareState;
 use gotham_ext::response::CompressedResponseStream;
+use gotham_ext::response::EmptyBody;
 use gotham_ext::response::ResponseStream;
 use gotham_ext::response::ResponseTryStreamExt;
 use gotham_ext::response::StreamBody;
 use gotham_ext::response::TryIntoResponse;
 use http::header::HeaderMap;
+use http::header::HeaderValue;
+use http::header::ACCEPT_RANGES;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_RANGE;
+use http::header::LOCATION;
 use http::header::RANGE;
+use http::StatusCode;
+use http::Uri;
+use hyper::Body;
+use hyper::Response;
 use mononoke_types::hash::Sha256;
 use mononoke_types::ContentId;
 use permission_checker::MononokeIdentitySet;
 use redactedblobstore::has_redaction_root_cause;
-use repo_blobstore::RepoBlobstoreRef;
 use serde::Deserialize;
+use sha2::Digest;
 use stats::prelude::*;
 
+use crate::batch::resolve_internal_object;
 use crate::config::ServerConfig;
 use crate::errors::ErrorKind;
+use crate::lfs_server_context::LfsServerContext;
 use crate::lfs_server_context::RepositoryRequestContext;
 use crate::middleware::LfsMethod;
 use crate::scuba::LfsScubaKey;
+use crate::slow_read_retry::SlowReadRetryBlobstore;
 use crate::util::is_identity_subset;
 
 define_stats! {
@@ -56,7 +75,15 @@ define_stats! {
         Duration::from_secs(5), Duration::from_secs(15), Duration::from_secs(60)
     ),
     load_shed_counter: dynamic_singleton_counter("{}", (key: String)),
+    redirects: timeseries(Rate, Sum),
+    verified: timeseries(Rate, Sum),
+    verify_mismatch: timeseries(Rate, Sum),
 }
+
+/// Clients ask for the object's digest with `Want-Digest: sha-256` (RFC 3230), and get it in a
+/// `Digest` header.
+const WANT_DIGEST: &str = "want-digest";
+const DIGEST: &str = "digest";
 #[derive(Deserialize, StateData, StaticResponseExtender)]
 pub struct DownloadParamsContentId {
     repository: String,
@@ -69,36 +96,128 @@ pub struct DownloadParamsSha256 {
     oid: String,
 }
 
-fn parse_range(header: &str) -> Result<Range, Error> {
-    static RE: once_cell::sync::Lazy<regex::Regex> =
-        once_cell::sync::Lazy::new(|| regex::Regex::new(r"bytes=(\d+)-(\d+)").unwrap());
-
-    let caps = RE
-        .captures(header)
-        .with_context(|| format!("Unsupported range: {}", header))?;
-
-    let range = Range::range_inclusive(
-        caps[1]
-            .parse()
-            .with_context(|| format!("Invalid range start: {}", &caps[1]))?,
-        caps[2]
-            .parse()
-            .with_context(|| format!("Invalid range end: {}", &caps[2]))?,
-    )?
-    .strict();
-
-    Ok(range)
+/// A byte range a client asked for in a `Range` header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ByteRange {
+    /// `bytes=start-end`, where the end is inclusive.
+    Bounded(u64, u64),
+    /// `bytes=start-`, up to the end of the object.
+    From(u64),
+    /// `bytes=-len`, the last `len` bytes of the object.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// The inclusive start and end of this range in an object of `size` bytes, or None if the range
+    /// doesn't overlap the object.
+    fn resolve(self, size: u64) -> Option<(u64, u64)> {
+        let last = size.checked_sub(1)?;
+        let (start, end) = match self {
+            Self::Bounded(start, end) => (start, end.min(last)),
+            Self::From(start) => (start, last),
+            Self::Suffix(0) => return None,
+            Self::Suffix(len) => (size.saturating_sub(len), last),
+        };
+
+        if start > end {
+            return None;
+        }
+
+        Some((start, end))
+    }
 }
 
-fn extract_range(state: &State) -> Result<Option<Range>, Error> {
-    let header = match HeaderMap::try_borrow_from(state).and_then(|h| h.get(RANGE)) {
-        Some(h) => h,
-        None => return Ok(None),
+/// Parses a `Range` header with a single range. Clients may send several ranges, or ranges in
+/// other units, but those aren't supported, so they are ignored and the whole object is sent, as
+/// the RFC allows. So are ranges that don't parse.
+fn parse_range(header: &str) -> Option<ByteRange> {
+    static RE: once_cell::sync::Lazy<regex::Regex> =
+        once_cell::sync::Lazy::new(|| regex::Regex::new(r"^bytes=(\d*)-(\d*)$").unwrap());
+
+    let caps = RE.captures(header.trim())?;
+
+    let parse = |bound: &str| -> Result<Option<u64>, ()> {
+        if bound.is_empty() {
+            return Ok(None);
+        }
+        bound.parse().map(Some).map_err(|_| ())
     };
 
-    let header = std::str::from_utf8(header.as_bytes()).context("Invalid range")?;
+    match (parse(&caps[1]).ok()?, parse(&caps[2]).ok()?) {
+        (Some(start), Some(end)) if start <= end => Some(ByteRange::Bounded(start, end)),
+        (Some(start), None) => Some(ByteRange::From(start)),
+        (None, Some(len)) => Some(ByteRange::Suffix(len)),
+        _ => None,
+    }
+}
+
+fn extract_range(state: &State) -> Option<ByteRange> {
+    HeaderMap::try_borrow_from(state)
+        .and_then(|h| h.get(RANGE))
+        .and_then(|h| h.to_str().ok())
+        .and_then(parse_range)
+}
 
-    Ok(Some(parse_range(header)?))
+/// Whether a `Want-Digest` header asks for SHA-256. Other algorithms aren't supported, so they are
+/// ignored, as the RFC allows.
+fn wants_sha256(header: &str) -> bool {
+    header.split(',').any(|want| {
+        let mut params = want.split(';').map(str::trim);
+        let algorithm = params.next().unwrap_or_default();
+        // A q-value of 0 means the client doesn't want it after all.
+        algorithm.eq_ignore_ascii_case("sha-256") && !params.any(is_zero_qvalue)
+    })
+}
+
+fn is_zero_qvalue(param: &str) -> bool {
+    param
+        .strip_prefix("q=")
+        .and_then(|q| q.parse::<f32>().ok())
+        .map_or(false, |q| q == 0.0)
+}
+
+fn extract_want_sha256(state: &State) -> bool {
+    HeaderMap::try_borrow_from(state)
+        .and_then(|h| h.get(WANT_DIGEST))
+        .and_then(|h| h.to_str().ok())
+        .map_or(false, wants_sha256)
+}
+
+/// Hashes `stream` as it goes, and fails it if it doesn't match `expected`. The last chunk is held
+/// back until the whole object was hashed, so that a mismatch leaves the body short of its
+/// Content-Length instead of passing for a complete download.
+fn verify_sha256<S>(stream: S, expected: Sha256) -> impl Stream<Item = Result<Bytes, Error>>
+where
+    S: Stream<Item = Result<Bytes, Error>>,
+{
+    stream
+        .map_ok(Some)
+        .chain(stream::once(future::ready(Ok(None))))
+        .scan(
+            (sha2::Sha256::new(), None::<Bytes>),
+            move |(hasher, held), item| {
+                let item = match item {
+                    Ok(Some(bytes)) => {
+                        hasher.update(&bytes);
+                        Ok(held.replace(bytes))
+                    }
+                    Ok(None) => {
+                        let actual =
+                            Sha256::from_byte_array(std::mem::take(hasher).finalize().into());
+                        if actual == expected {
+                            STATS::verified.add_value(1);
+                            Ok(held.take())
+                        } else {
+                            STATS::verify_mismatch.add_value(1);
+                            Err(ErrorKind::DownloadContentMismatch(expected, actual).into())
+                        }
+                    }
+                    Err(e) => Err(e),
+                };
+                future::ready(Some(item))
+            },
+        )
+        .try_filter_map(future::ok)
 }
 
 fn should_disable_compression(
@@ -112,28 +231,172 @@ fn should_disable_compression(
     is_identity_subset(config.disable_compression_identities(), client_idents)
 }
 
+fn fetch_error(e: Error) -> HttpError {
+    if has_redaction_root_cause(&e) {
+        HttpError::e410(e)
+    } else {
+        HttpError::e500(e.context(ErrorKind::FilestoreReadFailure))
+    }
+}
+
+/// Either the object itself, part of it, or a redirect to where it can be downloaded from.
+enum DownloadResponse<B> {
+    Object(B),
+    /// The bytes of the object covered by the `Content-Range`.
+    Partial(B, HeaderValue),
+    Redirect(HeaderValue),
+}
+
+impl<B: TryIntoResponse> TryIntoResponse for DownloadResponse<B> {
+    fn try_into_response(self, state: &mut State) -> Result<Response<Body>, Error> {
+        match self {
+            Self::Object(body) => {
+                let mut res = body.try_into_response(state)?;
+                res.headers_mut()
+                    .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+                Ok(res)
+            }
+            Self::Partial(body, content_range) => {
+                let mut res = body.try_into_response(state)?;
+                res.headers_mut()
+                    .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+                res.headers_mut().insert(CONTENT_RANGE, content_range);
+                Ok(res)
+            }
+            Self::Redirect(location) => {
+                let mut res = EmptyBody::new().try_into_response(state)?;
+                *res.status_mut() = StatusCode::FOUND;
+                res.headers_mut().insert(LOCATION, location);
+                Ok(res)
+            }
+        }
+    }
+}
+
+/// A response with the `Digest` of the whole object, for clients that asked for it.
+struct WithDigest<B> {
+    body: B,
+    sha256: Option<Sha256>,
+}
+
+impl<B: TryIntoResponse> TryIntoResponse for WithDigest<B> {
+    fn try_into_response(self, state: &mut State) -> Result<Response<Body>, Error> {
+        let mut res = self.body.try_into_response(state)?;
+        if let Some(sha256) = self.sha256 {
+            let digest = format!("sha-256={}", base64::encode(sha256.into_inner()));
+            res.headers_mut()
+                .insert(DIGEST, HeaderValue::from_str(&digest)?);
+        }
+        Ok(res)
+    }
+}
+
+/// Answers a `HEAD` request for an object: no body, but the object's size as the content length
+/// (unless it is redacted).
+struct ObjectExists {
+    size: Option<u64>,
+}
+
+impl TryIntoResponse for ObjectExists {
+    fn try_into_response(self, state: &mut State) -> Result<Response<Body>, Error> {
+        let mut res = EmptyBody::new().try_into_response(state)?;
+        match self.size {
+            Some(size) => {
+                res.headers_mut()
+                    .insert(CONTENT_LENGTH, HeaderValue::from(size));
+            }
+            None => {
+                res.headers_mut().remove(CONTENT_LENGTH);
+            }
+        }
+        Ok(res)
+    }
+}
+
+fn render_redirect_url(template: &str, content_id: &ContentId, oid: &Sha256) -> String {
+    template
+        .replace("{content_id}", &content_id.to_string())
+        .replace("{oid}", &oid.to_string())
+}
+
+async fn redirect_location(
+    ctx: &RepositoryRequestContext,
+    template: &str,
+    key: &FetchKey,
+) -> Result<HeaderValue, HttpError> {
+    // Whoever serves the redirect doesn't know about redactions, so check for them here. Starting
+    // a fetch does that without reading the content.
+    filestore::fetch_range_with_size(ctx.blobstore(), ctx.ctx.clone(), key, Range::all())
+        .await
+        .map_err(fetch_error)?
+        .ok_or(ErrorKind::ObjectDoesNotExist(*key))
+        .map_err(HttpError::e404)?;
+
+    let meta = filestore::get_metadata(&ctx.blobstore(), &ctx.ctx, key)
+        .await
+        .map_err(fetch_error)?
+        .ok_or(ErrorKind::ObjectDoesNotExist(*key))
+        .map_err(HttpError::e404)?;
+
+    let location = render_redirect_url(template, &meta.content_id, &meta.sha256);
+    HeaderValue::from_str(&location).map_err(HttpError::e500)
+}
+
 async fn fetch_by_key(
     ctx: RepositoryRequestContext,
     key: FetchKey,
     content_encoding: ContentEncoding,
-    range: Option<Range>,
+    range: Option<ByteRange>,
+    want_sha256: bool,
     scuba: &mut Option<&mut ScubaMiddlewareState>,
-) -> Result<impl TryIntoResponse, HttpError> {
+) -> Result<DownloadResponse<impl TryIntoResponse>, HttpError> {
+    // Ranges may be relative to the end of the object, so we need its size to resolve them. The
+    // Filestore then only fetches the chunks that overlap the range. Clients that want the digest
+    // get it from there too.
+    let meta = if range.is_some() || want_sha256 {
+        let meta = filestore::get_metadata(&ctx.blobstore(), &ctx.ctx, &key)
+            .await
+            .map_err(fetch_error)?
+            .ok_or(ErrorKind::ObjectDoesNotExist(key))
+            .map_err(HttpError::e404)?;
+        Some(meta)
+    } else {
+        None
+    };
+
+    let content_range = match (range, &meta) {
+        (Some(range), Some(meta)) => {
+            let (start, end) = range
+                .resolve(meta.total_size)
+                .ok_or(ErrorKind::RangeNotSatisfiable(meta.total_size))
+                .map_err(HttpError::e416)?;
+            Some((start, end, meta.total_size))
+        }
+        _ => None,
+    };
+
+    let sha256 = meta.filter(|_| want_sha256).map(|meta| meta.sha256);
+
+    let permit = ctx
+        .transfer_limiter()
+        .acquire(&ctx.config)
+        .await
+        .map_err(HttpError::e429)?;
+
     // Query a stream out of the Filestore
+    let blobstore =
+        SlowReadRetryBlobstore::new(ctx.blobstore(), ctx.config.slow_read_retry_delay());
     let fetched = filestore::fetch_range_with_size(
-        ctx.repo.repo_blobstore().clone(),
+        blobstore,
         ctx.ctx.clone(),
         &key,
-        range.unwrap_or_else(Range::all),
+        match content_range {
+            Some((start, end, _)) => Range::sized(start, end - start + 1),
+            None => Range::all(),
+        },
     )
     .await
-    .map_err(|e| {
-        if has_redaction_root_cause(&e) {
-            HttpError::e410(e)
-        } else {
-            HttpError::e500(e.context(ErrorKind::FilestoreReadFailure))
-        }
-    })?;
+    .map_err(fetch_error)?;
 
     // Return a 404 if the stream doesn't exist.
     let (stream, size) = fetched
@@ -142,6 +405,30 @@ async fn fetch_by_key(
 
     ScubaMiddlewareState::maybe_add(scuba, LfsScubaKey::DownloadContentSize, size);
 
+    let mut backlog = ctx.host_pressure().start_download(size);
+    let stream = stream.inspect_ok(move |bytes| {
+        // The download keeps its slot until the response is sent.
+        let _permit = &permit;
+        backlog.sent(bytes.len())
+    });
+
+    // Only whole objects can be checked against their digest.
+    let stream = match sha256 {
+        Some(sha256) if content_range.is_none() => verify_sha256(stream, sha256).left_stream(),
+        _ => stream.right_stream(),
+    };
+
+    let content_encoding = match content_encoding {
+        // The range is of the uncompressed object, so that's what we send.
+        ContentEncoding::Compressed(_) if content_range.is_some() => ContentEncoding::Identity,
+        // A failed check only shows if the body is shorter than its Content-Length.
+        ContentEncoding::Compressed(_) if sha256.is_some() => ContentEncoding::Identity,
+        ContentEncoding::Compressed(_) if size < ctx.config.min_compression_size_bytes() => {
+            ContentEncoding::Identity
+        }
+        content_encoding => content_encoding,
+    };
+
     let stream = match content_encoding {
         ContentEncoding::Identity => ResponseStream::new(stream)
             .set_content_length(size)
@@ -149,6 +436,49 @@ async fn fetch_by_key(
         ContentEncoding::Compressed(c) => CompressedResponseStream::new(stream, c).right_stream(),
     };
 
+    let egress_limit = ctx
+        .config
+        .request_limit(Some(ctx.ctx.metadata().identities()))
+        .and_then(|limit| Some((limit.identities.clone(), limit.max_egress_bytes_per_second?)));
+
+    let stream = match egress_limit {
+        Some((identities, bytes_per_second)) => {
+            let limiter = ctx.egress_limiter().clone();
+            let distributed = ctx
+                .config
+                .distributed_rate_limits()
+                .cloned()
+                .map(|config| (ctx.distributed_limiter().clone(), config));
+            let identities = Arc::new(identities);
+            stream
+                .and_then(move |bytes| {
+                    let delay =
+                        limiter.reserve(&identities, bytes_per_second, bytes.len(), Instant::now());
+                    let distributed = distributed.clone();
+                    let identities = identities.clone();
+                    async move {
+                        let delay = match distributed {
+                            Some((distributed, config)) => delay.max(
+                                distributed
+                                    .egress_delay(
+                                        &config,
+                                        &identities,
+                                        bytes_per_second,
+                                        bytes.len(),
+                                    )
+                                    .await,
+                            ),
+                            None => delay,
+                        };
+                        tokio::time::sleep(delay).await;
+                        Ok(bytes)
+                    }
+                })
+                .left_stream()
+        }
+        None => stream.right_stream(),
+    };
+
     let stream = if ctx.config.track_bytes_sent() {
         stream
             .inspect_ok(move |bytes| {
@@ -170,10 +500,18 @@ async fn fetch_by_key(
     let stream = stream.end_on_err();
 
     let mut body = StreamBody::new(stream, mime::APPLICATION_OCTET_STREAM);
-    if range.is_some() {
-        body.partial = true;
+    body.partial = content_range.is_some();
+    let body = WithDigest { body, sha256 };
+
+    match content_range {
+        Some((start, end, total_size)) => {
+            let content_range =
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, total_size))
+                    .map_err(HttpError::e500)?;
+            Ok(DownloadResponse::Partial(body, content_range))
+        }
+        None => Ok(DownloadResponse::Object(body)),
     }
-    Ok(body)
 }
 
 async fn download_inner(
@@ -182,10 +520,30 @@ async fn download_inner(
     key: FetchKey,
     method: LfsMethod,
 ) -> Result<impl TryIntoResponse, HttpError> {
-    let range = extract_range(state).map_err(HttpError::e400)?;
+    let range = extract_range(state);
+    let want_sha256 = extract_want_sha256(state);
 
     let ctx = RepositoryRequestContext::instantiate(state, repository.clone(), method).await?;
 
+    if ctx.config.has_denied_oids() {
+        let oid = match key {
+            FetchKey::Aliased(Alias::Sha256(oid)) => Some(oid),
+            key => filestore::get_metadata(&ctx.blobstore(), &ctx.ctx, &key)
+                .await
+                .map_err(fetch_error)?
+                .map(|meta| meta.sha256),
+        };
+        if let Some(oid) = oid {
+            ctx.check_denied(&oid).map_err(HttpError::e403)?;
+        }
+    }
+
+    if let Some(template) = ctx.config.download_redirect_url() {
+        let location = redirect_location(&ctx, template, &key).await?;
+        STATS::redirects.add_value(1);
+        return Ok(DownloadResponse::Redirect(location));
+    }
+
     let disable_compression =
         should_disable_compression(&ctx.config, Some(ctx.ctx.metadata().identities()));
 
@@ -197,7 +555,7 @@ async fn download_inner(
 
     let mut scuba = state.try_borrow_mut::<ScubaMiddlewareState>();
 
-    fetch_by_key(ctx, key, content_encoding, range, &mut scuba).await
+    fetch_by_key(ctx, key, content_encoding, range, want_sha256, &mut scuba).await
 }
 
 pub async fn download(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
@@ -212,7 +570,30 @@ pub async fn download(state: &mut State) -> Result<impl TryIntoResponse, HttpErr
 
     let key = FetchKey::Canonical(content_id);
 
-    download_inner(state, repository, key, LfsMethod::Download).await
+    let routing_key = routing_key(state);
+    let res = download_inner(state, repository, key, LfsMethod::Download).await;
+
+    if let Some(routing_key) = routing_key {
+        let failed = res
+            .as_ref()
+            .err()
+            .map_or(false, |e| e.status_code.is_server_error());
+        let lfs_ctx = LfsServerContext::borrow_from(state);
+        lfs_ctx
+            .routing_health()
+            .record(&lfs_ctx.get_config(), &routing_key, failed);
+    }
+
+    res
+}
+
+/// The routing key of downloads that were consistently routed here.
+fn routing_key(state: &State) -> Option<String> {
+    Uri::borrow_from(state)
+        .query()?
+        .split('&')
+        .find_map(|param| param.strip_prefix("routing="))
+        .map(String::from)
 }
 
 pub async fn download_sha256(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
@@ -227,19 +608,48 @@ pub async fn download_sha256(state: &mut State) -> Result<impl TryIntoResponse,
     download_inner(state, repository, key, LfsMethod::DownloadSha256).await
 }
 
+/// Checks whether an object exists and how big it is, without fetching its contents. This is
+/// cheaper than a batch request for clients that only want to know if an upload is needed.
+pub async fn exists(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let DownloadParamsSha256 { repository, oid } = state.take();
+
+    let oid = Sha256::from_str(&oid)
+        .context(ErrorKind::InvalidOid)
+        .map_err(HttpError::e400)?;
+
+    let ctx = RepositoryRequestContext::instantiate(state, repository, LfsMethod::Exists).await?;
+
+    ctx.check_denied(&oid).map_err(HttpError::e403)?;
+
+    let obj = resolve_internal_object(&ctx, oid)
+        .await
+        .map_err(HttpError::e500)?
+        .ok_or(ErrorKind::ObjectDoesNotExist(FetchKey::Aliased(
+            Alias::Sha256(oid),
+        )))
+        .map_err(HttpError::e404)?;
+
+    Ok(ObjectExists { size: obj.size() })
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::Arc;
 
     use anyhow::Error;
+    use bytes::Bytes;
     use fbinit::FacebookInit;
-    use http::StatusCode;
+    use filestore::FilestoreConfig;
+    use filestore::StoreRequest;
+    use futures::future;
+    use futures::stream;
     use maplit::hashmap;
     use mononoke_types::typed_hash::BlobstoreKey;
     use mononoke_types_mocks::contentid::ONES_CTID;
     use permission_checker::MononokeIdentity;
     use redactedblobstore::RedactedBlobs;
     use redactedblobstore::RedactedMetadata;
+    use repo_blobstore::RepoBlobstoreRef;
     use test_repo_factory::TestRepoFactory;
 
     use super::*;
@@ -266,7 +676,7 @@ mod test {
 
         let key = FetchKey::Canonical(content_id);
 
-        let err = fetch_by_key(ctx, key, ContentEncoding::Identity, None, &mut None)
+        let err = fetch_by_key(ctx, key, ContentEncoding::Identity, None, false, &mut None)
             .await
             .map(|_| ())
             .unwrap_err();
@@ -275,16 +685,152 @@ mod test {
         Ok(())
     }
 
+    #[fbinit::test]
+    async fn test_redirect_location(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = RepositoryRequestContext::test_builder(fb).await?.build()?;
+
+        let meta = filestore::store(
+            ctx.repo.repo_blobstore(),
+            FilestoreConfig::no_chunking_filestore(),
+            &ctx.ctx,
+            &StoreRequest::new(6),
+            stream::once(future::ready(Ok(Bytes::from("foobar")))),
+        )
+        .await?;
+
+        let template = "https://cdn/{oid}?id={content_id}";
+        let key = FetchKey::Aliased(Alias::Sha256(meta.sha256));
+        let location = redirect_location(&ctx, template, &key)
+            .await
+            .map_err(|e| e.error)?;
+        assert_eq!(
+            location,
+            render_redirect_url(template, &meta.content_id, &meta.sha256)
+        );
+        assert_eq!(
+            location,
+            format!(
+                "https://cdn/c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2?id={}",
+                meta.content_id
+            )
+        );
+
+        let err = redirect_location(&ctx, template, &FetchKey::Canonical(ONES_CTID))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_ranged_fetch(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = RepositoryRequestContext::test_builder(fb).await?.build()?;
+
+        let meta = filestore::store(
+            ctx.repo.repo_blobstore(),
+            FilestoreConfig::no_chunking_filestore(),
+            &ctx.ctx,
+            &StoreRequest::new(6),
+            stream::once(future::ready(Ok(Bytes::from("foobar")))),
+        )
+        .await?;
+        let key = FetchKey::Canonical(meta.content_id);
+
+        let res = fetch_by_key(
+            ctx.clone(),
+            key,
+            ContentEncoding::Identity,
+            Some(ByteRange::Suffix(3)),
+            false,
+            &mut None,
+        )
+        .await
+        .map_err(|e| e.error)?;
+        match res {
+            DownloadResponse::Partial(_, content_range) => {
+                assert_eq!(content_range, "bytes 3-5/6")
+            }
+            _ => panic!("Expected a partial response"),
+        }
+
+        let err = fetch_by_key(
+            ctx,
+            key,
+            ContentEncoding::Identity,
+            Some(ByteRange::From(6)),
+            false,
+            &mut None,
+        )
+        .await
+        .map(|_| ())
+        .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::RANGE_NOT_SATISFIABLE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=1-5"), Some(ByteRange::Bounded(1, 5)));
+        assert_eq!(parse_range("bytes=10-"), Some(ByteRange::From(10)));
+        assert_eq!(parse_range("bytes=-10"), Some(ByteRange::Suffix(10)));
+        // Anything else is ignored, and the whole object is sent.
+        assert_eq!(parse_range("1-5"), None);
+        assert_eq!(parse_range("foo=1-5"), None);
+        assert_eq!(parse_range("bytes=5-1"), None);
+        assert_eq!(parse_range("bytes=-"), None);
+        assert_eq!(parse_range("bytes=99999999999999999999-"), None);
+        assert_eq!(parse_range("bytes=1-5,7-9"), None);
+    }
+
     #[test]
-    fn test_parse_range() -> Result<(), Error> {
-        // NOTE: This range is inclusive, so here we want bytes 1, 2, 3, 5 (a 5-byte range starting
-        // at byte 1).
-        assert_eq!(parse_range("bytes=1-5")?, Range::sized(1, 5).strict());
-        assert!(parse_range("1-5").is_err());
-        assert!(parse_range("foo=1-5").is_err());
+    fn test_wants_sha256() {
+        assert!(wants_sha256("sha-256"));
+        assert!(wants_sha256("SHA-256;q=0.5"));
+        assert!(wants_sha256("md5;q=0.3, sha-256"));
+        assert!(!wants_sha256("sha-256;q=0"));
+        assert!(!wants_sha256("md5"));
+        assert!(!wants_sha256(""));
+    }
+
+    #[tokio::test]
+    async fn test_verify_sha256() -> Result<(), Error> {
+        let chunks = || stream::iter(vec![Ok(Bytes::from("foo")), Ok(Bytes::from("bar"))]);
+        let sha256 = Sha256::from_str(
+            "c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2",
+        )?;
+
+        let body = verify_sha256(chunks(), sha256)
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(body, vec![Bytes::from("foo"), Bytes::from("bar")]);
+
+        // The last chunk is never sent if the object doesn't match.
+        let res = verify_sha256(chunks(), Sha256::from_byte_array([1; 32]))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].as_ref().ok(), Some(&Bytes::from("foo")));
+        assert!(res[1].is_err());
+
         Ok(())
     }
 
+    #[test]
+    fn test_resolve_range() {
+        // NOTE: Ends are inclusive, so this is the 5 bytes starting at byte 1.
+        assert_eq!(ByteRange::Bounded(1, 5).resolve(10), Some((1, 5)));
+        assert_eq!(ByteRange::Bounded(5, 20).resolve(10), Some((5, 9)));
+        assert_eq!(ByteRange::Bounded(10, 20).resolve(10), None);
+        assert_eq!(ByteRange::From(4).resolve(10), Some((4, 9)));
+        assert_eq!(ByteRange::From(10).resolve(10), None);
+        assert_eq!(ByteRange::Suffix(3).resolve(10), Some((7, 9)));
+        assert_eq!(ByteRange::Suffix(20).resolve(10), Some((0, 9)));
+        assert_eq!(ByteRange::Suffix(0).resolve(10), None);
+        assert_eq!(ByteRange::From(0).resolve(0), None);
+    }
+
     #[test]
     fn test_should_disable_compression() -> Result<(), Error> {
         let mut config = ServerConfig::default();
@@ -0,0 +1,94 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use permission_checker::MononokeIdentitySet;
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets throttling downloads for each group of clients sharing a `RequestLimit` with
+/// `max_egress_bytes_per_second` set. Buckets hold up to one second worth of bytes.
+#[derive(Clone, Default)]
+pub struct EgressLimiter {
+    buckets: Arc<Mutex<HashMap<MononokeIdentitySet, TokenBucket>>>,
+}
+
+impl EgressLimiter {
+    /// Takes `bytes` out of the bucket for `identities`, and returns how long to wait before
+    /// sending them. The bucket may go into debt, which later callers wait out.
+    pub fn reserve(
+        &self,
+        identities: &MononokeIdentitySet,
+        bytes_per_second: NonZeroU64,
+        bytes: usize,
+        now: Instant,
+    ) -> Duration {
+        let rate = bytes_per_second.get() as f64;
+
+        let mut buckets = self.buckets.lock().expect("poisoned lock");
+        let bucket = buckets
+            .entry(identities.clone())
+            .or_insert_with(|| TokenBucket {
+                tokens: rate,
+                updated: now,
+            });
+
+        if now > bucket.updated {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+            bucket.updated = now;
+        }
+
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let limiter = EgressLimiter::default();
+        let identities = BTreeSet::new();
+        let rate = NonZeroU64::new(100).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.reserve(&identities, rate, 100, now), Duration::ZERO);
+        assert_eq!(
+            limiter.reserve(&identities, rate, 50, now),
+            Duration::from_millis(500)
+        );
+
+        // The debt is paid off after waiting.
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.reserve(&identities, rate, 0, later), Duration::ZERO);
+
+        // Idle time doesn't accumulate more than a second worth of bytes.
+        let much_later = later + Duration::from_secs(10);
+        assert_eq!(
+            limiter.reserve(&identities, rate, 200, much_later),
+            Duration::from_secs(1)
+        );
+    }
+}
@@ -5,13 +5,20 @@
  * GNU General Public License version 2.
  */
 
+use std::net::IpAddr;
+use std::time::Duration;
+
 use filestore::FetchKey;
 use gotham_ext::error::HttpError;
 use hyper::StatusCode;
 use lfs_protocol::RequestObject;
 use lfs_protocol::ResponseObject;
+use mononoke_types::hash::Sha256;
+use rate_limiting::RateLimitReason;
 use thiserror::Error;
 
+use crate::transfer_limiter::TransferKind;
+
 #[derive(Debug, Error)]
 pub enum ErrorKind {
     #[error("Client cancelled the request")]
@@ -50,6 +57,16 @@ pub enum ErrorKind {
     LocalAliasLoadError,
     #[error("Could not parse Request Batch")]
     InvalidBatch,
+    #[error("Operation is not supported in a Request Batch: {0}")]
+    InvalidBatchOperation(lfs_protocol::Operation),
+    #[error("Could not parse verify request")]
+    InvalidVerifyRequest,
+    #[error("Object size ({0}) does not match the verified size ({1})")]
+    VerifySizeMismatch(u64, u64),
+    #[error("Object {0} was read back as {1}")]
+    DownloadContentMismatch(Sha256, Sha256),
+    #[error("Requested range does not overlap the object, which is {0} bytes")]
+    RangeNotSatisfiable(u64),
     #[error("Could not parse Content ID")]
     InvalidContentId,
     #[error("Could not parse SHA256")]
@@ -60,10 +77,74 @@ pub enum ErrorKind {
     FilestoreWriteFailure,
     #[error("Object size ({0}) exceeds max allowed size ({1})")]
     UploadTooLarge(u64, u64),
+    #[error("Request body is larger than the declared object size ({0})")]
+    UploadBodyTooLarge(u64),
+    #[error("Request body exceeds max allowed size ({0})")]
+    RequestBodyTooLarge(u64),
+    #[error("Uploaded content does not match object {0}")]
+    UploadContentMismatch(Sha256),
+    #[error("Object is denied: {0}")]
+    ObjectDenied(Sha256),
+    #[error("Could not parse Content-Range: {0}")]
+    InvalidContentRange(String),
+    #[error("Content-Range total ({0}) does not match the declared object size ({1})")]
+    ContentRangeSizeMismatch(u64, u64),
+    #[error("Upload part starts at {0}, but {1} bytes were received so far")]
+    UploadRangeMismatch(u64, u64),
+    #[error("Request body does not match the Content-Range length ({0})")]
+    UploadPartSizeMismatch(u64),
+    #[error("Partial upload is missing part {0}")]
+    PartialUploadPartMissing(String),
+    #[error("Upload part of {0} bytes exceeds max allowed part size ({1})")]
+    UploadPartTooLarge(u64, u64),
+    #[error("Could not parse upload session: {0}")]
+    InvalidUploadSession(String),
+    #[error("Upload part starts at {0}, but has no upload session")]
+    MissingUploadSession(u64),
     #[error("Object is not internally available, and upstream is not available: {0}")]
     ObjectNotInternallyAvailableAndUpstreamUnavailable(lfs_protocol::Sha256),
     #[error("Object could not be synced from upstream: {0:?}")]
     ObjectCannotBeSynced(RequestObject),
+    #[error("Request rate limit exceeded ({0} requests per second)")]
+    RequestRateLimited(u32),
+    #[error("Concurrent request limit exceeded ({0} requests)")]
+    ConcurrentRequestsLimited(u32),
+    #[error("Request rate limit exceeded for {0} ({1} requests per second)")]
+    IpRateLimited(IpAddr, u32),
+    #[error("Concurrent upload limit exceeded ({0} uploads)")]
+    ConcurrentUploadsLimited(u32),
+    #[error("Upload of {0} bytes exceeds the quota of {1} bytes {2} ({3} bytes already uploaded)")]
+    UploadQuotaExceeded(u64, u64, &'static str, u64),
+    #[error("Timed out waiting for a {0} slot after {1:?}")]
+    TransferQueueTimeout(TransferKind, Duration),
+    #[error("Blobstore call timed out after {0:?}")]
+    BlobstoreTimeout(Duration),
+    #[error("Upstream request timed out after {0:?}")]
+    UpstreamTimeout(Duration),
+    #[error("Connecting to upstream timed out after {0:?}")]
+    UpstreamConnectTimeout(Duration),
+    #[error("Server is overloaded: {1} {0} (limit {2})")]
+    HostOverloaded(&'static str, u64, u64),
+    #[error("Fault injected into {0} request")]
+    InjectedFault(&'static str),
+    #[error("File locking is not enabled for repository {0}")]
+    LocksDisabled(String),
+    #[error("Could not parse lock request")]
+    InvalidLockRequest,
+    #[error("Invalid lock id: {0}")]
+    InvalidLockId(String),
+    #[error("Invalid lock cursor: {0}")]
+    InvalidLockCursor(String),
+    #[error("Lock does not exist: {0}")]
+    LockDoesNotExist(u64),
+    #[error("Lock {0} is owned by {1}")]
+    LockOwnedByOther(u64, String),
+    #[error("Could not determine who the lock belongs to")]
+    LockOwnerUnknown,
+    #[error("Could not replicate object to {0}")]
+    ReplicationFailed(String),
+    #[error("Object popularity reporting is not enabled")]
+    PopularityReportDisabled,
 
     /// A generic error occurred, and we'd like to propagate it.
     #[error(transparent)]
@@ -80,6 +161,8 @@ pub enum LfsServerContextErrorKind {
     RepositoryDoesNotExist(String),
     #[error("Missing host header")]
     MissingHostHeader,
+    #[error("Request throttled: {0}")]
+    Throttled(#[source] RateLimitReason),
 }
 
 impl From<LfsServerContextErrorKind> for HttpError {
@@ -90,6 +173,7 @@ impl From<LfsServerContextErrorKind> for HttpError {
             RepositoryDoesNotExist(_) => HttpError::e400(e),
             MissingHostHeader => HttpError::e400(e),
             NotAuthenticated => HttpError::e403(e),
+            Throttled(_) => HttpError::e429(e),
         }
     }
 }
@@ -27,7 +27,6 @@ use http::header::CONTENT_LENGTH;
 use hyper::Body;
 use mononoke_types::hash::GitSha1;
 use mononoke_types::hash::RichGitSha1;
-use repo_blobstore::RepoBlobstoreRef;
 use serde::Deserialize;
 use stats::prelude::*;
 
@@ -64,7 +63,7 @@ where
     STATS::total_uploads.add_value(1);
 
     filestore::store(
-        ctx.repo.repo_blobstore(),
+        &ctx.blobstore(),
         *ctx.repo.filestore_config(),
         &ctx.ctx,
         &StoreRequest::with_git_sha1(size, oid),
@@ -0,0 +1,236 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use hyper::Body;
+
+use crate::config::ServerConfig;
+use crate::errors::ErrorKind;
+use crate::util::hold_until_sent;
+
+const LAG_PROBE_INTERVAL: Duration = Duration::from_millis(100);
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tracks how loaded this host is: requests in flight, bytes that downloads still have to send,
+/// and how late the event loop runs tasks. This is shared by all requests, and compared against
+/// the thresholds in the server config.
+#[derive(Clone, Default)]
+pub struct HostPressure {
+    in_flight_requests: Arc<AtomicU64>,
+    egress_backlog_bytes: Arc<AtomicU64>,
+    event_loop_lag_ms: Arc<AtomicU64>,
+}
+
+/// Held for as long as a request is in flight.
+pub struct InFlightRequest {
+    in_flight_requests: Arc<AtomicU64>,
+}
+
+impl InFlightRequest {
+    /// Keeps the request in flight until `body` has been sent, or dropped because the client went
+    /// away.
+    pub fn hold_until_sent(self, body: Body) -> Body {
+        hold_until_sent(self, body)
+    }
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.in_flight_requests.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Held for as long as a download is being sent. Whatever wasn't sent by the time this is dropped
+/// is removed from the backlog.
+pub struct EgressBacklog {
+    egress_backlog_bytes: Arc<AtomicU64>,
+    remaining: u64,
+}
+
+impl EgressBacklog {
+    pub fn sent(&mut self, bytes: usize) {
+        let bytes = (bytes as u64).min(self.remaining);
+        self.remaining -= bytes;
+        self.egress_backlog_bytes
+            .fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+impl Drop for EgressBacklog {
+    fn drop(&mut self) {
+        self.egress_backlog_bytes
+            .fetch_sub(self.remaining, Ordering::Relaxed);
+    }
+}
+
+impl HostPressure {
+    pub fn start_request(&self) -> InFlightRequest {
+        self.in_flight_requests.fetch_add(1, Ordering::Relaxed);
+        InFlightRequest {
+            in_flight_requests: self.in_flight_requests.clone(),
+        }
+    }
+
+    pub fn start_download(&self, size: u64) -> EgressBacklog {
+        self.egress_backlog_bytes.fetch_add(size, Ordering::Relaxed);
+        EgressBacklog {
+            egress_backlog_bytes: self.egress_backlog_bytes.clone(),
+            remaining: size,
+        }
+    }
+
+    pub fn in_flight_requests(&self) -> u64 {
+        self.in_flight_requests.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once no requests are in flight, to let them finish when shutting down.
+    pub async fn wait_for_idle(&self) {
+        while self.in_flight_requests() > 0 {
+            tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Measures event loop lag by checking how late a sleeping task wakes up. This only observes
+    /// one of the runtime's workers at a time, but a saturated runtime delays all of them. The
+    /// task exits once all copies of this are dropped.
+    pub fn spawn_event_loop_monitor(&self) {
+        let event_loop_lag_ms = Arc::downgrade(&self.event_loop_lag_ms);
+
+        tokio::spawn(async move {
+            loop {
+                let start = Instant::now();
+                tokio::time::sleep(LAG_PROBE_INTERVAL).await;
+                let lag = start.elapsed().saturating_sub(LAG_PROBE_INTERVAL);
+
+                match event_loop_lag_ms.upgrade() {
+                    Some(event_loop_lag_ms) => {
+                        event_loop_lag_ms.store(lag.as_millis() as u64, Ordering::Relaxed)
+                    }
+                    None => break,
+                }
+            }
+        });
+    }
+
+    /// Fails with the reason the host is overloaded, if it is.
+    pub fn check(&self, config: &ServerConfig) -> Result<(), ErrorKind> {
+        let checks = [
+            (
+                "in-flight requests",
+                &self.in_flight_requests,
+                config.max_in_flight_requests(),
+            ),
+            (
+                "egress backlog bytes",
+                &self.egress_backlog_bytes,
+                config.max_egress_backlog_bytes(),
+            ),
+            (
+                "event loop lag ms",
+                &self.event_loop_lag_ms,
+                config.max_event_loop_lag_ms(),
+            ),
+        ];
+
+        for (name, value, max) in checks {
+            if let Some(max) = max {
+                let value = value.load(Ordering::Relaxed);
+                if value >= max {
+                    return Err(ErrorKind::HostOverloaded(name, value, max));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Error;
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn test_check() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.max_in_flight_requests = 2;
+        raw.max_egress_backlog_bytes = 100;
+        let config = ServerConfig::try_from(raw)?;
+
+        let pressure = HostPressure::default();
+        assert!(pressure.check(&config).is_ok());
+
+        let r1 = pressure.start_request();
+        let _r2 = pressure.start_request();
+        assert!(matches!(
+            pressure.check(&config),
+            Err(ErrorKind::HostOverloaded("in-flight requests", 2, 2))
+        ));
+        drop(r1);
+        assert!(pressure.check(&config).is_ok());
+
+        let mut d1 = pressure.start_download(60);
+        let d2 = pressure.start_download(60);
+        assert!(matches!(
+            pressure.check(&config),
+            Err(ErrorKind::HostOverloaded("egress backlog bytes", 120, 100))
+        ));
+
+        // Sending more than the declared size doesn't underflow the backlog.
+        d1.sent(100);
+        assert!(pressure.check(&config).is_ok());
+        drop(d1);
+        drop(d2);
+        assert_eq!(pressure.egress_backlog_bytes.load(Ordering::Relaxed), 0);
+
+        assert_eq!(pressure.in_flight_requests(), 1);
+
+        // Nothing is limited by default.
+        assert!(pressure.check(&ServerConfig::default()).is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_slow_body() -> Result<(), Error> {
+        let pressure = HostPressure::default();
+
+        let (mut sender, body) = Body::channel();
+        let body = pressure.start_request().hold_until_sent(body);
+
+        // The handler has returned, but draining waits for the body to be sent.
+        let idle = pressure.wait_for_idle();
+        futures::pin_mut!(idle);
+        assert!(futures::poll!(idle.as_mut()).is_pending());
+        assert_eq!(pressure.in_flight_requests(), 1);
+
+        sender
+            .try_send_data(Bytes::from("foo"))
+            .map_err(|_| anyhow::anyhow!("body closed"))?;
+        drop(sender);
+        assert_eq!(hyper::body::to_bytes(body).await?, Bytes::from("foo"));
+
+        assert_eq!(pressure.in_flight_requests(), 0);
+        idle.await;
+
+        // Bodies that are never sent, e.g. because the client went away, release the request too.
+        let (_sender, body) = Body::channel();
+        let body = pressure.start_request().hold_until_sent(body);
+        assert_eq!(pressure.in_flight_requests(), 1);
+        drop(body);
+        assert_eq!(pressure.in_flight_requests(), 0);
+
+        Ok(())
+    }
+}
@@ -22,10 +22,13 @@ use futures::future;
 use futures::stream::Stream;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
+use gotham::helpers::http::header::X_REQUEST_ID;
+use gotham::state::request_id;
 use gotham::state::FromState;
 use gotham::state::State;
 use gotham_derive::StateData;
 use gotham_ext::body_ext::BodyExt;
+use gotham_ext::middleware::ScubaMiddlewareState;
 use hostname::get_hostname;
 use http::header::HeaderMap;
 use http::uri::Authority;
@@ -43,32 +46,61 @@ use lfs_protocol::RequestBatch;
 use lfs_protocol::RequestObject;
 use lfs_protocol::ResponseBatch;
 use metaconfig_types::RepoConfigRef;
+use mononoke_types::hash::Sha256;
 use mononoke_types::ContentId;
 #[cfg(fbcode_build)]
 use network_util::get_device_network_speed_bits;
+use openssl::ssl::SslConnector;
+use openssl::ssl::SslMethod;
 use qps::Qps;
 use repo_authorization::AuthorizationContext;
+use repo_blobstore::RepoBlobstore;
+use repo_blobstore::RepoBlobstoreRef;
 use repo_permission_checker::RepoPermissionCheckerRef;
 use slog::info;
+use slog::warn;
 use slog::Logger;
 use tokio::runtime::Handle;
+use tokio::time::Instant;
 
+use crate::audit_log::AuditLog;
 use crate::config::ServerConfig;
+use crate::config_status::ConfigStatus;
+use crate::distributed_limits::DistributedLimiter;
+use crate::egress::EgressLimiter;
 use crate::errors::ErrorKind;
 use crate::errors::LfsServerContextErrorKind;
+use crate::host_pressure::HostPressure;
 use crate::middleware::LfsMethod;
 use crate::middleware::RequestContext;
+use crate::popularity::HotObjects;
+use crate::popularity::PopularObjects;
+use crate::replication::Replicator;
+use crate::routing_health::RoutingHealth;
+use crate::timeouts;
+use crate::timeouts::ConnectTimeout;
+use crate::timeouts::TimeoutBlobstore;
+use crate::trace_export::RequestTrace;
+use crate::trace_export::SpanKind;
+use crate::trace_export::TraceExporter;
+use crate::trace_export::TracedBlobstore;
+use crate::trace_export::TRACEPARENT;
+use crate::transfer_limiter::TransferLimiter;
+use crate::upload::UploadLimiter;
+use crate::upstream_authorization::UpstreamAuthorization;
 use crate::LfsRepos;
 use crate::Repo;
 
 pub type HttpsHyperClient = Client<HttpsConnector<HttpConnector>>;
 
+type UpstreamClient = Client<HttpsConnector<ConnectTimeout<HttpConnector>>>;
+
 // The user agent string presented to upstream
 const CLIENT_USER_AGENT: &str = "mononoke-lfs-server/0.1.0 git/2.15.1";
 
 struct LfsServerContextInner {
     repositories: LfsRepos,
-    client: Arc<HttpsHyperClient>,
+    client: Arc<UpstreamClient>,
     server: Arc<ServerUris>,
     always_wait_for_upstream: bool,
     max_upload_size: Option<u64>,
@@ -83,6 +115,19 @@ struct LfsServerContextInner {
 pub struct LfsServerContext {
     inner: Arc<Mutex<LfsServerContextInner>>,
     will_exit: Arc<AtomicBool>,
+    upload_limiter: UploadLimiter,
+    egress_limiter: EgressLimiter,
+    transfer_limiter: TransferLimiter,
+    hot_objects: HotObjects,
+    popular_objects: PopularObjects,
+    replicator: Replicator,
+    audit_log: AuditLog,
+    routing_health: RoutingHealth,
+    trace_exporter: TraceExporter,
+    host_pressure: HostPressure,
+    config_status: ConfigStatus,
+    distributed_limiter: DistributedLimiter,
+    upstream_authorization: UpstreamAuthorization,
 }
 
 impl LfsServerContext {
@@ -96,8 +141,21 @@ impl LfsServerContext {
         logger: Logger,
         qps: Option<Qps>,
         bandwidth: Option<i64>,
+        host_pressure: HostPressure,
+        config_status: ConfigStatus,
+        distributed_limiter: DistributedLimiter,
+        audit_log: AuditLog,
+        routing_health: RoutingHealth,
     ) -> Result<Self, Error> {
-        let connector = HttpsConnector::new()
+        // Set up as by HttpsConnector::new(), but with the upstream connect timeout.
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        let http = ConnectTimeout::new(http, config_handle.clone());
+        let connector = SslConnector::builder(SslMethod::tls())
+            .and_then(|mut ssl| {
+                ssl.set_alpn_protos(b"\x02h2\x08http/1.1")?;
+                HttpsConnector::with_connector(http, ssl)
+            })
             .map_err(Error::from)
             .context(ErrorKind::HttpClientInitializationFailed)?;
         let client = Client::builder().build(connector);
@@ -120,6 +178,19 @@ impl LfsServerContext {
         Ok(LfsServerContext {
             inner: Arc::new(Mutex::new(inner)),
             will_exit,
+            upload_limiter: UploadLimiter::default(),
+            egress_limiter: EgressLimiter::default(),
+            transfer_limiter: TransferLimiter::default(),
+            hot_objects: HotObjects::default(),
+            popular_objects: PopularObjects::default(),
+            replicator: Replicator::default(),
+            audit_log,
+            routing_health,
+            trace_exporter: TraceExporter::default(),
+            host_pressure,
+            config_status,
+            distributed_limiter,
+            upstream_authorization: UpstreamAuthorization::default(),
         })
     }
 
@@ -161,11 +232,23 @@ impl LfsServerContext {
             }
         };
 
+        let config = config.for_repo(&repository);
+
+        for limit in config.repo_loadshedding_limits().iter() {
+            limit
+                .should_load_shed(ctx.fb, Some(ctx.metadata().identities()))
+                .map_err(LfsServerContextErrorKind::Throttled)?;
+        }
+
         let enforce_acl_check =
             repo.repo_config().enforce_lfs_acl_check && config.enforce_acl_check();
 
         acl_check(&ctx, &repo, enforce_acl_check, method).await?;
 
+        if !method.is_read_only() && !config.allows_upload(Some(ctx.metadata().identities())) {
+            return Err(LfsServerContextErrorKind::Forbidden);
+        }
+
         Ok(RepositoryRequestContext {
             ctx,
             repo,
@@ -180,6 +263,18 @@ impl LfsServerContext {
             always_wait_for_upstream,
             max_upload_size,
             bandwidth,
+            egress_limiter: self.egress_limiter.clone(),
+            transfer_limiter: self.transfer_limiter.clone(),
+            hot_objects: self.hot_objects.clone(),
+            popular_objects: self.popular_objects.clone(),
+            replicator: self.replicator.clone(),
+            audit_log: self.audit_log.clone(),
+            routing_health: self.routing_health.clone(),
+            host_pressure: self.host_pressure.clone(),
+            distributed_limiter: self.distributed_limiter.clone(),
+            upstream_authorization: self.upstream_authorization.clone(),
+            request_id: None,
+            trace: None,
         })
     }
 
@@ -208,6 +303,34 @@ impl LfsServerContext {
     pub fn will_exit(&self) -> bool {
         self.will_exit.load(Ordering::Relaxed)
     }
+
+    pub fn upload_limiter(&self) -> &UploadLimiter {
+        &self.upload_limiter
+    }
+
+    pub fn host_pressure(&self) -> &HostPressure {
+        &self.host_pressure
+    }
+
+    pub fn config_status(&self) -> &ConfigStatus {
+        &self.config_status
+    }
+
+    pub fn popular_objects(&self) -> &PopularObjects {
+        &self.popular_objects
+    }
+
+    pub fn routing_health(&self) -> &RoutingHealth {
+        &self.routing_health
+    }
+
+    pub fn trace_exporter(&self) -> &TraceExporter {
+        &self.trace_exporter
+    }
+
+    pub fn distributed_limiter(&self) -> &DistributedLimiter {
+        &self.distributed_limiter
+    }
 }
 #[cfg(fbcode_build)]
 pub fn get_bandwidth(logger: &Logger) -> Option<i64> {
@@ -249,7 +372,7 @@ async fn acl_check(
 
 #[derive(Clone)]
 enum HttpClient {
-    Enabled(Arc<HttpsHyperClient>),
+    Enabled(Arc<UpstreamClient>),
     #[cfg(test)]
     Disabled,
 }
@@ -264,6 +387,20 @@ pub struct RepositoryRequestContext {
     max_upload_size: Option<u64>,
     client: HttpClient,
     bandwidth: Option<i64>,
+    egress_limiter: EgressLimiter,
+    transfer_limiter: TransferLimiter,
+    hot_objects: HotObjects,
+    popular_objects: PopularObjects,
+    replicator: Replicator,
+    audit_log: AuditLog,
+    routing_health: RoutingHealth,
+    host_pressure: HostPressure,
+    distributed_limiter: DistributedLimiter,
+    upstream_authorization: UpstreamAuthorization,
+    /// Sent along with upstream requests, so they can be correlated with this one.
+    request_id: Option<String>,
+    /// Set if this request is traced. Blobstore and upstream calls are recorded as its spans.
+    trace: Option<RequestTrace>,
 }
 
 pub struct HttpClientResponse<S: Stream<Item = Result<Bytes, Error>> + Send + 'static> {
@@ -338,25 +475,119 @@ impl RepositoryRequestContext {
         let host = get_host_header(&headers)?;
 
         let lfs_ctx = LfsServerContext::borrow_from(state);
-        lfs_ctx.request(ctx, repository, host, method).await
+        let mut ctx = lfs_ctx
+            .request(ctx, repository.clone(), host, method)
+            .await?;
+        ctx.request_id = Some(request_id(state).to_string());
+        ctx.trace = RequestTrace::start(
+            lfs_ctx.trace_exporter(),
+            &ctx.config,
+            headers,
+            method,
+            &repository,
+        );
+
+        // The root span is finished by the TraceMiddleware, once the response is sent.
+        if let Some(trace) = &ctx.trace {
+            state.put(trace.clone());
+        }
+
+        if let Some(rate) = ctx.config.access_log_sample_rate() {
+            ScubaMiddlewareState::try_set_sampling_rate(state, rate);
+        }
+
+        Ok(ctx)
     }
 
     pub fn logger(&self) -> &Logger {
         self.ctx.logger()
     }
 
+    /// Rejects objects that the config denies, logging the attempt so it can be audited.
+    pub fn check_denied(&self, oid: &Sha256) -> Result<(), ErrorKind> {
+        if !self.config.is_denied(oid) {
+            return Ok(());
+        }
+
+        warn!(
+            self.logger(),
+            "Denied access to object {} for {:?} in {}",
+            oid,
+            self.ctx.metadata().identities(),
+            self.uri_builder.repository,
+        );
+
+        Err(ErrorKind::ObjectDenied(*oid))
+    }
+
     pub fn always_wait_for_upstream(&self) -> bool {
         self.always_wait_for_upstream
     }
 
+    /// The smaller of the server's --max-upload-size and the live config's max_object_size_bytes.
     pub fn max_upload_size(&self) -> Option<u64> {
-        self.max_upload_size
+        match (self.max_upload_size, self.config.max_object_size_bytes()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
     }
 
     pub fn bandwidth(&self) -> Option<i64> {
         self.bandwidth
     }
 
+    pub fn egress_limiter(&self) -> &EgressLimiter {
+        &self.egress_limiter
+    }
+
+    pub fn transfer_limiter(&self) -> &TransferLimiter {
+        &self.transfer_limiter
+    }
+
+    pub fn hot_objects(&self) -> &HotObjects {
+        &self.hot_objects
+    }
+
+    pub fn popular_objects(&self) -> &PopularObjects {
+        &self.popular_objects
+    }
+
+    pub fn replicator(&self) -> &Replicator {
+        &self.replicator
+    }
+
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit_log
+    }
+
+    pub fn routing_health(&self) -> &RoutingHealth {
+        &self.routing_health
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub fn distributed_limiter(&self) -> &DistributedLimiter {
+        &self.distributed_limiter
+    }
+
+    /// The repository's blobstore, with the config's blobstore timeouts. Calls are recorded as
+    /// spans if this request is traced, including those that time out.
+    pub fn blobstore(&self) -> TracedBlobstore<TimeoutBlobstore<RepoBlobstore>> {
+        TracedBlobstore::new(
+            TimeoutBlobstore::new(
+                self.repo.repo_blobstore().clone(),
+                self.config.blobstore_timeouts().call(),
+            ),
+            self.trace.clone(),
+        )
+    }
+
+    pub fn host_pressure(&self) -> &HostPressure {
+        &self.host_pressure
+    }
+
     pub async fn dispatch(
         &self,
         mut request: Request<Body>,
@@ -372,7 +603,45 @@ impl RepositoryRequestContext {
             header::USER_AGENT,
             header::HeaderValue::from_static(CLIENT_USER_AGENT),
         );
-        let res = client.request(request);
+        if let Some(request_id) = &self.request_id {
+            if let Ok(request_id) = header::HeaderValue::from_str(request_id) {
+                request.headers_mut().insert(X_REQUEST_ID, request_id);
+            }
+        }
+        // Only the upstream gets its credentials, not the other hosts its actions point at.
+        if let Some(upstream) = self.config.upstream() {
+            if let Some(authorization_file) = &upstream.authorization_file {
+                if request.uri().authority() == upstream.url.authority()
+                    && !request.headers().contains_key(header::AUTHORIZATION)
+                {
+                    let authorization = self
+                        .upstream_authorization
+                        .get(
+                            self.ctx.logger(),
+                            authorization_file,
+                            Instant::now().into_std(),
+                        )
+                        .await?;
+                    request
+                        .headers_mut()
+                        .insert(header::AUTHORIZATION, authorization);
+                }
+            }
+        }
+        let span = self.trace.as_ref().map(|trace| {
+            let mut span = trace.span("upstream", SpanKind::Client);
+            span.attribute("http.method", request.method());
+            span.attribute("http.url", request.uri());
+            span
+        });
+        if let Some(span) = &span {
+            if let Ok(traceparent) = header::HeaderValue::from_str(&span.traceparent()) {
+                request.headers_mut().insert(TRACEPARENT, traceparent);
+            }
+        }
+        let timeouts = self.config.upstream_timeouts();
+        let started = Instant::now();
+        let res = timeouts::upstream_response(client.request(request), timeouts.call());
 
         // NOTE: We spawn the request on an executor because we'd like to read the response even if
         // we drop the future returned here. The reason for that is that if we don't read a
@@ -380,9 +649,10 @@ impl RepositoryRequestContext {
         // general case: if your server is sending you 5GB of data and you drop the future, you
         // don't want to read all that later just to reuse a connection).
         let fut = async move {
-            let res = res.await.context(ErrorKind::UpstreamDidNotRespond)?;
+            let res = res.await?.context(ErrorKind::UpstreamDidNotRespond)?;
 
             let (head, body) = res.into_parts();
+            let body = timeouts::upstream_body(body.map_err(Error::from), started, timeouts.total);
 
             if !head.status.is_success() {
                 let body = body.try_concat_body(&head.headers)?.await?;
@@ -398,19 +668,40 @@ impl RepositoryRequestContext {
             // our own wrapper type that wraps the response and the headers.
             Ok(HttpClientResponse {
                 headers: head.headers,
-                body: Some(body.map_err(Error::from)),
+                body: Some(body),
                 handle: Handle::current(),
             })
         };
 
+        // The span ends when the response head arrives, or the request fails.
+        let fut = async move {
+            let res = fut.await;
+            if let Some(span) = span {
+                span.finish(res.as_ref().err().map(|err| format!("{:#}", err)));
+            }
+            res
+        };
+
         tokio::spawn(fut).await?
     }
 
+    /// The batch endpoint of the upstream this repository proxies to, preferring the config's
+    /// upstream over the server's.
+    fn upstream_batch_uri(&self) -> Result<Option<Uri>, ErrorKind> {
+        match self.config.upstream() {
+            Some(upstream) => parse_and_check_uri(&upstream.url.to_string())?
+                .build(format_args!("objects/batch"))
+                .map(Some)
+                .map_err(|e| ErrorKind::UriBuilderFailed("upstream_batch_uri", e)),
+            None => self.uri_builder.upstream_batch_uri(),
+        }
+    }
+
     pub async fn upstream_batch(
         &self,
         batch: &RequestBatch,
     ) -> Result<Option<ResponseBatch>, ErrorKind> {
-        let uri = match self.uri_builder.upstream_batch_uri()? {
+        let uri = match self.upstream_batch_uri()? {
             Some(uri) => uri,
             None => {
                 return Ok(None);
@@ -466,6 +757,15 @@ impl UriBuilder {
             .map_err(|e| ErrorKind::UriBuilderFailed("upload_uri", e))
     }
 
+    pub fn verify_uri(&self) -> Result<Uri, ErrorKind> {
+        self.pick_uri()?
+            .build(format_args!(
+                "{}/verify?server_hostname={}",
+                &self.repository, self.server_hostname,
+            ))
+            .map_err(|e| ErrorKind::UriBuilderFailed("verify_uri", e))
+    }
+
     pub fn download_uri(&self, content_id: &ContentId) -> Result<Uri, ErrorKind> {
         self.pick_uri()?
             .build(format_args!(
@@ -576,15 +876,21 @@ impl BaseUri {
 mod test {
     use std::str::FromStr;
 
+    use context::SessionContainer;
     use fbinit::FacebookInit;
     use lfs_protocol::Sha256 as LfsSha256;
+    use metadata::Metadata;
     use mononoke_types::hash::Sha256;
     use mononoke_types::ContentId;
+    use permission_checker::MononokeIdentitySet;
     use repo_permission_checker::AlwaysAllowRepoPermissionChecker;
     use repo_permission_checker::MockRepoPermissionChecker;
+    use scribe_ext::Scribe;
     use test_repo_factory::TestRepoFactory;
 
     use super::*;
+    use crate::config::Upstream;
+    use crate::distributed_limits::TimeWindowCounters;
 
     const ONES_HASH: &str = "1111111111111111111111111111111111111111111111111111111111111111";
     const TWOS_HASH: &str = "2222222222222222222222222222222222222222222222222222222222222222";
@@ -615,6 +921,7 @@ mod test {
         upstream_uri: Option<String>,
         config: ServerConfig,
         host: String,
+        identities: MononokeIdentitySet,
     }
 
     impl TestContextBuilder<'_> {
@@ -633,6 +940,11 @@ mod test {
             self
         }
 
+        pub fn identities(mut self, identities: MononokeIdentitySet) -> Self {
+            self.identities = identities;
+            self
+        }
+
         pub fn build(self) -> Result<RepositoryRequestContext, Error> {
             let Self {
                 fb,
@@ -641,12 +953,16 @@ mod test {
                 upstream_uri,
                 config,
                 host,
+                identities,
             } = self;
 
             let uri_builder = uri_builder(self_uris, upstream_uri.as_deref(), host)?;
+            let session = SessionContainer::builder(fb)
+                .metadata(Arc::new(Metadata::default().set_identities(identities)))
+                .build();
 
             Ok(RepositoryRequestContext {
-                ctx: CoreContext::test_mock(fb),
+                ctx: CoreContext::test_mock_session(session),
                 repo: Arc::new(repo),
                 config: Arc::new(config),
                 uri_builder,
@@ -654,6 +970,18 @@ mod test {
                 max_upload_size: None,
                 client: HttpClient::Disabled,
                 bandwidth: None,
+                egress_limiter: EgressLimiter::default(),
+                transfer_limiter: TransferLimiter::default(),
+                hot_objects: HotObjects::default(),
+                popular_objects: PopularObjects::default(),
+                replicator: Replicator::default(),
+                audit_log: AuditLog::new(Scribe::new(fb))?,
+                routing_health: RoutingHealth::new(Arc::new(TimeWindowCounters::new(fb))),
+                host_pressure: HostPressure::default(),
+                distributed_limiter: DistributedLimiter::new(Arc::new(TimeWindowCounters::new(fb))),
+                upstream_authorization: UpstreamAuthorization::default(),
+                request_id: None,
+                trace: None,
             })
         }
     }
@@ -676,6 +1004,7 @@ mod test {
                 upstream_uri: Some("http://bar.com".to_string()),
                 config: ServerConfig::default(),
                 host: "foo.com".to_string(),
+                identities: MononokeIdentitySet::new(),
             })
         }
     }
@@ -894,6 +1223,58 @@ mod test {
         Ok(())
     }
 
+    #[fbinit::test]
+    async fn test_config_upstream_batch_uri(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = RepositoryRequestContext::test_builder(fb).await?.build()?;
+        assert_eq!(
+            ctx.upstream_batch_uri()?.map(|uri| uri.to_string()),
+            Some("http://bar.com/objects/batch".to_string()),
+        );
+
+        let mut config = ServerConfig::default();
+        *config.upstream_mut() = Some(Upstream {
+            url: "http://baz.com/repo".parse()?,
+            authorization_file: None,
+        });
+        let ctx = RepositoryRequestContext::test_builder(fb)
+            .await?
+            .config(config)
+            .build()?;
+        assert_eq!(
+            ctx.upstream_batch_uri()?.map(|uri| uri.to_string()),
+            Some("http://baz.com/repo/objects/batch".to_string()),
+        );
+
+        // Repositories can override the upstream.
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.repos.insert(
+            "repo1".to_string(),
+            lfs_server_config::LfsRepoConfig {
+                upstream: Some(lfs_server_config::Upstream {
+                    url: "http://qux.com/repo1".to_string(),
+                    authorization_file: "".to_string(),
+                }),
+                ..Default::default()
+            },
+        );
+        let config = Arc::new(ServerConfig::try_from(raw)?);
+        for (repo, expected) in [
+            ("repo1", "http://qux.com/repo1/objects/batch"),
+            ("repo2", "http://bar.com/objects/batch"),
+        ] {
+            let ctx = RepositoryRequestContext::test_builder(fb)
+                .await?
+                .config(config.for_repo(repo).as_ref().clone())
+                .build()?;
+            assert_eq!(
+                ctx.upstream_batch_uri()?.map(|uri| uri.to_string()),
+                Some(expected.to_string()),
+            );
+        }
+
+        Ok(())
+    }
+
     #[fbinit::test]
     async fn test_acl_check_no_certificates(fb: FacebookInit) -> Result<(), Error> {
         let aclchecker = AlwaysAllowRepoPermissionChecker::new();
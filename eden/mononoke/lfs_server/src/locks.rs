@@ -0,0 +1,290 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! The Git LFS file locking API, for clients that lock binary files before editing them. Locks
+//! are advisory, and only enforced by clients, so pushes are not checked against them.
+
+use anyhow::Context;
+use anyhow::Error;
+use gotham::state::request_id;
+use gotham::state::FromState;
+use gotham::state::State;
+use gotham_derive::StateData;
+use gotham_derive::StaticResponseExtender;
+use gotham_ext::error::HttpError;
+use gotham_ext::response::BytesBody;
+use gotham_ext::response::TryIntoResponse;
+use hyper::Body;
+use hyper::Response;
+use hyper::StatusCode;
+use lfs_locks::CreateLockOutcome;
+use lfs_locks::LfsLock;
+use lfs_locks::LfsLocksRef;
+use lfs_protocol::git_lfs_mime;
+use lfs_protocol::Lock;
+use lfs_protocol::LockOwner;
+use lfs_protocol::RequestCreateLock;
+use lfs_protocol::RequestUnlock;
+use lfs_protocol::RequestVerifyLocks;
+use lfs_protocol::ResponseCreateLock;
+use lfs_protocol::ResponseListLocks;
+use lfs_protocol::ResponseLockConflict;
+use lfs_protocol::ResponseUnlock;
+use lfs_protocol::ResponseVerifyLocks;
+use mononoke_types::DateTime;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::errors::ErrorKind;
+use crate::lfs_server_context::RepositoryRequestContext;
+use crate::middleware::LfsMethod;
+use crate::util::read_request_body;
+
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct LocksParams {
+    repository: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ListLocksQueryString {
+    path: Option<String>,
+    id: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct UnlockParams {
+    repository: String,
+    id: String,
+}
+
+/// A JSON response with a status other than 200.
+struct LocksResponse {
+    status: StatusCode,
+    body: String,
+}
+
+impl LocksResponse {
+    fn new(status: StatusCode, body: &impl Serialize) -> Result<Self, HttpError> {
+        let body = serde_json::to_string(body).map_err(HttpError::e500)?;
+        Ok(Self { status, body })
+    }
+}
+
+impl TryIntoResponse for LocksResponse {
+    fn try_into_response(self, state: &mut State) -> Result<Response<Body>, Error> {
+        let mut res = BytesBody::new(self.body, git_lfs_mime()).try_into_response(state)?;
+        *res.status_mut() = self.status;
+        Ok(res)
+    }
+}
+
+async fn locks_context(
+    state: &mut State,
+    repository: String,
+    method: LfsMethod,
+) -> Result<RepositoryRequestContext, HttpError> {
+    let ctx = RepositoryRequestContext::instantiate(state, repository.clone(), method).await?;
+
+    if !ctx.config.enable_locks() {
+        return Err(HttpError::e404(ErrorKind::LocksDisabled(repository)));
+    }
+
+    Ok(ctx)
+}
+
+async fn read_request<T: DeserializeOwned>(
+    state: &mut State,
+    ctx: &RepositoryRequestContext,
+) -> Result<T, HttpError> {
+    let body = read_request_body(state, ctx.config.max_request_body_bytes()).await?;
+
+    serde_json::from_slice(&body)
+        .context(ErrorKind::InvalidLockRequest)
+        .map_err(HttpError::e400)
+}
+
+/// Locks belong to the user making the request, or to the service if there is no user.
+fn lock_owner(ctx: &RepositoryRequestContext) -> Result<String, HttpError> {
+    let metadata = ctx.ctx.metadata();
+
+    metadata
+        .unix_name()
+        .map(|name| name.to_string())
+        .or_else(|| metadata.identities().iter().next().map(|i| i.to_string()))
+        .ok_or_else(|| HttpError::e403(ErrorKind::LockOwnerUnknown))
+}
+
+fn parse_id(id: &str) -> Result<u64, HttpError> {
+    id.parse()
+        .map_err(|_| HttpError::e400(ErrorKind::InvalidLockId(id.to_string())))
+}
+
+fn to_protocol(lock: LfsLock) -> Lock {
+    Lock {
+        id: lock.id.to_string(),
+        path: lock.path,
+        locked_at: DateTime::from(lock.locked_at).as_chrono().to_rfc3339(),
+        owner: Some(LockOwner { name: lock.owner }),
+    }
+}
+
+/// Lists a page of locks, along with the cursor for the next page if there is one. Cursors are
+/// the id of the last lock returned.
+async fn list_page(
+    ctx: &RepositoryRequestContext,
+    cursor: Option<&str>,
+    limit: Option<u64>,
+) -> Result<(Vec<LfsLock>, Option<String>), HttpError> {
+    let after = match cursor {
+        Some(cursor) => cursor
+            .parse()
+            .map_err(|_| HttpError::e400(ErrorKind::InvalidLockCursor(cursor.to_string())))?,
+        None => 0,
+    };
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    // Fetch one more lock than requested to find out if there is another page.
+    let mut locks = ctx
+        .repo
+        .lfs_locks()
+        .list_locks(&ctx.ctx, after, limit + 1)
+        .await
+        .map_err(HttpError::e500)?;
+
+    let next_cursor = if locks.len() as u64 > limit {
+        locks.truncate(limit as usize);
+        locks.last().map(|lock| lock.id.to_string())
+    } else {
+        None
+    };
+
+    Ok((locks, next_cursor))
+}
+
+pub async fn create_lock(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let LocksParams { repository } = state.take();
+    let ctx = locks_context(state, repository, LfsMethod::Lock).await?;
+    let request: RequestCreateLock = read_request(state, &ctx).await?;
+    let owner = lock_owner(&ctx)?;
+
+    let outcome = ctx
+        .repo
+        .lfs_locks()
+        .create_lock(&ctx.ctx, &request.path, &owner)
+        .await
+        .map_err(HttpError::e500)?;
+
+    match outcome {
+        CreateLockOutcome::Created(lock) => LocksResponse::new(
+            StatusCode::CREATED,
+            &ResponseCreateLock {
+                lock: to_protocol(lock),
+            },
+        ),
+        CreateLockOutcome::AlreadyLocked(lock) => LocksResponse::new(
+            StatusCode::CONFLICT,
+            &ResponseLockConflict {
+                lock: to_protocol(lock),
+                message: "already created lock".to_string(),
+                request_id: Some(request_id(state).to_string()),
+            },
+        ),
+    }
+}
+
+pub async fn list_locks(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let LocksParams { repository } = state.take();
+    let query = ListLocksQueryString::take_from(state);
+    let ctx = locks_context(state, repository, LfsMethod::ListLocks).await?;
+    let locks = ctx.repo.lfs_locks();
+
+    let (locks, next_cursor) = if let Some(id) = query.id {
+        let lock = locks
+            .get_lock(&ctx.ctx, parse_id(&id)?)
+            .await
+            .map_err(HttpError::e500)?;
+        let lock = lock.filter(|lock| query.path.as_ref().map_or(true, |p| *p == lock.path));
+        (lock.into_iter().collect(), None)
+    } else if let Some(path) = query.path {
+        let lock = locks
+            .get_lock_by_path(&ctx.ctx, &path)
+            .await
+            .map_err(HttpError::e500)?;
+        (lock.into_iter().collect(), None)
+    } else {
+        list_page(&ctx, query.cursor.as_deref(), query.limit).await?
+    };
+
+    LocksResponse::new(
+        StatusCode::OK,
+        &ResponseListLocks {
+            locks: locks.into_iter().map(to_protocol).collect(),
+            next_cursor,
+        },
+    )
+}
+
+pub async fn verify_locks(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let LocksParams { repository } = state.take();
+    let ctx = locks_context(state, repository, LfsMethod::ListLocks).await?;
+    let request: RequestVerifyLocks = read_request(state, &ctx).await?;
+    let owner = lock_owner(&ctx)?;
+
+    let (locks, next_cursor) = list_page(&ctx, request.cursor.as_deref(), request.limit).await?;
+    let (ours, theirs): (Vec<_>, Vec<_>) = locks.into_iter().partition(|lock| lock.owner == owner);
+
+    LocksResponse::new(
+        StatusCode::OK,
+        &ResponseVerifyLocks {
+            ours: ours.into_iter().map(to_protocol).collect(),
+            theirs: theirs.into_iter().map(to_protocol).collect(),
+            next_cursor,
+        },
+    )
+}
+
+/// Releases a lock. Anyone allowed to take locks can break someone else's lock with `force`, as
+/// they would otherwise have no way to unblock themselves when a lock is left behind.
+pub async fn unlock(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let UnlockParams { repository, id } = state.take();
+    let ctx = locks_context(state, repository, LfsMethod::Lock).await?;
+    let request: RequestUnlock = read_request(state, &ctx).await?;
+    let owner = lock_owner(&ctx)?;
+    let id = parse_id(&id)?;
+    let locks = ctx.repo.lfs_locks();
+
+    let lock = locks
+        .get_lock(&ctx.ctx, id)
+        .await
+        .map_err(HttpError::e500)?
+        .ok_or_else(|| HttpError::e404(ErrorKind::LockDoesNotExist(id)))?;
+
+    if lock.owner != owner && !request.force {
+        return Err(HttpError::e403(ErrorKind::LockOwnedByOther(id, lock.owner)));
+    }
+
+    if !locks
+        .delete_lock(&ctx.ctx, id)
+        .await
+        .map_err(HttpError::e500)?
+    {
+        return Err(HttpError::e404(ErrorKind::LockDoesNotExist(id)));
+    }
+
+    LocksResponse::new(
+        StatusCode::OK,
+        &ResponseUnlock {
+            lock: to_protocol(lock),
+        },
+    )
+}
@@ -0,0 +1,243 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Sampling of the server's logs according to the `log_sample_rate` config, so that high-volume
+//! logs can be turned down in production and back up during incidents without a restart.
+
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use cached_config::ConfigHandle;
+use gotham::state::FromState;
+use gotham::state::State;
+use gotham_derive::StateData;
+use gotham_ext::middleware::LogMiddleware;
+use gotham_ext::middleware::Middleware;
+use hyper::Body;
+use hyper::Response;
+use hyper::Uri;
+use slog::Drain;
+use slog::OwnedKVList;
+use slog::Record;
+
+use crate::config::PipelineStage;
+use crate::config::ServerConfig;
+use crate::util::route_name;
+
+/// Levels go from 1 (critical) to 6 (trace).
+const LEVELS: usize = 7;
+
+/// Logs 1 in `rate` of the calls that share `counter`.
+fn sample(counter: &AtomicU64, rate: Option<NonZeroU64>) -> bool {
+    match rate {
+        Some(rate) => counter.fetch_add(1, Ordering::Relaxed) % rate.get() == 0,
+        None => true,
+    }
+}
+
+/// Drops log messages of the levels that are sampled.
+pub struct SampledDrain<D> {
+    inner: D,
+    config: ConfigHandle<ServerConfig>,
+    counters: [AtomicU64; LEVELS],
+}
+
+impl<D> SampledDrain<D> {
+    pub fn new(inner: D, config: ConfigHandle<ServerConfig>) -> Self {
+        Self {
+            inner,
+            config,
+            counters: Default::default(),
+        }
+    }
+}
+
+impl<D: Drain<Ok = ()>> Drain for SampledDrain<D> {
+    type Ok = ();
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), D::Err> {
+        let level = record.level();
+        let rate = self.config.get().log_sample_rate_for_level(level);
+
+        if sample(&self.counters[level.as_usize()], rate) {
+            self.inner.log(record, values)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn is_enabled(&self, level: slog::Level) -> bool {
+        self.inner.is_enabled(level)
+    }
+}
+
+/// Set on requests that are logged to the access log, so their response is logged too.
+#[derive(StateData)]
+struct AccessLogged;
+
+/// Only passes the requests to each route that are sampled on to the access log.
+#[derive(Clone)]
+pub struct SampledLogMiddleware {
+    inner: LogMiddleware,
+    config: ConfigHandle<ServerConfig>,
+    counters: Arc<Mutex<HashMap<&'static str, Arc<AtomicU64>>>>,
+}
+
+impl SampledLogMiddleware {
+    pub fn new(inner: LogMiddleware, config: ConfigHandle<ServerConfig>) -> Self {
+        Self {
+            inner,
+            config,
+            counters: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn should_log(&self, route: &'static str) -> bool {
+        let config = self.config.get();
+        if !config.has_middleware(PipelineStage::AccessLog) {
+            return false;
+        }
+
+        let rate = match config.log_sample_rate_for_route(route) {
+            Some(rate) => rate,
+            None => return true,
+        };
+
+        let counter = self
+            .counters
+            .lock()
+            .expect("poisoned lock")
+            .entry(route)
+            .or_default()
+            .clone();
+
+        sample(&counter, Some(rate))
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for SampledLogMiddleware {
+    async fn inbound(&self, state: &mut State) -> Option<Response<Body>> {
+        let route = Uri::try_borrow_from(state).map_or("other", |uri| route_name(uri.path()));
+
+        if self.should_log(route) {
+            state.put(AccessLogged);
+            self.inner.inbound(state).await
+        } else {
+            None
+        }
+    }
+
+    async fn outbound(&self, state: &mut State, response: &mut Response<Body>) {
+        if state.has::<AccessLogged>() {
+            self.inner.outbound(state, response).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::sync::atomic::AtomicUsize;
+
+    use anyhow::Error;
+    use maplit::btreemap;
+    use slog::info;
+    use slog::o;
+    use slog::warn;
+    use slog::Logger;
+    use slog::Never;
+
+    use super::*;
+
+    /// Counts the messages that reach it.
+    #[derive(Clone, Default)]
+    struct CountingDrain(Arc<AtomicUsize>);
+
+    impl Drain for CountingDrain {
+        type Ok = ();
+        type Err = Never;
+
+        fn log(&self, _record: &Record, _values: &OwnedKVList) -> Result<(), Never> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    fn config_handle(
+        by_level: BTreeMap<String, i64>,
+        by_route: BTreeMap<String, i64>,
+        middleware_pipeline: Vec<String>,
+    ) -> Result<ConfigHandle<ServerConfig>, Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.log_sample_rate = Some(lfs_server_config::LogSampleRate { by_level, by_route });
+        raw.middleware_pipeline = middleware_pipeline;
+        ServerConfig::test_handle(raw)
+    }
+
+    #[test]
+    fn test_sample() {
+        let counter = AtomicU64::new(0);
+        let logged = (0..100)
+            .filter(|_| sample(&counter, NonZeroU64::new(10)))
+            .count();
+        assert_eq!(logged, 10);
+        assert!(sample(&counter, None));
+    }
+
+    #[test]
+    fn test_sampled_drain() -> Result<(), Error> {
+        let config = config_handle(
+            btreemap! { "info".to_string() => 10 },
+            BTreeMap::new(),
+            vec![],
+        )?;
+        let drain = CountingDrain::default();
+        let logger = Logger::root(SampledDrain::new(drain.clone(), config), o!());
+
+        for _ in 0..100 {
+            info!(logger, "sampled");
+        }
+        assert_eq!(drain.0.load(Ordering::Relaxed), 10);
+
+        // Levels without a rate are all logged.
+        for _ in 0..100 {
+            warn!(logger, "not sampled");
+        }
+        assert_eq!(drain.0.load(Ordering::Relaxed), 110);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_access_log_sampling() -> Result<(), Error> {
+        let config = config_handle(
+            BTreeMap::new(),
+            btreemap! { "download".to_string() => 10 },
+            vec![],
+        )?;
+        let middleware = SampledLogMiddleware::new(LogMiddleware::test_friendly(), config);
+
+        let logged = (0..100)
+            .filter(|_| middleware.should_log("download"))
+            .count();
+        assert_eq!(logged, 10);
+        assert!((0..100).all(|_| middleware.should_log("upload")));
+
+        // Nothing is logged if the access log is left out of the pipeline.
+        let config = config_handle(BTreeMap::new(), BTreeMap::new(), vec!["qps".to_string()])?;
+        let middleware = SampledLogMiddleware::new(LogMiddleware::test_friendly(), config);
+        assert!(!middleware.should_log("upload"));
+
+        Ok(())
+    }
+}
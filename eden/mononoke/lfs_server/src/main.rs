@@ -9,9 +9,14 @@
 #![feature(never_type)]
 #![feature(let_chains)]
 
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::Write;
 use std::net::ToSocketAddrs;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -22,14 +27,17 @@ use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 use cached_config::ConfigHandle;
+use cached_config::ConfigStore;
 use clap::Parser;
 use clientinfo::ClientEntryPoint;
 use cloned::cloned;
 use cmdlib_caching::CachelibSettings;
+use cmdlib_logging::ScribeLoggingArgs;
 use connection_security_checker::ConnectionSecurityChecker;
 use fbinit::FacebookInit;
 use filestore::FilestoreConfig;
 use futures::channel::oneshot;
+use futures::future;
 use futures::future::try_select;
 use futures::pin_mut;
 use futures::TryFutureExt;
@@ -44,6 +52,8 @@ use gotham_ext::middleware::TimerMiddleware;
 use gotham_ext::middleware::TlsSessionDataMiddleware;
 use gotham_ext::serve;
 use hyper::header::HeaderValue;
+use lfs_locks::LfsLocks;
+use lfs_quotas::LfsQuotas;
 use metaconfig_types::RepoConfig;
 use metaconfig_types::ShardedService;
 use mononoke_app::args::parse_config_spec_to_path;
@@ -61,27 +71,60 @@ use repo_blobstore::RepoBlobstore;
 use repo_identity::RepoIdentity;
 use repo_permission_checker::RepoPermissionChecker;
 use slog::info;
+use slog::o;
+use slog::warn;
+use slog::Logger;
 use tokio::net::TcpListener;
-
+use tokio::net::UnixListener;
+
+use crate::audit_log::AuditLog;
+use crate::config::ServerConfig;
+use crate::config_status::ConfigStatus;
+use crate::distributed_limits::DistributedLimiter;
+use crate::distributed_limits::SharedCounters;
+use crate::distributed_limits::TimeWindowCounters;
+use crate::host_pressure::HostPressure;
 use crate::lfs_server_context::get_bandwidth;
 use crate::lfs_server_context::LfsServerContext;
 use crate::lfs_server_context::ServerUris;
+use crate::log_sampling::SampledDrain;
+use crate::log_sampling::SampledLogMiddleware;
+use crate::middleware::CorsMiddleware;
 use crate::middleware::OdsMiddleware;
 use crate::middleware::RequestContextMiddleware;
+use crate::middleware::RetryAfterMiddleware;
+use crate::middleware::TraceMiddleware;
+use crate::routing_health::RoutingHealth;
 use crate::scuba::LfsScubaHandler;
 use crate::service::build_router;
 
+mod audit_log;
 mod batch;
 mod config;
+mod config_status;
+mod distributed_limits;
 mod download;
+mod egress;
 mod errors;
 mod git_upload;
+mod host_pressure;
 mod lfs_server_context;
+mod locks;
+mod log_sampling;
 mod middleware;
 mod popularity;
+mod quotas;
+mod replication;
+mod resumable_upload;
+mod routing_health;
 mod scuba;
 mod service;
+mod slow_read_retry;
+mod timeouts;
+mod trace_export;
+mod transfer_limiter;
 mod upload;
+mod upstream_authorization;
 mod util;
 
 const SERVICE_NAME: &str = "mononoke_lfs_server";
@@ -111,6 +154,12 @@ pub struct Repo {
 
     #[facet]
     repo_permission_checker: dyn RepoPermissionChecker,
+
+    #[facet]
+    lfs_locks: dyn LfsLocks,
+
+    #[facet]
+    lfs_quotas: dyn LfsQuotas,
 }
 
 /// Mononoke LFS Server
@@ -131,6 +180,19 @@ struct LfsServerArgs {
     /// Path for file in which to write the bound tcp address in rust std::net::SocketAddr format
     #[clap(long)]
     bound_address_file: Option<String>,
+    /// Also serve plain HTTP on a unix domain socket at this path, for a local proxy that
+    /// terminates TLS and authenticates clients. Only peers running as one of
+    /// --unix-socket-trusted-uid may assert client identities in headers, so anyone who can run
+    /// as those users can act as any client. Other peers are served without identities.
+    #[clap(long)]
+    listen_unix_socket: Option<PathBuf>,
+    /// Permissions of the unix socket, in octal, which limit who can connect to it.
+    #[clap(long, default_value = "600", value_parser = parse_socket_mode)]
+    unix_socket_mode: u32,
+    /// Users whose connections to the unix socket are trusted as coming from the proxy. May be
+    /// repeated.
+    #[clap(long)]
+    unix_socket_trusted_uid: Vec<u32>,
     /// The base URLs for this server
     #[clap(value_delimiter = ',')]
     self_urls: Vec<String>,
@@ -140,9 +202,14 @@ struct LfsServerArgs {
     /// Whether to always wait for an upstream response (primarily useful in testing)
     #[clap(long)]
     always_wait_for_upstream: bool,
-    /// Path to config in configerator
+    /// Comma-separated config sources, tried in order until one loads. Each is either a path to
+    /// config in configerator, optionally prefixed with "configerator:", "file:<path>" for a JSON
+    /// file read once at startup, or "default" for the built-in defaults.
     #[clap(long)]
     live_config: Option<String>,
+    /// Load and validate the live config, print it, and exit without serving
+    #[clap(long, requires = "live_config")]
+    check_config: bool,
     /// Whether or not to use test-friendly logging
     #[clap(long)]
     test_friendly_logging: bool,
@@ -154,11 +221,18 @@ struct LfsServerArgs {
     /// Whether to enable Mononoke-specific small git blob uploads
     #[clap(long)]
     git_blob_upload_allowed: bool,
+    /// Inject the faults in the live config's fault_injection section into requests. Only use
+    /// this for resilience testing: without it, that section is ignored.
+    #[clap(long)]
+    unsafe_allow_fault_injection: bool,
     /// A limit (in bytes) to enforce for uploads.
     #[clap(long)]
     max_upload_size: Option<u64>,
     #[clap(flatten)]
     readonly: ReadonlyArgs,
+    /// Where uploads are recorded when the live config's audit_log sends them to scribe
+    #[clap(flatten)]
+    scribe_logging_args: ScribeLoggingArgs,
     /// Path to config
     #[clap(long)]
     cslb_config: Option<String>,
@@ -183,6 +257,93 @@ impl LfsRepos {
     }
 }
 
+fn parse_socket_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .with_context(|| format!("Invalid socket mode: {}", mode))
+}
+
+/// Binds a unix socket at `path` with permissions `mode`, replacing the socket a previous run
+/// may have left behind. A socket that still accepts connections is in use, e.g. by another
+/// server, so it is left alone.
+fn bind_unix_socket(path: &Path, mode: u32) -> Result<UnixListener> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("Not replacing {}, which is not a socket", path.display());
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!(
+                "Not replacing {}, which is still accepting connections",
+                path.display()
+            );
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Could not remove stale socket {}", path.display()))?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Could not start unix socket listener at {}", path.display()))?;
+    // Connections made before this are only served once the server starts accepting them, and
+    // whether they are trusted doesn't depend on these permissions.
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Could not set the permissions of {}", path.display()))?;
+
+    Ok(listener)
+}
+
+/// Loads the live config from the first of the comma-separated `specs` that loads, so that e.g.
+/// `configerator:spec,file:/etc/lfs.json,default` falls back to a local file and then to defaults
+/// when Configerator is unavailable. Only Configerator sources pick up later changes.
+fn load_config_handle(
+    logger: &Logger,
+    config_store: &ConfigStore,
+    config_status: &ConfigStatus,
+    specs: &str,
+) -> Result<ConfigHandle<ServerConfig>> {
+    let mut errors = Vec::new();
+
+    for spec in specs.split(',') {
+        let handle = if spec == "default" {
+            Ok(ConfigHandle::default())
+        } else if let Some(path) = spec.strip_prefix("file:") {
+            std::fs::read_to_string(path)
+                .map_err(Error::from)
+                .and_then(|json| ConfigHandle::from_json(&json))
+        } else {
+            parse_config_spec_to_path(spec)
+                .and_then(|path| config_store.get_config_handle_DEPRECATED(path))
+                .map(|handle| {
+                    // Only Configerator sources can be reloaded on demand.
+                    config_status.set_config_store(config_store.clone());
+                    handle
+                })
+        };
+
+        match handle {
+            Ok(handle) => {
+                info!(logger, "Using live config source {}", spec);
+                config_status.set_source(spec);
+                return Ok(handle);
+            }
+            Err(e) => {
+                warn!(
+                    logger,
+                    "Could not load live config source {}: {:#}", spec, e
+                );
+                let error = format!("{}: {:#}", spec, e);
+                config_status.set_error(error.clone());
+                errors.push(error);
+            }
+        }
+    }
+
+    bail!(
+        "No live config source could be loaded ({})",
+        errors.join("; ")
+    )
+}
+
 #[fbinit::main]
 fn main(fb: FacebookInit) -> Result<(), Error> {
     let cachelib_settings = CachelibSettings {
@@ -205,8 +366,16 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
     let listen_host = args.listen_host.clone();
     let listen_port = args.listen_port.clone();
     let bound_addr_path = args.bound_address_file.clone();
+    let listen_unix_socket = args.listen_unix_socket.clone();
+    let unix_socket_mode = args.unix_socket_mode;
+    let unix_socket_trusted_uids = args
+        .unix_socket_trusted_uid
+        .iter()
+        .copied()
+        .collect::<HashSet<_>>();
 
     let git_blob_upload_allowed = args.git_blob_upload_allowed;
+    let allow_fault_injection = args.unsafe_allow_fault_injection;
 
     let addr = format!("{}:{}", listen_host, listen_port);
 
@@ -234,15 +403,32 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
 
     let scuba_logger = app.environment().scuba_sample_builder.clone();
 
+    let audit_log = AuditLog::new(args.scribe_logging_args.get_scribe(fb)?)?;
+
     let will_exit = Arc::new(AtomicBool::new(false));
+    let host_pressure = HostPressure::default();
+    let config_status = ConfigStatus::default();
 
     let config_handle = match &args.live_config {
-        Some(spec) => config_store.get_config_handle_DEPRECATED(parse_config_spec_to_path(spec)?),
-        None => Ok(ConfigHandle::default()),
+        Some(specs) => load_config_handle(&logger, config_store, &config_status, specs),
+        None => {
+            config_status.set_source("default");
+            Ok(ConfigHandle::default())
+        }
     };
 
     let config_handle = config_handle.context(Error::msg("Failed to load configuration"))?;
 
+    if args.check_config {
+        let config = config_handle.get();
+        config.validate().context("Invalid configuration")?;
+        println!("{}", serde_json::to_string_pretty(&*config)?);
+        return Ok(());
+    }
+
+    // Messages logged before the config is loaded can't be sampled.
+    let logger = Logger::root(SampledDrain::new(logger, config_handle.clone()), o!());
+
     let cslb_config = args.cslb_config;
 
     let qps = match cslb_config {
@@ -253,6 +439,13 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
     };
     let max_upload_size: Option<u64> = args.max_upload_size;
 
+    if allow_fault_injection {
+        warn!(
+            logger,
+            "Fault injection is allowed: requests will fail or be delayed as configured"
+        );
+    }
+
     let self_urls = args.self_urls;
     let upstream_url = args.upstream_url;
     let always_wait_for_upstream = args.always_wait_for_upstream;
@@ -261,6 +454,7 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
     } else {
         LogMiddleware::slog(logger.clone())
     };
+    let log_middleware = SampledLogMiddleware::new(log_middleware, config_handle.clone());
 
     app.start_monitoring(SERVICE_NAME, AliveService)?;
     app.start_stats_aggregation()?;
@@ -269,7 +463,15 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
     let internal_identity = common.internal_identity.clone();
     let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
     let server = {
-        cloned!(acl_provider, common, logger, will_exit);
+        cloned!(
+            acl_provider,
+            common,
+            logger,
+            will_exit,
+            host_pressure,
+            config_status,
+            audit_log
+        );
         move |app| async move {
             let repos = LfsRepos::new(&app)
                 .await
@@ -289,6 +491,11 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
             // because the certs user passed will be referencing listen_host
             let bound_addr = format!("{}:{}", listen_host, listener.local_addr()?.port());
 
+            let unix_listener = listen_unix_socket
+                .as_deref()
+                .map(|path| bind_unix_socket(path, unix_socket_mode))
+                .transpose()?;
+
             // For tests we use one empty string self_url, map it to None
             let self_urls =
                 if self_urls.is_empty() || (self_urls.len() == 1 && self_urls[0].is_empty()) {
@@ -311,6 +518,7 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
             let server_uris = ServerUris::new(self_urls, upstream_url)?;
 
             let bandwidth = get_bandwidth(&logger);
+            let shared_counters: Arc<dyn SharedCounters> = Arc::new(TimeWindowCounters::new(fb));
 
             let ctx = LfsServerContext::new(
                 repos,
@@ -322,10 +530,20 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
                 logger.clone(),
                 qps,
                 bandwidth,
+                host_pressure,
+                config_status,
+                DistributedLimiter::new(shared_counters.clone()),
+                audit_log,
+                RoutingHealth::new(shared_counters),
             )?;
             let enforce_authentication = ctx.get_config().enforce_authentication();
+            ctx.host_pressure().spawn_event_loop_monitor();
+            ctx.config_status().spawn_monitor(fb, config_handle.clone());
+            ctx.popular_objects().spawn_logger(config_handle.clone(), logger.clone());
+            ctx.routing_health().spawn_monitor(fb, config_handle.clone(), logger.clone());
+            ctx.trace_exporter().spawn_exporter(config_handle.clone(), logger.clone())?;
 
-            let router = build_router(fb, ctx, git_blob_upload_allowed);
+            let router = build_router(fb, ctx, git_blob_upload_allowed, allow_fault_injection);
 
             let capture_session_data = tls_session_data_log.is_some();
 
@@ -337,6 +555,8 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
                     internal_identity,
                     ClientEntryPoint::LfsServer,
                 ))
+                .add(CorsMiddleware::new(config_handle.clone()))
+                .add(RetryAfterMiddleware::new(config_handle.clone()))
                 .add(PostResponseMiddleware::with_config(config_handle))
                 .add(RequestContextMiddleware::new(
                     fb,
@@ -351,10 +571,14 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
                 )))
                 .add(<ScubaMiddleware<LfsScubaHandler>>::new(scuba_logger))
                 .add(OdsMiddleware::new())
+                .add(TraceMiddleware::new())
                 .add(TimerMiddleware::new())
                 .build(router);
 
             info!(&logger, "Listening on {}", bound_addr);
+            if let Some(path) = &listen_unix_socket {
+                info!(&logger, "Listening on unix socket {}", path.display());
+            }
 
             // Write out the bound address if requested, this is helpful in tests when using automatic binding with :0
             if let Some(bound_addr_path) = bound_addr_path {
@@ -363,6 +587,18 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
                 writer.write_all(b"\n")?;
             }
 
+            let serve_unix = {
+                cloned!(logger, handler);
+                async move {
+                    match unix_listener {
+                        Some(listener) => {
+                            serve::unix(logger, listener, unix_socket_trusted_uids, handler).await
+                        }
+                        None => future::pending().await,
+                    }
+                }
+            };
+
             let serve = async move {
                 if let Some(tls_acceptor) = tls_acceptor {
                     let connection_security_checker =
@@ -381,6 +617,7 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
                     serve::http(logger, listener, handler).await
                 }
             };
+            let serve = future::try_join(serve, serve_unix).map_ok(|_| ());
             pin_mut!(serve);
             try_select(
                 serve,
@@ -396,13 +633,21 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
         server,
         move || will_exit.store(true, Ordering::Relaxed),
         args.shutdown_timeout_args.shutdown_grace_period,
-        async move {
-            let _ = shutdown_tx.send(());
-            // Currently we kill off in-flight requests as soon as we've closed the listener.
-            // If this is a problem in prod, this would be the point at which to wait
-            // for all connections to shut down.
-            // To do this properly, we'd need to track the `Connection` futures that Gotham
-            // gets from Hyper, tell them to gracefully shutdown, then wait for them to complete
+        {
+            cloned!(logger);
+            async move {
+                // Stop accepting connections, then let requests in flight finish. Their
+                // connections keep running until the runtime is torn down, which happens once
+                // they are done or --shutdown-timeout expires.
+                let _ = shutdown_tx.send(());
+                info!(
+                    &logger,
+                    "Waiting for {} requests to complete",
+                    host_pressure.in_flight_requests()
+                );
+                host_pressure.wait_for_idle().await;
+                audit_log.flush().await;
+            }
         },
         args.shutdown_timeout_args.shutdown_timeout,
     )?;
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use cached_config::ConfigHandle;
+use gotham::state::FromState;
+use gotham::state::State;
+use gotham_ext::middleware::Middleware;
+use http::header::HeaderMap;
+use http::header::HeaderValue;
+use http::header::ACCESS_CONTROL_ALLOW_HEADERS;
+use http::header::ACCESS_CONTROL_ALLOW_METHODS;
+use http::header::ACCESS_CONTROL_ALLOW_ORIGIN;
+use http::header::ACCESS_CONTROL_MAX_AGE;
+use http::header::ACCESS_CONTROL_REQUEST_METHOD;
+use http::header::ORIGIN;
+use http::header::VARY;
+use hyper::Body;
+use hyper::Method;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::Uri;
+
+use crate::config::ServerConfig;
+use crate::util::route_name;
+
+/// Git LFS requests use their own media type, which browsers only send cross-origin if it is
+/// allowed, so it is allowed on top of the configured headers.
+const ALWAYS_ALLOWED_HEADERS: &str = "content-type";
+
+/// The methods that cross-origin requests may use on each route.
+fn allowed_methods(route: &str) -> Option<&'static str> {
+    match route {
+        "batch" | "verify" => Some("POST"),
+        "locks" => Some("GET, POST"),
+        "download_sha256" => Some("GET, HEAD"),
+        "download" | "config" | "health" => Some("GET"),
+        "upload" | "git_blob_upload" => Some("PUT"),
+        _ => None,
+    }
+}
+
+/// The origin the request was sent from, if it sent one and it is allowed.
+fn allowed_origin(state: &State, config: &ServerConfig) -> Option<HeaderValue> {
+    let cors = config.cors()?;
+    let origin = HeaderMap::try_borrow_from(state)?.get(ORIGIN)?;
+
+    if cors.allows_origin(origin.to_str().ok()?) {
+        Some(origin.clone())
+    } else {
+        None
+    }
+}
+
+/// Answers CORS preflight requests, and allows browsers to read the responses to cross-origin
+/// requests from the origins in the config.
+pub struct CorsMiddleware {
+    config: ConfigHandle<ServerConfig>,
+}
+
+impl CorsMiddleware {
+    pub fn new(config: ConfigHandle<ServerConfig>) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for CorsMiddleware {
+    async fn inbound(&self, state: &mut State) -> Option<Response<Body>> {
+        if *Method::borrow_from(state) != Method::OPTIONS {
+            return None;
+        }
+
+        let headers = HeaderMap::try_borrow_from(state)?;
+        if !headers.contains_key(ORIGIN) || !headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD) {
+            return None;
+        }
+
+        let config = self.config.get();
+        let cors = config.cors()?;
+
+        // Preflights that aren't allowed get a response without CORS headers, which browsers
+        // treat as a refusal.
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .expect("Couldn't build http response");
+
+        let methods = allowed_methods(route_name(Uri::borrow_from(state).path()));
+        if let (Some(methods), Some(_)) = (methods, allowed_origin(state, &config)) {
+            let headers = response.headers_mut();
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_METHODS,
+                HeaderValue::from_static(methods),
+            );
+
+            let allowed_headers = cors
+                .allowed_headers
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::once(ALWAYS_ALLOWED_HEADERS))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if let Ok(allowed_headers) = HeaderValue::from_str(&allowed_headers) {
+                headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+            }
+
+            if let Some(max_age_secs) = cors.max_age_secs {
+                headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age_secs));
+            }
+        }
+
+        Some(response)
+    }
+
+    async fn outbound(&self, state: &mut State, response: &mut Response<Body>) {
+        let config = self.config.get();
+        if config.cors().is_none() {
+            return;
+        }
+
+        let headers = response.headers_mut();
+
+        // The response depends on the origin, so caches must not serve it to other origins.
+        headers.append(VARY, HeaderValue::from_static("origin"));
+
+        if let Some(origin) = allowed_origin(state, &config) {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allowed_methods() {
+        assert_eq!(allowed_methods("batch"), Some("POST"));
+        assert_eq!(allowed_methods("upload"), Some("PUT"));
+        assert_eq!(allowed_methods("locks"), Some("GET, POST"));
+        assert_eq!(allowed_methods("download_sha256"), Some("GET, HEAD"));
+        assert_eq!(allowed_methods("other"), None);
+    }
+}
@@ -5,10 +5,16 @@
  * GNU General Public License version 2.
  */
 
+mod cors;
 mod ods;
 mod request_context;
+mod retry_after;
+mod trace;
 
+pub use self::cors::CorsMiddleware;
 pub use self::ods::OdsMiddleware;
 pub use self::request_context::LfsMethod;
 pub use self::request_context::RequestContext;
 pub use self::request_context::RequestContextMiddleware;
+pub use self::retry_after::RetryAfterMiddleware;
+pub use self::trace::TraceMiddleware;
@@ -34,6 +34,9 @@ define_stats! {
     upload_duration: dynamic_histogram("{}.upload_ms", (repo: String); 100, 0, 5000, Average, Sum, Count; P 5; P 25; P 50; P 75; P 95; P 97; P 99),
     download_duration: dynamic_histogram("{}.download_ms", (repo: String); 100, 0, 5000, Average, Sum, Count; P 5; P 25; P 50; P 75; P 95; P 97; P 99),
     download_sha256_duration: dynamic_histogram("{}.download_sha256_ms", (repo: String); 100, 0, 5000, Average, Sum, Count; P 5; P 25; P 50; P 75; P 95; P 97; P 99),
+    exists_duration: dynamic_histogram("{}.exists_ms", (repo: String); 10, 0, 500, Average, Sum, Count; P 5; P 25; P 50; P 75; P 95; P 97; P 99),
+    verify_duration: dynamic_histogram("{}.verify_ms", (repo: String); 10, 0, 500, Average, Sum, Count; P 5; P 25; P 50; P 75; P 95; P 97; P 99),
+    locks_duration: dynamic_histogram("{}.locks_ms", (repo: String); 10, 0, 500, Average, Sum, Count; P 5; P 25; P 50; P 75; P 95; P 97; P 99),
     batch_duration: dynamic_histogram("{}.batch_ms", (repo: String); 10, 0, 500, Average, Sum, Count; P 5; P 25; P 50; P 75; P 95; P 97; P 99),
     response_bytes_sent: dynamic_histogram("{}.response_bytes_sent", (repo_and_method: String); 1_500_000, 0, 150_000_000, Average, Sum, Count; P 5; P 25; P 50; P 75; P 95; P 97; P 99),
 }
@@ -72,9 +75,18 @@ fn log_stats(state: &mut State, status: StatusCode) -> Option<()> {
                     .add_value(duration.as_millis_unchecked() as i64, (repo,)),
                 LfsMethod::DownloadSha256 => STATS::download_sha256_duration
                     .add_value(duration.as_millis_unchecked() as i64, (repo,)),
+                LfsMethod::Exists => {
+                    STATS::exists_duration.add_value(duration.as_millis_unchecked() as i64, (repo,))
+                }
                 LfsMethod::Batch => {
                     STATS::batch_duration.add_value(duration.as_millis_unchecked() as i64, (repo,))
                 }
+                LfsMethod::Verify => {
+                    STATS::verify_duration.add_value(duration.as_millis_unchecked() as i64, (repo,))
+                }
+                LfsMethod::Lock | LfsMethod::ListLocks => {
+                    STATS::locks_duration.add_value(duration.as_millis_unchecked() as i64, (repo,))
+                }
                 LfsMethod::GitBlob => STATS::git_upload_blob_duration
                     .add_value(duration.as_millis_unchecked() as i64, (repo,)),
             }
@@ -28,12 +28,19 @@ use slog::error;
 use slog::o;
 use slog::Logger;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub enum LfsMethod {
     Upload,
     Download,
     DownloadSha256,
+    /// Checking whether an object exists, without downloading it.
+    Exists,
     Batch,
+    Verify,
+    /// Creating and releasing file locks.
+    Lock,
+    /// Listing and verifying file locks.
+    ListLocks,
     // Methods below this are for pushing git objects, not for LFS
     // They do not correspond to any LFS protocol
     GitBlob,
@@ -45,7 +52,11 @@ impl fmt::Display for LfsMethod {
             Self::Upload => "upload",
             Self::Download => "download",
             Self::DownloadSha256 => "download_sha256",
+            Self::Exists => "exists",
             Self::Batch => "batch",
+            Self::Verify => "verify",
+            Self::Lock => "lock",
+            Self::ListLocks => "list_locks",
             Self::GitBlob => "git_blob_upload",
         };
         write!(f, "{}", name)
@@ -55,8 +66,13 @@ impl fmt::Display for LfsMethod {
 impl LfsMethod {
     pub fn is_read_only(&self) -> bool {
         match self {
-            Self::Download | Self::DownloadSha256 | Self::Batch => true,
-            Self::Upload | Self::GitBlob => false,
+            Self::Download
+            | Self::DownloadSha256
+            | Self::Exists
+            | Self::Batch
+            | Self::Verify
+            | Self::ListLocks => true,
+            Self::Upload | Self::Lock | Self::GitBlob => false,
         }
     }
 }
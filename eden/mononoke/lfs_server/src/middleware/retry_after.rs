@@ -0,0 +1,107 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use cached_config::ConfigHandle;
+use gotham::state::State;
+use gotham_ext::middleware::Middleware;
+use http::header::HeaderValue;
+use http::header::RETRY_AFTER;
+use hyper::Body;
+use hyper::Response;
+use hyper::StatusCode;
+
+use crate::config::ServerConfig;
+
+/// Tells throttled and overloaded clients when to retry, whichever of the server's limits they
+/// hit. This is the only place that sets `Retry-After`, so that the config decides it everywhere.
+pub struct RetryAfterMiddleware {
+    config: ConfigHandle<ServerConfig>,
+}
+
+impl RetryAfterMiddleware {
+    pub fn new(config: ConfigHandle<ServerConfig>) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RetryAfterMiddleware {
+    async fn outbound(&self, _state: &mut State, response: &mut Response<Body>) {
+        match response.status() {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {}
+            _ => return,
+        }
+
+        let retry_after = HeaderValue::from(self.config.get().retry_after_secs());
+        response.headers_mut().insert(RETRY_AFTER, retry_after);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Error;
+    use gotham::state::FromState;
+    use gotham::test::TestServer;
+    use gotham_ext::handler::MononokeHttpHandler;
+    use hyper::Uri;
+
+    use super::*;
+
+    /// Responds with the status in the path, e.g. /429.
+    fn status_handler(state: State) -> (State, Response<Body>) {
+        let status = Uri::borrow_from(&state)
+            .path()
+            .trim_start_matches('/')
+            .parse::<u16>()
+            .expect("status in path");
+        let res = Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .expect("valid response");
+        (state, res)
+    }
+
+    /// The Retry-After header of a response with `status`, if it has one.
+    fn retry_after(
+        config: ConfigHandle<ServerConfig>,
+        status: u16,
+    ) -> Result<Option<String>, Error> {
+        let handler = MononokeHttpHandler::builder()
+            .add(RetryAfterMiddleware::new(config))
+            .build(status_handler);
+        let res = TestServer::new(handler)?
+            .client()
+            .get(format!("http://host/{}", status))
+            .perform()?;
+        assert_eq!(res.status().as_u16(), status);
+
+        Ok(res
+            .headers()
+            .get(RETRY_AFTER)
+            .map(|v| v.to_str().unwrap().to_string()))
+    }
+
+    #[test]
+    fn test_retry_after() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.retry_after_secs = 30;
+        let config = ServerConfig::test_handle(raw)?;
+
+        assert_eq!(retry_after(config.clone(), 429)?, Some("30".to_string()));
+        assert_eq!(retry_after(config.clone(), 503)?, Some("30".to_string()));
+        assert_eq!(retry_after(config.clone(), 200)?, None);
+        assert_eq!(retry_after(config, 500)?, None);
+
+        // Clients are told to wait a second by default.
+        assert_eq!(
+            retry_after(ConfigHandle::default(), 429)?,
+            Some("1".to_string())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use gotham::state::State;
+use gotham_ext::middleware::Middleware;
+use gotham_ext::middleware::PostResponseCallbacks;
+use hyper::Body;
+use hyper::Response;
+
+use crate::trace_export::RequestTrace;
+
+/// Ends the root span of traced requests once their response has been sent, so that it covers
+/// streaming the body too.
+pub struct TraceMiddleware {}
+
+impl TraceMiddleware {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for TraceMiddleware {
+    async fn outbound(&self, state: &mut State, response: &mut Response<Body>) {
+        let trace = match state.try_take::<RequestTrace>() {
+            Some(trace) => trace,
+            None => return,
+        };
+        let status = response.status();
+
+        if let Some(callbacks) = state.try_borrow_mut::<PostResponseCallbacks>() {
+            callbacks.add(move |info| {
+                let error = info.first_error().map(|err| format!("{:#}", err));
+                trace.finish(info.duration.unwrap_or_default(), status, error);
+            });
+        }
+    }
+}
@@ -5,12 +5,25 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::num::NonZeroU16;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Error;
+use cached_config::ConfigHandle;
 use fbinit::FacebookInit;
+use gotham_derive::StateData;
+use gotham_derive::StaticResponseExtender;
+use mononoke_types::hash::Sha256;
+use serde::Deserialize;
+use serde::Serialize;
 use slog::error;
+use slog::info;
+use slog::Logger;
 use stats::prelude::*;
 use time_window_counter::BoxGlobalTimeWindowCounter;
 use time_window_counter::GlobalTimeWindowCounterBuilder;
@@ -18,7 +31,10 @@ use tokio::time;
 
 use crate::batch::InternalObject;
 use crate::config::ConsistentRoutingRingMode;
+use crate::config::HotObjectSpreading;
 use crate::config::ObjectPopularity;
+use crate::config::PopularityReport;
+use crate::config::ServerConfig;
 use crate::lfs_server_context::RepositoryRequestContext;
 
 define_stats! {
@@ -26,10 +42,231 @@ define_stats! {
     success: timeseries(Rate, Sum),
     error: timeseries(Rate, Sum),
     timeout: timeseries(Rate, Sum),
+    hot: timeseries(Rate, Sum),
 }
 
 const OBJECT_POPULARITY_TIMEOUT: Duration = Duration::from_millis(10);
 
+/// Past this many objects, objects that aren't hot and weren't requested recently are forgotten.
+const MAX_TRACKED_OBJECTS: usize = 100_000;
+
+struct ObjectRequests {
+    window_start: Instant,
+    requests: u64,
+    hot_until: Option<Instant>,
+}
+
+/// Requests for each object served by this server, to spread the downloads of objects that get
+/// hot across all hosts as set by the `hot_object_spreading` config.
+#[derive(Clone, Default)]
+pub struct HotObjects {
+    objects: Arc<Mutex<HashMap<Sha256, ObjectRequests>>>,
+}
+
+impl HotObjects {
+    /// Counts a request for `oid`, and returns whether the object is hot.
+    pub fn record(&self, oid: Sha256, config: &HotObjectSpreading, now: Instant) -> bool {
+        let mut objects = self.objects.lock().expect("poisoned lock");
+
+        if objects.len() >= MAX_TRACKED_OBJECTS {
+            objects.retain(|_, o| {
+                now.saturating_duration_since(o.window_start) < config.window
+                    || o.hot_until.map_or(false, |until| now < until)
+            });
+        }
+
+        let object = objects.entry(oid).or_insert_with(|| ObjectRequests {
+            window_start: now,
+            requests: 0,
+            hot_until: None,
+        });
+
+        if now.saturating_duration_since(object.window_start) >= config.window {
+            object.window_start = now;
+            object.requests = 0;
+        }
+
+        object.requests += 1;
+        if object.requests > config.threshold {
+            if object.hot_until.map_or(true, |until| now >= until) {
+                STATS::hot.add_value(1);
+            }
+            object.hot_until = Some(now + config.cooldown);
+        }
+
+        object.hot_until.map_or(false, |until| now < until)
+    }
+}
+
+/// The popularity report window is split into this many buckets. Requests expire a bucket at a
+/// time, so reports cover the last `window`, give or take one bucket.
+const REPORT_BUCKETS: u32 = 12;
+
+/// How often the logger looks at the config again while logging is disabled.
+const REPORT_LOG_DISABLED_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many times an object was requested for download over the report window.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PopularObject {
+    pub repository: String,
+    pub oid: String,
+    pub downloads: u64,
+    pub bytes: u64,
+}
+
+/// Objects reported at `/popular` when the request doesn't ask for a number.
+pub const DEFAULT_POPULAR_LIMIT: usize = 100;
+
+/// Most objects reported at `/popular`.
+pub const MAX_POPULAR_LIMIT: usize = 10_000;
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct PopularQueryString {
+    pub limit: Option<usize>,
+}
+
+/// The response to `/popular`.
+#[derive(Debug, Serialize)]
+pub struct PopularReport {
+    pub window_secs: u64,
+    pub objects: Vec<PopularObject>,
+}
+
+struct ReportBucket {
+    start: Instant,
+    objects: HashMap<(String, Sha256), (u64, u64)>,
+}
+
+/// Rolling counts of the objects requested for download in batches, as set by the
+/// `popularity_report` config, for operators deciding what to warm in caches or offload to a CDN.
+#[derive(Clone, Default)]
+pub struct PopularObjects {
+    buckets: Arc<Mutex<VecDeque<ReportBucket>>>,
+}
+
+impl PopularObjects {
+    /// Counts a download of `size` bytes of `oid` from `repository`.
+    pub fn record(
+        &self,
+        repository: &str,
+        oid: Sha256,
+        size: u64,
+        config: &PopularityReport,
+        now: Instant,
+    ) {
+        let mut buckets = self.buckets.lock().expect("poisoned lock");
+        expire_buckets(&mut buckets, config, now);
+
+        let bucket_len = config.window / REPORT_BUCKETS;
+        let current = buckets.back().map_or(false, |b| {
+            now.saturating_duration_since(b.start) < bucket_len
+        });
+        if !current {
+            buckets.push_back(ReportBucket {
+                start: now,
+                objects: HashMap::new(),
+            });
+        }
+
+        let bucket = buckets.back_mut().expect("a bucket was just added");
+        let key = (repository.to_string(), oid);
+        if bucket.objects.len() >= MAX_TRACKED_OBJECTS && !bucket.objects.contains_key(&key) {
+            return;
+        }
+
+        let (downloads, bytes) = bucket.objects.entry(key).or_default();
+        *downloads += 1;
+        *bytes += size;
+    }
+
+    /// The `limit` most downloaded objects over the report window, most downloaded first.
+    pub fn top(&self, config: &PopularityReport, limit: usize, now: Instant) -> Vec<PopularObject> {
+        let mut buckets = self.buckets.lock().expect("poisoned lock");
+        expire_buckets(&mut buckets, config, now);
+
+        let mut totals: HashMap<&(String, Sha256), (u64, u64)> = HashMap::new();
+        for bucket in buckets.iter() {
+            for (key, (downloads, bytes)) in bucket.objects.iter() {
+                let total = totals.entry(key).or_default();
+                total.0 += downloads;
+                total.1 += bytes;
+            }
+        }
+
+        let mut objects = totals
+            .into_iter()
+            .map(|((repository, oid), (downloads, bytes))| PopularObject {
+                repository: repository.clone(),
+                oid: oid.to_string(),
+                downloads,
+                bytes,
+            })
+            .collect::<Vec<_>>();
+        objects.sort_by(|a, b| {
+            (b.downloads, b.bytes, &a.repository, &a.oid).cmp(&(
+                a.downloads,
+                a.bytes,
+                &b.repository,
+                &b.oid,
+            ))
+        });
+        objects.truncate(limit);
+        objects
+    }
+
+    /// Logs the most downloaded objects every `log_interval` of the `popularity_report` config.
+    pub fn spawn_logger(&self, config_handle: ConfigHandle<ServerConfig>, logger: Logger) {
+        let buckets = Arc::downgrade(&self.buckets);
+
+        tokio::spawn(async move {
+            loop {
+                let interval = config_handle
+                    .get()
+                    .popularity_report()
+                    .and_then(|report| report.log_interval);
+                let interval = match interval {
+                    Some(interval) => interval,
+                    None => {
+                        time::sleep(REPORT_LOG_DISABLED_POLL_INTERVAL).await;
+                        continue;
+                    }
+                };
+
+                time::sleep(interval).await;
+
+                let popular = match buckets.upgrade() {
+                    Some(buckets) => PopularObjects { buckets },
+                    None => break,
+                };
+                let config = config_handle.get();
+                let report = match config.popularity_report() {
+                    Some(report) => report,
+                    None => continue,
+                };
+
+                for object in popular.top(report, report.log_top, Instant::now()) {
+                    info!(
+                        logger,
+                        "Popular object {}/{}: {} downloads, {} bytes",
+                        object.repository,
+                        object.oid,
+                        object.downloads,
+                        object.bytes
+                    );
+                }
+            }
+        });
+    }
+}
+
+fn expire_buckets(buckets: &mut VecDeque<ReportBucket>, config: &PopularityReport, now: Instant) {
+    while buckets.front().map_or(false, |b| {
+        now.saturating_duration_since(b.start) >= config.window
+    }) {
+        buckets.pop_front();
+    }
+}
+
 pub trait PopularityBuilder {
     fn build(
         &self,
@@ -84,6 +321,15 @@ pub async fn consistent_routing<B: PopularityBuilder>(
     obj: InternalObject,
     builder: B,
 ) -> Option<NonZeroU16> {
+    if let Some(spreading) = ctx.config.hot_object_spreading() {
+        if ctx
+            .hot_objects()
+            .record(obj.oid(), spreading, Instant::now())
+        {
+            return None;
+        }
+    }
+
     let config = match ctx.config.object_popularity() {
         Some(r) => r,
         None => return Some(NonZeroU16::new(1).unwrap()),
@@ -141,12 +387,15 @@ mod test {
     use futures::future;
     use mononoke_types_mocks::contentid::ONES_CTID;
     use mononoke_types_mocks::hash::ONES_SHA256;
+    use mononoke_types_mocks::hash::TWOS_SHA256;
     use time_window_counter::GlobalTimeWindowCounter;
 
     use super::*;
     use crate::config::ConsistentRoutingRing;
     use crate::config::ConsistentRoutingRingMode;
+    use crate::config::HotObjectSpreading;
     use crate::config::ObjectPopularity;
+    use crate::config::PopularityReport;
     use crate::config::ServerConfig;
 
     fn dummy(size: impl Into<Option<u64>>) -> InternalObject {
@@ -244,6 +493,98 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_hot_objects() {
+        let hot_objects = HotObjects::default();
+        let config = HotObjectSpreading {
+            threshold: 2,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+        };
+        let now = Instant::now();
+
+        assert!(!hot_objects.record(ONES_SHA256, &config, now));
+        assert!(!hot_objects.record(ONES_SHA256, &config, now));
+        assert!(hot_objects.record(ONES_SHA256, &config, now));
+
+        // Objects stay hot through the cooldown, even if they are requested less.
+        let later = now + Duration::from_secs(20);
+        assert!(hot_objects.record(ONES_SHA256, &config, later));
+        let cooled = now + Duration::from_secs(31);
+        assert!(!hot_objects.record(ONES_SHA256, &config, cooled));
+    }
+
+    #[test]
+    fn test_popular_objects() {
+        let popular = PopularObjects::default();
+        let config = PopularityReport {
+            window: Duration::from_secs(120),
+            log_top: 10,
+            log_interval: None,
+        };
+        let now = Instant::now();
+
+        popular.record("repo1", ONES_SHA256, 10, &config, now);
+        popular.record("repo1", TWOS_SHA256, 5, &config, now);
+        let later = now + Duration::from_secs(60);
+        popular.record("repo1", TWOS_SHA256, 5, &config, later);
+        popular.record("repo2", ONES_SHA256, 10, &config, later);
+
+        let top = popular.top(&config, 2, later);
+        assert_eq!(
+            top,
+            vec![
+                PopularObject {
+                    repository: "repo1".to_string(),
+                    oid: TWOS_SHA256.to_string(),
+                    downloads: 2,
+                    bytes: 10,
+                },
+                PopularObject {
+                    repository: "repo1".to_string(),
+                    oid: ONES_SHA256.to_string(),
+                    downloads: 1,
+                    bytes: 10,
+                },
+            ]
+        );
+
+        // Downloads are forgotten once they are out of the window.
+        let top = popular.top(&config, 10, now + Duration::from_secs(150));
+        assert_eq!(top.len(), 2);
+        assert!(top.iter().all(|object| object.downloads == 1));
+    }
+
+    #[fbinit::test]
+    async fn test_hot_object_spreading(fb: FacebookInit) -> Result<(), Error> {
+        let mut config = ServerConfig::default();
+        *config.hot_object_spreading_mut() = Some(HotObjectSpreading {
+            threshold: 1,
+            window: Duration::from_secs(100),
+            cooldown: Duration::from_secs(100),
+        });
+
+        let ctx = RepositoryRequestContext::test_builder(fb)
+            .await?
+            .config(config)
+            .build()?;
+        let ctr = DummyCounter::default();
+
+        assert!(
+            consistent_routing(&ctx, dummy(4), ctr.clone())
+                .await
+                .is_some()
+        );
+
+        assert!(
+            consistent_routing(&ctx, dummy(4), ctr.clone())
+                .await
+                .is_none()
+        );
+
+        Ok(())
+    }
+
     #[fbinit::test]
     async fn test_popularity(fb: FacebookInit) -> Result<(), Error> {
         let mut config = ServerConfig::default();
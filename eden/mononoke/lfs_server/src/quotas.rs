@@ -0,0 +1,212 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Storage quotas, from the `max_upload_bytes_per_day` and `max_upload_bytes_per_week` of the
+//! request limits. Each client has its own usage, keyed by its identities, even when several of
+//! them share a limit. Uploads are only counted once they succeed, so concurrent uploads from the
+//! same client can go over a quota by up to their size.
+
+use std::num::NonZeroU64;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Context;
+use anyhow::Error;
+use lfs_quotas::LfsQuotasRef;
+use permission_checker::MononokeIdentitySet;
+use slog::warn;
+use stats::prelude::*;
+
+use crate::config::RequestLimit;
+use crate::errors::ErrorKind;
+use crate::lfs_server_context::RepositoryRequestContext;
+
+define_stats! {
+    prefix = "mononoke.lfs.quotas";
+    exceeded: timeseries(Rate, Sum),
+    record_failed: timeseries(Rate, Sum),
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const DAYS_PER_WEEK: u64 = 7;
+
+/// Days since the epoch, so that days start at midnight UTC.
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / SECONDS_PER_DAY)
+}
+
+/// The key usage is recorded under for a client with `identities`.
+fn client_key(identities: &MononokeIdentitySet) -> String {
+    identities
+        .iter()
+        .map(|identity| identity.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The quotas in `limit`, with the first day each of them counts uploads from.
+fn quotas(limit: &RequestLimit, today: u64) -> Vec<(NonZeroU64, u64, &'static str)> {
+    let day = limit
+        .max_upload_bytes_per_day
+        .map(|max| (max, today, "per day"));
+    let week = limit
+        .max_upload_bytes_per_week
+        .map(|max| (max, today.saturating_sub(DAYS_PER_WEEK - 1), "per week"));
+    day.into_iter().chain(week).collect()
+}
+
+fn quota_limit(ctx: &RepositoryRequestContext) -> Option<&RequestLimit> {
+    ctx.config
+        .request_limit(Some(ctx.ctx.metadata().identities()))
+        .filter(|limit| {
+            limit.max_upload_bytes_per_day.is_some() || limit.max_upload_bytes_per_week.is_some()
+        })
+}
+
+/// Fails if uploading `size` bytes would take the client over one of its quotas.
+pub async fn check(ctx: &RepositoryRequestContext, size: u64) -> Result<(), Error> {
+    let limit = match quota_limit(ctx) {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    let client = client_key(ctx.ctx.metadata().identities());
+
+    for (max, since, period) in quotas(limit, today()) {
+        let used = ctx
+            .repo
+            .lfs_quotas()
+            .usage_since(&ctx.ctx, &client, since)
+            .await
+            .context("Could not load upload quota usage")?;
+
+        if used.saturating_add(size) > max.get() {
+            STATS::exceeded.add_value(1);
+            return Err(ErrorKind::UploadQuotaExceeded(size, max.get(), period, used).into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts `size` bytes uploaded by the client towards its quotas. The upload already succeeded,
+/// so failures are only logged.
+pub async fn record(ctx: &RepositoryRequestContext, size: u64) {
+    let limit = match quota_limit(ctx) {
+        Some(limit) => limit,
+        None => return,
+    };
+    let client = client_key(ctx.ctx.metadata().identities());
+
+    let res = ctx
+        .repo
+        .lfs_quotas()
+        .add_usage(&ctx.ctx, &client, today(), size)
+        .await;
+
+    if let Err(e) = res {
+        STATS::record_failed.add_value(1);
+        warn!(
+            ctx.ctx.logger(),
+            "Could not record upload quota usage for {}: {:?}", client, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fbinit::FacebookInit;
+    use permission_checker::MononokeIdentity;
+
+    use super::*;
+    use crate::config::ServerConfig;
+
+    fn limit(per_day: u64, per_week: u64) -> RequestLimit {
+        RequestLimit {
+            identities: MononokeIdentitySet::new(),
+            requests_per_second: None,
+            max_concurrent_requests: None,
+            max_concurrent_uploads: None,
+            max_egress_bytes_per_second: None,
+            max_upload_bytes_per_day: NonZeroU64::new(per_day),
+            max_upload_bytes_per_week: NonZeroU64::new(per_week),
+        }
+    }
+
+    fn user(name: &str) -> MononokeIdentitySet {
+        MononokeIdentitySet::from([MononokeIdentity::new("USER", name)])
+    }
+
+    #[test]
+    fn test_quotas() {
+        let parsed = quotas(&limit(10, 50), 100)
+            .into_iter()
+            .map(|(max, since, period)| (max.get(), since, period))
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, vec![(10, 100, "per day"), (50, 94, "per week")]);
+
+        assert!(quotas(&limit(0, 0), 100).is_empty());
+    }
+
+    #[test]
+    fn test_client_key() {
+        let identities = MononokeIdentitySet::from([
+            MononokeIdentity::new("USER", "foo"),
+            MononokeIdentity::new("SERVICE_IDENTITY", "ci"),
+        ]);
+        assert_eq!(client_key(&identities), "SERVICE_IDENTITY:ci,USER:foo");
+    }
+
+    #[fbinit::test]
+    async fn test_check_and_record(fb: FacebookInit) -> Result<(), Error> {
+        let mut config = ServerConfig::default();
+        config.request_limits_mut().push(limit(100, 150));
+        let ctx = RepositoryRequestContext::test_builder(fb)
+            .await?
+            .config(config)
+            .identities(user("foo"))
+            .build()?;
+
+        check(&ctx, 100).await?;
+        record(&ctx, 60).await;
+        check(&ctx, 40).await?;
+        assert!(matches!(
+            check(&ctx, 41)
+                .await
+                .unwrap_err()
+                .downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::UploadQuotaExceeded(41, 100, "per day", 60))
+        ));
+
+        // Yesterday's uploads still count towards the weekly quota.
+        ctx.repo
+            .lfs_quotas()
+            .add_usage(&ctx.ctx, "USER:foo", today() - 1, 80)
+            .await?;
+        assert!(matches!(
+            check(&ctx, 20)
+                .await
+                .unwrap_err()
+                .downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::UploadQuotaExceeded(20, 150, "per week", 140))
+        ));
+
+        // Clients that share a limit don't share their usage.
+        let other = RepositoryRequestContext::test_builder_with_repo(fb, (*ctx.repo).clone())?
+            .config(ctx.config.as_ref().clone())
+            .identities(user("bar"))
+            .build()?;
+        check(&other, 100).await?;
+
+        // Clients without quotas are not limited.
+        let ctx = RepositoryRequestContext::test_builder(fb).await?.build()?;
+        check(&ctx, 1000).await?;
+
+        Ok(())
+    }
+}
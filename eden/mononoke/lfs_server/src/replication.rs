@@ -0,0 +1,223 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Replication of uploads to the peers in the `replication` config. Objects are pushed to each
+//! peer in the background once their upload completes, so that reads in other regions don't miss
+//! objects that were only just uploaded here. Pushes are retried with backoff, and uploads are
+//! not replicated while too many pushes are pending.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Error;
+use filestore::Alias;
+use filestore::FetchKey;
+use hyper::header::CONTENT_LENGTH;
+use hyper::Body;
+use hyper::Request;
+use mononoke_types::hash::Sha256;
+use slog::warn;
+use stats::prelude::*;
+
+use crate::config::Replication;
+use crate::errors::ErrorKind;
+use crate::lfs_server_context::RepositoryRequestContext;
+
+define_stats! {
+    prefix = "mononoke.lfs.replication";
+    backlog: singleton_counter(),
+    pushes: timeseries(Rate, Sum),
+    success: timeseries(Rate, Sum),
+    retries: timeseries(Rate, Sum),
+    failures: timeseries(Rate, Sum),
+    dropped: timeseries(Rate, Sum),
+}
+
+/// Sent with pushes to peers, so that they don't replicate the objects any further.
+pub const REPLICATED_HEADER: &str = "x-lfs-replicated";
+
+/// Counts the pushes to peers that are pending, to enforce `max_backlog`.
+#[derive(Clone, Default)]
+pub struct Replicator {
+    backlog: Arc<AtomicUsize>,
+}
+
+/// Held for as long as a push is pending.
+struct BacklogSlot {
+    backlog: Arc<AtomicUsize>,
+}
+
+impl Drop for BacklogSlot {
+    fn drop(&mut self) {
+        self.backlog.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// How long to wait after the `attempt`th failed push before trying again.
+fn retry_delay(config: &Replication, attempt: u32) -> Duration {
+    config
+        .retry_delay
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+}
+
+impl Replicator {
+    fn reserve(&self, max_backlog: usize) -> Option<BacklogSlot> {
+        self.backlog
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |backlog| {
+                Some(backlog + 1).filter(|b| *b <= max_backlog)
+            })
+            .ok()?;
+
+        Some(BacklogSlot {
+            backlog: self.backlog.clone(),
+        })
+    }
+
+    pub fn backlog(&self) -> usize {
+        self.backlog.load(Ordering::Relaxed)
+    }
+
+    /// Pushes `oid` to each peer in the background, if the config replicates uploads.
+    pub fn replicate(&self, ctx: &RepositoryRequestContext, oid: Sha256, size: u64) {
+        let config = match ctx.config.replication() {
+            Some(config) => config.clone(),
+            None => return,
+        };
+
+        for peer in config.peers.iter() {
+            let slot = match self.reserve(config.max_backlog) {
+                Some(slot) => slot,
+                None => {
+                    STATS::dropped.add_value(1);
+                    warn!(
+                        ctx.logger(),
+                        "Not replicating {} to {}: too many pushes pending", oid, peer
+                    );
+                    continue;
+                }
+            };
+            STATS::backlog.set_value(ctx.ctx.fb, self.backlog() as i64);
+
+            let ctx = ctx.clone();
+            let config = config.clone();
+            let peer = peer.clone();
+            let replicator = self.clone();
+
+            tokio::spawn(async move {
+                push_with_retries(&ctx, &config, &peer, oid, size).await;
+                drop(slot);
+                STATS::backlog.set_value(ctx.ctx.fb, replicator.backlog() as i64);
+            });
+        }
+    }
+}
+
+async fn push_with_retries(
+    ctx: &RepositoryRequestContext,
+    config: &Replication,
+    peer: &str,
+    oid: Sha256,
+    size: u64,
+) {
+    for attempt in 1..=config.max_attempts {
+        STATS::pushes.add_value(1);
+
+        let err = match push(ctx, peer, oid, size).await {
+            Ok(()) => {
+                STATS::success.add_value(1);
+                return;
+            }
+            Err(err) => err,
+        };
+
+        if attempt == config.max_attempts {
+            STATS::failures.add_value(1);
+            warn!(
+                ctx.logger(),
+                "Failed to replicate {} to {} after {} attempts: {:#}", oid, peer, attempt, err
+            );
+            return;
+        }
+
+        STATS::retries.add_value(1);
+        tokio::time::sleep(retry_delay(config, attempt)).await;
+    }
+}
+
+/// Uploads `oid` from our blobstore to `peer`.
+async fn push(
+    ctx: &RepositoryRequestContext,
+    peer: &str,
+    oid: Sha256,
+    size: u64,
+) -> Result<(), Error> {
+    let key = FetchKey::Aliased(Alias::Sha256(oid));
+    let stream = filestore::fetch(ctx.blobstore(), ctx.ctx.clone(), &key)
+        .await
+        .context(ErrorKind::FilestoreReadFailure)?
+        .ok_or(ErrorKind::ObjectDoesNotExist(key))?;
+
+    let uri = format!(
+        "{}/{}/upload/{}/{}",
+        peer, ctx.uri_builder.repository, oid, size
+    );
+    let req = Request::put(uri)
+        .header(CONTENT_LENGTH, size)
+        .header(REPLICATED_HEADER, "1")
+        .body(Body::wrap_stream(stream))?;
+
+    // NOTE: We read the response body here, otherwise Hyper will not allow this connection to be
+    // reused.
+    ctx.dispatch(req)
+        .await
+        .context(ErrorKind::ReplicationFailed(peer.to_string()))?
+        .discard()
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> Replication {
+        Replication {
+            peers: vec!["https://peer".to_string()],
+            max_attempts: 3,
+            retry_delay: Duration::from_millis(100),
+            max_backlog: 2,
+        }
+    }
+
+    #[test]
+    fn test_backlog() {
+        let replicator = Replicator::default();
+
+        let first = replicator.reserve(2);
+        let second = replicator.reserve(2);
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert!(replicator.reserve(2).is_none());
+        assert_eq!(replicator.backlog(), 2);
+
+        drop(first);
+        assert_eq!(replicator.backlog(), 1);
+        assert!(replicator.reserve(2).is_some());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let config = config();
+        assert_eq!(retry_delay(&config, 1), Duration::from_millis(100));
+        assert_eq!(retry_delay(&config, 2), Duration::from_millis(200));
+        assert_eq!(retry_delay(&config, 3), Duration::from_millis(400));
+    }
+}
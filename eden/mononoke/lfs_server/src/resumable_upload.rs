@@ -0,0 +1,298 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Resumable uploads, for clients that send an object in parts using `Content-Range` headers.
+//! Parts are kept in the blobstore until the last one arrives, at which point the object is
+//! streamed out of them into the regular upload path. Each upload gets its own session, so that
+//! clients uploading the same object at the same time don't overwrite each other's parts.
+
+use std::iter;
+use std::str::FromStr;
+
+use anyhow::Error;
+use blobstore::Blobstore;
+use blobstore::BlobstoreBytes;
+use bytes::Bytes;
+use filestore::FilestoreConfigRef;
+use futures::StreamExt;
+use futures::stream;
+use futures::stream::BoxStream;
+use futures::TryStreamExt;
+use mononoke_types::hash::Sha256;
+use rand::Rng;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::errors::ErrorKind;
+use crate::lfs_server_context::RepositoryRequestContext;
+
+/// The largest part accepted from repositories whose filestore doesn't chunk objects.
+const DEFAULT_MAX_PART_SIZE: u64 = 100 * 1024 * 1024;
+
+const DISCARD_CONCURRENCY: usize = 10;
+
+/// A parsed `Content-Range: bytes <start>-<end>/<total>` header, where the range is inclusive. A
+/// `bytes */<total>` header carries no data, and asks how much of the object was received.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContentRange {
+    pub range: Option<(u64, u64)>,
+    pub total: u64,
+}
+
+impl FromStr for ContentRange {
+    type Err = ErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (range, total) = s.strip_prefix("bytes ")?.split_once('/')?;
+            let total = total.parse().ok()?;
+
+            let range = match range {
+                "*" => None,
+                range => {
+                    let (start, end) = range.split_once('-')?;
+                    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                    if start > end || end >= total {
+                        return None;
+                    }
+                    Some((start, end))
+                }
+            };
+
+            Some(Self { range, total })
+        };
+
+        parse().ok_or_else(|| ErrorKind::InvalidContentRange(s.to_string()))
+    }
+}
+
+/// Identifies the parts sent by one client for one upload of an object. The server picks one when
+/// an upload starts from zero, and clients send it back with the rest of the parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadSession(String);
+
+impl UploadSession {
+    pub fn new() -> Self {
+        Self(format!("{:032x}", rand::thread_rng().gen::<u128>()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for UploadSession {
+    type Err = ErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Sessions end up in blobstore keys, so only take ones that look like what we hand out.
+        if s.len() == 32 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+            Ok(Self(s.to_string()))
+        } else {
+            Err(ErrorKind::InvalidUploadSession(s.to_string()))
+        }
+    }
+}
+
+/// The parts of an object received so far, as the offset each of them ends at. Parts are
+/// contiguous, so each one starts where the previous one ended.
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct PartialUpload {
+    ends: Vec<u64>,
+}
+
+impl PartialUpload {
+    pub fn received(&self) -> u64 {
+        self.ends.last().copied().unwrap_or(0)
+    }
+
+    fn starts(&self) -> impl Iterator<Item = u64> + '_ {
+        iter::once(0)
+            .chain(self.ends.iter().copied())
+            .take(self.ends.len())
+    }
+}
+
+fn progress_key(oid: &Sha256, session: &UploadSession) -> String {
+    format!("lfs.partial_upload.sha256.{}.{}", oid, session.as_str())
+}
+
+fn part_key(oid: &Sha256, session: &UploadSession, start: u64) -> String {
+    format!(
+        "lfs.partial_upload.sha256.{}.{}.{}",
+        oid,
+        session.as_str(),
+        start
+    )
+}
+
+/// The largest part accepted. Parts are held in memory and stored as a single blob, so they are
+/// limited to the filestore's chunk size.
+pub fn max_part_size(ctx: &RepositoryRequestContext) -> u64 {
+    ctx.repo
+        .filestore_config()
+        .chunk_size
+        .unwrap_or(DEFAULT_MAX_PART_SIZE)
+}
+
+pub async fn load(
+    ctx: &RepositoryRequestContext,
+    oid: &Sha256,
+    session: &UploadSession,
+) -> Result<PartialUpload, Error> {
+    let blob = ctx
+        .blobstore()
+        .get(&ctx.ctx, &progress_key(oid, session))
+        .await?;
+
+    match blob {
+        // Discarded uploads leave an empty blob behind.
+        Some(blob) if !blob.as_raw_bytes().is_empty() => {
+            Ok(serde_json::from_slice(blob.as_raw_bytes())?)
+        }
+        _ => Ok(PartialUpload::default()),
+    }
+}
+
+pub async fn save(
+    ctx: &RepositoryRequestContext,
+    oid: &Sha256,
+    session: &UploadSession,
+    progress: &PartialUpload,
+) -> Result<(), Error> {
+    let bytes = serde_json::to_vec(progress)?;
+    ctx.blobstore()
+        .put(
+            &ctx.ctx,
+            progress_key(oid, session),
+            BlobstoreBytes::from_bytes(bytes),
+        )
+        .await
+}
+
+/// Stores `part` after the parts received so far. The returned progress includes it, but still
+/// needs saving.
+pub async fn put_part(
+    ctx: &RepositoryRequestContext,
+    oid: &Sha256,
+    session: &UploadSession,
+    mut progress: PartialUpload,
+    part: Bytes,
+) -> Result<PartialUpload, Error> {
+    let start = progress.received();
+    let end = start + part.len() as u64;
+
+    ctx.blobstore()
+        .put(
+            &ctx.ctx,
+            part_key(oid, session, start),
+            BlobstoreBytes::from_bytes(part),
+        )
+        .await?;
+
+    progress.ends.push(end);
+    Ok(progress)
+}
+
+/// Streams the object back out of its parts.
+pub fn stream_parts(
+    ctx: &RepositoryRequestContext,
+    oid: Sha256,
+    session: UploadSession,
+    progress: &PartialUpload,
+) -> BoxStream<'static, Result<Bytes, Error>> {
+    let blobstore = ctx.blobstore();
+    let ctx = ctx.ctx.clone();
+
+    stream::iter(progress.starts().collect::<Vec<_>>())
+        .then(move |start| {
+            let blobstore = blobstore.clone();
+            let ctx = ctx.clone();
+            let key = part_key(&oid, &session, start);
+            async move {
+                let blob = blobstore
+                    .get(&ctx, &key)
+                    .await?
+                    .ok_or(ErrorKind::PartialUploadPartMissing(key))?;
+                Ok::<_, Error>(blob.into_raw_bytes())
+            }
+        })
+        .boxed()
+}
+
+/// Discards the parts and progress of an upload once its object is stored. Blobstores can't
+/// delete keys, so they are overwritten with empty blobs instead, which frees their contents.
+pub async fn discard(
+    ctx: &RepositoryRequestContext,
+    oid: &Sha256,
+    session: &UploadSession,
+    progress: &PartialUpload,
+) -> Result<(), Error> {
+    let blobstore = ctx.blobstore();
+    let keys = progress
+        .starts()
+        .map(|start| part_key(oid, session, start))
+        .chain(iter::once(progress_key(oid, session)));
+
+    stream::iter(keys.map(Ok))
+        .try_for_each_concurrent(DISCARD_CONCURRENCY, |key| {
+            blobstore.put(&ctx.ctx, key, BlobstoreBytes::empty())
+        })
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            "bytes 0-9/20".parse::<ContentRange>().unwrap(),
+            ContentRange {
+                range: Some((0, 9)),
+                total: 20
+            }
+        );
+        assert_eq!(
+            "bytes */20".parse::<ContentRange>().unwrap(),
+            ContentRange {
+                range: None,
+                total: 20
+            }
+        );
+
+        assert!("bytes 10-9/20".parse::<ContentRange>().is_err());
+        assert!("bytes 10-20/20".parse::<ContentRange>().is_err());
+        assert!("bytes 0-9/*".parse::<ContentRange>().is_err());
+        assert!("0-9/20".parse::<ContentRange>().is_err());
+    }
+
+    #[test]
+    fn test_parse_upload_session() {
+        let session = UploadSession::new();
+        assert_eq!(session.as_str().parse::<UploadSession>().unwrap(), session);
+
+        assert!("".parse::<UploadSession>().is_err());
+        assert!("0123456789ABCDEF0123456789ABCDEF".parse::<UploadSession>().is_err());
+        assert!("0123456789abcdef".parse::<UploadSession>().is_err());
+        assert!("../../0123456789abcdef0123456789".parse::<UploadSession>().is_err());
+    }
+
+    #[test]
+    fn test_partial_upload_starts() {
+        let progress = PartialUpload {
+            ends: vec![10, 15, 30],
+        };
+        assert_eq!(progress.received(), 30);
+        assert_eq!(progress.starts().collect::<Vec<_>>(), vec![0, 10, 15]);
+
+        let progress = PartialUpload::default();
+        assert_eq!(progress.received(), 0);
+        assert_eq!(progress.starts().count(), 0);
+    }
+}
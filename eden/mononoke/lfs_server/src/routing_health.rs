@@ -0,0 +1,336 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Fallback from consistent routing to uniform routing for the destinations whose routed downloads
+//! fail too often, according to the `routing_error_budget` config. Servers count the routed
+//! downloads they serve in shared counters, and check the totals of each destination in the
+//! background, so that routing a batch never waits for the counters. A disabled destination is
+//! routed to again once its cool-down is over.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use cached_config::ConfigHandle;
+use fbinit::FacebookInit;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+use stats::prelude::*;
+use tokio::time;
+
+use crate::config::RoutingErrorBudget;
+use crate::config::ServerConfig;
+use crate::distributed_limits::SharedCounters;
+
+define_stats! {
+    prefix = "mononoke.lfs.routing_health";
+    routed: timeseries(Rate, Sum),
+    routed_errors: timeseries(Rate, Sum),
+    disabled: timeseries(Rate, Sum),
+    reenabled: timeseries(Rate, Sum),
+    disabled_destinations: singleton_counter(),
+    counter_error: timeseries(Rate, Sum),
+    counter_timeout: timeseries(Rate, Sum),
+}
+
+/// How often to check whether the config has a `routing_error_budget` when it doesn't.
+const BUDGET_DISABLED_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Without rendezvous routing, the destinations of routing keys are up to the proxy, so all
+/// routed downloads are counted together under this destination.
+const ALL_DESTINATIONS: &str = "*";
+
+/// The destination that downloads with `routing_key` go to.
+fn destination<'a>(config: &ServerConfig, routing_key: &'a str) -> &'a str {
+    match config.rendezvous_routing() {
+        Some(rendezvous) if rendezvous.targets().any(|t| t == routing_key) => routing_key,
+        _ => ALL_DESTINATIONS,
+    }
+}
+
+/// The destinations that downloads may be routed to.
+fn destinations(config: &ServerConfig) -> Vec<String> {
+    let targets = config
+        .rendezvous_routing()
+        .into_iter()
+        .flat_map(|rendezvous| rendezvous.targets());
+
+    std::iter::once(ALL_DESTINATIONS)
+        .chain(targets)
+        .map(String::from)
+        .collect()
+}
+
+fn counter_key(kind: &str, destination: &str) -> String {
+    format!("{}/{}", kind, destination)
+}
+
+#[derive(Debug, PartialEq)]
+enum Transition {
+    Disabled { requests: f64, errors: f64 },
+    Reenabled,
+}
+
+/// Tracks which destinations consistent routing is disabled for.
+#[derive(Clone)]
+pub struct RoutingHealth {
+    counters: Arc<dyn SharedCounters>,
+    /// Disabled destinations, and when their cool-down is over.
+    disabled: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl RoutingHealth {
+    pub fn new(counters: Arc<dyn SharedCounters>) -> Self {
+        Self {
+            counters,
+            disabled: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Whether downloads with `routing_key` should be routed uniformly instead.
+    pub fn is_disabled(&self, config: &ServerConfig, routing_key: &str) -> bool {
+        self.disabled
+            .lock()
+            .expect("poisoned lock")
+            .contains_key(destination(config, routing_key))
+    }
+
+    /// Counts a download that was routed here with `routing_key` towards the error rate of its
+    /// destination, in the background.
+    pub fn record(&self, config: &ServerConfig, routing_key: &str, failed: bool) {
+        let budget = match config.routing_error_budget() {
+            Some(budget) => budget.clone(),
+            None => return,
+        };
+
+        STATS::routed.add_value(1);
+        if failed {
+            STATS::routed_errors.add_value(1);
+        }
+
+        let destination = destination(config, routing_key).to_string();
+        let counters = self.counters.clone();
+
+        tokio::spawn(async move {
+            add(
+                &*counters,
+                &budget,
+                &counter_key("requests", &destination),
+                1,
+            )
+            .await;
+            if failed {
+                add(&*counters, &budget, &counter_key("errors", &destination), 1).await;
+            }
+        });
+    }
+
+    /// Disables `destination` if it is over `budget` at these rates of routed downloads and
+    /// errors, or enables it again if its cool-down is over.
+    fn update(
+        &self,
+        budget: &RoutingErrorBudget,
+        destination: &str,
+        rates: Option<(f64, f64)>,
+        now: Instant,
+    ) -> Option<Transition> {
+        let mut disabled = self.disabled.lock().expect("poisoned lock");
+
+        if let Some(until) = disabled.get(destination) {
+            if now < *until {
+                return None;
+            }
+            disabled.remove(destination);
+            return Some(Transition::Reenabled);
+        }
+
+        // If the counters didn't answer, leave the destination be.
+        let (requests, errors) = rates?;
+        if requests == 0.0 || requests < budget.min_requests_per_second as f64 {
+            return None;
+        }
+        if errors / requests <= budget.max_error_rate {
+            return None;
+        }
+
+        disabled.insert(destination.to_string(), now + budget.cool_down);
+        Some(Transition::Disabled { requests, errors })
+    }
+
+    async fn check(&self, budget: &RoutingErrorBudget, destination: &str) -> Option<Transition> {
+        let requests = add(
+            &*self.counters,
+            budget,
+            &counter_key("requests", destination),
+            0,
+        );
+        let errors = add(
+            &*self.counters,
+            budget,
+            &counter_key("errors", destination),
+            0,
+        );
+        let rates = match futures::join!(requests, errors) {
+            (Some(requests), Some(errors)) => Some((requests, errors)),
+            _ => None,
+        };
+
+        self.update(budget, destination, rates, Instant::now())
+    }
+
+    /// Checks the error rates of all destinations every `check_interval` of the
+    /// `routing_error_budget` config, and logs when routing to one is disabled or enabled again.
+    pub fn spawn_monitor(
+        &self,
+        fb: FacebookInit,
+        config_handle: ConfigHandle<ServerConfig>,
+        logger: Logger,
+    ) {
+        let health = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let config = config_handle.get();
+                let budget = match config.routing_error_budget() {
+                    Some(budget) => budget.clone(),
+                    None => {
+                        health.disabled.lock().expect("poisoned lock").clear();
+                        STATS::disabled_destinations.set_value(fb, 0);
+                        time::sleep(BUDGET_DISABLED_POLL_INTERVAL).await;
+                        continue;
+                    }
+                };
+
+                time::sleep(budget.check_interval).await;
+
+                for destination in destinations(&config) {
+                    match health.check(&budget, &destination).await {
+                        Some(Transition::Disabled { requests, errors }) => {
+                            STATS::disabled.add_value(1);
+                            warn!(
+                                logger,
+                                "Disabling consistent routing to {} for {:?}: {:.1} of {:.1} routed downloads per second failed",
+                                destination,
+                                budget.cool_down,
+                                errors,
+                                requests
+                            );
+                        }
+                        Some(Transition::Reenabled) => {
+                            STATS::reenabled.add_value(1);
+                            info!(
+                                logger,
+                                "Re-enabling consistent routing to {} after cool-down", destination
+                            );
+                        }
+                        None => {}
+                    }
+                }
+
+                let disabled = health.disabled.lock().expect("poisoned lock").len();
+                STATS::disabled_destinations.set_value(fb, disabled as i64);
+            }
+        });
+    }
+}
+
+/// The fleet's rate for `key` after adding `value`, or `None` if the shared counters didn't
+/// answer in time.
+async fn add(
+    counters: &dyn SharedCounters,
+    budget: &RoutingErrorBudget,
+    key: &str,
+    value: u64,
+) -> Option<f64> {
+    let add = counters.add(&budget.category, key, value);
+
+    match time::timeout(budget.timeout, add).await {
+        Ok(Ok(rate)) => Some(rate),
+        Ok(Err(_)) => {
+            STATS::counter_error.add_value(1);
+            None
+        }
+        Err(_) => {
+            STATS::counter_timeout.add_value(1);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Error;
+    use async_trait::async_trait;
+
+    use super::*;
+
+    struct NoCounters;
+
+    #[async_trait]
+    impl SharedCounters for NoCounters {
+        async fn add(&self, _: &str, _: &str, _: u64) -> Result<f64, Error> {
+            Err(Error::msg("no counters"))
+        }
+    }
+
+    fn budget() -> RoutingErrorBudget {
+        RoutingErrorBudget {
+            category: "test".to_string(),
+            max_error_rate: 0.5,
+            min_requests_per_second: 10,
+            cool_down: Duration::from_secs(30),
+            check_interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let health = RoutingHealth::new(Arc::new(NoCounters));
+        let config = ServerConfig::default();
+        let budget = budget();
+        let now = Instant::now();
+
+        // Within budget, too few requests to judge, or no answer from the counters.
+        assert_eq!(health.update(&budget, "*", Some((20.0, 10.0)), now), None);
+        assert_eq!(health.update(&budget, "*", Some((5.0, 5.0)), now), None);
+        assert_eq!(health.update(&budget, "*", None, now), None);
+        assert!(!health.is_disabled(&config, "key"));
+
+        // Over budget.
+        assert_eq!(
+            health.update(&budget, "*", Some((20.0, 11.0)), now),
+            Some(Transition::Disabled {
+                requests: 20.0,
+                errors: 11.0
+            })
+        );
+        assert!(health.is_disabled(&config, "key"));
+
+        // Routing stays disabled until the cool-down is over, even if errors stop.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(health.update(&budget, "*", Some((20.0, 0.0)), later), None);
+        assert!(health.is_disabled(&config, "key"));
+
+        let after_cool_down = now + budget.cool_down;
+        assert_eq!(
+            health.update(&budget, "*", None, after_cool_down),
+            Some(Transition::Reenabled)
+        );
+        assert!(!health.is_disabled(&config, "key"));
+    }
+
+    #[test]
+    fn test_destinations() {
+        let config = ServerConfig::default();
+        assert_eq!(destination(&config, "abc-1"), ALL_DESTINATIONS);
+        assert_eq!(destinations(&config), vec![ALL_DESTINATIONS.to_string()]);
+    }
+}
@@ -5,7 +5,14 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::anyhow;
 use anyhow::Result;
@@ -22,15 +29,43 @@ use gotham_ext::middleware::MetadataState;
 use gotham_ext::response::build_error_response;
 use http::HeaderMap;
 use hyper::Uri;
+use permission_checker::MononokeIdentitySet;
 use qps::Qps;
+use rand::Rng;
 use slog::trace;
+use stats::prelude::*;
 
 use super::error_formatter::LfsErrorFormatter;
+use crate::config::PipelineStage;
+use crate::config::RequestLimit;
 use crate::config::ServerConfig;
+use crate::distributed_limits::DistributedLimiter;
+use crate::errors::ErrorKind;
+use crate::util::hold_until_sent;
+use crate::util::route_name;
 use crate::LfsServerContext;
 
 const HEADER_REVPROXY_REGION: &str = "x-fb-revproxy-region";
 
+define_stats! {
+    prefix = "mononoke.lfs.request_limit";
+    rate_limited: timeseries(Rate, Sum),
+    concurrency_limited: timeseries(Rate, Sum),
+    host_overloaded: timeseries(Rate, Sum),
+    ip_rate_limited: timeseries(Rate, Sum),
+}
+
+/// Past this many addresses, addresses that haven't made requests recently are forgotten.
+const MAX_TRACKED_IPS: usize = 100_000;
+
+/// Health checks are exempt from throttling and request accounting, so that a busy server
+/// isn't taken out of rotation.
+fn is_health_check(state: &State) -> bool {
+    Uri::try_borrow_from(state).map_or(false, |uri| {
+        uri.path() == "/health_check" || uri.path() == "/health"
+    })
+}
+
 // NOTE: Our Throttling middleware is implemented as Gotham middleware for 3 reasons:
 // - It needs to replace responses.
 // - It needs to do asynchronously.
@@ -53,10 +88,8 @@ impl Middleware for ThrottleMiddleware {
     where
         Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
     {
-        if let Some(uri) = Uri::try_borrow_from(&state) {
-            if uri.path() == "/health_check" {
-                return chain(state);
-            }
+        if is_health_check(&state) {
+            return chain(state);
         }
         let identities = state
             .try_borrow::<MetadataState>()
@@ -77,6 +110,339 @@ impl Middleware for ThrottleMiddleware {
     }
 }
 
+#[derive(Default)]
+struct RequestCounters {
+    window_start: Option<Instant>,
+    requests_in_window: u32,
+    in_flight: u32,
+}
+
+/// Tracks requests for each group of clients sharing a `RequestLimit`, keyed by the
+/// limit's identities.
+#[derive(Clone, Default)]
+struct RequestLimiter {
+    counters: Arc<Mutex<HashMap<MononokeIdentitySet, RequestCounters>>>,
+}
+
+/// Held for as long as a request counts towards its concurrency limit, until its response body
+/// is sent.
+struct RequestLimitGuard {
+    limiter: RequestLimiter,
+    key: MononokeIdentitySet,
+    window_start: Instant,
+}
+
+impl RequestLimitGuard {
+    /// Gives back the request's slot in the rate limit window, for requests that are rejected
+    /// after all, so that they don't count against the client.
+    fn cancel(self) {
+        let mut counters = self.limiter.counters.lock().expect("poisoned lock");
+        if let Some(c) = counters.get_mut(&self.key) {
+            if c.window_start == Some(self.window_start) {
+                c.requests_in_window = c.requests_in_window.saturating_sub(1);
+            }
+        }
+    }
+}
+
+impl Drop for RequestLimitGuard {
+    fn drop(&mut self) {
+        let mut counters = self.limiter.counters.lock().expect("poisoned lock");
+        if let Some(c) = counters.get_mut(&self.key) {
+            c.in_flight = c.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+impl RequestLimiter {
+    fn acquire(&self, limit: &RequestLimit, now: Instant) -> Result<RequestLimitGuard, ErrorKind> {
+        let mut counters = self.counters.lock().expect("poisoned lock");
+        let c = counters.entry(limit.identities.clone()).or_default();
+
+        let window_start = match c.window_start {
+            Some(start) if now.duration_since(start) < Duration::from_secs(1) => start,
+            _ => {
+                c.window_start = Some(now);
+                c.requests_in_window = 0;
+                now
+            }
+        };
+
+        if let Some(rps) = limit.requests_per_second {
+            if c.requests_in_window >= rps.get() {
+                STATS::rate_limited.add_value(1);
+                return Err(ErrorKind::RequestRateLimited(rps.get()));
+            }
+        }
+
+        if let Some(max) = limit.max_concurrent_requests {
+            if c.in_flight >= max.get() {
+                STATS::concurrency_limited.add_value(1);
+                return Err(ErrorKind::ConcurrentRequestsLimited(max.get()));
+            }
+        }
+
+        c.requests_in_window += 1;
+        c.in_flight += 1;
+
+        Ok(RequestLimitGuard {
+            limiter: self.clone(),
+            key: limit.identities.clone(),
+            window_start,
+        })
+    }
+}
+
+/// Enforces the per-client `request_limits` from the server config. Unlike
+/// `ThrottleMiddleware`, which sheds load based on server-wide counters, this limits how many
+/// requests each group of clients can make. Request rates are also checked across the fleet if
+/// the config has `distributed_rate_limits`.
+#[derive(Clone, NewMiddleware)]
+pub struct RequestLimitMiddleware {
+    handle: ConfigHandle<ServerConfig>,
+    limiter: RequestLimiter,
+    distributed: DistributedLimiter,
+}
+
+impl RequestLimitMiddleware {
+    pub fn new(handle: ConfigHandle<ServerConfig>, distributed: DistributedLimiter) -> Self {
+        Self {
+            handle,
+            limiter: RequestLimiter::default(),
+            distributed,
+        }
+    }
+}
+
+impl Middleware for RequestLimitMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        if is_health_check(&state) {
+            return chain(state);
+        }
+        let identities = state
+            .try_borrow::<MetadataState>()
+            .map(|metadata_state| metadata_state.metadata().identities());
+
+        let config = self.handle.get();
+        let limit = match config.request_limit(identities) {
+            Some(limit) => limit,
+            None => return chain(state),
+        };
+
+        let guard = match self.limiter.acquire(limit, Instant::now()) {
+            Ok(guard) => guard,
+            Err(err) => {
+                let err = HttpError::e429(err);
+                return async move { build_error_response(err, state, &LfsErrorFormatter) }.boxed();
+            }
+        };
+
+        let distributed_config = match config.distributed_rate_limits() {
+            Some(distributed_config) => distributed_config.clone(),
+            None => {
+                return chain(state)
+                    .map(move |res| {
+                        res.map(|(state, res)| {
+                            (state, res.map(|body| hold_until_sent(guard, body)))
+                        })
+                    })
+                    .boxed();
+            }
+        };
+
+        let distributed = self.distributed.clone();
+        let limit = limit.clone();
+        async move {
+            if let Err(err) = distributed.acquire(&distributed_config, &limit).await {
+                guard.cancel();
+                let err = HttpError::e429(err);
+                return build_error_response(err, state, &LfsErrorFormatter);
+            }
+
+            let (state, res) = chain(state).await?;
+            Ok((state, res.map(|body| hold_until_sent(guard, body))))
+        }
+        .boxed()
+    }
+}
+
+struct IpBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets for each address throttled by the `ip_throttle` config. Buckets hold up to one
+/// second worth of requests.
+#[derive(Clone, Default)]
+struct IpLimiter {
+    buckets: Arc<Mutex<HashMap<IpAddr, IpBucket>>>,
+}
+
+impl IpLimiter {
+    fn acquire(
+        &self,
+        ip: IpAddr,
+        requests_per_second: NonZeroU32,
+        now: Instant,
+    ) -> Result<(), ErrorKind> {
+        let rate = requests_per_second.get() as f64;
+        let mut buckets = self.buckets.lock().expect("poisoned lock");
+
+        if buckets.len() >= MAX_TRACKED_IPS {
+            // Buckets that were idle for a second are full, which is how new ones start.
+            buckets
+                .retain(|_, b| now.saturating_duration_since(b.updated) < Duration::from_secs(1));
+        }
+
+        let bucket = buckets.entry(ip).or_insert_with(|| IpBucket {
+            tokens: rate,
+            updated: now,
+        });
+
+        if now > bucket.updated {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+            bucket.updated = now;
+        }
+
+        if bucket.tokens < 1.0 {
+            STATS::ip_rate_limited.add_value(1);
+            return Err(ErrorKind::IpRateLimited(ip, requests_per_second.get()));
+        }
+
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Throttles clients without identities by their IP address, for deployments where requests
+/// don't come with identities. Clients with identities are limited by `RequestLimitMiddleware`.
+#[derive(Clone, NewMiddleware)]
+pub struct IpThrottleMiddleware {
+    handle: ConfigHandle<ServerConfig>,
+    limiter: IpLimiter,
+}
+
+impl IpThrottleMiddleware {
+    pub fn new(handle: ConfigHandle<ServerConfig>) -> Self {
+        Self {
+            handle,
+            limiter: IpLimiter::default(),
+        }
+    }
+}
+
+impl Middleware for IpThrottleMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        if is_health_check(&state) {
+            return chain(state);
+        }
+
+        let config = self.handle.get();
+        let throttle = match config.ip_throttle() {
+            Some(throttle) => throttle,
+            None => return chain(state),
+        };
+
+        let ip = match state.try_borrow::<MetadataState>() {
+            Some(metadata_state) if metadata_state.metadata().identities().is_empty() => {
+                metadata_state.metadata().client_ip().copied()
+            }
+            _ => None,
+        };
+
+        let (ip, requests_per_second) = match (ip, throttle.requests_per_second) {
+            (Some(ip), Some(rps)) if !throttle.is_exempt(ip) => (ip, rps),
+            _ => return chain(state),
+        };
+
+        match self
+            .limiter
+            .acquire(ip, requests_per_second, Instant::now())
+        {
+            Ok(()) => chain(state),
+            Err(err) => {
+                let err = HttpError::e429(err);
+                async move { build_error_response(err, state, &LfsErrorFormatter) }.boxed()
+            }
+        }
+    }
+}
+
+/// Rejects batch requests while the host is overloaded. Batch requests only start new transfers,
+/// so rejecting them first lets uploads and downloads that are already underway finish.
+#[derive(Clone, NewMiddleware)]
+pub struct HostPressureMiddleware {
+    lfs_ctx: LfsServerContext,
+}
+
+impl HostPressureMiddleware {
+    pub fn new(lfs_ctx: LfsServerContext) -> Self {
+        Self { lfs_ctx }
+    }
+}
+
+fn is_batch_request(uri: &Uri) -> bool {
+    uri.path().trim_end_matches('/').ends_with("/objects/batch")
+}
+
+impl Middleware for HostPressureMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        let is_batch = Uri::try_borrow_from(&state).map_or(false, is_batch_request);
+        if !is_batch {
+            return chain(state);
+        }
+
+        if let Err(err) = self.lfs_ctx.host_pressure().check(&self.lfs_ctx.get_config()) {
+            STATS::host_overloaded.add_value(1);
+            let err = HttpError::e503(err);
+            return async move { build_error_response(err, state, &LfsErrorFormatter) }.boxed();
+        }
+
+        chain(state)
+    }
+}
+
+/// Counts requests in flight, until their response body is sent. Shutdown waits for these, and
+/// `HostPressureMiddleware` compares them against `max_in_flight_requests`.
+#[derive(Clone, NewMiddleware)]
+pub struct InFlightMiddleware {
+    lfs_ctx: LfsServerContext,
+}
+
+impl InFlightMiddleware {
+    pub fn new(lfs_ctx: LfsServerContext) -> Self {
+        Self { lfs_ctx }
+    }
+}
+
+impl Middleware for InFlightMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        if is_health_check(&state) {
+            return chain(state);
+        }
+
+        let request = self.lfs_ctx.host_pressure().start_request();
+        chain(state)
+            .map(move |res| {
+                res.map(|(state, res)| (state, res.map(|body| request.hold_until_sent(body))))
+            })
+            .boxed()
+    }
+}
+
 #[derive(Clone, NewMiddleware)]
 pub struct QpsMiddleware {
     lfs_ctx: LfsServerContext,
@@ -93,10 +459,8 @@ impl Middleware for QpsMiddleware {
     where
         Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
     {
-        if let Some(uri) = Uri::try_borrow_from(&state) {
-            if uri.path() == "/health_check" {
-                return chain(state);
-            }
+        if is_health_check(&state) {
+            return chain(state);
         }
 
         let headers = HeaderMap::try_borrow_from(&state).expect("No headers in the request");
@@ -122,3 +486,234 @@ fn bump_qps(headers: &HeaderMap, qps: &Option<Qps>) -> Result<()> {
         None => Err(anyhow!("No {:?} header.", HEADER_REVPROXY_REGION)),
     }
 }
+
+/// Injects the faults in the config into requests, to test how the deployment copes with them.
+/// Does nothing unless fault injection was allowed when the server was started.
+#[derive(Clone, NewMiddleware)]
+pub struct FaultInjectionMiddleware {
+    handle: ConfigHandle<ServerConfig>,
+    allowed: bool,
+}
+
+impl FaultInjectionMiddleware {
+    pub fn new(handle: ConfigHandle<ServerConfig>, allowed: bool) -> Self {
+        Self { handle, allowed }
+    }
+}
+
+/// Whether a fault injected into `percentage` percent of requests is injected into this one.
+fn roll(percentage: u64) -> bool {
+    percentage > 0 && rand::thread_rng().gen_range(0..100) < percentage
+}
+
+impl Middleware for FaultInjectionMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        if !self.allowed {
+            return chain(state);
+        }
+
+        let route = Uri::try_borrow_from(&state).map_or("other", |uri| route_name(uri.path()));
+        let faults = match self.handle.get().route_faults(route) {
+            Some(faults) => faults.clone(),
+            None => return chain(state),
+        };
+
+        async move {
+            if roll(faults.latency_percentage) {
+                tokio::time::sleep(faults.latency).await;
+            }
+
+            if roll(faults.error_percentage) {
+                let err = HttpError::e503(ErrorKind::InjectedFault(route));
+                return build_error_response(err, state, &LfsErrorFormatter);
+            }
+
+            chain(state).await
+        }
+        .boxed()
+    }
+}
+
+type BoxedChain = Box<dyn FnOnce(State) -> Pin<Box<HandlerFuture>> + Send>;
+
+/// Runs the optional middlewares in the order listed by the config's `middleware_pipeline`, so
+/// that deployments can leave some out or reorder them without a code change. The pipeline is
+/// read for each request, so changes apply to the next request. Requests that make it through
+/// the pipeline are always counted as in flight, so that shutdown waits for them whatever the
+/// config says.
+#[derive(Clone, NewMiddleware)]
+pub struct ConfigurablePipelineMiddleware {
+    handle: ConfigHandle<ServerConfig>,
+    load_shedding: ThrottleMiddleware,
+    request_limits: RequestLimitMiddleware,
+    ip_throttle: IpThrottleMiddleware,
+    host_pressure: HostPressureMiddleware,
+    qps: QpsMiddleware,
+    fault_injection: FaultInjectionMiddleware,
+    in_flight: InFlightMiddleware,
+}
+
+impl ConfigurablePipelineMiddleware {
+    pub fn new(fb: FacebookInit, lfs_ctx: LfsServerContext, allow_fault_injection: bool) -> Self {
+        let handle = lfs_ctx.get_config_handle();
+        Self {
+            load_shedding: ThrottleMiddleware::new(fb, handle.clone()),
+            request_limits: RequestLimitMiddleware::new(
+                handle.clone(),
+                lfs_ctx.distributed_limiter().clone(),
+            ),
+            ip_throttle: IpThrottleMiddleware::new(handle.clone()),
+            host_pressure: HostPressureMiddleware::new(lfs_ctx.clone()),
+            qps: QpsMiddleware::new(lfs_ctx.clone()),
+            in_flight: InFlightMiddleware::new(lfs_ctx),
+            fault_injection: FaultInjectionMiddleware::new(handle.clone(), allow_fault_injection),
+            handle,
+        }
+    }
+
+    /// Runs `stage`, followed by `chain`.
+    fn stage(&self, stage: PipelineStage, chain: BoxedChain) -> BoxedChain {
+        match stage {
+            PipelineStage::LoadShedding => {
+                let middleware = self.load_shedding.clone();
+                Box::new(move |state| middleware.call(state, chain))
+            }
+            PipelineStage::RequestLimits => {
+                let middleware = self.request_limits.clone();
+                Box::new(move |state| middleware.call(state, chain))
+            }
+            PipelineStage::IpThrottle => {
+                let middleware = self.ip_throttle.clone();
+                Box::new(move |state| middleware.call(state, chain))
+            }
+            PipelineStage::HostPressure => {
+                let middleware = self.host_pressure.clone();
+                Box::new(move |state| middleware.call(state, chain))
+            }
+            PipelineStage::Qps => {
+                let middleware = self.qps.clone();
+                Box::new(move |state| middleware.call(state, chain))
+            }
+            PipelineStage::FaultInjection => {
+                let middleware = self.fault_injection.clone();
+                Box::new(move |state| middleware.call(state, chain))
+            }
+            // The access log is written by `SampledLogMiddleware`, which checks the pipeline
+            // itself.
+            PipelineStage::AccessLog => chain,
+        }
+    }
+}
+
+impl Middleware for ConfigurablePipelineMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        let in_flight = self.in_flight.clone();
+        let chain: BoxedChain = Box::new(move |state| in_flight.call(state, chain));
+
+        let config = self.handle.get();
+        let chain = config
+            .middleware_pipeline()
+            .iter()
+            .rev()
+            .fold(chain, |chain, stage| self.stage(*stage, chain));
+
+        chain(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    #[test]
+    fn test_is_batch_request() {
+        let uri = |s: &str| s.parse::<Uri>().unwrap();
+        assert!(is_batch_request(&uri("/repo/objects/batch")));
+        assert!(is_batch_request(&uri("/repo/objects/batch/")));
+        assert!(!is_batch_request(&uri("/repo/download/abcd")));
+    }
+
+    #[test]
+    fn test_roll() {
+        assert!(!(0..1000).any(|_| roll(0)));
+        assert!((0..1000).all(|_| roll(100)));
+    }
+
+    #[test]
+    fn test_ip_limiter() {
+        let limiter = IpLimiter::default();
+        let rate = NonZeroU32::new(2).unwrap();
+        let ip1: IpAddr = "10.0.0.1".parse().unwrap();
+        let ip2: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.acquire(ip1, rate, now).is_ok());
+        assert!(limiter.acquire(ip1, rate, now).is_ok());
+        assert!(matches!(
+            limiter.acquire(ip1, rate, now),
+            Err(ErrorKind::IpRateLimited(ip, 2)) if ip == ip1
+        ));
+
+        // Each address has its own bucket.
+        assert!(limiter.acquire(ip2, rate, now).is_ok());
+
+        // Tokens are refilled over time.
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.acquire(ip1, rate, later).is_ok());
+        assert!(limiter.acquire(ip1, rate, later).is_err());
+    }
+
+    #[test]
+    fn test_request_limiter() {
+        let limiter = RequestLimiter::default();
+        let limit = RequestLimit {
+            identities: BTreeSet::new(),
+            requests_per_second: NonZeroU32::new(3),
+            max_concurrent_requests: NonZeroU32::new(2),
+            max_concurrent_uploads: None,
+            max_egress_bytes_per_second: None,
+            max_upload_bytes_per_day: None,
+            max_upload_bytes_per_week: None,
+        };
+        let now = Instant::now();
+
+        let g1 = limiter.acquire(&limit, now).unwrap();
+        let g2 = limiter.acquire(&limit, now).unwrap();
+        assert!(matches!(
+            limiter.acquire(&limit, now),
+            Err(ErrorKind::ConcurrentRequestsLimited(2))
+        ));
+
+        drop(g1);
+        let g3 = limiter.acquire(&limit, now).unwrap();
+        drop(g2);
+        drop(g3);
+        assert!(matches!(
+            limiter.acquire(&limit, now),
+            Err(ErrorKind::RequestRateLimited(3))
+        ));
+
+        // A new window resets the rate, but not the concurrency count.
+        let later = now + Duration::from_secs(1);
+        let g4 = limiter.acquire(&limit, later).unwrap();
+
+        // Cancelled requests don't count towards the rate.
+        limiter.acquire(&limit, later).unwrap().cancel();
+        limiter.acquire(&limit, later).unwrap().cancel();
+        let _g5 = limiter.acquire(&limit, later).unwrap();
+        drop(g4);
+        let _g6 = limiter.acquire(&limit, later).unwrap();
+        assert!(matches!(
+            limiter.acquire(&limit, later),
+            Err(ErrorKind::RequestRateLimited(3))
+        ));
+    }
+}
@@ -6,6 +6,8 @@
  */
 
 use std::pin::Pin;
+use std::time::Instant;
+use std::time::SystemTime;
 
 use fbinit::FacebookInit;
 use futures::FutureExt;
@@ -21,18 +23,28 @@ use gotham::router::builder::DrawRoutes;
 use gotham::router::Router;
 use gotham::state::FromState;
 use gotham::state::State;
+use gotham_ext::error::HttpError;
+use gotham_ext::middleware::MetadataState;
+use gotham_ext::response::build_error_response;
 use gotham_ext::response::build_response;
 use hyper::Body;
 use hyper::Response;
 use hyper::StatusCode;
 
 use super::error_formatter::LfsErrorFormatter;
-use super::middleware::QpsMiddleware;
-use super::middleware::ThrottleMiddleware;
+use super::middleware::ConfigurablePipelineMiddleware;
 use crate::batch;
+use crate::config_status::ReloadOutcome;
 use crate::download;
+use crate::errors::ErrorKind;
+use crate::errors::LfsServerContextErrorKind;
 use crate::git_upload;
 use crate::lfs_server_context::LfsServerContext;
+use crate::locks;
+use crate::popularity::DEFAULT_POPULAR_LIMIT;
+use crate::popularity::MAX_POPULAR_LIMIT;
+use crate::popularity::PopularQueryString;
+use crate::popularity::PopularReport;
 use crate::upload;
 
 // These 3 methods are wrappers to go from async fn's to the implementations Gotham expects,
@@ -45,6 +57,46 @@ fn batch_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
     .boxed()
 }
 
+fn verify_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let res = batch::verify(&mut state).await;
+        build_response(res, state, &LfsErrorFormatter)
+    }
+    .boxed()
+}
+
+fn create_lock_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let res = locks::create_lock(&mut state).await;
+        build_response(res, state, &LfsErrorFormatter)
+    }
+    .boxed()
+}
+
+fn list_locks_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let res = locks::list_locks(&mut state).await;
+        build_response(res, state, &LfsErrorFormatter)
+    }
+    .boxed()
+}
+
+fn verify_locks_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let res = locks::verify_locks(&mut state).await;
+        build_response(res, state, &LfsErrorFormatter)
+    }
+    .boxed()
+}
+
+fn unlock_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let res = locks::unlock(&mut state).await;
+        build_response(res, state, &LfsErrorFormatter)
+    }
+    .boxed()
+}
+
 fn download_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
     async move {
         let res = download::download(&mut state).await;
@@ -61,6 +113,14 @@ fn download_sha256_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
     .boxed()
 }
 
+fn exists_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let res = download::exists(&mut state).await;
+        build_response(res, state, &LfsErrorFormatter)
+    }
+    .boxed()
+}
+
 fn upload_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
     async move {
         let res = upload::upload(&mut state).await;
@@ -77,9 +137,13 @@ fn git_upload_blob_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
     .boxed()
 }
 
+fn is_draining(lfs_ctx: &LfsServerContext) -> bool {
+    lfs_ctx.will_exit() || lfs_ctx.get_config().drain()
+}
+
 fn health_handler(state: State) -> (State, &'static str) {
     let lfs_ctx = LfsServerContext::borrow_from(&state);
-    let res = if lfs_ctx.will_exit() {
+    let res = if is_draining(lfs_ctx) {
         "EXITING"
     } else {
         "I_AM_ALIVE"
@@ -87,6 +151,19 @@ fn health_handler(state: State) -> (State, &'static str) {
     (state, res)
 }
 
+/// Like `health_handler`, but reports draining through the status code, for load balancers that
+/// don't look at the body.
+fn health_status_handler(state: State) -> (State, Response<Body>) {
+    let lfs_ctx = LfsServerContext::borrow_from(&state);
+    let (status, body) = if is_draining(lfs_ctx) {
+        (StatusCode::SERVICE_UNAVAILABLE, "EXITING")
+    } else {
+        (StatusCode::OK, "I_AM_ALIVE")
+    };
+    let res = create_response(&state, status, mime::TEXT_PLAIN, body);
+    (state, res)
+}
+
 fn config_handler(state: State) -> (State, Response<Body>) {
     let lfs_ctx = LfsServerContext::borrow_from(&state);
 
@@ -98,14 +175,111 @@ fn config_handler(state: State) -> (State, Response<Body>) {
     (state, res)
 }
 
+/// Serves where the live config came from and how long ago it last changed.
+fn config_status_handler(state: State) -> (State, Response<Body>) {
+    let lfs_ctx = LfsServerContext::borrow_from(&state);
+    let report = lfs_ctx.config_status().report(SystemTime::now());
+
+    let res = match serde_json::to_string(&report) {
+        Ok(json) => create_response(&state, StatusCode::OK, mime::APPLICATION_JSON, json),
+        Err(_) => create_empty_response(&state, StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    (state, res)
+}
+
+/// Polls the config source right away, so operators can confirm that a config change landed
+/// without waiting for the next poll. Only clients in the config's `admin_acl` may do this.
+fn reload_config_handler(state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let lfs_ctx = LfsServerContext::borrow_from(&state).clone();
+        let identities = state
+            .try_borrow::<MetadataState>()
+            .map(|metadata_state| metadata_state.metadata().identities());
+        let allowed = lfs_ctx.get_config().allows_admin(identities);
+
+        if !allowed {
+            let err = HttpError::e403(LfsServerContextErrorKind::Forbidden);
+            return build_error_response(err, state, &LfsErrorFormatter);
+        }
+
+        let config_handle = lfs_ctx.get_config_handle();
+        let outcome =
+            tokio::task::spawn_blocking(move || lfs_ctx.config_status().reload(&config_handle))
+                .await
+                .unwrap_or_else(|e| ReloadOutcome::Error {
+                    message: e.to_string(),
+                });
+
+        let status = match outcome {
+            ReloadOutcome::Error { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ReloadOutcome::Updated | ReloadOutcome::Unchanged => StatusCode::OK,
+        };
+        let res = match serde_json::to_string(&outcome) {
+            Ok(json) => create_response(&state, status, mime::APPLICATION_JSON, json),
+            Err(_) => create_empty_response(&state, StatusCode::INTERNAL_SERVER_ERROR),
+        };
+
+        Ok((state, res))
+    }
+    .boxed()
+}
+
+/// Serves the objects downloaded the most from this server over the `popularity_report`
+/// window. Only clients in the config's `admin_acl` may see this.
+fn popular_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let lfs_ctx = LfsServerContext::borrow_from(&state).clone();
+        let identities = state
+            .try_borrow::<MetadataState>()
+            .map(|metadata_state| metadata_state.metadata().identities());
+        let config = lfs_ctx.get_config();
+
+        if !config.allows_admin(identities) {
+            let err = HttpError::e403(LfsServerContextErrorKind::Forbidden);
+            return build_error_response(err, state, &LfsErrorFormatter);
+        }
+
+        let report = match config.popularity_report() {
+            Some(report) => report,
+            None => {
+                let err = HttpError::e404(ErrorKind::PopularityReportDisabled);
+                return build_error_response(err, state, &LfsErrorFormatter);
+            }
+        };
+
+        let query = PopularQueryString::take_from(&mut state);
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_POPULAR_LIMIT)
+            .min(MAX_POPULAR_LIMIT);
+        let popular = PopularReport {
+            window_secs: report.window.as_secs(),
+            objects: lfs_ctx.popular_objects().top(report, limit, Instant::now()),
+        };
+
+        let res = match serde_json::to_string(&popular) {
+            Ok(json) => create_response(&state, StatusCode::OK, mime::APPLICATION_JSON, json),
+            Err(_) => create_empty_response(&state, StatusCode::INTERNAL_SERVER_ERROR),
+        };
+
+        Ok((state, res))
+    }
+    .boxed()
+}
+
 pub fn build_router(
     fb: FacebookInit,
     lfs_ctx: LfsServerContext,
     allow_git_blob_upload: bool,
+    allow_fault_injection: bool,
 ) -> Router {
     let pipeline = new_pipeline()
-        .add(ThrottleMiddleware::new(fb, lfs_ctx.get_config_handle()))
-        .add(QpsMiddleware::new(lfs_ctx.clone()))
+        .add(ConfigurablePipelineMiddleware::new(
+            fb,
+            lfs_ctx.clone(),
+            allow_fault_injection,
+        ))
         .add(StateMiddleware::new(lfs_ctx))
         .build();
 
@@ -117,6 +291,32 @@ pub fn build_router(
             .with_path_extractor::<batch::BatchParams>()
             .to(batch_handler);
 
+        route
+            .post("/:repository/verify")
+            .with_path_extractor::<batch::VerifyParams>()
+            .to(verify_handler);
+
+        route
+            .post("/:repository/locks")
+            .with_path_extractor::<locks::LocksParams>()
+            .to(create_lock_handler);
+
+        route
+            .get("/:repository/locks")
+            .with_path_extractor::<locks::LocksParams>()
+            .with_query_string_extractor::<locks::ListLocksQueryString>()
+            .to(list_locks_handler);
+
+        route
+            .post("/:repository/locks/verify")
+            .with_path_extractor::<locks::LocksParams>()
+            .to(verify_locks_handler);
+
+        route
+            .post("/:repository/locks/:id/unlock")
+            .with_path_extractor::<locks::UnlockParams>()
+            .to(unlock_handler);
+
         route
             .get("/:repository/download/:content_id")
             .with_path_extractor::<download::DownloadParamsContentId>()
@@ -127,6 +327,11 @@ pub fn build_router(
             .with_path_extractor::<download::DownloadParamsSha256>()
             .to(download_sha256_handler);
 
+        route
+            .head("/:repository/download_sha256/:oid")
+            .with_path_extractor::<download::DownloadParamsSha256>()
+            .to(exists_handler);
+
         route
             .put("/:repository/upload/:oid/:size")
             .with_path_extractor::<upload::UploadParams>()
@@ -140,6 +345,13 @@ pub fn build_router(
         }
 
         route.get("/health_check").to(health_handler);
+        route.get("/health").to(health_status_handler);
         route.get("/config").to(config_handler);
+        route.get("/config/status").to(config_status_handler);
+        route.post("/admin/reload_config").to(reload_config_handler);
+        route
+            .get("/popular")
+            .with_query_string_extractor::<PopularQueryString>()
+            .to(popular_handler);
     })
 }
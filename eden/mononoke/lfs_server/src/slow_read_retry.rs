@@ -0,0 +1,176 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use blobstore::Blobstore;
+use blobstore::BlobstoreBytes;
+use blobstore::BlobstoreGetData;
+use blobstore::BlobstoreIsPresent;
+use context::CoreContext;
+use futures::future;
+use futures::future::Either;
+use futures::pin_mut;
+use stats::prelude::*;
+
+define_stats! {
+    prefix = "mononoke.lfs.slow_read_retry";
+    retried: timeseries(Rate, Sum),
+    retry_won: timeseries(Rate, Sum),
+}
+
+/// Retries any read that hasn't completed after `delay` against the same blobstore, without
+/// cancelling the first attempt, and uses whichever attempt completes first. This is not a hedge
+/// across stores: both attempts go through the same blobstore stack, so the retry only helps when
+/// the slowness is specific to the first attempt, e.g. a stalled connection or a slow host behind
+/// a multiplexed or load balanced store. It doubles the load of slow reads, and does nothing for
+/// a store that is slow as a whole. Writes go straight to the inner blobstore.
+#[derive(Clone, Debug)]
+pub struct SlowReadRetryBlobstore<B> {
+    inner: B,
+    delay: Option<Duration>,
+}
+
+impl<B> SlowReadRetryBlobstore<B> {
+    /// Reads are not retried if `delay` is `None`.
+    pub fn new(inner: B, delay: Option<Duration>) -> Self {
+        Self { inner, delay }
+    }
+}
+
+impl<B: fmt::Display> fmt::Display for SlowReadRetryBlobstore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SlowReadRetryBlobstore<{}>", &self.inner)
+    }
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for SlowReadRetryBlobstore<B> {
+    async fn get<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<Option<BlobstoreGetData>> {
+        let delay = match self.delay {
+            Some(delay) => delay,
+            None => return self.inner.get(ctx, key).await,
+        };
+
+        let first = self.inner.get(ctx, key);
+        let timer = tokio::time::sleep(delay);
+        pin_mut!(timer);
+
+        let first = match future::select(first, timer).await {
+            Either::Left((res, _)) => return res,
+            Either::Right(((), first)) => first,
+        };
+
+        STATS::retried.add_value(1);
+        let second = self.inner.get(ctx, key);
+
+        // If either attempt fails, the other one might still succeed.
+        match future::select(first, second).await {
+            Either::Left((Ok(res), _)) => Ok(res),
+            Either::Right((Ok(res), _)) => {
+                STATS::retry_won.add_value(1);
+                Ok(res)
+            }
+            Either::Left((Err(_), other)) | Either::Right((Err(_), other)) => other.await,
+        }
+    }
+
+    async fn put<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<()> {
+        self.inner.put(ctx, key, value).await
+    }
+
+    async fn is_present<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<BlobstoreIsPresent> {
+        self.inner.is_present(ctx, key).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use anyhow::Error;
+    use fbinit::FacebookInit;
+    use memblob::Memblob;
+
+    use super::*;
+
+    /// A blobstore whose first read never completes.
+    #[derive(Debug)]
+    struct StuckFirstRead {
+        inner: Memblob,
+        reads: AtomicU64,
+    }
+
+    impl fmt::Display for StuckFirstRead {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "StuckFirstRead")
+        }
+    }
+
+    #[async_trait]
+    impl Blobstore for StuckFirstRead {
+        async fn get<'a>(
+            &'a self,
+            ctx: &'a CoreContext,
+            key: &'a str,
+        ) -> Result<Option<BlobstoreGetData>> {
+            if self.reads.fetch_add(1, Ordering::Relaxed) == 0 {
+                future::pending::<()>().await;
+            }
+            self.inner.get(ctx, key).await
+        }
+
+        async fn put<'a>(
+            &'a self,
+            ctx: &'a CoreContext,
+            key: String,
+            value: BlobstoreBytes,
+        ) -> Result<()> {
+            self.inner.put(ctx, key, value).await
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_slow_read_retry(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let inner = Arc::new(StuckFirstRead {
+            inner: Memblob::default(),
+            reads: AtomicU64::new(0),
+        });
+        let value = BlobstoreBytes::from_bytes("foo");
+        inner.put(&ctx, "key".to_string(), value.clone()).await?;
+
+        let blobstore = SlowReadRetryBlobstore::new(inner.clone(), Some(Duration::from_millis(10)));
+        let res = blobstore.get(&ctx, "key").await?;
+        assert_eq!(res.map(|data| data.into_bytes()), Some(value));
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 2);
+
+        // Reads that complete in time are only sent once.
+        assert!(blobstore.get(&ctx, "missing").await?.is_none());
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 3);
+
+        Ok(())
+    }
+}
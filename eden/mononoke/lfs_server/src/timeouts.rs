@@ -0,0 +1,286 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fmt;
+use std::future::Future;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use anyhow::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use blobstore::Blobstore;
+use blobstore::BlobstoreBytes;
+use blobstore::BlobstoreGetData;
+use blobstore::BlobstoreIsPresent;
+use cached_config::ConfigHandle;
+use context::CoreContext;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use futures::stream;
+use futures::Stream;
+use futures::StreamExt;
+use hyper::service::Service;
+use hyper::Uri;
+use stats::prelude::*;
+use tokio::time::Instant;
+
+use crate::config::ServerConfig;
+use crate::errors::ErrorKind;
+
+define_stats! {
+    prefix = "mononoke.lfs.timeouts";
+    blobstore: timeseries(Rate, Sum),
+    upstream: timeseries(Rate, Sum),
+}
+
+/// Fails blobstore calls that take longer than `timeout`, instead of waiting on them forever.
+#[derive(Clone, Debug)]
+pub struct TimeoutBlobstore<B> {
+    inner: B,
+    timeout: Option<Duration>,
+}
+
+impl<B> TimeoutBlobstore<B> {
+    /// Calls are not bounded if `timeout` is `None`.
+    pub fn new(inner: B, timeout: Option<Duration>) -> Self {
+        Self { inner, timeout }
+    }
+
+    async fn call<T>(&self, fut: impl Future<Output = Result<T>>) -> Result<T> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return fut.await,
+        };
+
+        match tokio::time::timeout(timeout, fut).await {
+            Ok(res) => res,
+            Err(_) => {
+                STATS::blobstore.add_value(1);
+                Err(ErrorKind::BlobstoreTimeout(timeout).into())
+            }
+        }
+    }
+}
+
+impl<B: fmt::Display> fmt::Display for TimeoutBlobstore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TimeoutBlobstore<{}>", &self.inner)
+    }
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for TimeoutBlobstore<B> {
+    async fn get<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<Option<BlobstoreGetData>> {
+        self.call(self.inner.get(ctx, key)).await
+    }
+
+    async fn put<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<()> {
+        self.call(self.inner.put(ctx, key, value)).await
+    }
+
+    async fn is_present<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<BlobstoreIsPresent> {
+        self.call(self.inner.is_present(ctx, key)).await
+    }
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Fails connections to the upstream that take longer than the `upstream_timeouts` in the live
+/// config. The config is read for each connection, so changes apply to the next one.
+#[derive(Clone)]
+pub struct ConnectTimeout<C> {
+    inner: C,
+    config_handle: ConfigHandle<ServerConfig>,
+}
+
+impl<C> ConnectTimeout<C> {
+    pub fn new(inner: C, config_handle: ConfigHandle<ServerConfig>) -> Self {
+        Self {
+            inner,
+            config_handle,
+        }
+    }
+}
+
+impl<C> Service<Uri> for ConnectTimeout<C>
+where
+    C: Service<Uri>,
+    C::Response: Send + 'static,
+    C::Error: Into<BoxError>,
+    C::Future: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let timeout = self.config_handle.get().upstream_timeouts().connect;
+        let connect = self.inner.call(dst);
+
+        async move {
+            let timeout = match timeout {
+                Some(timeout) => timeout,
+                None => return connect.await.map_err(Into::into),
+            };
+
+            match tokio::time::timeout(timeout, connect).await {
+                Ok(res) => res.map_err(Into::into),
+                Err(_) => {
+                    STATS::upstream.add_value(1);
+                    Err(ErrorKind::UpstreamConnectTimeout(timeout).into())
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Fails an upstream request that hasn't responded after `timeout`.
+pub async fn upstream_response<T, E>(
+    fut: impl Future<Output = Result<T, E>>,
+    timeout: Option<Duration>,
+) -> Result<Result<T, E>, ErrorKind> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Ok(fut.await),
+    };
+
+    tokio::time::timeout(timeout, fut).await.map_err(|_| {
+        STATS::upstream.add_value(1);
+        ErrorKind::UpstreamTimeout(timeout)
+    })
+}
+
+/// Fails an upstream response body that hasn't been read by `started + timeout`, and ends it
+/// there.
+pub fn upstream_body<S, T>(
+    body: S,
+    started: Instant,
+    timeout: Option<Duration>,
+) -> impl Stream<Item = Result<T, Error>>
+where
+    S: Stream<Item = Result<T, Error>>,
+{
+    stream::unfold(Some(Box::pin(body)), move |body| async move {
+        let mut body = body?;
+
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return body.next().await.map(|item| (item, Some(body))),
+        };
+
+        match tokio::time::timeout_at(started + timeout, body.next()).await {
+            Ok(item) => item.map(|item| (item, Some(body))),
+            Err(_) => {
+                STATS::upstream.add_value(1);
+                Some((Err(ErrorKind::UpstreamTimeout(timeout).into()), None))
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use fbinit::FacebookInit;
+    use futures::future;
+    use futures::TryStreamExt;
+    use memblob::Memblob;
+
+    use super::*;
+
+    #[fbinit::test]
+    async fn test_timeout_blobstore(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let blobstore = TimeoutBlobstore::new(Memblob::default(), Some(Duration::from_secs(10)));
+
+        let value = BlobstoreBytes::from_bytes("foo");
+        blobstore
+            .put(&ctx, "key".to_string(), value.clone())
+            .await?;
+        let res = blobstore.get(&ctx, "key").await?;
+        assert_eq!(res.map(|data| data.into_bytes()), Some(value));
+
+        let blobstore = TimeoutBlobstore::new(Memblob::default(), Some(Duration::from_millis(10)));
+        let res = blobstore.call(future::pending::<Result<()>>()).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::BlobstoreTimeout(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.upstream_timeouts = Some(lfs_server_config::BackendTimeouts {
+            connect_ms: 10,
+            first_byte_ms: 0,
+            total_ms: 0,
+        });
+        let config_handle = ServerConfig::test_handle(raw)?;
+
+        let connector = hyper::service::service_fn(|_: Uri| future::pending::<Result<(), Error>>());
+        let mut connector = ConnectTimeout::new(connector, config_handle);
+        let err = connector
+            .call(Uri::from_static("https://upstream.example.com"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::UpstreamConnectTimeout(_))
+        ));
+
+        // Connections aren't bounded without a connect timeout.
+        let connector = hyper::service::service_fn(|_: Uri| future::ready(Ok::<_, Error>(())));
+        let mut connector = ConnectTimeout::new(connector, ConfigHandle::default());
+        let res = connector
+            .call(Uri::from_static("https://upstream.example.com"))
+            .await;
+        assert!(res.is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upstream_body() -> Result<(), Error> {
+        let body = stream::iter(vec![Ok::<_, Error>(1), Ok(2)]);
+        let items = upstream_body(body, Instant::now(), Some(Duration::from_secs(10)))
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(items, vec![1, 2]);
+
+        // Bodies that take too long end with an error.
+        let body = stream::iter(vec![Ok::<_, Error>(1)]).chain(stream::pending());
+        let items = upstream_body(body, Instant::now(), Some(Duration::from_millis(10)))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(items.len(), 2);
+        assert!(items[1].is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,680 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Spans for requests, and for the blobstore and upstream calls they make, exported to the trace
+//! collector in the `trace_export` config. Requests that carry a W3C `traceparent` header continue
+//! the caller's trace, and upstream requests carry ours, so that latency can be followed across
+//! services. Spans are queued and sent in batches in the background, and dropped if the collector
+//! can't keep up, so that tracing never slows requests down.
+
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::bail;
+use anyhow::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use blobstore::Blobstore;
+use blobstore::BlobstoreBytes;
+use blobstore::BlobstoreGetData;
+use blobstore::BlobstoreIsPresent;
+use cached_config::ConfigHandle;
+use context::CoreContext;
+use gotham_derive::StateData;
+use hostname::get_hostname;
+use http::header::HeaderMap;
+use http::StatusCode;
+use hyper::Body;
+use hyper::Client;
+use hyper::Request;
+use hyper_openssl::HttpsConnector;
+use rand::Rng;
+use serde_json::json;
+use serde_json::Value;
+use slog::warn;
+use slog::Logger;
+use stats::prelude::*;
+use tokio::time;
+
+use crate::config::ServerConfig;
+use crate::config::TraceExport;
+use crate::config::TraceProtocol;
+use crate::lfs_server_context::HttpsHyperClient;
+use crate::middleware::LfsMethod;
+
+define_stats! {
+    prefix = "mononoke.lfs.trace_export";
+    spans: timeseries(Rate, Sum),
+    dropped: timeseries(Rate, Sum),
+    exported: timeseries(Rate, Sum),
+    export_failed: timeseries(Rate, Sum),
+}
+
+pub const TRACEPARENT: &str = "traceparent";
+
+/// How often to check whether the config has a `trace_export` when it doesn't.
+const EXPORT_DISABLED_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Name of the instrumentation in OTLP exports.
+const SCOPE_NAME: &str = "mononoke_lfs_server";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    /// A request this server handled.
+    Server,
+    /// A request this server made to another service.
+    Client,
+    Internal,
+}
+
+/// A finished span, waiting to be exported.
+#[derive(Debug, Clone)]
+pub struct SpanData {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    duration: Duration,
+    attributes: Vec<(&'static str, String)>,
+    /// Why the operation failed, if it did.
+    error: Option<String>,
+}
+
+/// Queues finished spans, and sends them to the collector in the background.
+#[derive(Clone, Debug, Default)]
+pub struct TraceExporter {
+    queue: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl TraceExporter {
+    fn record(&self, export: &TraceExport, span: SpanData) {
+        let mut queue = self.queue.lock().expect("poisoned lock");
+        if queue.len() >= export.max_queued_spans {
+            STATS::dropped.add_value(1);
+            return;
+        }
+        STATS::spans.add_value(1);
+        queue.push(span);
+    }
+
+    fn take(&self) -> Vec<SpanData> {
+        mem::take(&mut *self.queue.lock().expect("poisoned lock"))
+    }
+
+    /// Sends the queued spans every `flush_interval` of the `trace_export` config. Spans that
+    /// can't be sent are dropped, rather than retried, so that a collector outage doesn't grow the
+    /// queue.
+    pub fn spawn_exporter(
+        &self,
+        config_handle: ConfigHandle<ServerConfig>,
+        logger: Logger,
+    ) -> Result<(), Error> {
+        let client: HttpsHyperClient = Client::builder().build(HttpsConnector::new()?);
+        let hostname = get_hostname().unwrap_or_else(|_| "UNKNOWN_HOSTNAME".to_string());
+        let exporter = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let export = match config_handle.get().trace_export() {
+                    Some(export) => export.clone(),
+                    None => {
+                        exporter.take();
+                        time::sleep(EXPORT_DISABLED_POLL_INTERVAL).await;
+                        continue;
+                    }
+                };
+
+                time::sleep(export.flush_interval).await;
+
+                let spans = exporter.take();
+                if spans.is_empty() {
+                    continue;
+                }
+
+                let count = spans.len();
+                match send(&client, &export, &hostname, spans).await {
+                    Ok(()) => STATS::exported.add_value(count as i64),
+                    Err(err) => {
+                        STATS::export_failed.add_value(count as i64);
+                        warn!(
+                            logger,
+                            "Failed to export {} spans to {}: {:#}",
+                            count,
+                            export.collector_url,
+                            err
+                        );
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+async fn send(
+    client: &HttpsHyperClient,
+    export: &TraceExport,
+    hostname: &str,
+    spans: Vec<SpanData>,
+) -> Result<(), Error> {
+    let body = match export.protocol {
+        TraceProtocol::Otlp => encode_otlp(&export.service_name, hostname, &spans),
+        TraceProtocol::Zipkin => encode_zipkin(&export.service_name, &spans),
+    };
+
+    let req = Request::post(export.collector_url.clone())
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(&body)?))?;
+    let res = client.request(req).await?;
+
+    if !res.status().is_success() {
+        bail!("Collector responded with {}", res.status());
+    }
+
+    Ok(())
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos())
+}
+
+/// Encodes spans as an OTLP/HTTP `ExportTraceServiceRequest`, in its JSON encoding.
+fn encode_otlp(service_name: &str, hostname: &str, spans: &[SpanData]) -> Value {
+    let attribute = |key: &str, value: &str| json!({"key": key, "value": {"stringValue": value}});
+
+    let spans = spans
+        .iter()
+        .map(|span| {
+            let start = unix_nanos(span.start);
+            let end = start + span.duration.as_nanos();
+            let kind = match span.kind {
+                SpanKind::Internal => 1,
+                SpanKind::Server => 2,
+                SpanKind::Client => 3,
+            };
+            let status = match &span.error {
+                None => json!({"code": 1}),
+                Some(error) => json!({"code": 2, "message": error}),
+            };
+
+            json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "spanId": format!("{:016x}", span.span_id),
+                "parentSpanId": span.parent_id.map_or_else(String::new, |id| format!("{:016x}", id)),
+                "name": span.name,
+                "kind": kind,
+                "startTimeUnixNano": start.to_string(),
+                "endTimeUnixNano": end.to_string(),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| attribute(key, value))
+                    .collect::<Vec<_>>(),
+                "status": status,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", service_name),
+                    attribute("host.name", hostname),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": {"name": SCOPE_NAME},
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// Encodes spans as a list of Zipkin v2 spans, in JSON.
+fn encode_zipkin(service_name: &str, spans: &[SpanData]) -> Value {
+    let spans = spans
+        .iter()
+        .map(|span| {
+            let mut tags = span
+                .attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), Value::from(value.as_str())))
+                .collect::<serde_json::Map<_, _>>();
+            if let Some(error) = &span.error {
+                tags.insert("error".to_string(), Value::from(error.as_str()));
+            }
+
+            let mut encoded = json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "id": format!("{:016x}", span.span_id),
+                "name": span.name,
+                "timestamp": (unix_nanos(span.start) / 1000) as u64,
+                // Zipkin drops spans with a duration of 0.
+                "duration": (span.duration.as_micros() as u64).max(1),
+                "localEndpoint": {"serviceName": service_name},
+                "tags": tags,
+            });
+            if let Some(parent_id) = span.parent_id {
+                encoded["parentId"] = Value::from(format!("{:016x}", parent_id));
+            }
+            match span.kind {
+                SpanKind::Server => encoded["kind"] = Value::from("SERVER"),
+                SpanKind::Client => encoded["kind"] = Value::from("CLIENT"),
+                SpanKind::Internal => {}
+            }
+            encoded
+        })
+        .collect::<Vec<_>>();
+
+    Value::from(spans)
+}
+
+/// The trace id, parent span id and sampling decision in a W3C `traceparent` header, e.g.
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+fn parse_traceparent(header: &str) -> Option<(u128, u64, bool)> {
+    let mut parts = header.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+
+    // Later versions may append fields, but version 00 has exactly these.
+    if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    if trace_id.len() != 32 || parent_id.len() != 16 || flags.len() != 2 {
+        return None;
+    }
+
+    let trace_id = u128::from_str_radix(trace_id, 16)
+        .ok()
+        .filter(|id| *id != 0)?;
+    let parent_id = u64::from_str_radix(parent_id, 16)
+        .ok()
+        .filter(|id| *id != 0)?;
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+
+    Some((trace_id, parent_id, flags & 1 == 1))
+}
+
+fn format_traceparent(trace_id: u128, span_id: u64) -> String {
+    format!("00-{:032x}-{:016x}-01", trace_id, span_id)
+}
+
+fn new_span_id() -> u64 {
+    rand::thread_rng().gen_range(1..=u64::MAX)
+}
+
+/// The trace of a request that was sampled, whose root span covers the whole request.
+#[derive(Clone, Debug, StateData)]
+pub struct RequestTrace {
+    exporter: TraceExporter,
+    export: TraceExport,
+    trace_id: u128,
+    span_id: u64,
+    /// The caller's span, if the request continues its trace.
+    parent_id: Option<u64>,
+    method: LfsMethod,
+    repository: String,
+}
+
+impl RequestTrace {
+    /// Starts tracing a request, if the config exports traces and the request is sampled. Requests
+    /// that continue a trace follow the caller's sampling decision.
+    pub fn start(
+        exporter: &TraceExporter,
+        config: &ServerConfig,
+        headers: Option<&HeaderMap>,
+        method: LfsMethod,
+        repository: &str,
+    ) -> Option<Self> {
+        let export = config.trace_export()?;
+
+        let traceparent = headers
+            .and_then(|headers| headers.get(TRACEPARENT))
+            .and_then(|header| header.to_str().ok())
+            .and_then(parse_traceparent);
+
+        let (trace_id, parent_id) = match traceparent {
+            Some((trace_id, parent_id, sampled)) => {
+                if !sampled {
+                    return None;
+                }
+                (trace_id, Some(parent_id))
+            }
+            None => {
+                if rand::thread_rng().gen_range(0..export.sample_rate.get()) != 0 {
+                    return None;
+                }
+                (rand::thread_rng().gen_range(1..=u128::MAX), None)
+            }
+        };
+
+        Some(Self {
+            exporter: exporter.clone(),
+            export: export.clone(),
+            trace_id,
+            span_id: new_span_id(),
+            parent_id,
+            method,
+            repository: repository.to_string(),
+        })
+    }
+
+    /// Starts a span for part of the request.
+    pub fn span(&self, name: &str, kind: SpanKind) -> Span {
+        Span {
+            trace: self.clone(),
+            span_id: new_span_id(),
+            name: name.to_string(),
+            kind,
+            start: SystemTime::now(),
+            started: Instant::now(),
+            attributes: vec![],
+        }
+    }
+
+    /// Ends the root span of the request, which ended now after `duration`.
+    pub fn finish(self, duration: Duration, status: StatusCode, error: Option<String>) {
+        let start = SystemTime::now()
+            .checked_sub(duration)
+            .unwrap_or_else(SystemTime::now);
+        let error = error.or_else(|| status.is_server_error().then(|| status.to_string()));
+
+        let span = SpanData {
+            trace_id: self.trace_id,
+            span_id: self.span_id,
+            parent_id: self.parent_id,
+            name: self.method.to_string(),
+            kind: SpanKind::Server,
+            start,
+            duration,
+            attributes: vec![
+                ("lfs.repository", self.repository.clone()),
+                ("lfs.method", self.method.to_string()),
+                ("http.status_code", status.as_u16().to_string()),
+            ],
+            error,
+        };
+        self.exporter.record(&self.export, span);
+    }
+}
+
+/// A span for part of a traced request. It is only exported once it is finished.
+pub struct Span {
+    trace: RequestTrace,
+    span_id: u64,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    started: Instant,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl Span {
+    pub fn attribute(&mut self, key: &'static str, value: impl ToString) {
+        self.attributes.push((key, value.to_string()));
+    }
+
+    /// The `traceparent` header for a request made as part of this span.
+    pub fn traceparent(&self) -> String {
+        format_traceparent(self.trace.trace_id, self.span_id)
+    }
+
+    pub fn finish(self, error: Option<String>) {
+        let span = SpanData {
+            trace_id: self.trace.trace_id,
+            span_id: self.span_id,
+            parent_id: Some(self.trace.span_id),
+            name: self.name,
+            kind: self.kind,
+            start: self.start,
+            duration: self.started.elapsed(),
+            attributes: self.attributes,
+            error,
+        };
+        self.trace.exporter.record(&self.trace.export, span);
+    }
+}
+
+/// Runs `fut` in a span named `name`, if the request is traced.
+async fn traced<T>(
+    trace: Option<&RequestTrace>,
+    name: &str,
+    key: &str,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let mut span = match trace {
+        Some(trace) => trace.span(name, SpanKind::Client),
+        None => return fut.await,
+    };
+    span.attribute("blobstore.key", key);
+
+    let res = fut.await;
+    span.finish(res.as_ref().err().map(|err| format!("{:#}", err)));
+    res
+}
+
+/// Records a span for each blobstore call of a traced request.
+#[derive(Clone, Debug)]
+pub struct TracedBlobstore<B> {
+    inner: B,
+    trace: Option<RequestTrace>,
+}
+
+impl<B> TracedBlobstore<B> {
+    pub fn new(inner: B, trace: Option<RequestTrace>) -> Self {
+        Self { inner, trace }
+    }
+}
+
+impl<B: fmt::Display> fmt::Display for TracedBlobstore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TracedBlobstore<{}>", &self.inner)
+    }
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for TracedBlobstore<B> {
+    async fn get<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<Option<BlobstoreGetData>> {
+        traced(
+            self.trace.as_ref(),
+            "blobstore.get",
+            key,
+            self.inner.get(ctx, key),
+        )
+        .await
+    }
+
+    async fn put<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<()> {
+        let traced_key = key.clone();
+        traced(
+            self.trace.as_ref(),
+            "blobstore.put",
+            &traced_key,
+            self.inner.put(ctx, key, value),
+        )
+        .await
+    }
+
+    async fn is_present<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<BlobstoreIsPresent> {
+        traced(
+            self.trace.as_ref(),
+            "blobstore.is_present",
+            key,
+            self.inner.is_present(ctx, key),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU64;
+
+    use super::*;
+
+    fn export() -> TraceExport {
+        TraceExport {
+            collector_url: "http://collector:4318/v1/traces".parse().unwrap(),
+            protocol: TraceProtocol::Otlp,
+            sample_rate: NonZeroU64::new(1).unwrap(),
+            service_name: "mononoke-lfs".to_string(),
+            flush_interval: Duration::from_secs(1),
+            max_queued_spans: 2,
+        }
+    }
+
+    #[test]
+    fn test_parse_traceparent() {
+        assert_eq!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some((0x4bf92f3577b34da6a3ce929d0e0e4736, 0x00f067aa0ba902b7, true))
+        );
+        assert_eq!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"),
+            Some((
+                0x4bf92f3577b34da6a3ce929d0e0e4736,
+                0x00f067aa0ba902b7,
+                false
+            ))
+        );
+
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-xyz92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(parse_traceparent(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_request_trace() {
+        let exporter = TraceExporter::default();
+        let mut config = ServerConfig::default();
+
+        // Nothing is traced without a trace_export config.
+        assert!(RequestTrace::start(&exporter, &config, None, LfsMethod::Batch, "repo").is_none());
+
+        *config.trace_export_mut() = Some(export());
+
+        // Callers' sampling decisions are followed.
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT,
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"
+                .parse()
+                .unwrap(),
+        );
+        assert!(
+            RequestTrace::start(&exporter, &config, Some(&headers), LfsMethod::Batch, "repo")
+                .is_none()
+        );
+
+        headers.insert(
+            TRACEPARENT,
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        let trace =
+            RequestTrace::start(&exporter, &config, Some(&headers), LfsMethod::Batch, "repo")
+                .expect("trace is sampled");
+
+        let mut span = trace.span("blobstore.get", SpanKind::Client);
+        span.attribute("blobstore.key", "key");
+        assert!(
+            span.traceparent()
+                .starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-")
+        );
+        span.finish(None);
+        trace.finish(
+            Duration::from_millis(10),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            None,
+        );
+
+        let spans = exporter.take();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].parent_id, Some(spans[1].span_id));
+        assert_eq!(spans[1].parent_id, Some(0x00f067aa0ba902b7));
+        assert_eq!(spans[1].name, "batch");
+        assert_eq!(spans[1].error.as_deref(), Some("500 Internal Server Error"));
+
+        // Spans past max_queued_spans are dropped.
+        let trace = RequestTrace::start(&exporter, &config, None, LfsMethod::Upload, "repo")
+            .expect("trace is sampled");
+        for _ in 0..3 {
+            trace.span("blobstore.put", SpanKind::Client).finish(None);
+        }
+        assert_eq!(exporter.take().len(), 2);
+    }
+
+    #[test]
+    fn test_encode() {
+        let span = SpanData {
+            trace_id: 1,
+            span_id: 2,
+            parent_id: None,
+            name: "download".to_string(),
+            kind: SpanKind::Server,
+            start: UNIX_EPOCH + Duration::from_secs(1),
+            duration: Duration::from_millis(5),
+            attributes: vec![("lfs.repository", "repo".to_string())],
+            error: Some("failed".to_string()),
+        };
+
+        let otlp = encode_otlp("lfs", "host", &[span.clone()]);
+        let encoded = &otlp["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(encoded["traceId"], "00000000000000000000000000000001");
+        assert_eq!(encoded["spanId"], "0000000000000002");
+        assert_eq!(encoded["parentSpanId"], "");
+        assert_eq!(encoded["kind"], 2);
+        assert_eq!(encoded["startTimeUnixNano"], "1000000000");
+        assert_eq!(encoded["endTimeUnixNano"], "1005000000");
+        assert_eq!(encoded["status"]["code"], 2);
+
+        let zipkin = encode_zipkin("lfs", &[span]);
+        let encoded = &zipkin[0];
+        assert_eq!(encoded["id"], "0000000000000002");
+        assert_eq!(encoded["timestamp"], 1000000);
+        assert_eq!(encoded["duration"], 5000);
+        assert_eq!(encoded["kind"], "SERVER");
+        assert_eq!(encoded["tags"]["lfs.repository"], "repo");
+        assert_eq!(encoded["tags"]["error"], "failed");
+        assert!(encoded.get("parentId").is_none());
+    }
+}
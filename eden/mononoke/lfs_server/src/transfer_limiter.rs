@@ -0,0 +1,203 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use stats::prelude::*;
+use tokio::sync::Notify;
+
+use crate::config::ServerConfig;
+use crate::errors::ErrorKind;
+
+define_stats! {
+    prefix = "mononoke.lfs.transfer_limit";
+    upload_queue_wait_ms: histogram(10, 0, 5000, Average, Sum, Count; P 50; P 95; P 99),
+    download_queue_wait_ms: histogram(10, 0, 5000, Average, Sum, Count; P 50; P 95; P 99),
+    upload_queue_timeout: timeseries(Rate, Sum),
+    download_queue_timeout: timeseries(Rate, Sum),
+}
+
+/// Waiters check the limit again this often, so that they notice when it is raised.
+const RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransferKind {
+    Upload,
+    Download,
+}
+
+impl fmt::Display for TransferKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Upload => write!(f, "upload"),
+            Self::Download => write!(f, "download"),
+        }
+    }
+}
+
+impl TransferKind {
+    pub fn record_wait(&self, wait: Duration) {
+        let wait = wait.as_millis() as i64;
+        match self {
+            Self::Upload => STATS::upload_queue_wait_ms.add_value(wait),
+            Self::Download => STATS::download_queue_wait_ms.add_value(wait),
+        }
+    }
+
+    pub fn record_timeout(&self) {
+        match self {
+            Self::Upload => STATS::upload_queue_timeout.add_value(1),
+            Self::Download => STATS::download_queue_timeout.add_value(1),
+        }
+    }
+}
+
+/// How long a transfer that started waiting for a slot at `start` should wait before checking
+/// again, or `None` once it waited for `timeout`.
+pub fn next_wait(start: Instant, timeout: Option<Duration>) -> Option<Duration> {
+    match timeout {
+        Some(timeout) => match timeout.checked_sub(start.elapsed()) {
+            Some(remaining) if !remaining.is_zero() => Some(remaining.min(RECHECK_INTERVAL)),
+            _ => None,
+        },
+        None => Some(RECHECK_INTERVAL),
+    }
+}
+
+#[derive(Default)]
+struct Slots {
+    in_flight: Mutex<u64>,
+    released: Notify,
+}
+
+impl Slots {
+    fn try_take(&self, max: Option<u64>) -> bool {
+        let mut in_flight = self.in_flight.lock().expect("poisoned lock");
+        if max.map_or(false, |max| *in_flight >= max) {
+            return false;
+        }
+        *in_flight += 1;
+        true
+    }
+}
+
+/// Limits how many downloads are transferred at once, so that a burst of them can't starve
+/// uploads. Requests past the limit wait for a slot. Uploads are limited by `UploadLimiter`
+/// instead. Limits are read from the config of each request, so changes apply to requests that
+/// arrive after them.
+#[derive(Clone, Default)]
+pub struct TransferLimiter {
+    downloads: Arc<Slots>,
+}
+
+/// Held for as long as a transfer occupies a slot.
+pub struct TransferPermit {
+    slots: Arc<Slots>,
+}
+
+impl Drop for TransferPermit {
+    fn drop(&mut self) {
+        let mut in_flight = self.slots.in_flight.lock().expect("poisoned lock");
+        *in_flight = in_flight.saturating_sub(1);
+        self.slots.released.notify_one();
+    }
+}
+
+impl TransferLimiter {
+    /// Waits for a download slot, up to the config's transfer queue timeout.
+    pub async fn acquire(&self, config: &ServerConfig) -> Result<TransferPermit, ErrorKind> {
+        let kind = TransferKind::Download;
+        let slots = &self.downloads;
+        let max = config.max_concurrent_download_transfers();
+        let timeout = config.transfer_queue_timeout();
+        let start = Instant::now();
+
+        loop {
+            let released = slots.released.notified();
+
+            if slots.try_take(max) {
+                kind.record_wait(start.elapsed());
+                return Ok(TransferPermit {
+                    slots: slots.clone(),
+                });
+            }
+
+            let wait = match next_wait(start, timeout) {
+                Some(wait) => wait,
+                None => {
+                    kind.record_timeout();
+                    return Err(ErrorKind::TransferQueueTimeout(
+                        kind,
+                        timeout.unwrap_or_default(),
+                    ));
+                }
+            };
+
+            let _ = tokio::time::timeout(wait, released).await;
+        }
+    }
+
+    #[cfg(test)]
+    fn in_flight(&self) -> u64 {
+        *self.downloads.in_flight.lock().expect("poisoned lock")
+    }
+}
+
+#[cfg(test)]
+    fn in_flight(&self, kind: TransferKind) -> u64 {
+        *self.slots(kind).in_flight.lock().expect("poisoned lock")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Error;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_acquire() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.max_concurrent_download_transfers = 1;
+        raw.transfer_queue_timeout_ms = 50;
+        let config = ServerConfig::try_from(raw)?;
+
+        let limiter = TransferLimiter::default();
+        let download = limiter.acquire(&config).await?;
+        assert_eq!(limiter.in_flight(), 1);
+
+        assert!(matches!(
+            limiter.acquire(&config).await,
+            Err(ErrorKind::TransferQueueTimeout(TransferKind::Download, _))
+        ));
+
+        // A waiting download gets the slot once it is released.
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(&config).await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(download);
+        waiting.await??;
+
+        assert_eq!(limiter.in_flight(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_wait() {
+        let start = Instant::now();
+        assert_eq!(next_wait(start, None), Some(RECHECK_INTERVAL));
+        assert_eq!(next_wait(start, Some(Duration::ZERO)), None);
+        assert!(next_wait(start, Some(Duration::from_secs(60))).is_some());
+    }
+}
@@ -8,10 +8,14 @@
 use std::collections::HashMap;
 use std::str;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Instant;
 
 use anyhow::Context;
 use anyhow::Error;
 use bytes::Bytes;
+use bytes::BytesMut;
 use filestore::Alias;
 use filestore::FetchKey;
 use filestore::FilestoreConfigRef;
@@ -32,8 +36,12 @@ use gotham_ext::middleware::ScubaMiddlewareState;
 use gotham_ext::response::EmptyBody;
 use gotham_ext::response::TryIntoResponse;
 use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_RANGE;
+use http::header::HeaderMap;
+use http::header::HeaderValue;
 use hyper::Body;
 use hyper::Request;
+use hyper::Response;
 use lfs_protocol::ObjectAction;
 use lfs_protocol::ObjectStatus;
 use lfs_protocol::Operation;
@@ -43,14 +51,26 @@ use lfs_protocol::ResponseBatch;
 use lfs_protocol::Sha256 as LfsSha256;
 use lfs_protocol::Transfer;
 use mononoke_types::hash::Sha256;
-use repo_blobstore::RepoBlobstoreRef;
+use permission_checker::MononokeIdentitySet;
 use serde::Deserialize;
+use slog::warn;
 use stats::prelude::*;
+use tokio::sync::Notify;
 
+use crate::config::ServerConfig;
 use crate::errors::ErrorKind;
+use crate::lfs_server_context::LfsServerContext;
 use crate::lfs_server_context::RepositoryRequestContext;
 use crate::middleware::LfsMethod;
+use crate::quotas;
+use crate::replication::REPLICATED_HEADER;
+use crate::resumable_upload;
+use crate::resumable_upload::ContentRange;
+use crate::resumable_upload::PartialUpload;
+use crate::resumable_upload::UploadSession;
 use crate::scuba::LfsScubaKey;
+use crate::transfer_limiter;
+use crate::transfer_limiter::TransferKind;
 use crate::util::read_header_value;
 
 define_stats! {
@@ -59,12 +79,159 @@ define_stats! {
     upstream_success: timeseries(Rate, Sum),
     internal_uploads: timeseries(Rate, Sum),
     internal_success: timeseries(Rate, Sum),
+    concurrency_limited: timeseries(Rate, Sum),
+    content_mismatch: timeseries(Rate, Sum),
     size_bytes: histogram(1_500_000, 0, 150_000_000, Average, Sum, Count; P 5; P 25; P 50; P 75; P 95; P 97; P 99),
 }
 
 // Small buffers for Filestore & Dewey
 const BUFFER_SIZE: usize = 5;
 
+// Tells resumable upload clients how many bytes of the object were received so far.
+const UPLOAD_OFFSET: &str = "upload-offset";
+
+// Identifies a resumable upload. Clients get it with the first part, and send it with the others.
+const UPLOAD_SESSION: &str = "upload-session";
+
+#[derive(Default)]
+struct UploadCounts {
+    total: u32,
+    queued: u64,
+    by_identities: HashMap<MononokeIdentitySet, u32>,
+}
+
+/// Counts uploads in flight, in total and for each group of clients sharing a `RequestLimit`, to
+/// enforce `max_concurrent_uploads`. Uploads past the server-wide limit wait for a slot, up to the
+/// config's transfer queue timeout, while fewer than `max_queued_uploads` are already waiting.
+/// Other uploads past the limits are rejected.
+#[derive(Clone, Default)]
+pub struct UploadLimiter {
+    counts: Arc<Mutex<UploadCounts>>,
+    released: Arc<Notify>,
+}
+
+/// Held for as long as an upload counts towards the limits.
+pub struct UploadPermit {
+    limiter: UploadLimiter,
+    identities: Option<MononokeIdentitySet>,
+}
+
+impl Drop for UploadPermit {
+    fn drop(&mut self) {
+        let mut counts = self.limiter.counts.lock().expect("poisoned lock");
+        counts.total = counts.total.saturating_sub(1);
+        if let Some(identities) = &self.identities {
+            if let Some(count) = counts.by_identities.get_mut(identities) {
+                *count = count.saturating_sub(1);
+            }
+        }
+        self.limiter.released.notify_one();
+    }
+}
+
+/// Held for as long as an upload waits for a slot.
+struct QueuedUpload {
+    limiter: UploadLimiter,
+}
+
+impl Drop for QueuedUpload {
+    fn drop(&mut self) {
+        let mut counts = self.limiter.counts.lock().expect("poisoned lock");
+        counts.queued = counts.queued.saturating_sub(1);
+    }
+}
+
+impl UploadLimiter {
+    pub async fn acquire(
+        &self,
+        config: &ServerConfig,
+        client_idents: Option<&MononokeIdentitySet>,
+    ) -> Result<UploadPermit, ErrorKind> {
+        let timeout = config.transfer_queue_timeout();
+        let start = Instant::now();
+        let mut queued = None;
+
+        loop {
+            let released = self.released.notified();
+
+            if let Some(permit) = self.try_acquire(config, client_idents, &mut queued)? {
+                if queued.is_some() {
+                    TransferKind::Upload.record_wait(start.elapsed());
+                }
+                return Ok(permit);
+            }
+
+            let wait = match transfer_limiter::next_wait(start, timeout) {
+                Some(wait) => wait,
+                None => {
+                    TransferKind::Upload.record_timeout();
+                    return Err(ErrorKind::TransferQueueTimeout(
+                        TransferKind::Upload,
+                        timeout.unwrap_or_default(),
+                    ));
+                }
+            };
+
+            let _ = tokio::time::timeout(wait, released).await;
+        }
+    }
+
+    /// Takes a slot if there is one. If the server-wide limit is reached, this joins the queue,
+    /// unless it is full, and returns `None`.
+    fn try_acquire(
+        &self,
+        config: &ServerConfig,
+        client_idents: Option<&MononokeIdentitySet>,
+        queued: &mut Option<QueuedUpload>,
+    ) -> Result<Option<UploadPermit>, ErrorKind> {
+        let mut counts = self.counts.lock().expect("poisoned lock");
+
+        if let Some(max) = config.max_concurrent_uploads() {
+            if counts.total >= max.get() {
+                if queued.is_none() {
+                    if counts.queued >= config.max_queued_uploads().unwrap_or(0) {
+                        STATS::concurrency_limited.add_value(1);
+                        return Err(ErrorKind::ConcurrentUploadsLimited(max.get()));
+                    }
+                    counts.queued += 1;
+                    *queued = Some(QueuedUpload {
+                        limiter: self.clone(),
+                    });
+                }
+                return Ok(None);
+            }
+        }
+
+        let identities = match config.request_limit(client_idents) {
+            Some(limit) => {
+                if let Some(max) = limit.max_concurrent_uploads {
+                    let count = counts
+                        .by_identities
+                        .get(&limit.identities)
+                        .copied()
+                        .unwrap_or(0);
+                    if count >= max.get() {
+                        STATS::concurrency_limited.add_value(1);
+                        return Err(ErrorKind::ConcurrentUploadsLimited(max.get()));
+                    }
+                }
+                *counts
+                    .by_identities
+                    .entry(limit.identities.clone())
+                    .or_default() += 1;
+                Some(limit.identities.clone())
+            }
+            None => None,
+        };
+        counts.total += 1;
+
+        Ok(Some(UploadPermit {
+            limiter: self.clone(),
+            identities,
+        }))
+    }
+}
+
 mod closeable_sender {
     use std::pin::Pin;
 
@@ -167,15 +334,24 @@ where
 {
     STATS::internal_uploads.add_value(1);
 
+    // The Filestore hashes the content as it streams in, and only makes it reachable by its
+    // aliases once the hashes and size match what was requested.
     filestore::store(
-        ctx.repo.repo_blobstore(),
+        &ctx.blobstore(),
         *ctx.repo.filestore_config(),
         &ctx.ctx,
         &StoreRequest::with_sha256(size, oid),
         data,
     )
     .await
-    .context(ErrorKind::FilestoreWriteFailure)?;
+    .map_err(|e| match e.downcast_ref::<filestore::ErrorKind>() {
+        Some(filestore::ErrorKind::InvalidSha256(_))
+        | Some(filestore::ErrorKind::InvalidSize(..)) => {
+            STATS::content_mismatch.add_value(1);
+            e.context(ErrorKind::UploadContentMismatch(oid))
+        }
+        _ => e.context(ErrorKind::FilestoreWriteFailure),
+    })?;
 
     STATS::internal_success.add_value(1);
 
@@ -279,10 +455,17 @@ where
 
     let mut received: usize = 0;
 
+    // The declared size is what gets checked against the upload size limits, so stop reading as
+    // soon as the client sends more than that.
     let mut data = body
-        .map_ok(|chunk| {
+        .map(|chunk| {
+            let chunk = chunk?;
             received += chunk.len();
-            chunk
+            if received as u64 > size {
+                Err(())
+            } else {
+                Ok(chunk)
+            }
         })
         .map(Ok);
 
@@ -306,9 +489,60 @@ where
 
     ScubaMiddlewareState::maybe_add(scuba, HttpScubaKey::RequestBytesReceived, received);
 
+    if received as u64 > size {
+        return Err(ErrorKind::UploadBodyTooLarge(size).into());
+    }
+
     res.map(|_| ())
 }
 
+/// Uploads an object whose parts were all received through resumable uploads.
+async fn upload_from_parts(
+    ctx: &RepositoryRequestContext,
+    oid: Sha256,
+    size: u64,
+    session: UploadSession,
+    progress: &PartialUpload,
+    scuba: &mut Option<&mut ScubaMiddlewareState>,
+) -> Result<(), Error> {
+    // Failures can't go through the upload channels, so hold on to them here to report them
+    // instead of a generic cancellation.
+    let part_error = Arc::new(Mutex::new(None));
+
+    let body = resumable_upload::stream_parts(ctx, oid, session, progress).map_err({
+        let part_error = part_error.clone();
+        move |e| {
+            *part_error.lock().expect("poisoned lock") = Some(e);
+        }
+    });
+
+    let res = upload_from_client(ctx, oid, size, body, scuba).await;
+
+    if let Some(e) = part_error.lock().expect("poisoned lock").take() {
+        return Err(e);
+    }
+
+    res
+}
+
+async fn read_part(body: Body, len: u64) -> Result<Bytes, Error> {
+    let mut body = body.map_err(|_| ErrorKind::ClientCancelled);
+    let mut part = BytesMut::new();
+
+    while let Some(chunk) = body.try_next().await? {
+        part.extend_from_slice(&chunk);
+        if part.len() as u64 > len {
+            return Err(ErrorKind::UploadPartSizeMismatch(len).into());
+        }
+    }
+
+    if part.len() as u64 != len {
+        return Err(ErrorKind::UploadPartSizeMismatch(len).into());
+    }
+
+    Ok(part.freeze())
+}
+
 async fn sync_internal_and_upstream(
     ctx: &RepositoryRequestContext,
     oid: Sha256,
@@ -317,7 +551,7 @@ async fn sync_internal_and_upstream(
 ) -> Result<(), Error> {
     let key = FetchKey::Aliased(Alias::Sha256(oid));
 
-    let res = filestore::fetch(ctx.repo.repo_blobstore().clone(), ctx.ctx.clone(), &key).await?;
+    let res = filestore::fetch(ctx.blobstore(), ctx.ctx.clone(), &key).await?;
 
     match res {
         Some(stream) => {
@@ -365,6 +599,157 @@ async fn sync_internal_and_upstream(
     Ok(())
 }
 
+/// The response to an upload. Parts of resumable uploads also report how much of the object was
+/// received, and the session to send the rest of the parts with, so that clients know where to
+/// resume from.
+pub struct UploadResponse {
+    offset: Option<u64>,
+    session: Option<UploadSession>,
+}
+
+impl UploadResponse {
+    fn complete() -> Self {
+        Self {
+            offset: None,
+            session: None,
+        }
+    }
+
+    fn partial(offset: u64, session: Option<UploadSession>) -> Self {
+        Self {
+            offset: Some(offset),
+            session,
+        }
+    }
+}
+
+impl TryIntoResponse for UploadResponse {
+    fn try_into_response(self, state: &mut State) -> Result<Response<Body>, Error> {
+        let mut res = EmptyBody::new().try_into_response(state)?;
+        if let Some(offset) = self.offset {
+            res.headers_mut()
+                .insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+        }
+        if let Some(session) = self.session {
+            res.headers_mut()
+                .insert(UPLOAD_SESSION, HeaderValue::from_str(session.as_str())?);
+        }
+        Ok(res)
+    }
+}
+
+/// Objects pushed to us by a peer were already replicated where they came from.
+fn is_replicated(state: &State) -> bool {
+    HeaderMap::try_borrow_from(state)
+        .map_or(false, |headers| headers.contains_key(REPLICATED_HEADER))
+}
+
+fn upload_error(e: Error) -> HttpError {
+    match e.downcast_ref::<ErrorKind>() {
+        Some(ErrorKind::UploadBodyTooLarge(_)) | Some(ErrorKind::UploadContentMismatch(_)) => {
+            HttpError::e400(e)
+        }
+        Some(ErrorKind::UploadQuotaExceeded(..)) => HttpError::e403(e),
+        _ => HttpError::e500(e),
+    }
+}
+
+/// Handles an upload sent in parts. Parts must be sent in order, and an upload restarts whenever
+/// a part starts from zero, in a new session. The object is uploaded once its last part arrives.
+async fn upload_part(
+    state: &mut State,
+    ctx: &RepositoryRequestContext,
+    oid: Sha256,
+    size: u64,
+    content_range: ContentRange,
+) -> Result<UploadResponse, HttpError> {
+    if content_range.total != size {
+        return Err(HttpError::e400(ErrorKind::ContentRangeSizeMismatch(
+            content_range.total,
+            size,
+        )));
+    }
+
+    let session: Option<UploadSession> = read_header_value(state, UPLOAD_SESSION)
+        .transpose()
+        .map_err(HttpError::e400)?;
+
+    let (start, end) = match (content_range.range, session) {
+        (Some(range), _) => range,
+        (None, Some(session)) => {
+            let progress = resumable_upload::load(ctx, &oid, &session)
+                .await
+                .map_err(HttpError::e500)?;
+            return Ok(UploadResponse::partial(progress.received(), Some(session)));
+        }
+        (None, None) => return Ok(UploadResponse::partial(0, None)),
+    };
+
+    let max_part_size = resumable_upload::max_part_size(ctx);
+    if end - start + 1 > max_part_size {
+        return Err(HttpError::e413(ErrorKind::UploadPartTooLarge(
+            end - start + 1,
+            max_part_size,
+        )));
+    }
+
+    let (session, progress) = match (start, session) {
+        (0, _) => {
+            quotas::check(ctx, size).await.map_err(upload_error)?;
+            (UploadSession::new(), PartialUpload::default())
+        }
+        (start, None) => {
+            return Err(HttpError::e400(ErrorKind::MissingUploadSession(start)));
+        }
+        (start, Some(session)) => {
+            let progress = resumable_upload::load(ctx, &oid, &session)
+                .await
+                .map_err(HttpError::e500)?;
+            if start != progress.received() {
+                return Err(HttpError::e416(ErrorKind::UploadRangeMismatch(
+                    start,
+                    progress.received(),
+                )));
+            }
+            (session, progress)
+        }
+    };
+
+    let part = read_part(Body::take_from(state), end - start + 1)
+        .await
+        .map_err(HttpError::e400)?;
+
+    let progress = resumable_upload::put_part(ctx, &oid, &session, progress, part)
+        .await
+        .map_err(HttpError::e500)?;
+
+    // The last part is only recorded once the object is uploaded, so that it can be retried.
+    if progress.received() < size {
+        resumable_upload::save(ctx, &oid, &session, &progress)
+            .await
+            .map_err(HttpError::e500)?;
+        return Ok(UploadResponse::partial(progress.received(), Some(session)));
+    }
+
+    let mut scuba = state.try_borrow_mut::<ScubaMiddlewareState>();
+    upload_from_parts(ctx, oid, size, session.clone(), &progress, &mut scuba)
+        .await
+        .map_err(upload_error)?;
+    quotas::record(ctx, size).await;
+    let replicated = is_replicated(state);
+    ctx.audit_log().record_upload(ctx, oid, size, replicated);
+    if !replicated {
+        ctx.replicator().replicate(ctx, oid, size);
+    }
+
+    // The object is stored, so failing to clean up after it doesn't fail the upload.
+    if let Err(e) = resumable_upload::discard(ctx, &oid, &session, &progress).await {
+        warn!(ctx.logger(), "Failed to discard parts of {}: {:?}", oid, e);
+    }
+
+    Ok(UploadResponse::complete())
+}
+
 pub async fn upload(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
     let UploadParams {
         repository,
@@ -375,11 +760,21 @@ pub async fn upload(state: &mut State) -> Result<impl TryIntoResponse, HttpError
     let ctx =
         RepositoryRequestContext::instantiate(state, repository.clone(), LfsMethod::Upload).await?;
 
+    let upload_limiter = LfsServerContext::borrow_from(state).upload_limiter().clone();
+    let _permit = upload_limiter
+        .acquire(&ctx.config, Some(ctx.ctx.metadata().identities()))
+        .await
+        .map_err(HttpError::e429)?;
+
     let oid = Sha256::from_str(&oid).map_err(HttpError::e400)?;
+    ctx.check_denied(&oid).map_err(HttpError::e403)?;
     let size = size.parse().map_err(Error::from).map_err(HttpError::e400)?;
     let content_length: Option<u64> = read_header_value(state, CONTENT_LENGTH)
         .transpose()
         .map_err(HttpError::e400)?;
+    let content_range: Option<ContentRange> = read_header_value(state, CONTENT_RANGE)
+        .transpose()
+        .map_err(HttpError::e400)?;
 
     if let Some(content_length) = content_length {
         ScubaMiddlewareState::try_borrow_add(
@@ -400,6 +795,10 @@ pub async fn upload(state: &mut State) -> Result<impl TryIntoResponse, HttpError
         }
     }
 
+    if let Some(content_range) = content_range {
+        return upload_part(state, &ctx, oid, size, content_range).await;
+    }
+
     // The key invariant of our proxy design is that if you upload to this LFS server, then the
     // content will be present in both this LFS server and its upstream. To do so, we've
     // historically asked the client to upload whatever data either server is missing. However,
@@ -420,22 +819,32 @@ pub async fn upload(state: &mut State) -> Result<impl TryIntoResponse, HttpError
                 .map_err(HttpError::e500)?;
         }
         _ => {
+            // Syncs don't take new content from the client, so only these count towards quotas.
+            quotas::check(&ctx, size).await.map_err(upload_error)?;
+
             // TODO: More appropriate status codes here
             let body = Body::take_from(state).map_err(|_| ());
             let mut scuba = state.try_borrow_mut::<ScubaMiddlewareState>();
             upload_from_client(&ctx, oid, size, body, &mut scuba)
                 .await
-                .map_err(HttpError::e500)?;
+                .map_err(upload_error)?;
+            quotas::record(&ctx, size).await;
+            let replicated = is_replicated(state);
+            ctx.audit_log().record_upload(&ctx, oid, size, replicated);
+            if !replicated {
+                ctx.replicator().replicate(&ctx, oid, size);
+            }
         }
     }
 
-    Ok(EmptyBody::new())
+    Ok(UploadResponse::complete())
 }
 
 #[cfg(test)]
 mod test {
     use std::num::NonZeroU32;
     use std::sync::Arc;
+    use std::time::Duration;
 
     use chaosblob::ChaosBlobstore;
     use chaosblob::ChaosOptions;
@@ -443,9 +852,86 @@ mod test {
     use futures::future;
     use futures::stream;
     use memblob::Memblob;
+    use permission_checker::MononokeIdentity;
+    use repo_blobstore::RepoBlobstoreRef;
     use test_repo_factory::TestRepoFactory;
 
     use super::*;
+    use crate::config::RequestLimit;
+
+    #[tokio::test]
+    async fn test_upload_limiter() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.max_concurrent_uploads = 3;
+        let mut config = ServerConfig::try_from(raw)?;
+        let foo = MononokeIdentitySet::from([MononokeIdentity::new("USER", "foo")]);
+        config.request_limits_mut().push(RequestLimit {
+            identities: foo.clone(),
+            requests_per_second: None,
+            max_concurrent_requests: None,
+            max_concurrent_uploads: NonZeroU32::new(1),
+            max_egress_bytes_per_second: None,
+            max_upload_bytes_per_day: None,
+            max_upload_bytes_per_week: None,
+        });
+
+        let limiter = UploadLimiter::default();
+        let p1 = limiter.acquire(&config, Some(&foo)).await?;
+        assert!(matches!(
+            limiter.acquire(&config, Some(&foo)).await,
+            Err(ErrorKind::ConcurrentUploadsLimited(1))
+        ));
+
+        let _p2 = limiter.acquire(&config, None).await?;
+        let _p3 = limiter.acquire(&config, None).await?;
+        assert!(matches!(
+            limiter.acquire(&config, None).await,
+            Err(ErrorKind::ConcurrentUploadsLimited(3))
+        ));
+
+        drop(p1);
+        let _p4 = limiter.acquire(&config, Some(&foo)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_limiter_queue() -> Result<(), Error> {
+        let mut raw = ServerConfig::default().raw_server_config;
+        raw.max_concurrent_uploads = 1;
+        raw.max_queued_uploads = 1;
+        raw.transfer_queue_timeout_ms = 50;
+        let config = ServerConfig::try_from(raw)?;
+
+        let limiter = UploadLimiter::default();
+        let p1 = limiter.acquire(&config, None).await?;
+
+        // Uploads past the limit wait for a slot, up to the queue timeout.
+        assert!(matches!(
+            limiter.acquire(&config, None).await,
+            Err(ErrorKind::TransferQueueTimeout(TransferKind::Upload, _))
+        ));
+
+        // A waiting upload gets the slot once it is released, and uploads past the queue are
+        // rejected meanwhile.
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            let config = config.clone();
+            async move { limiter.acquire(&config, None).await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(
+            limiter.acquire(&config, None).await,
+            Err(ErrorKind::ConcurrentUploadsLimited(1))
+        ));
+        drop(p1);
+        waiting.await??;
+
+        let counts = limiter.counts.lock().unwrap();
+        assert_eq!((counts.total, counts.queued), (0, 0));
+
+        Ok(())
+    }
 
     #[fbinit::test]
     async fn test_upload_from_client_discard_upstream(fb: FacebookInit) -> Result<(), Error> {
@@ -464,6 +950,75 @@ mod test {
         Ok(())
     }
 
+    #[fbinit::test]
+    async fn test_upload_from_client_body_too_large(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = RepositoryRequestContext::test_builder(fb)
+            .await?
+            .upstream_uri(None)
+            .build()?;
+
+        let body = stream::once(future::ready(Ok(Bytes::from("foobar"))));
+        let oid =
+            Sha256::from_str("c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2")?;
+        let size = 3;
+
+        let err = upload_from_client(&ctx, oid, size, body, &mut None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::UploadBodyTooLarge(3))
+        ));
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_upload_from_parts(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = RepositoryRequestContext::test_builder(fb)
+            .await?
+            .upstream_uri(None)
+            .build()?;
+
+        let oid =
+            Sha256::from_str("c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2")?;
+        let size = 6;
+
+        let session = UploadSession::new();
+        let progress = PartialUpload::default();
+        let progress =
+            resumable_upload::put_part(&ctx, &oid, &session, progress, Bytes::from("foo")).await?;
+        resumable_upload::save(&ctx, &oid, &session, &progress).await?;
+
+        // Another upload of the same object doesn't see this one's parts.
+        let other = UploadSession::new();
+        let other_progress = resumable_upload::load(&ctx, &oid, &other).await?;
+        assert_eq!(other_progress.received(), 0);
+
+        let progress = resumable_upload::load(&ctx, &oid, &session).await?;
+        assert_eq!(progress.received(), 3);
+        let progress =
+            resumable_upload::put_part(&ctx, &oid, &session, progress, Bytes::from("bar")).await?;
+
+        upload_from_parts(&ctx, oid, size, session.clone(), &progress, &mut None).await?;
+
+        let key = FetchKey::Aliased(Alias::Sha256(oid));
+        let content = filestore::fetch_concat(ctx.repo.repo_blobstore(), &ctx.ctx, key).await?;
+        assert_eq!(content, Bytes::from("foobar"));
+
+        // Once the object is stored, its parts are discarded.
+        resumable_upload::discard(&ctx, &oid, &session, &progress).await?;
+        let parts: Vec<Bytes> =
+            resumable_upload::stream_parts(&ctx, oid, session.clone(), &progress)
+                .try_collect()
+                .await?;
+        assert!(parts.iter().all(|part| part.is_empty()));
+        let progress = resumable_upload::load(&ctx, &oid, &session).await?;
+        assert_eq!(progress.received(), 0);
+
+        Ok(())
+    }
+
     #[fbinit::test]
     async fn test_upload_from_client_failing_internal(fb: FacebookInit) -> Result<(), Error> {
         // Create a test repo with a blobstore that fails all reads and writes.
@@ -0,0 +1,155 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use http::header::HeaderValue;
+use slog::warn;
+use slog::Logger;
+use stats::prelude::*;
+
+define_stats! {
+    prefix = "mononoke.lfs.upstream_authorization";
+    read_failed: timeseries(Rate, Sum),
+}
+
+/// How often authorization files are read again, to pick up rotated credentials.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+struct AuthorizationFile {
+    read_at: Instant,
+    authorization: Option<HeaderValue>,
+}
+
+/// The Authorization headers sent to upstreams, read from the `authorization_file`s in their
+/// config. Files are read when first needed and again every `RELOAD_INTERVAL`, so that rotated
+/// credentials are picked up without a config change. If a file can't be read again, the last
+/// value read from it keeps being used.
+#[derive(Clone, Default)]
+pub struct UpstreamAuthorization {
+    files: Arc<Mutex<HashMap<PathBuf, AuthorizationFile>>>,
+}
+
+async fn read_authorization(path: &Path) -> Result<HeaderValue, Error> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read authorization_file: {}", path.display()))?;
+    let mut authorization = HeaderValue::from_str(contents.trim())
+        .with_context(|| format!("Invalid authorization in {}", path.display()))?;
+    authorization.set_sensitive(true);
+    Ok(authorization)
+}
+
+impl UpstreamAuthorization {
+    /// The Authorization header held in the file at `path`. This fails if the file was never
+    /// read successfully.
+    pub async fn get(
+        &self,
+        logger: &Logger,
+        path: &Path,
+        now: Instant,
+    ) -> Result<HeaderValue, Error> {
+        {
+            let files = self.files.lock().expect("poisoned lock");
+            if let Some(file) = files.get(path) {
+                if now.saturating_duration_since(file.read_at) < RELOAD_INTERVAL {
+                    return file.authorization.clone().ok_or_else(|| {
+                        anyhow!("authorization_file was not readable: {}", path.display())
+                    });
+                }
+            }
+        }
+
+        let res = read_authorization(path).await;
+
+        let mut files = self.files.lock().expect("poisoned lock");
+        let file = files
+            .entry(path.to_path_buf())
+            .or_insert_with(|| AuthorizationFile {
+                read_at: now,
+                authorization: None,
+            });
+        file.read_at = now;
+
+        match res {
+            Ok(authorization) => {
+                file.authorization = Some(authorization.clone());
+                Ok(authorization)
+            }
+            Err(e) => {
+                STATS::read_failed.add_value(1);
+                match &file.authorization {
+                    Some(authorization) => {
+                        warn!(logger, "Using the last authorization read: {:?}", e);
+                        Ok(authorization.clone())
+                    }
+                    None => Err(e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use slog::o;
+    use slog::Discard;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_upstream_authorization() -> Result<(), Error> {
+        let logger = Logger::root(Discard, o!());
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("authorization");
+        let authorization = UpstreamAuthorization::default();
+        let now = Instant::now();
+
+        // Files that were never read fail, until they are read again.
+        assert!(authorization.get(&logger, &path, now).await.is_err());
+        fs::write(&path, "Bearer token\n")?;
+        assert!(authorization.get(&logger, &path, now).await.is_err());
+
+        let later = now + RELOAD_INTERVAL;
+        let value = authorization.get(&logger, &path, later).await?;
+        assert_eq!(value, "Bearer token");
+        assert!(value.is_sensitive());
+
+        // Rotated credentials are picked up once the file is read again.
+        fs::write(&path, "Bearer rotated")?;
+        assert_eq!(
+            authorization.get(&logger, &path, later).await?,
+            "Bearer token"
+        );
+        let later = later + RELOAD_INTERVAL;
+        assert_eq!(
+            authorization.get(&logger, &path, later).await?,
+            "Bearer rotated"
+        );
+
+        // If the file can't be read again, the last value read is used.
+        fs::remove_file(&path)?;
+        let later = later + RELOAD_INTERVAL;
+        assert_eq!(
+            authorization.get(&logger, &path, later).await?,
+            "Bearer rotated"
+        );
+
+        Ok(())
+    }
+}
@@ -7,13 +7,25 @@
 
 use std::str::FromStr;
 
+use anyhow::Context;
 use anyhow::Error;
+use bytes::Bytes;
+use bytes::BytesMut;
+use futures::Stream;
+use futures::StreamExt;
+use futures::TryStreamExt;
 use gotham::state::FromState;
 use gotham::state::State;
+use gotham_ext::error::HttpError;
 use http::header::AsHeaderName;
 use http::header::HeaderMap;
+use http::header::CONTENT_LENGTH;
+use hyper::body::HttpBody;
+use hyper::Body;
 use permission_checker::MononokeIdentitySet;
 
+use crate::errors::ErrorKind;
+
 pub fn read_header_value<K, T>(state: &State, header: K) -> Option<Result<T, Error>>
 where
     K: AsHeaderName,
@@ -54,3 +66,136 @@ pub fn is_identity_subset<'a>(
         .into_iter()
         .any(|subset_ids| subset_ids.is_subset(client_idents))
 }
+
+async fn concat_limited<S>(
+    body: S,
+    content_length: Option<u64>,
+    limit: Option<u64>,
+) -> Result<Bytes, HttpError>
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>>,
+{
+    let too_large = |size: u64| limit.map_or(false, |limit| size > limit);
+    let too_large_error = || HttpError::e413(ErrorKind::RequestBodyTooLarge(limit.unwrap_or(0)));
+
+    if content_length.map_or(false, too_large) {
+        return Err(too_large_error());
+    }
+
+    let mut buff = BytesMut::with_capacity(content_length.unwrap_or(0) as usize);
+    futures::pin_mut!(body);
+
+    while let Some(chunk) = body
+        .try_next()
+        .await
+        .context(ErrorKind::ClientCancelled)
+        .map_err(HttpError::e400)?
+    {
+        if too_large((buff.len() + chunk.len()) as u64) {
+            return Err(too_large_error());
+        }
+        buff.extend_from_slice(&chunk);
+    }
+
+    Ok(buff.freeze())
+}
+
+/// Reads the whole body of a request, which must not be larger than `limit`. Bodies that declare
+/// a larger Content-Length are rejected without reading them, and others as soon as they go past
+/// the limit.
+pub async fn read_request_body(state: &mut State, limit: Option<u64>) -> Result<Bytes, HttpError> {
+    let content_length = read_header_value(state, CONTENT_LENGTH)
+        .transpose()
+        .map_err(HttpError::e400)?;
+    let body = Body::take_from(state);
+
+    concat_limited(body, content_length, limit).await
+}
+
+/// Holds on to `guard` until `body` has been sent, or dropped because the client went away.
+/// Responses are returned before their body is sent, so anything released when the handler
+/// returns would otherwise be released as soon as the headers go out.
+pub fn hold_until_sent<T: Send + 'static>(guard: T, body: Body) -> Body {
+    if body.is_end_stream() {
+        return body;
+    }
+
+    Body::wrap_stream(body.map(move |chunk| {
+        let _ = &guard;
+        chunk
+    }))
+}
+
+/// The routes that can be named in the server config, besides "other".
+pub const ROUTE_NAMES: &[&str] = &[
+    "batch",
+    "verify",
+    "locks",
+    "download",
+    "download_sha256",
+    "upload",
+    "git_blob_upload",
+    "health",
+    "config",
+];
+
+/// The name of the route a request is for, as used in the server config.
+pub fn route_name(path: &str) -> &'static str {
+    let mut segments = path.trim_start_matches('/').split('/');
+
+    match (segments.next(), segments.next()) {
+        (Some("health_check" | "health"), _) => "health",
+        (Some("config"), _) => "config",
+        (Some("git_blob_upload"), _) => "git_blob_upload",
+        (Some(_), Some("objects")) => "batch",
+        (Some(_), Some("verify")) => "verify",
+        (Some(_), Some("locks")) => "locks",
+        (Some(_), Some("download")) => "download",
+        (Some(_), Some("download_sha256")) => "download_sha256",
+        (Some(_), Some("upload")) => "upload",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::stream;
+    use hyper::StatusCode;
+
+    use super::*;
+
+    #[test]
+    fn test_route_name() {
+        assert_eq!(route_name("/repo1/objects/batch"), "batch");
+        assert_eq!(route_name("/repo1/download/abcd"), "download");
+        assert_eq!(route_name("/repo1/download_sha256/abcd"), "download_sha256");
+        assert_eq!(route_name("/repo1/upload/abcd/6"), "upload");
+        assert_eq!(route_name("/repo1/locks/1/unlock"), "locks");
+        assert_eq!(
+            route_name("/git_blob_upload/repo1/abcd/6"),
+            "git_blob_upload"
+        );
+        assert_eq!(route_name("/health_check"), "health");
+        assert_eq!(route_name("/config/status"), "config");
+        assert_eq!(route_name("/repo1"), "other");
+    }
+
+    #[tokio::test]
+    async fn test_concat_limited() -> Result<(), Error> {
+        let body = || stream::iter(vec![Ok(Bytes::from("12")), Ok(Bytes::from("34"))]);
+
+        let res = concat_limited(body(), None, Some(4)).await;
+        assert_eq!(res.map_err(|e| e.error)?, Bytes::from("1234"));
+        let res = concat_limited(body(), None, None).await;
+        assert_eq!(res.map_err(|e| e.error)?, Bytes::from("1234"));
+
+        let res = concat_limited(body(), None, Some(3)).await;
+        assert_eq!(res.unwrap_err().status_code, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Bodies that declare a size past the limit are rejected without reading them.
+        let res = concat_limited(stream::pending(), Some(5), Some(4)).await;
+        assert_eq!(res.unwrap_err().status_code, StatusCode::PAYLOAD_TOO_LARGE);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! File locks taken by Git LFS clients, so that users editing binary files that can't be merged
+//! don't overwrite each other's changes. Locks are advisory: they are only enforced by clients.
+//!
+//! Locks are stored in a table in the metadata database.
+
+use anyhow::anyhow;
+use anyhow::Result;
+use async_trait::async_trait;
+use context::CoreContext;
+use context::PerfCounterType;
+use mononoke_types::RepositoryId;
+use mononoke_types::Timestamp;
+use sql_construct::SqlConstruct;
+use sql_construct::SqlConstructFromMetadataDatabaseConfig;
+use sql_ext::mononoke_queries;
+use sql_ext::SqlConnections;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LfsLock {
+    pub id: u64,
+    pub path: String,
+    pub owner: String,
+    pub locked_at: Timestamp,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CreateLockOutcome {
+    Created(LfsLock),
+    /// The path was already locked by this lock, which may or may not belong to the same owner.
+    AlreadyLocked(LfsLock),
+}
+
+#[facet::facet]
+#[async_trait]
+pub trait LfsLocks: Send + Sync {
+    /// Lock `path` for `owner`, unless it is already locked.
+    async fn create_lock(
+        &self,
+        ctx: &CoreContext,
+        path: &str,
+        owner: &str,
+    ) -> Result<CreateLockOutcome>;
+
+    async fn get_lock(&self, ctx: &CoreContext, id: u64) -> Result<Option<LfsLock>>;
+
+    async fn get_lock_by_path(&self, ctx: &CoreContext, path: &str) -> Result<Option<LfsLock>>;
+
+    /// List up to `limit` locks in the order they were created, starting after the lock with id
+    /// `after`.
+    async fn list_locks(&self, ctx: &CoreContext, after: u64, limit: u64) -> Result<Vec<LfsLock>>;
+
+    /// Remove a lock. Returns whether it existed.
+    async fn delete_lock(&self, ctx: &CoreContext, id: u64) -> Result<bool>;
+}
+
+mononoke_queries! {
+    write InsertLock(values: (repo_id: RepositoryId, path: String, owner: String, locked_at: Timestamp)) {
+        insert_or_ignore,
+        "{insert_or_ignore} INTO lfs_locks (repo_id, path, owner, locked_at) VALUES {values}"
+    }
+
+    write DeleteLock(repo_id: RepositoryId, id: u64) {
+        none,
+        "DELETE FROM lfs_locks WHERE repo_id = {repo_id} AND id = {id}"
+    }
+
+    read SelectLock(repo_id: RepositoryId, id: u64) -> (u64, String, String, Timestamp) {
+        "SELECT id, path, owner, locked_at FROM lfs_locks WHERE repo_id = {repo_id} AND id = {id}"
+    }
+
+    read SelectLockByPath(repo_id: RepositoryId, path: String) -> (u64, String, String, Timestamp) {
+        "SELECT id, path, owner, locked_at FROM lfs_locks WHERE repo_id = {repo_id} AND path = {path}"
+    }
+
+    read SelectLocks(repo_id: RepositoryId, after: u64, limit: u64) -> (u64, String, String, Timestamp) {
+        "SELECT id, path, owner, locked_at FROM lfs_locks
+         WHERE repo_id = {repo_id} AND id > {after}
+         ORDER BY id
+         LIMIT {limit}"
+    }
+}
+
+fn to_lock((id, path, owner, locked_at): (u64, String, String, Timestamp)) -> LfsLock {
+    LfsLock {
+        id,
+        path,
+        owner,
+        locked_at,
+    }
+}
+
+pub struct SqlLfsLocks {
+    repo_id: RepositoryId,
+    connections: SqlConnections,
+}
+
+pub struct SqlLfsLocksBuilder {
+    connections: SqlConnections,
+}
+
+impl SqlConstruct for SqlLfsLocksBuilder {
+    const LABEL: &'static str = "lfs_locks";
+
+    const CREATION_QUERY: &'static str = include_str!("../schemas/sqlite-lfs-locks.sql");
+
+    fn from_sql_connections(connections: SqlConnections) -> Self {
+        Self { connections }
+    }
+}
+
+impl SqlConstructFromMetadataDatabaseConfig for SqlLfsLocksBuilder {}
+
+impl SqlLfsLocksBuilder {
+    pub fn build(self, repo_id: RepositoryId) -> SqlLfsLocks {
+        SqlLfsLocks {
+            repo_id,
+            connections: self.connections,
+        }
+    }
+}
+
+#[async_trait]
+impl LfsLocks for SqlLfsLocks {
+    async fn create_lock(
+        &self,
+        ctx: &CoreContext,
+        path: &str,
+        owner: &str,
+    ) -> Result<CreateLockOutcome> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlWrites);
+        let path = path.to_string();
+        let owner = owner.to_string();
+        let locked_at = Timestamp::now();
+        let res = InsertLock::query(
+            &self.connections.write_connection,
+            &[(&self.repo_id, &path, &owner, &locked_at)],
+        )
+        .await?;
+
+        match res.last_insert_id() {
+            Some(id) if res.affected_rows() == 1 => Ok(CreateLockOutcome::Created(LfsLock {
+                id,
+                path,
+                owner,
+                locked_at,
+            })),
+            _ => {
+                // Somebody else holds the lock. It might have been released since, in which case
+                // the client can retry.
+                let existing = self
+                    .get_lock_by_path(ctx, &path)
+                    .await?
+                    .ok_or_else(|| anyhow!("Lock on {} was released concurrently", path))?;
+                Ok(CreateLockOutcome::AlreadyLocked(existing))
+            }
+        }
+    }
+
+    async fn get_lock(&self, ctx: &CoreContext, id: u64) -> Result<Option<LfsLock>> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+        let rows =
+            SelectLock::query(&self.connections.read_master_connection, &self.repo_id, &id).await?;
+        Ok(rows.into_iter().next().map(to_lock))
+    }
+
+    async fn get_lock_by_path(&self, ctx: &CoreContext, path: &str) -> Result<Option<LfsLock>> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+        let rows = SelectLockByPath::query(
+            &self.connections.read_master_connection,
+            &self.repo_id,
+            &path.to_string(),
+        )
+        .await?;
+        Ok(rows.into_iter().next().map(to_lock))
+    }
+
+    async fn list_locks(&self, ctx: &CoreContext, after: u64, limit: u64) -> Result<Vec<LfsLock>> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+        let rows = SelectLocks::query(
+            &self.connections.read_master_connection,
+            &self.repo_id,
+            &after,
+            &limit,
+        )
+        .await?;
+        Ok(rows.into_iter().map(to_lock).collect())
+    }
+
+    async fn delete_lock(&self, ctx: &CoreContext, id: u64) -> Result<bool> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlWrites);
+        let res = DeleteLock::query(&self.connections.write_connection, &self.repo_id, &id).await?;
+        Ok(res.affected_rows() > 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fbinit::FacebookInit;
+
+    use super::*;
+
+    #[fbinit::test]
+    async fn test_locks(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let builder = SqlLfsLocksBuilder::with_sqlite_in_memory()?;
+        let connections = builder.connections.clone();
+        let locks = builder.build(RepositoryId::new(1));
+
+        let lock = match locks.create_lock(&ctx, "foo.bin", "alice").await? {
+            CreateLockOutcome::Created(lock) => lock,
+            outcome => panic!("Unexpected outcome: {:?}", outcome),
+        };
+        assert_eq!(lock.path, "foo.bin");
+        assert_eq!(lock.owner, "alice");
+
+        assert_eq!(
+            locks.create_lock(&ctx, "foo.bin", "bob").await?,
+            CreateLockOutcome::AlreadyLocked(lock.clone())
+        );
+
+        let other = match locks.create_lock(&ctx, "bar.bin", "bob").await? {
+            CreateLockOutcome::Created(lock) => lock,
+            outcome => panic!("Unexpected outcome: {:?}", outcome),
+        };
+
+        assert_eq!(locks.get_lock(&ctx, lock.id).await?, Some(lock.clone()));
+        assert_eq!(
+            locks.get_lock_by_path(&ctx, "bar.bin").await?,
+            Some(other.clone())
+        );
+        assert_eq!(
+            locks.list_locks(&ctx, 0, 10).await?,
+            vec![lock.clone(), other.clone()]
+        );
+        assert_eq!(locks.list_locks(&ctx, 0, 1).await?, vec![lock.clone()]);
+        assert_eq!(locks.list_locks(&ctx, lock.id, 10).await?, vec![other]);
+
+        // Locks are per repository.
+        let repo2 = SqlLfsLocksBuilder { connections }.build(RepositoryId::new(2));
+        assert_eq!(repo2.get_lock(&ctx, lock.id).await?, None);
+        assert!(!repo2.delete_lock(&ctx, lock.id).await?);
+
+        assert!(locks.delete_lock(&ctx, lock.id).await?);
+        assert!(!locks.delete_lock(&ctx, lock.id).await?);
+        assert_eq!(locks.get_lock(&ctx, lock.id).await?, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,14 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+CREATE TABLE IF NOT EXISTS `lfs_quota_usage` (
+  `repo_id` INT NOT NULL,
+  `client` VARBINARY(2048) NOT NULL,
+  `day` BIGINT NOT NULL,
+  `bytes` BIGINT NOT NULL,
+  PRIMARY KEY (`repo_id`, `client`, `day`)
+);
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Bytes uploaded to the LFS server by each group of clients, so that their uploads can be
+//! limited by storage quotas.
+//!
+//! Usage is counted per day in a table in the metadata database.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use context::CoreContext;
+use context::PerfCounterType;
+use mononoke_types::RepositoryId;
+use sql_construct::SqlConstruct;
+use sql_construct::SqlConstructFromMetadataDatabaseConfig;
+use sql_ext::mononoke_queries;
+use sql_ext::SqlConnections;
+
+#[facet::facet]
+#[async_trait]
+pub trait LfsQuotas: Send + Sync {
+    /// Count `bytes` uploaded by `client` on `day`, in days since the epoch.
+    async fn add_usage(&self, ctx: &CoreContext, client: &str, day: u64, bytes: u64) -> Result<()>;
+
+    /// The number of bytes uploaded by `client` from `since` onwards, in days since the epoch.
+    async fn usage_since(&self, ctx: &CoreContext, client: &str, since: u64) -> Result<u64>;
+}
+
+mononoke_queries! {
+    write AddUsage(repo_id: RepositoryId, client: String, day: u64, bytes: u64) {
+        none,
+        mysql("INSERT INTO lfs_quota_usage (repo_id, client, day, bytes)
+               VALUES ({repo_id}, {client}, {day}, {bytes})
+               ON DUPLICATE KEY UPDATE bytes = bytes + {bytes}")
+
+        sqlite("INSERT INTO lfs_quota_usage (repo_id, client, day, bytes)
+                VALUES ({repo_id}, {client}, {day}, {bytes})
+                ON CONFLICT (repo_id, client, day) DO UPDATE SET bytes = bytes + {bytes}")
+    }
+
+    read SelectUsage(repo_id: RepositoryId, client: String, since: u64) -> (Option<u64>,) {
+        "SELECT CAST(SUM(bytes) AS UNSIGNED) FROM lfs_quota_usage
+         WHERE repo_id = {repo_id} AND client = {client} AND day >= {since}"
+    }
+}
+
+pub struct SqlLfsQuotas {
+    repo_id: RepositoryId,
+    connections: SqlConnections,
+}
+
+pub struct SqlLfsQuotasBuilder {
+    connections: SqlConnections,
+}
+
+impl SqlConstruct for SqlLfsQuotasBuilder {
+    const LABEL: &'static str = "lfs_quotas";
+
+    const CREATION_QUERY: &'static str = include_str!("../schemas/sqlite-lfs-quotas.sql");
+
+    fn from_sql_connections(connections: SqlConnections) -> Self {
+        Self { connections }
+    }
+}
+
+impl SqlConstructFromMetadataDatabaseConfig for SqlLfsQuotasBuilder {}
+
+impl SqlLfsQuotasBuilder {
+    pub fn build(self, repo_id: RepositoryId) -> SqlLfsQuotas {
+        SqlLfsQuotas {
+            repo_id,
+            connections: self.connections,
+        }
+    }
+}
+
+#[async_trait]
+impl LfsQuotas for SqlLfsQuotas {
+    async fn add_usage(&self, ctx: &CoreContext, client: &str, day: u64, bytes: u64) -> Result<()> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlWrites);
+        AddUsage::query(
+            &self.connections.write_connection,
+            &self.repo_id,
+            &client.to_string(),
+            &day,
+            &bytes,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn usage_since(&self, ctx: &CoreContext, client: &str, since: u64) -> Result<u64> {
+        // Quotas are checked before every upload, so reads go to the master to see the latest
+        // uploads.
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+        let rows = SelectUsage::query(
+            &self.connections.read_master_connection,
+            &self.repo_id,
+            &client.to_string(),
+            &since,
+        )
+        .await?;
+        Ok(rows
+            .into_iter()
+            .next()
+            .and_then(|(bytes,)| bytes)
+            .unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fbinit::FacebookInit;
+
+    use super::*;
+
+    #[fbinit::test]
+    async fn test_usage(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let builder = SqlLfsQuotasBuilder::with_sqlite_in_memory()?;
+        let connections = builder.connections.clone();
+        let quotas = builder.build(RepositoryId::new(1));
+
+        assert_eq!(quotas.usage_since(&ctx, "ci", 0).await?, 0);
+
+        quotas.add_usage(&ctx, "ci", 10, 100).await?;
+        quotas.add_usage(&ctx, "ci", 10, 50).await?;
+        quotas.add_usage(&ctx, "ci", 12, 20).await?;
+        quotas.add_usage(&ctx, "other", 12, 1000).await?;
+
+        assert_eq!(quotas.usage_since(&ctx, "ci", 0).await?, 170);
+        assert_eq!(quotas.usage_since(&ctx, "ci", 11).await?, 20);
+        assert_eq!(quotas.usage_since(&ctx, "ci", 13).await?, 0);
+        assert_eq!(quotas.usage_since(&ctx, "other", 0).await?, 1000);
+
+        // Usage is per repository.
+        let repo2 = SqlLfsQuotasBuilder { connections }.build(RepositoryId::new(2));
+        assert_eq!(repo2.usage_since(&ctx, "ci", 0).await?, 0);
+
+        Ok(())
+    }
+}
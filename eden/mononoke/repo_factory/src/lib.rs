@@ -99,6 +99,10 @@ use hook_manager::manager::ArcHookManager;
 use hook_manager::manager::HookManager;
 use hook_manager::TextOnlyHookFileContentProvider;
 use hooks::hook_loader::load_hooks;
+use lfs_locks::ArcLfsLocks;
+use lfs_locks::SqlLfsLocksBuilder;
+use lfs_quotas::ArcLfsQuotas;
+use lfs_quotas::SqlLfsQuotasBuilder;
 use live_commit_sync_config::CfgrLiveCommitSyncConfig;
 use memcache::KeyGen;
 use memcache::MemcacheClient;
@@ -692,6 +696,12 @@ pub enum RepoFactoryError {
     #[error("Error opening mutable counters")]
     MutableCounters,
 
+    #[error("Error opening LFS locks")]
+    LfsLocks,
+
+    #[error("Error opening LFS quotas")]
+    LfsQuotas,
+
     #[error("Error creating hook manager")]
     HookManager,
 
@@ -1315,6 +1325,32 @@ impl RepoFactory {
         ))
     }
 
+    pub async fn lfs_locks(
+        &self,
+        repo_identity: &ArcRepoIdentity,
+        repo_config: &ArcRepoConfig,
+    ) -> Result<ArcLfsLocks> {
+        Ok(Arc::new(
+            self.open_sql::<SqlLfsLocksBuilder>(repo_config)
+                .await
+                .context(RepoFactoryError::LfsLocks)?
+                .build(repo_identity.id()),
+        ))
+    }
+
+    pub async fn lfs_quotas(
+        &self,
+        repo_identity: &ArcRepoIdentity,
+        repo_config: &ArcRepoConfig,
+    ) -> Result<ArcLfsQuotas> {
+        Ok(Arc::new(
+            self.open_sql::<SqlLfsQuotasBuilder>(repo_config)
+                .await
+                .context(RepoFactoryError::LfsQuotas)?
+                .build(repo_identity.id()),
+        ))
+    }
+
     pub fn acl_regions(
         &self,
         repo_config: &ArcRepoConfig,
@@ -61,6 +61,10 @@ use git_types::RootGitDeltaManifestId;
 use git_types::TreeHandle;
 use hook_manager::manager::ArcHookManager;
 use hook_manager::manager::HookManager;
+use lfs_locks::ArcLfsLocks;
+use lfs_locks::SqlLfsLocksBuilder;
+use lfs_quotas::ArcLfsQuotas;
+use lfs_quotas::SqlLfsQuotasBuilder;
 use live_commit_sync_config::TestLiveCommitSyncConfig;
 use maplit::hashmap;
 use maplit::hashset;
@@ -279,9 +283,11 @@ impl TestRepoFactory {
         metadata_con.execute_batch(SqlSyncedCommitMapping::CREATION_QUERY)?;
         metadata_con.execute_batch(SegmentedChangelogSqlConnections::CREATION_QUERY)?;
         metadata_con.execute_batch(SqlRepoLock::CREATION_QUERY)?;
+        metadata_con.execute_batch(SqlLfsLocksBuilder::CREATION_QUERY)?;
         metadata_con.execute_batch(SqlSparseProfilesSizes::CREATION_QUERY)?;
         metadata_con.execute_batch(StreamingCloneBuilder::CREATION_QUERY)?;
         metadata_con.execute_batch(SqlCommitGraphStorageBuilder::CREATION_QUERY)?;
+        metadata_con.execute_batch(SqlLfsQuotasBuilder::CREATION_QUERY)?;
         let metadata_db = SqlConnections::new_single(match callbacks {
             Some(callbacks) => Connection::with_sqlite_callbacks(metadata_con, callbacks),
             None => Connection::with_sqlite(metadata_con),
@@ -726,6 +732,22 @@ impl TestRepoFactory {
         }))
     }
 
+    /// LFS locks
+    pub fn lfs_locks(&self, repo_identity: &ArcRepoIdentity) -> Result<ArcLfsLocks> {
+        Ok(Arc::new(
+            SqlLfsLocksBuilder::from_sql_connections(self.metadata_db.clone())
+                .build(repo_identity.id()),
+        ))
+    }
+
+    /// LFS quotas
+    pub fn lfs_quotas(&self, repo_identity: &ArcRepoIdentity) -> Result<ArcLfsQuotas> {
+        Ok(Arc::new(
+            SqlLfsQuotasBuilder::from_sql_connections(self.metadata_db.clone())
+                .build(repo_identity.id()),
+        ))
+    }
+
     /// Mutable counters
     pub fn mutable_counters(&self, repo_identity: &ArcRepoIdentity) -> Result<ArcMutableCounters> {
         Ok(Arc::new(
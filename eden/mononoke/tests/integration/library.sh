@@ -1530,13 +1530,18 @@ function lfs_server {
     elif
       [[ "$1" = "--always-wait-for-upstream" ]] ||
       [[ "$1" = "--readonly" ]] ||
-      [[ "$1" = "--git-blob-upload-allowed" ]]
+      [[ "$1" = "--git-blob-upload-allowed" ]] ||
+      [[ "$1" = "--unsafe-allow-fault-injection" ]]
     then
       opts=("${opts[@]}" "$1")
       shift
     elif
       [[ "$1" = "--scuba-dataset" ]] ||
-      [[ "$1" = "--max-upload-size" ]]
+      [[ "$1" = "--max-upload-size" ]] ||
+      [[ "$1" = "--scribe-logging-directory" ]] ||
+      [[ "$1" = "--listen-unix-socket" ]] ||
+      [[ "$1" = "--unix-socket-mode" ]] ||
+      [[ "$1" = "--unix-socket-trusted-uid" ]]
     then
       opts=("${opts[@]}" "$1" "$2")
       shift
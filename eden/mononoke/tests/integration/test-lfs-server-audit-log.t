@@ -0,0 +1,49 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, with uploads recorded to a scribe category
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo1
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "audit_log": {
+  >     "scribe_category": "lfs_uploads",
+  >     "file_path": ""
+  >   }
+  > }
+  > EOF
+
+# Start an LFS server, logging scribe writes to files
+  $ scribe_logs="$TESTTMP/scribe_logs"
+  $ mkdir "$scribe_logs"
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_root="$(lfs_server --tls --log "$lfs_log" --scribe-logging-directory "$scribe_logs" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")"
+
+# Upload an object
+  $ oid="2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+  $ printf hello | sslcurlas client0 -s -o /dev/null -w "%{http_code}\n" -X PUT --data-binary @- "${lfs_root}/repo1/upload/${oid}/5"
+  200
+
+# Downloads aren't recorded
+  $ sslcurlas client0 -s -o /dev/null -w "%{http_code}\n" "${lfs_root}/repo1/download_sha256/${oid}"
+  200
+
+# The upload is recorded, along with who sent it
+  $ wc -l < "$scribe_logs/lfs_uploads"
+  1
+  $ jq -c '{repository, oid, size, replicated}' < "$scribe_logs/lfs_uploads"
+  {"repository":"repo1","oid":"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824","size":5,"replicated":false}
+  $ jq --arg id "$CLIENT0_ID_TYPE:$CLIENT0_ID_DATA" '.identities | index($id) != null' < "$scribe_logs/lfs_uploads"
+  true
+  $ jq '.request_id != null and .timestamp > 0' < "$scribe_logs/lfs_uploads"
+  true
@@ -0,0 +1,44 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config lfs1
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "min_compression_size_bytes": 4096
+  > }
+  > EOF
+
+# Start a LFS server for this repository (no upstream)
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_uri="$(lfs_server --log "$lfs_log" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")/lfs1"
+
+# Send some data that is smaller than the compression threshold
+  $ yes A 2>/dev/null | head -c 2KiB | hg --config extensions.lfs= debuglfssend "$lfs_uri"
+  ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746 2048
+
+# Send some data that is larger than the compression threshold
+  $ yes A 2>/dev/null | head -c 8KiB | hg --config extensions.lfs= debuglfssend "$lfs_uri"
+  19191fa25b63b33ff8e4c8043844f09a5b9cd23623fcf3af31111bde63874363 8192
+
+# The small object is returned as-is, even though the client accepts gzip
+  $ curl "${lfs_uri}/download_sha256/ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746" -s -o small -H "Accept-Encoding: gzip"
+  $ wc -c small
+  2048 small
+
+# The large object is compressed
+  $ curl "${lfs_uri}/download_sha256/19191fa25b63b33ff8e4c8043844f09a5b9cd23623fcf3af31111bde63874363" -s -o large -H "Accept-Encoding: gzip"
+  $ test "$(wc -c < large)" -lt 8192
+  $ gunzip < large | wc -c
+  8192
@@ -0,0 +1,57 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, allowing one origin
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo1
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "cors": {
+  >     "allowed_origins": ["https://example.com"],
+  >     "allowed_headers": ["x-client-info"],
+  >     "max_age_secs": 600
+  >   }
+  > }
+  > EOF
+
+# Start an LFS server
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_root="$(lfs_server --log "$lfs_log" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")"
+  $ cors_headers() {
+  >   curl -s -D - -o /dev/null "$@" | tr -d '\r' | grep -i -e '^access-control' -e '^vary' | sort
+  > }
+
+# Preflights from allowed origins list the methods for each route
+  $ cors_headers -X OPTIONS -H "Origin: https://example.com" -H "Access-Control-Request-Method: POST" "${lfs_root}/repo1/objects/batch"
+  access-control-allow-headers: x-client-info, content-type
+  access-control-allow-methods: POST
+  access-control-allow-origin: https://example.com
+  access-control-max-age: 600
+  vary: origin
+  $ cors_headers -X OPTIONS -H "Origin: https://example.com" -H "Access-Control-Request-Method: PUT" "${lfs_root}/repo1/upload/abcd/6"
+  access-control-allow-headers: x-client-info, content-type
+  access-control-allow-methods: PUT
+  access-control-allow-origin: https://example.com
+  access-control-max-age: 600
+  vary: origin
+
+# Preflights from other origins are refused
+  $ cors_headers -X OPTIONS -H "Origin: https://example.org" -H "Access-Control-Request-Method: POST" "${lfs_root}/repo1/objects/batch"
+  vary: origin
+
+# Responses to allowed origins can be read by the browser
+  $ cors_headers -H "Origin: https://example.com" "${lfs_root}/health_check"
+  access-control-allow-origin: https://example.com
+  vary: origin
+  $ cors_headers -H "Origin: https://example.org" "${lfs_root}/health_check"
+  vary: origin
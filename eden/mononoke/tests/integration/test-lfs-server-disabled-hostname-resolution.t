@@ -27,15 +27,55 @@
 # Get the config
   $ curl -fs "${lfs_root}/config" | jq -S .
   {
+    "access_log_sample_rate": 0,
+    "admin_acl": [],
+    "audit_log": null,
+    "blobstore_timeouts": null,
+    "cors": null,
+    "denied_oids": [],
     "disable_compression": false,
     "disable_compression_identities": [],
     "disable_hostname_logging": true,
+    "distributed_rate_limits": null,
+    "download_redirect_url": null,
+    "drain": false,
     "enable_consistent_routing": false,
+    "enable_locks": false,
+    "enable_verify_action": false,
     "enforce_acl_check": false,
     "enforce_authentication": false,
+    "fault_injection": {},
+    "hot_object_spreading": null,
+    "ip_throttle": null,
     "loadshedding_limits": [],
+    "log_sample_rate": null,
+    "max_batch_request_bytes": 0,
+    "max_concurrent_download_transfers": 0,
+    "max_concurrent_uploads": 0,
+    "max_egress_backlog_bytes": 0,
+    "max_event_loop_lag_ms": 0,
+    "max_in_flight_requests": 0,
+    "max_object_size_bytes": 0,
+    "max_queued_uploads": 0,
+    "max_request_body_bytes": 0,
+    "middleware_pipeline": [],
+    "min_compression_size_bytes": 0,
     "object_popularity": null,
-    "track_bytes_sent": true
+    "popularity_report": null,
+    "rendezvous_routing": null,
+    "replication": null,
+    "repos": {},
+    "request_limits": [],
+    "retry_after_secs": 0,
+    "rollout": {},
+    "routing_error_budget": null,
+    "slow_read_retry_delay_ms": 0,
+    "trace_export": null,
+    "track_bytes_sent": true,
+    "transfer_queue_timeout_ms": 0,
+    "upload_acl": null,
+    "upstream": null,
+    "upstream_timeouts": null
   }
 
 # Send some data
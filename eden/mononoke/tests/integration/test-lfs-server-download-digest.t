@@ -0,0 +1,47 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, with small chunks so the object spans several of them
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config lfs1
+
+# Start a LFS server for this repository (no upstream)
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_uri="$(lfs_server --log "$lfs_log")/lfs1"
+
+# Send some data
+  $ printf "0123456789abcdefghijklmnopqrstuvwxyz" | hg --config extensions.lfs= debuglfssend "$lfs_uri"
+  74e7e5bb9d22d6db26bf76946d40fff3ea9f0346b884fd0694920fccfad15e33 36
+  $ url="${lfs_uri}/download_sha256/74e7e5bb9d22d6db26bf76946d40fff3ea9f0346b884fd0694920fccfad15e33"
+
+# Without asking for it, there is no digest
+  $ curl -s -D headers "$url"; echo
+  0123456789abcdefghijklmnopqrstuvwxyz
+  $ grep -ic "^digest" headers
+  0
+  [1]
+
+# Ask for the digest: the object is verified as it is sent, and sent uncompressed
+  $ curl -s -D headers -H "Want-Digest: sha-256" -H "Accept-Encoding: zstd" "$url"; echo
+  0123456789abcdefghijklmnopqrstuvwxyz
+  $ grep -i -e "^digest" -e "^content-length" -e "^content-encoding" headers | tr -d '\r' | sort
+  content-length: 36
+  digest: sha-256=dOflu50i1tsmv3aUbUD/8+qfA0a4hP0GlJIPzPrRXjM=
+
+# Ranges come with the digest of the whole object
+  $ curl -s -D headers -H "Want-Digest: sha-256" -H "Range: bytes=30-" "$url"; echo
+  uvwxyz
+  $ grep -i "^digest" headers | tr -d '\r'
+  digest: sha-256=dOflu50i1tsmv3aUbUD/8+qfA0a4hP0GlJIPzPrRXjM=
+
+# Other algorithms aren't supported
+  $ curl -s -D headers -H "Want-Digest: md5" "$url"; echo
+  0123456789abcdefghijklmnopqrstuvwxyz
+  $ grep -ic "^digest" headers
+  0
+  [1]
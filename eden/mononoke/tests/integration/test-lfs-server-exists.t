@@ -0,0 +1,29 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config lfs1
+
+# Start a LFS server for this repository (no upstream)
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_uri="$(lfs_server --log "$lfs_log")/lfs1"
+
+# Send some data
+  $ yes A 2>/dev/null | head -c 2KiB | hg --config extensions.lfs= debuglfssend "$lfs_uri"
+  ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746 2048
+
+# A HEAD request reports the size of an object that exists, without sending it
+  $ curl -s -I "${lfs_uri}/download_sha256/ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746" | grep -i -e "^HTTP" -e "^content-length" | tr -d '\r'
+  HTTP/1.1 200 OK
+  content-length: 2048
+
+# And a 404 for an object that doesn't
+  $ curl -s -I -o /dev/null -w "%{http_code}\n" "${lfs_uri}/download_sha256/19191fa25b63b33ff8e4c8043844f09a5b9cd23623fcf3af31111bde63874363"
+  404
+
@@ -0,0 +1,43 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, with errors injected into every batch request
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo1
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "fault_injection": {
+  >     "batch": {
+  >       "latency_percentage": 0,
+  >       "latency_ms": 0,
+  >       "error_percentage": 100
+  >     }
+  >   }
+  > }
+  > EOF
+
+# Servers that don't allow fault injection ignore the config
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_uri="$(lfs_server --log "$lfs_log" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")/repo1"
+  $ curl -s -o /dev/null -w "%{http_code}\n" "${lfs_uri}/objects/batch" --data '{"operation": "download", "objects": []}'
+  200
+
+# Servers that allow it fail the requests
+  $ unsafe_lfs_log="$TESTTMP/unsafe_lfs.log"
+  $ unsafe_lfs_uri="$(lfs_server --unsafe-allow-fault-injection --log "$unsafe_lfs_log" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")/repo1"
+  $ curl -s "${unsafe_lfs_uri}/objects/batch" --data '{"operation": "download", "objects": []}'
+  {"message":"Fault injected into batch request","request_id":"*"} (no-eol) (glob)
+
+# Other routes are served as usual
+  $ curl -s -o /dev/null -w "%{http_code}\n" "${unsafe_lfs_uri%/repo1}/health_check"
+  200
@@ -26,15 +26,55 @@
 # Get the config
   $ curl -fs "${lfs_root}/config" | jq -S .
   {
+    "access_log_sample_rate": 0,
+    "admin_acl": [],
+    "audit_log": null,
+    "blobstore_timeouts": null,
+    "cors": null,
+    "denied_oids": [],
     "disable_compression": false,
     "disable_compression_identities": [],
     "disable_hostname_logging": false,
+    "distributed_rate_limits": null,
+    "download_redirect_url": null,
+    "drain": false,
     "enable_consistent_routing": false,
+    "enable_locks": false,
+    "enable_verify_action": false,
     "enforce_acl_check": false,
     "enforce_authentication": false,
+    "fault_injection": {},
+    "hot_object_spreading": null,
+    "ip_throttle": null,
     "loadshedding_limits": [],
+    "log_sample_rate": null,
+    "max_batch_request_bytes": 0,
+    "max_concurrent_download_transfers": 0,
+    "max_concurrent_uploads": 0,
+    "max_egress_backlog_bytes": 0,
+    "max_event_loop_lag_ms": 0,
+    "max_in_flight_requests": 0,
+    "max_object_size_bytes": 0,
+    "max_queued_uploads": 0,
+    "max_request_body_bytes": 0,
+    "middleware_pipeline": [],
+    "min_compression_size_bytes": 0,
     "object_popularity": null,
-    "track_bytes_sent": true
+    "popularity_report": null,
+    "rendezvous_routing": null,
+    "replication": null,
+    "repos": {},
+    "request_limits": [],
+    "retry_after_secs": 0,
+    "rollout": {},
+    "routing_error_budget": null,
+    "slow_read_retry_delay_ms": 0,
+    "trace_export": null,
+    "track_bytes_sent": true,
+    "transfer_queue_timeout_ms": 0,
+    "upload_acl": null,
+    "upstream": null,
+    "upstream_timeouts": null
   }
 
 # Update the config
@@ -55,13 +95,75 @@
 # Get the updated config
   $ curl -fs "${lfs_root}/config" | jq -S .
   {
+    "access_log_sample_rate": 0,
+    "admin_acl": [],
+    "audit_log": null,
+    "blobstore_timeouts": null,
+    "cors": null,
+    "denied_oids": [],
     "disable_compression": false,
     "disable_compression_identities": [],
     "disable_hostname_logging": false,
+    "distributed_rate_limits": null,
+    "download_redirect_url": null,
+    "drain": false,
     "enable_consistent_routing": false,
+    "enable_locks": false,
+    "enable_verify_action": false,
     "enforce_acl_check": false,
     "enforce_authentication": false,
+    "fault_injection": {},
+    "hot_object_spreading": null,
+    "ip_throttle": null,
     "loadshedding_limits": [],
+    "log_sample_rate": null,
+    "max_batch_request_bytes": 0,
+    "max_concurrent_download_transfers": 0,
+    "max_concurrent_uploads": 0,
+    "max_egress_backlog_bytes": 0,
+    "max_event_loop_lag_ms": 0,
+    "max_in_flight_requests": 0,
+    "max_object_size_bytes": 0,
+    "max_queued_uploads": 0,
+    "max_request_body_bytes": 0,
+    "middleware_pipeline": [],
+    "min_compression_size_bytes": 0,
     "object_popularity": null,
-    "track_bytes_sent": false
+    "popularity_report": null,
+    "rendezvous_routing": null,
+    "replication": null,
+    "repos": {},
+    "request_limits": [],
+    "retry_after_secs": 0,
+    "rollout": {},
+    "routing_error_budget": null,
+    "slow_read_retry_delay_ms": 0,
+    "trace_export": null,
+    "track_bytes_sent": false,
+    "transfer_queue_timeout_ms": 0,
+    "upload_acl": null,
+    "upstream": null,
+    "upstream_timeouts": null
+  }
+
+# Start a server that falls back to a local file when the first source is missing
+  $ cat > "$TESTTMP/fallback.json" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false
+  > }
+  > EOF
+  $ fallback_log="$TESTTMP/lfs_fallback.log"
+  $ fallback_root="$(lfs_server --log "$fallback_log" --live-config "file:$TESTTMP/missing.json,file:$TESTTMP/fallback.json,default")"
+  $ curl -fs "${fallback_root}/config" | jq .track_bytes_sent
+  true
+  $ grep "live config source" "$fallback_log"
+  * Could not load live config source file:$TESTTMP/missing.json: * (glob)
+  * Using live config source file:$TESTTMP/fallback.json (glob)
+  $ curl -fs "${fallback_root}/config/status" | jq -S '{source, last_error}'
+  {
+    "last_error": "file:$TESTTMP/missing.json: * (glob)
+    "source": "file:$TESTTMP/fallback.json"
   }
@@ -0,0 +1,70 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, with locks enabled
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo1
+  $ REPOID=2 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo2
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "repos": {
+  >     "repo1": {
+  >       "enable_locks": true
+  >     }
+  >   }
+  > }
+  > EOF
+
+# Start an LFS server
+  $ LFS_LOG="$TESTTMP/lfs.log"
+  $ LFS_ROOT="$(lfs_server --log "$LFS_LOG" --tls --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")"
+  $ LOCKS_URI="$LFS_ROOT/repo1/locks"
+
+# Locks are not available unless enabled for the repository
+  $ sslcurlas client0 -s -o /dev/null -w "%{http_code}\n" "$LFS_ROOT/repo2/locks"
+  404
+
+# Create a lock
+  $ sslcurlas client0 -s -X POST "$LOCKS_URI" --data '{"path": "foo/bar.zip", "ref": {"name": "refs/heads/main"}}' | jq -c '{id: .lock.id, path: .lock.path, owner: .lock.owner}'
+  {"id":"*","path":"foo/bar.zip","owner":{"name":"*"}} (glob)
+  $ sslcurlas client0 -s -o /dev/null -w "%{http_code}\n" -X POST "$LOCKS_URI" --data '{"path": "foo/baz.zip"}'
+  201
+
+# Locking the same path again conflicts with the existing lock
+  $ sslcurlas client0 -s -o /dev/null -w "%{http_code}\n" -X POST "$LOCKS_URI" --data '{"path": "foo/bar.zip"}'
+  409
+  $ sslcurlas client0 -s -X POST "$LOCKS_URI" --data '{"path": "foo/bar.zip"}' | jq -r '.message, .lock.path'
+  already created lock
+  foo/bar.zip
+
+# List locks, by path and in pages
+  $ sslcurlas client0 -s "$LOCKS_URI" | jq -c '[.locks[].path]'
+  ["foo/bar.zip","foo/baz.zip"]
+  $ sslcurlas client0 -s "$LOCKS_URI?path=foo/baz.zip" | jq -c '[.locks[].path]'
+  ["foo/baz.zip"]
+  $ CURSOR="$(sslcurlas client0 -s "$LOCKS_URI?limit=1" | jq -r '.next_cursor')"
+  $ sslcurlas client0 -s "$LOCKS_URI?limit=1&cursor=$CURSOR" | jq -c '{paths: [.locks[].path], next_cursor}'
+  {"paths":["foo/baz.zip"],"next_cursor":null}
+
+# Verify locks: they were all created by this client
+  $ sslcurlas client0 -s -X POST "$LOCKS_URI/verify" --data '{}' | jq -c '{ours: [.ours[].path], theirs: [.theirs[].path]}'
+  {"ours":["foo/bar.zip","foo/baz.zip"],"theirs":[]}
+
+# Unlock
+  $ LOCK_ID="$(sslcurlas client0 -s "$LOCKS_URI?path=foo/bar.zip" | jq -r '.locks[0].id')"
+  $ sslcurlas client0 -s -X POST "$LOCKS_URI/$LOCK_ID/unlock" --data '{}' | jq -r '.lock.path'
+  foo/bar.zip
+  $ sslcurlas client0 -s -o /dev/null -w "%{http_code}\n" -X POST "$LOCKS_URI/$LOCK_ID/unlock" --data '{}'
+  404
+  $ sslcurlas client0 -s "$LOCKS_URI" | jq -c '[.locks[].path]'
+  ["foo/baz.zip"]
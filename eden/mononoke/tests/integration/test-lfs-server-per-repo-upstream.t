@@ -0,0 +1,64 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create two upstream repositories, and two repositories that proxy to them
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config upstream1
+  $ REPOID=2 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config upstream2
+  $ REPOID=3 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config proxy1
+  $ REPOID=4 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config proxy2
+
+# Start the upstream LFS server
+  $ log_upstream="$TESTTMP/lfs_upstream.log"
+  $ upstream_root="$(lfs_server --log "$log_upstream")"
+
+# Start a gateway that sends each repository to its own upstream
+  $ echo "Bearer secret" > "$TESTTMP/authorization"
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "repos": {
+  >     "proxy1": {
+  >       "upstream": {"url": "$upstream_root/upstream1", "authorization_file": "$TESTTMP/authorization"}
+  >     },
+  >     "proxy2": {
+  >       "upstream": {"url": "$upstream_root/upstream2", "authorization_file": ""},
+  >       "upstream_timeouts": {"connect_ms": 0, "first_byte_ms": 0, "total_ms": 10000}
+  >     }
+  >   }
+  > }
+  > EOF
+  $ log_proxy="$TESTTMP/lfs_proxy.log"
+  $ proxy_root="$(lfs_server --always-wait-for-upstream --log "$log_proxy" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")"
+
+# Upload different data to each upstream
+  $ yes A 2>/dev/null | head -c 2KiB | hg --config extensions.lfs= debuglfssend "$upstream_root/upstream1"
+  ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746 2048
+  $ yes B 2>/dev/null | head -c 2KiB | hg --config extensions.lfs= debuglfssend "$upstream_root/upstream2"
+  a1bcf2c963bec9588aaa30bd33ef07873792e3ec241453b0d21635d1c4bbae84 2048
+  $ truncate -s 0 "$log_upstream"
+
+# Each proxy reads from its own upstream
+  $ hg --config extensions.lfs= debuglfsreceive ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746 2048 "$proxy_root/proxy1" | sha256sum
+  ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746  -
+  $ hg --config extensions.lfs= debuglfsreceive a1bcf2c963bec9588aaa30bd33ef07873792e3ec241453b0d21635d1c4bbae84 2048 "$proxy_root/proxy2" | sha256sum
+  a1bcf2c963bec9588aaa30bd33ef07873792e3ec241453b0d21635d1c4bbae84  -
+
+  $ grep "objects/batch" "$log_upstream"
+  IN  > POST /upstream1/objects/batch -
+  OUT < POST /upstream1/objects/batch 200 OK
+  IN  > POST /upstream2/objects/batch -
+  OUT < POST /upstream2/objects/batch 200 OK
+
+# Objects only in the other upstream aren't found
+  $ curl -s "$proxy_root/proxy2/objects/batch" --data '{"operation": "download", "objects": [{"oid": "ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746", "size": 2048}]}' | jq -c ".objects[0].error.code"
+  404
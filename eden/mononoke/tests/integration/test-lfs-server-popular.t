@@ -0,0 +1,46 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, with popularity reporting enabled for one admin client
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo1
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "admin_acl": [["$CLIENT0_ID_TYPE:$CLIENT0_ID_DATA"]],
+  >   "popularity_report": {
+  >     "window_secs": 3600,
+  >     "log_top": 0,
+  >     "log_interval_secs": 0
+  >   }
+  > }
+  > EOF
+
+# Start an LFS server
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_root="$(lfs_server --tls --log "$lfs_log" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")"
+
+# Upload an object, and ask for it twice
+  $ oid="2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+  $ printf hello | sslcurlas client0 -s -o /dev/null -w "%{http_code}\n" -X PUT --data-binary @- "${lfs_root}/repo1/upload/${oid}/5"
+  200
+  $ for _ in 1 2; do
+  >   sslcurlas client0 -s -o /dev/null "${lfs_root}/repo1/objects/batch" --data "{\"operation\": \"download\", \"objects\": [{\"oid\": \"${oid}\", \"size\": 5}]}"
+  > done
+
+# Other clients may not see the report
+  $ sslcurlas client1 -s -o /dev/null -w "%{http_code}\n" "${lfs_root}/popular"
+  403
+
+# The object is reported with its downloads
+  $ sslcurlas client0 -s "${lfs_root}/popular?limit=1" | jq -c .
+  {"window_secs":3600,"objects":[{"repository":"repo1","oid":"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824","downloads":2,"bytes":10}]}
@@ -73,7 +73,7 @@
 
   $ curl --silent -XPUT --data-binary "@/dev/null" "${lfs_proxy}/lfs2/upload/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa/2048" | jq -S .
   {
-    "message": "Upstream batch response included an invalid object: ResponseObject { object: RequestObject { oid: Sha256(aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa), size: 2048 }, status: Err { error: ObjectError { code: 404, message: \"Object does not exist\" } } }",
+    "message": "Upstream batch response included an invalid object: ResponseObject { object: RequestObject { oid: Sha256(aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa), size: 2048 }, status: Err { error: ObjectError { code: 404, message: \"Object does not exist\", error_code: Some(NotFound), retryable: false } } }",
     "request_id": "*" (glob)
   }
 
@@ -0,0 +1,47 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, with small chunks so ranges span several of them
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config lfs1
+
+# Start a LFS server for this repository (no upstream)
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_uri="$(lfs_server --log "$lfs_log")/lfs1"
+
+# Send some data
+  $ printf "0123456789abcdefghijklmnopqrstuvwxyz" | hg --config extensions.lfs= debuglfssend "$lfs_uri"
+  74e7e5bb9d22d6db26bf76946d40fff3ea9f0346b884fd0694920fccfad15e33 36
+  $ url="${lfs_uri}/download_sha256/74e7e5bb9d22d6db26bf76946d40fff3ea9f0346b884fd0694920fccfad15e33"
+
+# Fetch a range across chunks
+  $ curl -s -D headers -H "Range: bytes=8-21" "$url"; echo
+  89abcdefghijkl
+  $ grep -i -e "^HTTP" -e "^content-range" headers | tr -d '\r'
+  HTTP/1.1 206 Partial Content
+  content-range: bytes 8-21/36
+
+# Resume from an offset
+  $ curl -s -H "Range: bytes=30-" "$url"; echo
+  uvwxyz
+
+# Fetch the end of the object
+  $ curl -s -H "Range: bytes=-4" "$url"; echo
+  wxyz
+
+# Ranges past the end of the object are rejected
+  $ curl -s -o /dev/null -w "%{http_code}\n" -H "Range: bytes=36-" "$url"
+  416
+
+# Several ranges, and ranges that don't parse, are ignored and the whole object is sent
+  $ curl -s -D headers -H "Range: bytes=0-1,4-5" "$url"; echo
+  0123456789abcdefghijklmnopqrstuvwxyz
+  $ grep -i -e "^HTTP" -e "^content-range" headers | tr -d '\r'
+  HTTP/1.1 200 OK
+  $ curl -s -H "Range: lines=1-2" "$url"; echo
+  0123456789abcdefghijklmnopqrstuvwxyz
@@ -0,0 +1,47 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, with one client allowed to use the admin routes
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo1
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "admin_acl": [["$CLIENT0_ID_TYPE:$CLIENT0_ID_DATA"]]
+  > }
+  > EOF
+
+# Start an LFS server
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_root="$(lfs_server --tls --log "$lfs_log" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")"
+
+# Other clients may not reload the config
+  $ sslcurlas client1 -s -o /dev/null -w "%{http_code}\n" -X POST "${lfs_root}/admin/reload_config"
+  403
+
+# Reloading a config that didn't change leaves it as it is
+  $ sslcurlas client0 -s -X POST "${lfs_root}/admin/reload_config"
+  {"outcome":"unchanged"} (no-eol)
+
+# Changes are served once the config is reloaded
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": false,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "admin_acl": [["$CLIENT0_ID_TYPE:$CLIENT0_ID_DATA"]]
+  > }
+  > EOF
+  $ sslcurlas client0 -s -o /dev/null -X POST "${lfs_root}/admin/reload_config"
+  $ sslcurlas client0 -s "${lfs_root}/config" | jq .track_bytes_sent
+  false
@@ -0,0 +1,44 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config lfs1
+
+# Start a LFS server standing in for a peer region
+  $ log_peer="$TESTTMP/lfs_peer.log"
+  $ lfs_peer="$(lfs_server --log "$log_peer")"
+
+# Start a LFS server that replicates uploads to the peer
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "replication": {
+  >     "peers": ["$lfs_peer"],
+  >     "max_attempts": 0,
+  >     "retry_delay_ms": 0,
+  >     "max_backlog": 0
+  >   }
+  > }
+  > EOF
+  $ log_origin="$TESTTMP/lfs_origin.log"
+  $ lfs_origin="$(lfs_server --log "$log_origin" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")"
+
+# Upload an object
+  $ yes A 2>/dev/null | head -c 2KiB | hg --config extensions.lfs= debuglfssend "${lfs_origin}/lfs1"
+  ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746 2048
+
+# It is pushed to the peer in the background, which doesn't replicate it any further
+  $ for _ in $(seq 50); do grep -q "OUT < PUT" "$log_peer" && break; sleep 0.1; done
+  $ cat "$log_peer"
+  IN  > PUT /lfs1/upload/ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746/2048 -
+  OUT < PUT /lfs1/upload/ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746/2048 200 OK
@@ -0,0 +1,42 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, with small limits on request bodies
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo1
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "max_batch_request_bytes": 500,
+  >   "max_request_body_bytes": 20
+  > }
+  > EOF
+
+# Start an LFS server
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_uri="$(lfs_server --log "$lfs_log" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")/repo1"
+
+# Batch requests under the limit are served
+  $ curl -s -o /dev/null -w "%{http_code}\n" "${lfs_uri}/objects/batch" --data '{"operation": "download", "objects": []}'
+  200
+
+# Larger batch requests are rejected
+  $ curl -s "${lfs_uri}/objects/batch" --data "{\"operation\": \"download\", \"objects\": [], \"ref\": {\"name\": \"$(yes A 2>/dev/null | head -c 500)\"}}"
+  {"message":"Request body exceeds max allowed size (500)","request_id":"*"} (no-eol) (glob)
+
+# Other requests have their own limit
+  $ curl -s -o /dev/null -w "%{http_code}\n" "${lfs_uri}/verify" --data '{"oid": "1111111111111111111111111111111111111111111111111111111111111111", "size": 1}'
+  413
+
+# Uploads are not limited by it
+  $ yes A 2>/dev/null | head -c 30 | hg --config extensions.lfs= debuglfssend "$lfs_uri"
+  * 30 (glob)
@@ -0,0 +1,36 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo1
+
+# Start a LFS server that also listens on a unix socket, where a stale socket was left behind
+  $ SOCKET="$TESTTMP/lfs.sock"
+  $ python3 -c "import socket, sys; socket.socket(socket.AF_UNIX).bind(sys.argv[1])" "$SOCKET"
+  $ LFS_LOG="$TESTTMP/lfs.log"
+  $ LFS_URI="$(lfs_server --log "$LFS_LOG" --listen-unix-socket "$SOCKET" --unix-socket-trusted-uid "$(id -u)")/repo1"
+
+# The socket is only accessible to the server's user by default
+  $ stat -c %a "$SOCKET"
+  600
+
+# Upload some data over TCP
+  $ yes A 2>/dev/null | head -c 2KiB | hg --config extensions.lfs= debuglfssend "$LFS_URI"
+  ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746 2048
+
+# It can be read over the unix socket
+  $ curl -s --unix-socket "$SOCKET" http://localhost/health_check
+  I_AM_ALIVE (no-eol)
+  $ curl -s --unix-socket "$SOCKET" http://localhost/repo1/download_sha256/ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746 | sha256sum
+  ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746  -
+
+  $ grep "download_sha256" "$LFS_LOG"
+  IN  > GET /repo1/download_sha256/ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746 -
+  OUT < GET /repo1/download_sha256/ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746 200 OK
+
@@ -0,0 +1,49 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+# Create a repository, with a daily upload quota for all clients
+  $ setup_common_config
+  $ REPOID=1 FILESTORE=1 FILESTORE_CHUNK_SIZE=10 setup_mononoke_repo_config repo1
+  $ LIVE_CONFIG="${LOCAL_CONFIGERATOR_PATH}/live.json"
+  $ cat > "$LIVE_CONFIG" << EOF
+  > {
+  >   "track_bytes_sent": true,
+  >   "enable_consistent_routing": false,
+  >   "disable_hostname_logging": false,
+  >   "enforce_acl_check": false,
+  >   "request_limits": [
+  >     {
+  >       "identities": [],
+  >       "requests_per_second": 0,
+  >       "max_concurrent_requests": 0,
+  >       "max_concurrent_uploads": 0,
+  >       "max_egress_bytes_per_second": 0,
+  >       "max_upload_bytes_per_day": 50,
+  >       "max_upload_bytes_per_week": 0
+  >     }
+  >   ]
+  > }
+  > EOF
+
+# Start an LFS server
+  $ lfs_log="$TESTTMP/lfs.log"
+  $ lfs_uri="$(lfs_server --log "$lfs_log" --live-config "$(get_configerator_relative_path "${LIVE_CONFIG}")")/repo1"
+
+# Uploads within the quota are accepted
+  $ yes A 2>/dev/null | head -c 30 | hg --config extensions.lfs= debuglfssend "$lfs_uri"
+  * 30 (glob)
+
+# Uploads that would go over it are refused
+  $ yes B 2>/dev/null | head -c 30 > "$TESTTMP/blob"
+  $ oid="$(sha256sum "$TESTTMP/blob" | cut -d " " -f 1)"
+  $ curl -s -X PUT --data-binary @"$TESTTMP/blob" "${lfs_uri}/upload/${oid}/30"
+  {"message":"Upload of 30 bytes exceeds the quota of 50 bytes per day (30 bytes already uploaded)","request_id":"*"} (no-eol) (glob)
+
+# Smaller uploads still fit
+  $ yes C 2>/dev/null | head -c 20 | hg --config extensions.lfs= debuglfssend "$lfs_uri"
+  * 20 (glob)
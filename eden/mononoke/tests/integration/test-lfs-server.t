@@ -95,3 +95,7 @@
   $ curl -s -w "\n%{http_code}" "${lfs_uri}/objects/batch/" --data-binary "@request"
   {"transfer":"basic","objects":[{"oid":"ab02c2a1923c8eb11cb3ddab70320746d71d32ad63f255698dc67c3295757746","size":2048,"authenticated":false,"actions":{"download":{"href":"http://$LOCALIP:*/lfs1/download/d28548bc21aabf04d143886d717d72375e3deecd0dafb3d110676b70a192cb5d?server_hostname=*"}}}]} (glob)
   200 (no-eol)
+
+# Request IDs sent by the client are returned in full
+  $ curl -s -D - -o /dev/null -H "X-Request-ID: client-request-id-1234" "${lfs_uri}/objects/batch/" --data-binary "@request" | tr -d '\r' | grep -i "^x-request-id"
+  x-request-id: client-request-id-1234
@@ -164,6 +164,36 @@ fn main() {
         })
     });
 
+    {
+        let dir = tempdir().unwrap();
+        let mut idx = open_opts().open(dir.path().join("i")).expect("open");
+        let buf = gen_buf(N * 20);
+        for i in 0..N {
+            idx.insert(&&buf[20 * i..20 * (i + 1)], i as u64)
+                .expect("insert");
+        }
+        idx.flush().expect("flush");
+
+        // The total work is the same. The time should decrease linearly
+        // as the thread count increases, until running out of CPUs.
+        for threads in [1, 2, 4, 8] {
+            bench(format!("index lookup (disk, {} threads)", threads), || {
+                elapsed(|| {
+                    std::thread::scope(|s| {
+                        for t in 0..threads {
+                            let (idx, buf) = (&idx, &buf);
+                            s.spawn(move || {
+                                for i in (t..N).step_by(threads) {
+                                    idx.get(&&buf[20 * i..20 * (i + 1)]).expect("lookup");
+                                }
+                            });
+                        }
+                    })
+                })
+            });
+        }
+    }
+
     bench("index size (5M owned keys)", || {
         const N: usize = 5000000;
         let dir = tempdir().unwrap();
@@ -13,18 +13,23 @@
 //
 // ```plain,ignore
 // INDEX       := HEADER + ENTRY_LIST
-// HEADER      := '\0'  (takes offset 0, so 0 is not a valid offset for ENTRY)
+// HEADER      := '\0' + ('' | KEY_LEN)  (takes offset 0, so 0 is not a valid offset for ENTRY)
+// KEY_LEN     := '\9' + VLQ(FIXED_KEY_LEN)
 // ENTRY_LIST  := RADIX | ENTRY_LIST + ENTRY
-// ENTRY       := RADIX | LEAF | LINK | KEY | ROOT + REVERSED(VLQ(ROOT_LEN)) |
+// ENTRY       := RADIX | LEAF | LINK | KEY | FIXED_KEY | ROOT + REVERSED(VLQ(ROOT_LEN)) |
 //                ROOT + CHECKSUM + REVERSED(VLQ(ROOT_LEN + CHECKSUM_LEN))
-// RADIX       := '\2' + RADIX_FLAG (1 byte) + BITMAP (2 bytes) +
+// RADIX       := '\2' + RADIX_FLAG (1 byte) + PREFIX + BITMAP (2 or 32 bytes) +
 //                PTR2(RADIX | LEAF) * popcnt(BITMAP) + PTR2(LINK)
-// LEAF        := '\3' + PTR(KEY | EXT_KEY) + PTR(LINK)
+// PREFIX      := '' (if not HAVE_PREFIX) | VLQ(PREFIX_LEN) + PREFIX_DIGITS
+// LEAF        := '\3' + PTR(KEY | FIXED_KEY | EXT_KEY) + PTR(LINK)
 // LINK        := '\4' + VLQ(VALUE) + PTR(NEXT_LINK | NULL)
 // KEY         := '\5' + VLQ(KEY_LEN) + KEY_BYTES
+// FIXED_KEY   := '\10' + KEY_BYTES (FIXED_KEY_LEN bytes)
 // EXT_KEY     := '\6' + VLQ(KEY_START) + VLQ(KEY_LEN)
-// INLINE_LEAF := '\7' + EXT_KEY + LINK
-// ROOT        := '\1' + PTR(RADIX) + VLQ(META_LEN) + META
+// INLINE_LEAF := '\7' + (EXT_KEY | KEY | FIXED_KEY) + LINK
+// ROOT        := '\1' + PTR(RADIX) + VLQ(META_LEN) + META |
+//                '\11' + PTR(RADIX) + VLQ(ROOT_FLAGS) + ROOT_EXT + VLQ(META_LEN) + META
+// ROOT_EXT    := ('' | PTR(REVERSE_RADIX)) + ('' | VLQ(KEY_COUNT) + VLQ(VALUE_COUNT))
 // CHECKSUM    := '\8' + PTR(PREVIOUS_CHECKSUM) + VLQ(CHUNK_SIZE_LOGARITHM) +
 //                VLQ(CHECKSUM_CHUNK_START) + XXHASH_LIST + CHECKSUM_XX32 (LE32)
 // XXHASH_LIST := A list of 64-bit xxhash in Little Endian.
@@ -32,7 +37,8 @@
 // PTR(ENTRY)  := VLQ(the offset of ENTRY)
 // PTR2(ENTRY) := the offset of ENTRY, in 0 or 4, or 8 bytes depending on BITMAP and FLAGS
 //
-// RADIX_FLAG := USE_64_BIT (1 bit) + RESERVED (6 bits) + HAVE_LINK (1 bit)
+// RADIX_FLAG := USE_64_BIT (1 bit) + BASE256 (1 bit) + HAVE_PREFIX (1 bit) + RESERVED (4 bits) +
+//               HAVE_LINK (1 bit)
 // ```
 //
 // Some notes about the format:
@@ -41,6 +47,13 @@
 //   in a radix entry could be less than 16 if some of the children are missing (ex. offset = 0).
 //   The corresponding jump table bytes of missing children are 0s. If child i exists, then
 //   `jumptable[i]` is the relative (to the beginning of radix entry) offset of PTR(child offset).
+// - If BASE256 is set, the "RADIX" entry has 256 children and a 32-byte BITMAP instead. Each
+//   level of the tree then consumes a byte of the key, instead of 4 bits. This is used for
+//   non-hash keys. All radix entries in an index share the same fanout.
+// - If HAVE_PREFIX is set, the "RADIX" entry is path compressed. PREFIX_DIGITS are the radix
+//   digits consumed before looking at children, as if there is a chain of single-child radix
+//   entries. PREFIX_LEN is the count of digits. Base16 digits are packed into bytes, high 4
+//   bits first.
 // - A "ROOT" entry its length recorded as the last byte. Normally the root entry is written
 //   at the end. This makes it easier for the caller - it does not have to record the position
 //   of the root entry. The caller could optionally provide a root location.
@@ -52,13 +65,22 @@
 // - The "EXT_KEY" type has a logically similar function with "KEY". But it refers to an external
 //   buffer. This is useful to save spaces if the index is not a source of truth and keys are
 //   long.
-// - The "INLINE_LEAF" type is basically an inlined version of EXT_KEY and LINK, to save space.
+// - The "INLINE_LEAF" type is basically an inlined version of EXT_KEY (or KEY) and LINK, to save
+//   space. KEY is only inlined if it is short (see INLINE_KEY_MAX_LEN).
 // - The "ROOT_LEN" is reversed so it can be read byte-by-byte from the end of a file.
+// - A "ROOT" entry with type '\11' has optional fields decided by ROOT_FLAGS. If bit 0 is set,
+//   it has a REVERSE_RADIX tree. Its keys are the big-endian u64 values followed by keys of the
+//   main tree, so keys can be looked up by value. If bit 1 is set, it has the number of keys and
+//   values in the main tree.
+// - If HEADER contains "KEY_LEN", all keys in the index have FIXED_KEY_LEN bytes. Embedded keys
+//   are then written as "FIXED_KEY" without the length. This is mainly for source control
+//   hashes (20 or 32 bytes). The "KEY_LEN" is only written when the file is created.
 
 use std::borrow::Cow;
 use std::cmp::Ordering::Equal;
 use std::cmp::Ordering::Greater;
 use std::cmp::Ordering::Less;
+use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Formatter;
@@ -76,6 +98,8 @@ use std::ops::Bound::Excluded;
 use std::ops::Bound::Included;
 use std::ops::Bound::Unbounded;
 use std::ops::Deref;
+use std::ops::DerefMut;
+use std::ops::Range;
 use std::ops::RangeBounds;
 use std::path::Path;
 use std::path::PathBuf;
@@ -98,7 +122,6 @@ use vlqencoding::VLQEncode;
 
 use crate::base16::base16_to_base256;
 use crate::base16::single_hex_to_base16;
-use crate::base16::Base16Iter;
 use crate::config;
 use crate::errors::IoResultExt;
 use crate::errors::ResultExt;
@@ -110,10 +133,20 @@ use crate::utils::xxhash32;
 
 //// Structures and serialization
 
-#[derive(Clone, PartialEq, Default)]
+#[derive(Clone, PartialEq)]
 struct MemRadix {
-    pub offsets: [Offset; 16],
+    pub offsets: RadixChildren,
     pub link_offset: LinkOffset,
+    pub prefix: Box<[u8]>, // unpacked digits, see RADIX_FLAG_HAVE_PREFIX
+}
+
+/// Children of an in-memory radix entry. Derefs to a slice of 16 or 256
+/// offsets depending on [`RadixFanout`].
+#[derive(Clone, PartialEq)]
+enum RadixChildren {
+    Base16([Offset; 16]),
+    // Boxed so the more common base16 entries stay small.
+    Base256(Box<[Offset; 256]>),
 }
 
 #[derive(Clone, PartialEq)]
@@ -144,6 +177,16 @@ struct MemLink {
 struct MemRoot {
     pub radix_offset: RadixOffset,
     pub meta: Box<[u8]>,
+    pub reverse_radix_offset: Option<RadixOffset>,
+    pub counts: Option<RootCounts>,
+}
+
+/// Number of keys and values in the main radix tree.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct RootCounts {
+    /// Keys with at least one value.
+    pub keys: u64,
+    pub values: u64,
 }
 
 /// A Checksum entry specifies the checksums for all bytes before the checksum
@@ -227,6 +270,14 @@ const TYPE_KEY: u8 = 5;
 const TYPE_EXT_KEY: u8 = 6;
 const TYPE_INLINE_LEAF: u8 = 7;
 const TYPE_CHECKSUM: u8 = 8;
+// On-disk only types. They do not have in-memory entries.
+const TYPE_FIXED_KEY_LEN: u8 = 9;
+const TYPE_FIXED_KEY: u8 = 10;
+const TYPE_EXT_ROOT: u8 = 11;
+
+// Bits of ROOT_FLAGS.
+const ROOT_FLAG_REVERSE_RADIX: u64 = 1;
+const ROOT_FLAG_COUNTS: u64 = 2;
 
 // Bits needed to represent the above type integers.
 const TYPE_BITS: usize = 3;
@@ -235,9 +286,15 @@ const TYPE_BITS: usize = 3;
 const TYPE_BYTES: usize = 1;
 const RADIX_FLAG_BYTES: usize = 1;
 const RADIX_BITMAP_BYTES: usize = 2;
+const RADIX_BASE256_BITMAP_BYTES: usize = 32;
+
+// Max length of an embedded KEY to be written inside an INLINE_LEAF.
+const INLINE_KEY_MAX_LEN: usize = 32;
 
 // Bit flags used by radix
 const RADIX_FLAG_USE_64BIT: u8 = 1;
+const RADIX_FLAG_BASE256: u8 = 1 << 1;
+const RADIX_FLAG_HAVE_PREFIX: u8 = 1 << 2;
 const RADIX_FLAG_HAVE_LINK: u8 = 1 << 7;
 
 /// Offset to an entry. The type of the entry is yet to be resolved.
@@ -300,7 +357,7 @@ impl Offset {
             TYPE_RADIX => Ok(TypedOffset::Radix(RadixOffset(self))),
             TYPE_LEAF => Ok(TypedOffset::Leaf(LeafOffset(self))),
             TYPE_LINK => Ok(TypedOffset::Link(LinkOffset(self))),
-            TYPE_KEY => Ok(TypedOffset::Key(KeyOffset(self))),
+            TYPE_KEY | TYPE_FIXED_KEY => Ok(TypedOffset::Key(KeyOffset(self))),
             TYPE_EXT_KEY => Ok(TypedOffset::ExtKey(ExtKeyOffset(self))),
             // LeafOffset handles inline transparently.
             TYPE_INLINE_LEAF => Ok(TypedOffset::Leaf(LeafOffset(self))),
@@ -341,7 +398,7 @@ impl Offset {
             Some(TYPE_RADIX) => Some(TypedOffset::Radix(RadixOffset(self))),
             Some(TYPE_LEAF) => Some(TypedOffset::Leaf(LeafOffset(self))),
             Some(TYPE_LINK) => Some(TypedOffset::Link(LinkOffset(self))),
-            Some(TYPE_KEY) => Some(TypedOffset::Key(KeyOffset(self))),
+            Some(TYPE_KEY | TYPE_FIXED_KEY) => Some(TypedOffset::Key(KeyOffset(self))),
             Some(TYPE_EXT_KEY) => Some(TypedOffset::ExtKey(ExtKeyOffset(self))),
             // LeafOffset handles inline transparently.
             Some(TYPE_INLINE_LEAF) => Some(TypedOffset::Leaf(LeafOffset(self))),
@@ -422,17 +479,18 @@ impl RadixOffset {
                 .buf
                 .get(flag_start)
                 .ok_or_else(|| index.range_error(flag_start, 1))?;
+            let (_, prefix_bytes) = Self::read_prefix_len_unchecked(index, flag, flag_start)?;
+            let bitmap_start = flag_start + RADIX_FLAG_BYTES + prefix_bytes;
+            let bitmap_bytes = Self::parse_bitmap_bytes_from_flag(flag);
             index.verify_checksum(
                 flag_start as u64,
-                (RADIX_FLAG_BYTES + RADIX_BITMAP_BYTES) as u64,
+                (bitmap_start + bitmap_bytes - flag_start) as u64,
             )?;
 
             if Self::parse_have_link_from_flag(flag) {
-                let bitmap_start = flag_start + RADIX_FLAG_BYTES;
-                let bitmap = Self::read_bitmap_unchecked(index, bitmap_start)?;
+                let bitmap = Self::read_bitmap_unchecked(index, flag, bitmap_start)?;
                 let int_size = Self::parse_int_size_from_flag(flag);
-                let link_offset =
-                    bitmap_start + RADIX_BITMAP_BYTES + bitmap.count_ones() as usize * int_size;
+                let link_offset = bitmap_start + bitmap_bytes + bitmap.count() * int_size;
                 index.verify_checksum(link_offset as u64, int_size as u64)?;
                 let raw_offset = Self::read_raw_int_unchecked(index, int_size, link_offset)?;
                 Ok(LinkOffset::from_offset(
@@ -449,25 +507,28 @@ impl RadixOffset {
     /// Return stored offset, or `Offset(0)` if that child does not exist.
     #[inline]
     fn child(self, index: &Index, i: u8) -> crate::Result<Offset> {
-        // "i" is not derived from user input.
-        assert!(i < 16);
         if self.is_dirty() {
-            Ok(index.dirty_radixes[self.dirty_index()].offsets[i as usize])
+            let offsets = &index.dirty_radixes[self.dirty_index()].offsets;
+            // "i" is not derived from user input.
+            assert!((i as usize) < offsets.len());
+            Ok(offsets[i as usize])
         } else {
             let flag_start = TYPE_BYTES + usize::from(self);
-            let bitmap_start = flag_start + RADIX_FLAG_BYTES;
-            // Integrity of "bitmap" is checked below to reduce calls to verify_checksum, since
-            // this is a hot path.
-            let bitmap = Self::read_bitmap_unchecked(index, bitmap_start)?;
-            let has_child = (1u16 << i) & bitmap != 0;
-            if has_child {
-                let flag = *index
-                    .buf
-                    .get(flag_start)
-                    .ok_or_else(|| index.range_error(flag_start, 1))?;
+            let flag = *index
+                .buf
+                .get(flag_start)
+                .ok_or_else(|| index.range_error(flag_start, 1))?;
+            let (_, prefix_bytes) = Self::read_prefix_len_unchecked(index, flag, flag_start)?;
+            let bitmap_start = flag_start + RADIX_FLAG_BYTES + prefix_bytes;
+            let bitmap_bytes = Self::parse_bitmap_bytes_from_flag(flag);
+            assert!(i <= Self::parse_fanout_from_flag(flag).last_child());
+            // Integrity of "flag", "prefix" and "bitmap" is checked below to reduce calls to
+            // verify_checksum, since this is a hot path.
+            let bitmap = Self::read_bitmap_unchecked(index, flag, bitmap_start)?;
+            if bitmap.has(i) {
                 let int_size = Self::parse_int_size_from_flag(flag);
-                let skip_child_count = (((1u16 << i) - 1) & bitmap).count_ones() as usize;
-                let child_offset = bitmap_start + RADIX_BITMAP_BYTES + skip_child_count * int_size;
+                let skip_child_count = bitmap.count_before(i);
+                let child_offset = bitmap_start + bitmap_bytes + skip_child_count * int_size;
                 index.verify_checksum(
                     flag_start as u64,
                     (child_offset + int_size - flag_start) as u64,
@@ -475,12 +536,46 @@ impl RadixOffset {
                 let raw_offset = Self::read_raw_int_unchecked(index, int_size, child_offset)?;
                 Ok(Offset::from_disk(index, raw_offset)?)
             } else {
-                index.verify_checksum(bitmap_start as u64, RADIX_BITMAP_BYTES as u64)?;
+                index.verify_checksum(
+                    flag_start as u64,
+                    (bitmap_start + bitmap_bytes - flag_start) as u64,
+                )?;
                 Ok(Offset::default())
             }
         }
     }
 
+    /// Digits consumed by a path-compressed radix entry before looking at
+    /// its children. Empty if the radix entry is not path-compressed.
+    #[inline]
+    fn prefix(self, index: &Index) -> crate::Result<KeyDigits<'_>> {
+        if self.is_dirty() {
+            // In-memory digits are not packed. Base256 iterates them as-is.
+            let prefix = &index.dirty_radixes[self.dirty_index()].prefix;
+            Ok(KeyDigits::new(prefix, RadixFanout::Base256))
+        } else {
+            let flag_start = TYPE_BYTES + usize::from(self);
+            let flag = *index
+                .buf
+                .get(flag_start)
+                .ok_or_else(|| index.range_error(flag_start, 1))?;
+            let (prefix_len, prefix_bytes) =
+                Self::read_prefix_len_unchecked(index, flag, flag_start)?;
+            if prefix_len == 0 {
+                return Ok(KeyDigits::new(&[], RadixFanout::Base256));
+            }
+            let fanout = Self::parse_fanout_from_flag(flag);
+            let packed_end = flag_start + RADIX_FLAG_BYTES + prefix_bytes;
+            let packed_start = packed_end - fanout.packed_len(prefix_len);
+            index.verify_checksum(flag_start as u64, (packed_end - flag_start) as u64)?;
+            let packed = index
+                .buf
+                .get(packed_start..packed_end)
+                .ok_or_else(|| index.range_error(packed_start, packed_end - packed_start))?;
+            Ok(KeyDigits::from_packed(packed, prefix_len, fanout))
+        }
+    }
+
     /// Copy an on-disk entry to memory so it can be modified. Return new offset.
     /// If the offset is already in-memory, return it as-is.
     #[inline]
@@ -498,7 +593,6 @@ impl RadixOffset {
     /// Change a child of `MemRadix`. Panic if the offset points to an on-disk entry.
     #[inline]
     fn set_child(self, index: &mut Index, i: u8, value: Offset) {
-        assert!(i < 16);
         if self.is_dirty() {
             index.dirty_radixes[self.dirty_index()].offsets[i as usize] = value;
         } else {
@@ -520,7 +614,7 @@ impl RadixOffset {
     /// Panic if the offset points to an on-disk entry.
     fn set_all_to_null(self, index: &mut Index) {
         if self.is_dirty() {
-            index.dirty_radixes[self.dirty_index()] = MemRadix::default();
+            index.dirty_radixes[self.dirty_index()] = MemRadix::new(index.fanout);
         } else {
             panic!("bug: set_all_to_null called on immutable radix entry");
         }
@@ -550,13 +644,62 @@ impl RadixOffset {
         }
     }
 
+    /// Read the digit count and the byte size of the prefix, without integrity
+    /// check. Return `(0, 0)` if the radix entry is not path-compressed.
+    #[inline]
+    fn read_prefix_len_unchecked(
+        index: &Index,
+        flag: u8,
+        flag_start: usize,
+    ) -> crate::Result<(usize, usize)> {
+        if flag & RADIX_FLAG_HAVE_PREFIX == 0 {
+            return Ok((0, 0));
+        }
+        let (prefix_len, vlq_len): (usize, _) = index
+            .buf
+            .read_vlq_at(flag_start + RADIX_FLAG_BYTES)
+            .context(index.path(), "cannot read radix prefix length")
+            .corruption()?;
+        let fanout = Self::parse_fanout_from_flag(flag);
+        if prefix_len > index.buf.len() {
+            return Err(index.corruption(format!(
+                "radix prefix length {} at {} is too large",
+                prefix_len, flag_start
+            )));
+        }
+        Ok((prefix_len, vlq_len + fanout.packed_len(prefix_len)))
+    }
+
+    /// Parse fanout from a flag.
+    #[inline]
+    fn parse_fanout_from_flag(flag: u8) -> RadixFanout {
+        if flag & RADIX_FLAG_BASE256 == 0 {
+            RadixFanout::Base16
+        } else {
+            RadixFanout::Base256
+        }
+    }
+
+    /// Parse bitmap size (in bytes) from a flag.
+    #[inline]
+    fn parse_bitmap_bytes_from_flag(flag: u8) -> usize {
+        match Self::parse_fanout_from_flag(flag) {
+            RadixFanout::Base16 => RADIX_BITMAP_BYTES,
+            RadixFanout::Base256 => RADIX_BASE256_BITMAP_BYTES,
+        }
+    }
+
     /// Read bitmap from the given offset without integrity check.
     #[inline]
-    fn read_bitmap_unchecked(index: &Index, bitmap_offset: usize) -> crate::Result<u16> {
-        debug_assert_eq!(RADIX_BITMAP_BYTES, size_of::<u16>());
+    fn read_bitmap_unchecked(
+        index: &Index,
+        flag: u8,
+        bitmap_offset: usize,
+    ) -> crate::Result<RadixBitmap> {
         let buf = &index.buf;
-        buf.get(bitmap_offset..bitmap_offset + RADIX_BITMAP_BYTES)
-            .map(LittleEndian::read_u16)
+        let bitmap_bytes = Self::parse_bitmap_bytes_from_flag(flag);
+        buf.get(bitmap_offset..bitmap_offset + bitmap_bytes)
+            .map(RadixBitmap::read_from)
             .ok_or_else(|| {
                 crate::Error::corruption(
                     &index.path,
@@ -585,6 +728,176 @@ impl RadixOffset {
     }
 }
 
+/// Bitmap of existing children of a radix entry. Base16 radix entries only
+/// use the lowest 16 bits.
+#[derive(Copy, Clone, Default)]
+struct RadixBitmap([u64; 4]);
+
+impl RadixBitmap {
+    /// Read a bitmap of 2 or 32 bytes stored in Little Endian.
+    #[inline]
+    fn read_from(buf: &[u8]) -> Self {
+        let mut bitmap = Self::default();
+        if buf.len() == RADIX_BITMAP_BYTES {
+            bitmap.0[0] = LittleEndian::read_u16(buf) as u64;
+        } else {
+            debug_assert_eq!(buf.len(), RADIX_BASE256_BITMAP_BYTES);
+            LittleEndian::read_u64_into(buf, &mut bitmap.0);
+        }
+        bitmap
+    }
+
+    /// Write the bitmap using `len` (2 or 32) bytes.
+    fn write_to<W: Write>(&self, writer: &mut W, len: usize) -> io::Result<()> {
+        if len == RADIX_BITMAP_BYTES {
+            writer.write_u16::<LittleEndian>(self.0[0] as u16)
+        } else {
+            debug_assert_eq!(len, RADIX_BASE256_BITMAP_BYTES);
+            self.0
+                .iter()
+                .try_for_each(|&word| writer.write_u64::<LittleEndian>(word))
+        }
+    }
+
+    #[inline]
+    fn has(&self, i: u8) -> bool {
+        (self.0[(i / 64) as usize] >> (i % 64)) & 1 == 1
+    }
+
+    #[inline]
+    fn set(&mut self, i: u8) {
+        self.0[(i / 64) as usize] |= 1 << (i % 64);
+    }
+
+    /// Count of children before the `i`-th child.
+    #[inline]
+    fn count_before(&self, i: u8) -> usize {
+        let word = (i / 64) as usize;
+        let mask = (1u64 << (i % 64)) - 1;
+        let before: u32 = self.0[..word].iter().map(|w| w.count_ones()).sum();
+        (before + (self.0[word] & mask).count_ones()) as usize
+    }
+
+    /// Count of all children.
+    #[inline]
+    fn count(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+}
+
+impl RadixChildren {
+    #[inline]
+    fn fanout(&self) -> RadixFanout {
+        match self {
+            RadixChildren::Base16(_) => RadixFanout::Base16,
+            RadixChildren::Base256(_) => RadixFanout::Base256,
+        }
+    }
+}
+
+impl Deref for RadixChildren {
+    type Target = [Offset];
+
+    #[inline]
+    fn deref(&self) -> &[Offset] {
+        match self {
+            RadixChildren::Base16(offsets) => &offsets[..],
+            RadixChildren::Base256(offsets) => &offsets[..],
+        }
+    }
+}
+
+impl DerefMut for RadixChildren {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [Offset] {
+        match self {
+            RadixChildren::Base16(offsets) => &mut offsets[..],
+            RadixChildren::Base256(offsets) => &mut offsets[..],
+        }
+    }
+}
+
+/// Iterating through radix digits of a key. A digit is 4 bits for
+/// [`RadixFanout::Base16`], or a byte for [`RadixFanout::Base256`].
+#[derive(Clone, Copy)]
+struct KeyDigits<'a> {
+    key: &'a [u8],
+    start: usize,
+    end: usize,
+    fanout: RadixFanout,
+}
+
+impl<'a> KeyDigits<'a> {
+    #[inline]
+    fn new(key: &'a [u8], fanout: RadixFanout) -> Self {
+        let end = key.len() * fanout.digits_per_byte();
+        Self {
+            key,
+            start: 0,
+            end,
+            fanout,
+        }
+    }
+
+    /// Iterate through the first `len` digits of packed bytes.
+    #[inline]
+    fn from_packed(packed: &'a [u8], len: usize, fanout: RadixFanout) -> Self {
+        debug_assert!(fanout.packed_len(len) <= packed.len());
+        Self {
+            key: packed,
+            start: 0,
+            end: len,
+            fanout,
+        }
+    }
+
+    #[inline]
+    fn digit(&self, i: usize) -> u8 {
+        match self.fanout {
+            RadixFanout::Base16 => {
+                let byte = self.key[i / 2];
+                if i & 1 == 0 {
+                    byte >> 4
+                } else {
+                    byte & 0xf
+                }
+            }
+            RadixFanout::Base256 => self.key[i],
+        }
+    }
+
+    #[inline]
+    fn skip(self, n: usize) -> Self {
+        Self {
+            start: (self.start + n).min(self.end),
+            ..self
+        }
+    }
+}
+
+impl<'a> Iterator for KeyDigits<'a> {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        if self.start >= self.end {
+            None
+        } else {
+            let digit = self.digit(self.start);
+            self.start += 1;
+            Some(digit)
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+}
+
+impl<'a> ExactSizeIterator for KeyDigits<'a> {}
+
 /// Extract key_content from an untyped Offset. Internal use only.
 fn extract_key_content(index: &Index, key_offset: Offset) -> crate::Result<&[u8]> {
     let typed_offset = key_offset.to_typed(index)?;
@@ -608,13 +921,13 @@ impl LeafOffset {
                 TYPE_INLINE_LEAF => {
                     let raw_key_offset = u64::from(self) + TYPE_BYTES as u64;
                     // PERF: Consider skip checksum for this read.
-                    let key_offset = ExtKeyOffset::from_offset(
-                        Offset::from_disk(index, raw_key_offset)?,
-                        index,
-                    )?;
+                    let key_offset = Offset::from_disk(index, raw_key_offset)?;
                     // Avoid using key_content. Skip one checksum check.
-                    let (key_content, key_entry_size) =
-                        key_offset.key_content_and_entry_size_unchecked(index)?;
+                    let (key_content, key_entry_size) = match key_offset.to_typed(index)? {
+                        TypedOffset::ExtKey(x) => x.key_content_and_entry_size_unchecked(index)?,
+                        TypedOffset::Key(x) => x.key_content_and_entry_size_unchecked(index)?,
+                        _ => return Err(index.corruption("unexpected key type in inline leaf")),
+                    };
                     let key_entry_size = key_entry_size.unwrap();
                     let raw_link_offset = raw_key_offset + key_entry_size as u64;
                     index.verify_checksum(u64::from(self), raw_link_offset - u64::from(self))?;
@@ -726,6 +1039,10 @@ impl<'a> Iterator for LeafValueIter<'a> {
 
 /// Iterator returned by [`Index::range`].
 /// Provide access to full keys and values (as [`LinkOffset`]), sorted by key.
+///
+/// Keys stored in leaf entries are borrowed from the on-disk buffer, or the
+/// external key buffer, without copying. Only keys that end at a radix entry
+/// (ex. "a" if "ab" also exists) are reconstructed and owned.
 pub struct RangeIter<'a> {
     index: &'a Index,
 
@@ -753,17 +1070,28 @@ impl<'a> RangeIter<'a> {
 
     /// Reconstruct "key" from the stack.
     fn key(stack: &[IterState], index: &Index) -> crate::Result<Vec<u8>> {
-        // Reconstruct key. Collect base16 child stack (prefix + visiting),
-        // then convert to base256.
+        // Reconstruct key. Collect child stack (prefix + visiting), then
+        // convert to base256 if it is base16.
+        let last_child = index.fanout.last_child();
         let mut prefix = Vec::with_capacity(stack.len() - 1);
-        for frame in stack.iter().take(stack.len() - 1).cloned() {
-            prefix.push(match frame {
+        for frame in stack.iter().cloned() {
+            let radix = match frame {
+                IterState::RadixChild(radix, _) | IterState::RadixLeaf(radix) => radix,
+                _ => unreachable!("bug: malicious iterator state"),
+            };
+            // Digits of a path-compressed radix entry.
+            prefix.extend(radix.prefix(index)?);
+            match frame {
                 // The frame contains the "current" child being visited.
-                IterState::RadixChild(_, child) if child < 16 => child,
+                IterState::RadixChild(_, child) if child <= last_child => prefix.push(child),
+                // The last frame.
+                IterState::RadixLeaf(_) => break,
                 _ => unreachable!("bug: malicious iterator state"),
-            })
+            }
         }
-        if prefix.len() & 1 == 1 {
+        if index.fanout == RadixFanout::Base256 {
+            Ok(prefix)
+        } else if prefix.len() & 1 == 1 {
             // Odd-length key
             Err(index.corruption("unexpected odd-length key"))
         } else {
@@ -779,7 +1107,8 @@ impl<'a> RangeIter<'a> {
         exclusive: IterState,
     ) -> Option<crate::Result<(Cow<'a, [u8]>, LinkOffset)>> {
         loop {
-            let state = match stack.pop().unwrap().step(towards) {
+            let last_child = index.fanout.last_child();
+            let state = match stack.pop().unwrap().step(towards, last_child) {
                 // Pop. Visit next.
                 None => continue,
                 Some(state) => state,
@@ -874,6 +1203,107 @@ impl<'a> DoubleEndedIterator for RangeIter<'a> {
     }
 }
 
+/// Iterator returned by [`Index::dirty_iter`].
+/// Provide access to keys and their values inserted since the last flush,
+/// sorted by key.
+pub struct DirtyIter<'a> {
+    index: &'a Index,
+
+    // In-memory entries to visit, and radix digits leading to them.
+    stack: Vec<(Offset, Vec<u8>)>,
+
+    // Completed. Either error out, or the iteration ends.
+    completed: bool,
+}
+
+/// Key, and its in-memory values.
+type DirtyEntry<'a> = (Cow<'a, [u8]>, Vec<u64>);
+
+impl<'a> DirtyIter<'a> {
+    fn new(index: &'a Index) -> Self {
+        let root: Offset = index.dirty_root.radix_offset.into();
+        let stack = if root.is_dirty() {
+            vec![(root, Vec::new())]
+        } else {
+            Vec::new()
+        };
+        Self {
+            index,
+            stack,
+            completed: false,
+        }
+    }
+
+    /// Values in the linked list that are not flushed, newest first.
+    fn dirty_values(&self, mut link: LinkOffset) -> crate::Result<Vec<u64>> {
+        let mut values = Vec::new();
+        // On-disk links never refer to in-memory links.
+        while link.is_dirty() {
+            let (value, next) = link.value_and_next(self.index)?;
+            values.push(value);
+            link = next;
+        }
+        Ok(values)
+    }
+
+    fn next_entry(&mut self) -> crate::Result<Option<DirtyEntry<'a>>> {
+        let index = self.index;
+        while let Some((offset, mut digits)) = self.stack.pop() {
+            match offset.to_typed(index)? {
+                TypedOffset::Radix(radix) => {
+                    digits.extend(radix.prefix(index)?);
+                    // On-disk entries never refer to in-memory entries. Skip them.
+                    for i in (0..=index.fanout.last_child()).rev() {
+                        let child = radix.child(index, i)?;
+                        if child.is_dirty() {
+                            let mut child_digits = digits.clone();
+                            child_digits.push(i);
+                            self.stack.push((child, child_digits));
+                        }
+                    }
+                    let values = self.dirty_values(radix.link_offset(index)?)?;
+                    if !values.is_empty() {
+                        let key = if index.fanout == RadixFanout::Base256 {
+                            digits
+                        } else if digits.len() & 1 == 1 {
+                            return Err(index.corruption("unexpected odd-length key"));
+                        } else {
+                            base16_to_base256(&digits)
+                        };
+                        return Ok(Some((Cow::Owned(key), values)));
+                    }
+                }
+                TypedOffset::Leaf(leaf) => {
+                    let (key, link) = leaf.key_and_link_offset(index)?;
+                    let values = self.dirty_values(link)?;
+                    if !values.is_empty() {
+                        return Ok(Some((Cow::Borrowed(key), values)));
+                    }
+                }
+                _ => return Err(index.corruption("unexpected type during iteration")),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a> Iterator for DirtyIter<'a> {
+    type Item = crate::Result<DirtyEntry<'a>>;
+
+    /// Return the next key, and its in-memory values, newest first.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.completed {
+            return None;
+        }
+        let result = self.next_entry().transpose();
+        match result {
+            Some(Err(_)) | None => self.completed = true,
+            _ => {}
+        }
+        result
+    }
+}
+
 impl LinkOffset {
     /// Iterating through values referred by this linked list.
     pub fn values(self, index: &Index) -> LeafValueIter<'_> {
@@ -931,8 +1361,32 @@ impl KeyOffset {
     /// Key content of a key entry.
     #[inline]
     fn key_content(self, index: &Index) -> crate::Result<&[u8]> {
+        let (key_content, entry_size) = self.key_content_and_entry_size_unchecked(index)?;
+        if let Some(entry_size) = entry_size {
+            index.verify_checksum(u64::from(self), entry_size as u64)?;
+        }
+        Ok(key_content)
+    }
+
+    /// Key content and key entry size. Used internally.
+    #[inline]
+    fn key_content_and_entry_size_unchecked(
+        self,
+        index: &Index,
+    ) -> crate::Result<(&[u8], Option<usize>)> {
         if self.is_dirty() {
-            Ok(&index.dirty_keys[self.dirty_index()].key[..])
+            Ok((&index.dirty_keys[self.dirty_index()].key[..], None))
+        } else if index.buf.get(usize::from(self)) == Some(&TYPE_FIXED_KEY) {
+            let key_len = index.fixed_key_len.ok_or_else(|| {
+                index.corruption("unexpected FIXED_KEY without KEY_LEN in header")
+            })?;
+            let start = usize::from(self) + TYPE_BYTES;
+            let end = start + key_len;
+            if end > index.buf.len() {
+                Err(index.range_error(start, key_len))
+            } else {
+                Ok((&index.buf[start..end], Some(TYPE_BYTES + key_len)))
+            }
         } else {
             let (key_len, vlq_len): (usize, _) = index
                 .buf
@@ -944,11 +1398,10 @@ impl KeyOffset {
                 .corruption()?;
             let start = usize::from(self) + TYPE_BYTES + vlq_len;
             let end = start + key_len;
-            index.verify_checksum(u64::from(self), end as u64 - u64::from(self))?;
             if end > index.buf.len() {
                 Err(index.range_error(start, end - start))
             } else {
-                Ok(&index.buf[start..end])
+                Ok((&index.buf[start..end], Some(TYPE_BYTES + vlq_len + key_len)))
             }
         }
     }
@@ -1060,6 +1513,18 @@ fn check_type(index: impl IndexBuf, offset: usize, expected: u8) -> crate::Resul
 }
 
 impl MemRadix {
+    fn new(fanout: RadixFanout) -> Self {
+        let offsets = match fanout {
+            RadixFanout::Base16 => RadixChildren::Base16([Offset::default(); 16]),
+            RadixFanout::Base256 => RadixChildren::Base256(Box::new([Offset::default(); 256])),
+        };
+        MemRadix {
+            offsets,
+            link_offset: LinkOffset::default(),
+            prefix: Default::default(),
+        }
+    }
+
     fn read_from(index: &Index, offset: u64) -> crate::Result<Self> {
         let buf = &index.buf;
         let offset = offset as usize;
@@ -1074,14 +1539,29 @@ impl MemRadix {
             .ok_or_else(|| index.range_error(offset + pos, 1))?;
         pos += RADIX_FLAG_BYTES;
 
-        let bitmap = RadixOffset::read_bitmap_unchecked(index, offset + pos)?;
-        pos += RADIX_BITMAP_BYTES;
+        let fanout = RadixOffset::parse_fanout_from_flag(flag);
+        let (prefix_len, prefix_bytes) =
+            RadixOffset::read_prefix_len_unchecked(index, flag, offset + TYPE_BYTES)?;
+        pos += prefix_bytes;
+        let prefix: Box<[u8]> = if prefix_len > 0 {
+            let packed_start = offset + pos - fanout.packed_len(prefix_len);
+            let packed = buf
+                .get(packed_start..offset + pos)
+                .ok_or_else(|| index.range_error(packed_start, offset + pos - packed_start))?;
+            KeyDigits::from_packed(packed, prefix_len, fanout).collect()
+        } else {
+            Default::default()
+        };
+
+        let bitmap = RadixOffset::read_bitmap_unchecked(index, flag, offset + pos)?;
+        pos += RadixOffset::parse_bitmap_bytes_from_flag(flag);
 
         let int_size = RadixOffset::parse_int_size_from_flag(flag);
 
-        let mut offsets = [Offset::default(); 16];
-        for (i, o) in offsets.iter_mut().enumerate() {
-            if (bitmap >> i) & 1 == 1 {
+        let mut radix = MemRadix::new(fanout);
+        radix.prefix = prefix;
+        for (i, o) in radix.offsets.iter_mut().enumerate() {
+            if bitmap.has(i as u8) {
                 *o = Offset::from_disk(
                     index,
                     RadixOffset::read_raw_int_unchecked(index, int_size, offset + pos)?,
@@ -1097,19 +1577,17 @@ impl MemRadix {
         } else {
             LinkOffset::default()
         };
+        radix.link_offset = link_offset;
 
         index.verify_checksum(offset as u64, pos as u64)?;
 
-        Ok(MemRadix {
-            offsets,
-            link_offset,
-        })
+        Ok(radix)
     }
 
     fn write_to<W: Write>(&self, writer: &mut W, offset_map: &OffsetMap) -> io::Result<()> {
         // Prepare data to write
         let mut flag = 0;
-        let mut bitmap = 0;
+        let mut bitmap = RadixBitmap::default();
         let u32_max = ::std::u32::MAX as u64;
 
         let link_offset = if !self.link_offset.is_null() {
@@ -1123,10 +1601,19 @@ impl MemRadix {
             0
         };
 
-        let mut child_offsets = [0u64; 16];
+        let fanout = self.offsets.fanout();
+        if fanout == RadixFanout::Base256 {
+            flag |= RADIX_FLAG_BASE256;
+        }
+        if !self.prefix.is_empty() {
+            flag |= RADIX_FLAG_HAVE_PREFIX;
+        }
+
+        let mut child_offsets = [0u64; 256];
+        let child_offsets = &mut child_offsets[..self.offsets.len()];
         for (i, child_offset) in self.offsets.iter().enumerate() {
             if !child_offset.is_null() {
-                bitmap |= 1u16 << i;
+                bitmap.set(i as u8);
                 let child_offset = child_offset.to_disk(offset_map);
                 if child_offset > u32_max {
                     flag |= RADIX_FLAG_USE_64BIT;
@@ -1137,7 +1624,19 @@ impl MemRadix {
 
         // Write them
         writer.write_all(&[TYPE_RADIX, flag])?;
-        writer.write_u16::<LittleEndian>(bitmap)?;
+        if !self.prefix.is_empty() {
+            writer.write_vlq(self.prefix.len())?;
+            match fanout {
+                RadixFanout::Base16 => {
+                    for pair in self.prefix.chunks(2) {
+                        let low = pair.get(1).cloned().unwrap_or(0);
+                        writer.write_all(&[(pair[0] << 4) | low])?;
+                    }
+                }
+                RadixFanout::Base256 => writer.write_all(&self.prefix)?,
+            }
+        }
+        bitmap.write_to(writer, RadixOffset::parse_bitmap_bytes_from_flag(flag))?;
 
         if flag & RADIX_FLAG_USE_64BIT != 0 {
             for &child_offset in child_offsets.iter() {
@@ -1171,16 +1670,28 @@ impl MemLeaf {
                 let key_offset = offset + TYPE_BYTES;
                 // Skip the key part
                 let offset = key_offset + TYPE_BYTES;
-                let (_key_start, vlq_len): (u64, _) = buf
-                    .read_vlq_at(offset)
-                    .context(index.path(), "cannot read key_start in MemLeaf::read_from")
-                    .corruption()?;
-                let offset = offset + vlq_len;
-                let (_key_len, vlq_len): (u64, _) = buf
-                    .read_vlq_at(offset)
-                    .context(index.path(), "cannot read key_len in MemLeaf::read_from")
-                    .corruption()?;
-                let offset = offset + vlq_len;
+                let offset = match buf.get(key_offset) {
+                    Some(&TYPE_KEY) => {
+                        let (key_len, vlq_len): (usize, _) = buf
+                            .read_vlq_at(offset)
+                            .context(index.path(), "cannot read key_len in MemLeaf::read_from")
+                            .corruption()?;
+                        offset + vlq_len + key_len
+                    }
+                    Some(&TYPE_FIXED_KEY) => offset + index.fixed_key_len.unwrap_or_default(),
+                    _ => {
+                        let (_key_start, vlq_len): (u64, _) = buf
+                            .read_vlq_at(offset)
+                            .context(index.path(), "cannot read key_start in MemLeaf::read_from")
+                            .corruption()?;
+                        let offset = offset + vlq_len;
+                        let (_key_len, vlq_len): (u64, _) = buf
+                            .read_vlq_at(offset)
+                            .context(index.path(), "cannot read key_len in MemLeaf::read_from")
+                            .corruption()?;
+                        offset + vlq_len
+                    }
+                };
                 // Checksum will be verified by ExtKey and Leaf nodes
                 let key_offset = Offset::from_disk(index, key_offset as u64)?;
                 let link_offset =
@@ -1220,67 +1731,88 @@ impl MemLeaf {
     ///
     /// The caller probably wants to set this entry to "unused" to prevent writing twice,
     /// if true is returned.
+    #[allow(clippy::too_many_arguments)]
     fn maybe_write_inline_to(
         &self,
         writer: &mut Vec<u8>,
         buf: &[u8],
         buf_offset: u64,
-        dirty_ext_keys: &mut Vec<MemExtKey>,
-        dirty_links: &mut Vec<MemLink>,
+        dirty_keys: &mut [MemKey],
+        dirty_ext_keys: &mut [MemExtKey],
+        dirty_links: &mut [MemLink],
         offset_map: &mut OffsetMap,
+        fixed_key_len: Option<usize>,
     ) -> crate::Result<bool> {
         debug_assert!(!self.is_unused());
 
         // Conditions to be inlined:
         // - Both Key and Link are dirty (in-memory). Otherwise this might waste space.
-        // - Key is ExtKey. This is just to make implementation easier. Owned key support might be
-        // added in the future.
+        // - Key is ExtKey, or a Key not longer than INLINE_KEY_MAX_LEN. Long keys are written
+        //   separately so the leaf entry stays small.
         // - Link does not refer to another in-memory link that hasn't been written yet (i.e.
         //   does not exist in offset_map). This is just to make implementation easier.
 
         let are_dependencies_dirty = self.key_offset.is_dirty() && self.link_offset.is_dirty();
+        if !are_dependencies_dirty {
+            return Ok(false);
+        }
 
-        if are_dependencies_dirty {
-            // Not being able to read the key offset is not a fatal error here - it
-            // disables the inline optimization. But everything else works just fine.
-            if let Some(TypedOffset::ExtKey(key_offset)) = self.key_offset.to_optional_typed(buf) {
-                let ext_key_index = key_offset.dirty_index();
-                let link_index = self.link_offset.dirty_index();
-                let ext_key = dirty_ext_keys.get_mut(ext_key_index).unwrap();
-                let link = dirty_links.get_mut(link_index).unwrap();
-
-                let next_link_offset = link.next_link_offset;
-                if next_link_offset.is_dirty()
-                    && offset_map.link_map[next_link_offset.dirty_index()] == 0
-                {
-                    // Dependent Link is not written yet.
+        // Not being able to read the key offset is not a fatal error here - it
+        // disables the inline optimization. But everything else works just fine.
+        let key_offset = self.key_offset.to_optional_typed(buf);
+        match key_offset {
+            Some(TypedOffset::ExtKey(_)) => {}
+            Some(TypedOffset::Key(key_offset)) => {
+                let key = &dirty_keys[key_offset.dirty_index()];
+                if key.is_unused() || key.key.len() > INLINE_KEY_MAX_LEN {
                     return Ok(false);
                 }
+            }
+            _ => return Ok(false),
+        }
+
+        let link_index = self.link_offset.dirty_index();
+        let link = dirty_links.get_mut(link_index).unwrap();
+        let next_link_offset = link.next_link_offset;
+        if next_link_offset.is_dirty() && offset_map.link_map[next_link_offset.dirty_index()] == 0 {
+            // Dependent Link is not written yet.
+            return Ok(false);
+        }
 
-                // Header
-                writer.write_all(&[TYPE_INLINE_LEAF]).infallible()?;
+        // Header
+        writer.write_all(&[TYPE_INLINE_LEAF]).infallible()?;
 
-                // Inlined ExtKey
-                let offset = buf.len() as u64 + buf_offset;
+        // Inlined ExtKey or Key
+        let offset = writer.len() as u64 + buf_offset;
+        match key_offset {
+            Some(TypedOffset::ExtKey(key_offset)) => {
+                let ext_key_index = key_offset.dirty_index();
+                let ext_key = dirty_ext_keys.get_mut(ext_key_index).unwrap();
                 offset_map.ext_key_map[ext_key_index] = offset;
                 ext_key.write_to(writer, offset_map).infallible()?;
-
-                // Inlined Link
-                let offset = buf.len() as u64 + buf_offset;
-                offset_map.link_map[ext_key_index] = offset;
-                link.write_to(writer, offset_map).infallible()?;
-
                 ext_key.mark_unused();
-                link.mark_unused();
-
-                Ok(true)
-            } else {
-                // InlineLeaf only supports ExtKey, not embedded Key.
-                Ok(false)
             }
-        } else {
-            Ok(false)
+            Some(TypedOffset::Key(key_offset)) => {
+                let key_index = key_offset.dirty_index();
+                let key = dirty_keys.get_mut(key_index).unwrap();
+                offset_map.key_map[key_index] = offset;
+                // Keys of the reverse index are longer. They use KEY.
+                match fixed_key_len {
+                    Some(len) if len == key.key.len() => key.write_fixed_to(writer).infallible()?,
+                    _ => key.write_to(writer, offset_map).infallible()?,
+                }
+                key.mark_unused();
+            }
+            _ => unreachable!(),
         }
+
+        // Inlined Link
+        let offset = writer.len() as u64 + buf_offset;
+        offset_map.link_map[link_index] = offset;
+        link.write_to(writer, offset_map).infallible()?;
+        link.mark_unused();
+
+        Ok(true)
     }
 
     /// Write a Leaf entry.
@@ -1376,6 +1908,13 @@ impl MemKey {
         Ok(())
     }
 
+    /// Write a FIXED_KEY entry. The key length is defined by the header.
+    fn write_fixed_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&[TYPE_FIXED_KEY])?;
+        writer.write_all(&self.key)?;
+        Ok(())
+    }
+
     /// Mark the entry as unused. An unused entry won't be written to disk.
     fn mark_unused(&mut self) {
         self.key = Vec::new().into_boxed_slice();
@@ -1429,11 +1968,39 @@ impl MemExtKey {
 }
 
 impl MemRoot {
-    fn read_from(index: impl IndexBuf, offset: u64) -> crate::Result<(Self, usize)> {
-        let offset = offset as usize;
-        let mut cur = offset;
-        check_type(&index, offset, TYPE_ROOT)?;
-        cur += TYPE_BYTES;
+    /// Create a root entry for an empty index. Its radix entries are dirty.
+    fn new_dirty(reverse_index: bool, track_counts: bool) -> Self {
+        Self {
+            radix_offset: RadixOffset::from_dirty_index(0),
+            meta: Default::default(),
+            reverse_radix_offset: reverse_index.then(|| RadixOffset::from_dirty_index(1)),
+            counts: track_counts.then(RootCounts::default),
+        }
+    }
+
+    /// Dirty radix entries needed by the root entry of an empty index.
+    /// See [`MemRoot::new_dirty`].
+    fn new_dirty_radixes(&self, fanout: RadixFanout) -> Vec<MemRadix> {
+        let mut radixes = Vec::new();
+        if self.radix_offset.is_dirty() {
+            assert_eq!(self.radix_offset, RadixOffset::from_dirty_index(0));
+            radixes.push(MemRadix::new(fanout));
+            if let Some(reverse_radix_offset) = self.reverse_radix_offset {
+                assert_eq!(reverse_radix_offset, RadixOffset::from_dirty_index(1));
+                radixes.push(MemRadix::new(fanout));
+            }
+        }
+        radixes
+    }
+
+    fn read_from(index: impl IndexBuf, offset: u64) -> crate::Result<(Self, usize)> {
+        let offset = offset as usize;
+        let mut cur = offset;
+        let is_ext = index.buf().get(offset) == Some(&TYPE_EXT_ROOT);
+        if !is_ext {
+            check_type(&index, offset, TYPE_ROOT)?;
+        }
+        cur += TYPE_BYTES;
 
         let (radix_offset, vlq_len) = index
             .buf()
@@ -1445,6 +2012,53 @@ impl MemRoot {
         let radix_offset =
             RadixOffset::from_offset(Offset::from_disk(&index, radix_offset)?, &index)?;
 
+        let flags = if is_ext {
+            let (flags, vlq_len): (u64, _) = index
+                .buf()
+                .read_vlq_at(cur)
+                .context(index.path(), "cannot read root flags")
+                .corruption()?;
+            cur += vlq_len;
+            if flags & !(ROOT_FLAG_REVERSE_RADIX | ROOT_FLAG_COUNTS) != 0 {
+                return Err(index.corruption(format!("unsupported root flags {}", flags)));
+            }
+            flags
+        } else {
+            0
+        };
+
+        let reverse_radix_offset = if flags & ROOT_FLAG_REVERSE_RADIX != 0 {
+            let (reverse_radix_offset, vlq_len) = index
+                .buf()
+                .read_vlq_at(cur)
+                .context(index.path(), "cannot read reverse_radix_offset")
+                .corruption()?;
+            cur += vlq_len;
+            Some(RadixOffset::from_offset(
+                Offset::from_disk(&index, reverse_radix_offset)?,
+                &index,
+            )?)
+        } else {
+            None
+        };
+
+        let counts = if flags & ROOT_FLAG_COUNTS != 0 {
+            let mut read_count = |name: &str| -> crate::Result<u64> {
+                let (count, vlq_len) = index
+                    .buf()
+                    .read_vlq_at(cur)
+                    .context(index.path(), || format!("cannot read {} count", name))
+                    .corruption()?;
+                cur += vlq_len;
+                Ok(count)
+            };
+            let keys = read_count("key")?;
+            let values = read_count("value")?;
+            Some(RootCounts { keys, values })
+        } else {
+            None
+        };
+
         let (meta_len, vlq_len): (usize, _) = index
             .buf()
             .read_vlq_at(cur)
@@ -1463,6 +2077,8 @@ impl MemRoot {
             MemRoot {
                 radix_offset,
                 meta: meta.to_vec().into_boxed_slice(),
+                reverse_radix_offset,
+                counts,
             },
             cur - offset,
         ))
@@ -1470,8 +2086,28 @@ impl MemRoot {
 
     fn write_to<W: Write>(&self, writer: &mut W, offset_map: &OffsetMap) -> io::Result<usize> {
         let mut buf = Vec::with_capacity(16);
-        buf.write_all(&[TYPE_ROOT])?;
-        buf.write_vlq(self.radix_offset.to_disk(offset_map))?;
+        if self.reverse_radix_offset.is_none() && self.counts.is_none() {
+            buf.write_all(&[TYPE_ROOT])?;
+            buf.write_vlq(self.radix_offset.to_disk(offset_map))?;
+        } else {
+            let mut flags = 0;
+            if self.reverse_radix_offset.is_some() {
+                flags |= ROOT_FLAG_REVERSE_RADIX;
+            }
+            if self.counts.is_some() {
+                flags |= ROOT_FLAG_COUNTS;
+            }
+            buf.write_all(&[TYPE_EXT_ROOT])?;
+            buf.write_vlq(self.radix_offset.to_disk(offset_map))?;
+            buf.write_vlq(flags)?;
+            if let Some(reverse_radix_offset) = self.reverse_radix_offset {
+                buf.write_vlq(reverse_radix_offset.to_disk(offset_map))?;
+            }
+            if let Some(counts) = self.counts {
+                buf.write_vlq(counts.keys)?;
+                buf.write_vlq(counts.values)?;
+            }
+        }
         buf.write_vlq(self.meta.len())?;
         buf.write_all(&self.meta)?;
         let len = buf.len();
@@ -1784,6 +2420,24 @@ impl MemChecksum {
         }
     }
 
+    /// Find chunks that do not match their checksums. Return their byte
+    /// ranges, with adjacent chunks merged.
+    fn corrupted_ranges(&self, buf: &[u8]) -> Vec<Range<u64>> {
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        for i in 0..self.xxhash_list.len() {
+            if self.check_chunk(buf, i) {
+                continue;
+            }
+            let start = (i as u64) << self.chunk_size_logarithm;
+            let end = self.end.min(((i + 1) as u64) << self.chunk_size_logarithm);
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        ranges
+    }
+
     #[inline]
     fn is_enabled(&self) -> bool {
         self.end > 0
@@ -1907,7 +2561,7 @@ use Side::Front;
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum IterState {
     /// Visiting the child of a radix node.
-    /// child must be inside 0..16 range.
+    /// child must be inside 0..=last_child range (15 or 255, see [`RadixFanout`]).
     RadixChild(RadixOffset, u8),
 
     /// Visiting the leaf of a radix node.
@@ -1928,9 +2582,9 @@ enum IterState {
 impl IterState {
     /// Get the next state on the same frame.
     /// Return `None` if the frame should be popped.
-    fn next(self) -> Option<Self> {
+    fn next(self, last_child: u8) -> Option<Self> {
         match self {
-            IterState::RadixChild(radix, 15) => Some(IterState::RadixEnd(radix)),
+            IterState::RadixChild(radix, i) if i == last_child => Some(IterState::RadixEnd(radix)),
             IterState::RadixChild(radix, i) => Some(IterState::RadixChild(radix, i + 1)),
             IterState::RadixStart(radix) => Some(IterState::RadixLeaf(radix)),
             IterState::RadixLeaf(radix) => Some(IterState::RadixChild(radix, 0)),
@@ -1942,11 +2596,11 @@ impl IterState {
 
     /// Get the previous state on the same frame.
     /// Return `None` if the frame should be popped.
-    fn prev(self) -> Option<Self> {
+    fn prev(self, last_child: u8) -> Option<Self> {
         match self {
             IterState::RadixChild(radix, 0) => Some(IterState::RadixLeaf(radix)),
             IterState::RadixChild(radix, i) => Some(IterState::RadixChild(radix, i - 1)),
-            IterState::RadixEnd(radix) => Some(IterState::RadixChild(radix, 15)),
+            IterState::RadixEnd(radix) => Some(IterState::RadixChild(radix, last_child)),
             IterState::RadixLeaf(radix) => Some(IterState::RadixStart(radix)),
             IterState::LeafEnd(leaf) => Some(IterState::Leaf(leaf)),
             IterState::Leaf(leaf) => Some(IterState::LeafStart(leaf)),
@@ -1955,10 +2609,10 @@ impl IterState {
     }
 
     /// Move one step towards the given side.
-    fn step(self, towards: Side) -> Option<Self> {
+    fn step(self, towards: Side, last_child: u8) -> Option<Self> {
         match towards {
-            Front => self.prev(),
-            Back => self.next(),
+            Front => self.prev(last_child),
+            Back => self.next(last_child),
         }
     }
 }
@@ -1968,9 +2622,15 @@ impl IterState {
 /// Insertion-only mapping from `bytes` to a list of [u64]s.
 ///
 /// An [`Index`] is backed by an append-only file in the filesystem. Internally,
-/// it uses base16 radix trees for keys and linked list for [u64] values. The
-/// file format was designed to be able to support other types of indexes (ex.
-/// non-radix-trees). Though none of them are implemented.
+/// it uses base16 (or base256, see [`RadixFanout`]) radix trees for keys and
+/// linked list for [u64] values. The file format was designed to be able to
+/// support other types of indexes (ex. non-radix-trees). Though none of them
+/// are implemented.
+///
+/// [`Index`] is `Send` and `Sync`. Lookups only take `&self` and do not
+/// need locks, so a shared [`Index`] can serve reads from multiple threads.
+/// The only state changed by reads is the set of verified checksum chunks,
+/// which uses atomics.
 pub struct Index {
     // For locking and low-level access.
     file: Option<File>,
@@ -1988,6 +2648,12 @@ pub struct Index {
     checksum_max_chain_len: u32,
     fsync: bool,
     write: Option<bool>,
+    fanout: RadixFanout,
+    path_compression: bool,
+    fixed_key_len: Option<usize>,
+
+    // Set by `open_readonly`. Reject in-memory changes too.
+    readonly: bool,
 
     // Used by `clear_dirty`.
     clean_root: MemRoot,
@@ -2007,6 +2673,61 @@ pub struct Index {
     pub(crate) key_buf: Arc<dyn ReadonlyBuffer + Send + Sync>,
 }
 
+/// Saved in-memory state of an [`Index`]. Created by [`Index::checkpoint`]
+/// and consumed by [`Index::rollback`].
+pub struct IndexCheckpoint {
+    // Length of the on-disk buffer. Used to detect flushes.
+    buf_len: usize,
+
+    dirty_root: MemRoot,
+    dirty_radixes: Vec<MemRadix>,
+    dirty_leafs: Vec<MemLeaf>,
+    dirty_links: Vec<MemLink>,
+    dirty_keys: Vec<MemKey>,
+    dirty_ext_keys: Vec<MemExtKey>,
+}
+
+/// Result of [`Index::verify_tree`].
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Count of radix entries reachable from the root.
+    pub radix_count: usize,
+
+    /// Count of leaf entries reachable from the root.
+    pub leaf_count: usize,
+
+    /// Count of link entries reachable from radix or leaf entries.
+    pub link_count: usize,
+
+    /// Problems found. Empty if the index is consistent.
+    pub problems: Vec<VerifyProblem>,
+}
+
+/// A problem found by [`Index::verify_tree`].
+#[derive(Debug)]
+pub struct VerifyProblem {
+    /// Offset of the problematic entry. Offsets of in-memory entries are
+    /// at least `DIRTY_OFFSET`.
+    pub offset: u64,
+
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl VerifyReport {
+    /// Test if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn problem(&mut self, offset: Offset, message: impl ToString) {
+        self.problems.push(VerifyProblem {
+            offset: offset.0,
+            message: message.to_string(),
+        });
+    }
+}
+
 /// Abstraction of the "external key buffer".
 ///
 /// This makes it possible to use non-contiguous memory for a buffer,
@@ -2032,6 +2753,49 @@ pub enum InsertKey<'a> {
     Reference((u64, u64)),
 }
 
+/// Number of children per radix entry. Decides how many bits of a key each
+/// level of the radix tree consumes.
+///
+/// `Base16` suits hex hashes. `Base256` uses fewer levels for keys that are
+/// not hashes (ex. paths, integers), at the cost of larger radix entries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RadixFanout {
+    /// 16 children per radix entry. Each level consumes 4 bits of the key.
+    Base16,
+
+    /// 256 children per radix entry. Each level consumes a byte of the key.
+    Base256,
+}
+
+impl RadixFanout {
+    /// The largest child index of a radix entry.
+    #[inline]
+    fn last_child(self) -> u8 {
+        match self {
+            RadixFanout::Base16 => 15,
+            RadixFanout::Base256 => 255,
+        }
+    }
+
+    /// Number of bytes needed to store `len` digits.
+    #[inline]
+    fn packed_len(self, len: usize) -> usize {
+        match self {
+            RadixFanout::Base16 => len.div_ceil(2),
+            RadixFanout::Base256 => len,
+        }
+    }
+
+    /// Number of radix levels a key byte takes.
+    #[inline]
+    fn digits_per_byte(self) -> usize {
+        match self {
+            RadixFanout::Base16 => 2,
+            RadixFanout::Base256 => 1,
+        }
+    }
+}
+
 /// Options used to configured how an [`Index`] is opened.
 ///
 /// Similar to [std::fs::OpenOptions], to use this, first call `new`, then
@@ -2046,6 +2810,11 @@ pub struct OpenOptions {
     len: Option<u64>,
     write: Option<bool>,
     key_buf: Option<Arc<dyn ReadonlyBuffer + Send + Sync>>,
+    radix_fanout: RadixFanout,
+    radix_path_compression: bool,
+    fixed_key_len: Option<usize>,
+    reverse_index: bool,
+    track_counts: bool,
 }
 
 impl OpenOptions {
@@ -2057,6 +2826,10 @@ impl OpenOptions {
     /// - no fsync
     /// - read root entry from the end of the file
     /// - open as read-write but fallback to read-only
+    /// - base16 radix fanout, without path compression
+    /// - variable-length keys
+    /// - no reverse (value to key) index
+    /// - do not track key and value counts
     pub fn new() -> OpenOptions {
         OpenOptions {
             checksum_max_chain_len: config::INDEX_CHECKSUM_MAX_CHAIN_LEN.load(Acquire),
@@ -2066,6 +2839,11 @@ impl OpenOptions {
             len: None,
             write: None,
             key_buf: None,
+            radix_fanout: RadixFanout::Base16,
+            radix_path_compression: false,
+            fixed_key_len: None,
+            reverse_index: false,
+            track_counts: false,
         }
     }
 
@@ -2098,7 +2876,7 @@ impl OpenOptions {
     /// Set fsync behavior.
     ///
     /// If true, then [`Index::flush`] will use `fsync` to flush data to the
-    /// physical device before returning.
+    /// physical device before returning. This is [`FsyncPolicy::Data`].
     pub fn fsync(&mut self, fsync: bool) -> &mut Self {
         self.fsync = fsync;
         self
@@ -2140,6 +2918,65 @@ impl OpenOptions {
         self
     }
 
+    /// Set the fanout of radix entries.
+    ///
+    /// This only affects new (empty or in-memory) indexes. An existing index
+    /// file keeps the fanout it was created with.
+    pub fn radix_fanout(&mut self, fanout: RadixFanout) -> &mut Self {
+        self.radix_fanout = fanout;
+        self
+    }
+
+    /// Set whether to create path-compressed radix entries.
+    ///
+    /// If true, keys sharing a long common prefix are split using a single
+    /// radix entry that stores the common digits, instead of a chain of
+    /// single-child radix entries. Existing path-compressed entries are
+    /// always readable regardless of this option. Older versions of this
+    /// library cannot read path-compressed entries.
+    pub fn radix_path_compression(&mut self, enabled: bool) -> &mut Self {
+        self.radix_path_compression = enabled;
+        self
+    }
+
+    /// Require all keys to have the given length.
+    ///
+    /// The length is recorded in the header so keys can be written without
+    /// their lengths. Inserting a key with a different length is an error.
+    ///
+    /// Like [`OpenOptions::radix_fanout`], this only affects new indexes. An
+    /// existing index file keeps the key length it was created with.
+    pub fn fixed_key_len(&mut self, len: Option<usize>) -> &mut Self {
+        self.fixed_key_len = len;
+        self
+    }
+
+    /// Set whether to maintain a reverse index from values to keys.
+    ///
+    /// If true, insertions and removals also update a second radix tree so
+    /// [`Index::keys_by_value`] can find keys without scanning. This doubles
+    /// the cost of writes.
+    ///
+    /// This only affects new indexes. An existing index file keeps the reverse
+    /// index if it was created with it. Older versions of this library cannot
+    /// read indexes with a reverse index.
+    pub fn reverse_index(&mut self, enabled: bool) -> &mut Self {
+        self.reverse_index = enabled;
+        self
+    }
+
+    /// Set whether to maintain the number of keys and values in the index.
+    ///
+    /// If true, the counts are stored in the root entry so
+    /// [`Index::key_count`] and [`Index::value_count`] are cheap. Insertions
+    /// and removals need extra lookups to keep the counts accurate.
+    ///
+    /// Like [`OpenOptions::reverse_index`], this only affects new indexes.
+    pub fn track_counts(&mut self, enabled: bool) -> &mut Self {
+        self.track_counts = enabled;
+        self
+    }
+
     /// Open the index file with given options.
     ///
     /// Driven by the "immutable by default" idea, together with append-only
@@ -2204,12 +3041,11 @@ impl OpenOptions {
             let (dirty_radixes, clean_root, mut checksum) = if bytes.is_empty() {
                 // Empty file. Create root radix entry as an dirty entry, and
                 // rebuild checksum table (in case it's corrupted).
-                let radix_offset = RadixOffset::from_dirty_index(0);
                 let _ = utils::fix_perm_file(&file, false);
-                let meta = Default::default();
-                let root = MemRoot { radix_offset, meta };
+                let root = MemRoot::new_dirty(self.reverse_index, self.track_counts);
                 let checksum = MemChecksum::default();
-                (vec![MemRadix::default()], root, checksum)
+                let dirty_radixes = root.new_dirty_radixes(self.radix_fanout);
+                (dirty_radixes, root, checksum)
             } else {
                 let end = bytes.len();
                 let (root, mut checksum) = read_root_checksum_at_end(path, &bytes, end)?;
@@ -2220,6 +3056,27 @@ impl OpenOptions {
             };
 
             checksum.set_chunk_size_logarithm(&bytes, self.checksum_chunk_size_logarithm)?;
+            let fanout = if clean_root.radix_offset.is_dirty() {
+                self.radix_fanout
+            } else {
+                // The root radix entry decides the fanout of an existing index.
+                let flag_offset = TYPE_BYTES + usize::from(clean_root.radix_offset);
+                let flag = *bytes.get(flag_offset).ok_or_else(|| {
+                    crate::Error::corruption(
+                        path,
+                        format!("cannot read radix flag at {}", flag_offset),
+                    )
+                })?;
+                checksum
+                    .check_range(&bytes, flag_offset as u64, RADIX_FLAG_BYTES as u64)
+                    .context(path, "failed to verify root Radix entry")?;
+                RadixOffset::parse_fanout_from_flag(flag)
+            };
+            let fixed_key_len = if bytes.is_empty() {
+                self.fixed_key_len
+            } else {
+                read_fixed_key_len(path, &bytes, &checksum)?
+            };
             let key_buf = self.key_buf.clone();
             let dirty_root = clean_root.clone();
 
@@ -2233,6 +3090,10 @@ impl OpenOptions {
                 checksum_max_chain_len: open_options.checksum_max_chain_len,
                 fsync: open_options.fsync,
                 write: open_options.write,
+                fanout,
+                path_compression: open_options.radix_path_compression,
+                fixed_key_len,
+                readonly: false,
                 clean_root,
                 dirty_root,
                 checksum,
@@ -2254,12 +3115,8 @@ impl OpenOptions {
     pub fn create_in_memory(&self) -> crate::Result<Index> {
         let result: crate::Result<_> = (|| {
             let buf = Bytes::new();
-            let dirty_radixes = vec![MemRadix::default()];
-            let clean_root = {
-                let radix_offset = RadixOffset::from_dirty_index(0);
-                let meta = Default::default();
-                MemRoot { radix_offset, meta }
-            };
+            let clean_root = MemRoot::new_dirty(self.reverse_index, self.track_counts);
+            let dirty_radixes = clean_root.new_dirty_radixes(self.radix_fanout);
             let key_buf = self.key_buf.clone();
             let dirty_root = clean_root.clone();
             let mut checksum = MemChecksum::default();
@@ -2273,6 +3130,10 @@ impl OpenOptions {
                 checksum_max_chain_len: self.checksum_max_chain_len,
                 fsync: self.fsync,
                 write: self.write,
+                fanout: self.radix_fanout,
+                path_compression: self.radix_path_compression,
+                fixed_key_len: self.fixed_key_len,
+                readonly: false,
                 clean_root,
                 dirty_root,
                 checksum,
@@ -2288,6 +3149,43 @@ impl OpenOptions {
     }
 }
 
+/// Decide the order of writing dirty radix entries so children are written
+/// before their parents.
+///
+/// Children are usually created after their parents, so this is mostly the
+/// reversed order. Splitting a path-compressed radix entry inserts a new
+/// parent after its child.
+fn radix_write_order(radixes: &[MemRadix]) -> Vec<usize> {
+    let mut order = Vec::with_capacity(radixes.len());
+    let mut visited = vec![false; radixes.len()];
+    // (index, children visited)
+    let mut stack = Vec::new();
+    for i in (0..radixes.len()).rev() {
+        stack.push((i, false));
+        while let Some((i, children_visited)) = stack.pop() {
+            if children_visited {
+                order.push(i);
+                continue;
+            }
+            if visited[i] {
+                continue;
+            }
+            visited[i] = true;
+            stack.push((i, true));
+            for child in radixes[i].offsets.iter() {
+                if child.is_dirty() {
+                    if let Some(TypedOffset::Radix(x)) = child.to_optional_typed(&[]) {
+                        if !visited[x.dirty_index()] {
+                            stack.push((x.dirty_index(), false));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    order
+}
+
 /// Load root and checksum from the logical end.
 fn read_root_checksum_at_end(
     path: &Path,
@@ -2347,6 +3245,33 @@ fn read_root_checksum_at_end(
     Ok((root, checksum))
 }
 
+/// Read FIXED_KEY_LEN from the optional KEY_LEN entry after the header byte.
+fn read_fixed_key_len(
+    path: &Path,
+    bytes: &[u8],
+    checksum: &MemChecksum,
+) -> crate::Result<Option<usize>> {
+    if bytes.get(TYPE_BYTES) != Some(&TYPE_FIXED_KEY_LEN) {
+        return Ok(None);
+    }
+    let (len, vlq_len): (usize, _) = bytes
+        .read_vlq_at(TYPE_BYTES * 2)
+        .context(path, "cannot read fixed_key_len")
+        .corruption()?;
+    checksum
+        .check_range(bytes, TYPE_BYTES as u64, (TYPE_BYTES + vlq_len) as u64)
+        .context(path, "failed to verify KEY_LEN entry")?;
+    Ok(Some(len))
+}
+
+/// Key in the reverse index: the big-endian value followed by the key.
+fn reverse_index_key(value: u64, key: &[u8]) -> Vec<u8> {
+    let mut reverse_key = Vec::with_capacity(8 + key.len());
+    reverse_key.extend_from_slice(&value.to_be_bytes());
+    reverse_key.extend_from_slice(key);
+    reverse_key
+}
+
 impl fmt::Debug for OpenOptions {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "OpenOptions {{ ")?;
@@ -2358,6 +3283,15 @@ impl fmt::Debug for OpenOptions {
         write!(f, "fsync: {}, ", self.fsync)?;
         write!(f, "len: {:?}, ", self.len)?;
         write!(f, "write: {:?}, ", self.write)?;
+        write!(f, "radix_fanout: {:?}, ", self.radix_fanout)?;
+        write!(
+            f,
+            "radix_path_compression: {}, ",
+            self.radix_path_compression
+        )?;
+        write!(f, "fixed_key_len: {:?}, ", self.fixed_key_len)?;
+        write!(f, "reverse_index: {}, ", self.reverse_index)?;
+        write!(f, "track_counts: {}, ", self.track_counts)?;
         let key_buf_desc = match self.key_buf {
             Some(ref _buf) => "Some(_)",
             None => "None",
@@ -2437,6 +3371,20 @@ fn checksum_error(checksum: &MemChecksum, offset: u64, length: u64) -> io::Resul
 }
 
 impl Index {
+    /// Open an existing index file in read-only mode.
+    ///
+    /// The file is opened without write permission and only takes shared
+    /// locks, so this works on read-only file systems. Unlike
+    /// `OpenOptions::write(Some(false))`, in-memory changes like
+    /// [`Index::insert`] are also rejected.
+    ///
+    /// Options like the radix fanout are read from the file.
+    pub fn open_readonly(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let mut index = OpenOptions::new().write(Some(false)).open(path)?;
+        index.readonly = true;
+        Ok(index)
+    }
+
     /// Return a cloned [`Index`] with pending in-memory changes.
     pub fn try_clone(&self) -> crate::Result<Self> {
         self.try_clone_internal(true)
@@ -2448,6 +3396,9 @@ impl Index {
     ///
     /// This is logically equivalent to calling `clear_dirty` immediately
     /// on the result after `try_clone`, but potentially cheaper.
+    ///
+    /// The on-disk buffer is shared with `self`, not copied. Only the file
+    /// handle is duplicated. Clones can be sent to other threads as readers.
     pub fn try_clone_without_dirty(&self) -> crate::Result<Self> {
         self.try_clone_internal(false)
             .context("in Index::try_clone_without_dirty")
@@ -2469,6 +3420,10 @@ impl Index {
                 checksum_max_chain_len: self.checksum_max_chain_len,
                 fsync: self.fsync,
                 write: self.write,
+                fanout: self.fanout,
+                path_compression: self.path_compression,
+                fixed_key_len: self.fixed_key_len,
+                readonly: self.readonly,
                 clean_root: self.clean_root.clone(),
                 dirty_root: self.dirty_root.clone(),
                 checksum: self.checksum.clone(),
@@ -2488,6 +3443,10 @@ impl Index {
                 checksum_max_chain_len: self.checksum_max_chain_len,
                 fsync: self.fsync,
                 write: self.write,
+                fanout: self.fanout,
+                path_compression: self.path_compression,
+                fixed_key_len: self.fixed_key_len,
+                readonly: self.readonly,
                 clean_root: self.clean_root.clone(),
                 dirty_root: self.clean_root.clone(),
                 checksum: self.checksum.clone(),
@@ -2495,12 +3454,8 @@ impl Index {
                 dirty_ext_keys: Vec::new(),
                 dirty_leafs: Vec::new(),
                 dirty_links: Vec::new(),
-                dirty_radixes: if self.clean_root.radix_offset.is_dirty() {
-                    // See `clear_dirty` for this special case.
-                    vec![MemRadix::default()]
-                } else {
-                    Vec::new()
-                },
+                // See `clear_dirty` for this special case.
+                dirty_radixes: self.clean_root.new_dirty_radixes(self.fanout),
                 key_buf: self.key_buf.clone(),
             }
         };
@@ -2521,6 +3476,30 @@ impl Index {
         &self.clean_root.meta
     }
 
+    /// Get metadata stored in the ROOT entry on disk. Unlike [`Index::get_meta`],
+    /// this only changes after [`Index::flush`], so it can be used to record
+    /// how far the index has caught up with the data it indexes (ex. "indexed
+    /// through log offset N") and stays consistent after a crash.
+    pub fn root_metadata(&self) -> &[u8] {
+        self.get_original_meta()
+    }
+
+    /// Get the number of keys that have values, including pending changes.
+    ///
+    /// Return `None` if the index does not track counts. See
+    /// [`OpenOptions::track_counts`].
+    pub fn key_count(&self) -> Option<u64> {
+        self.dirty_root.counts.map(|counts| counts.keys)
+    }
+
+    /// Get the number of values of all keys, including pending changes.
+    ///
+    /// Return `None` if the index does not track counts. See
+    /// [`OpenOptions::track_counts`].
+    pub fn value_count(&self) -> Option<u64> {
+        self.dirty_root.counts.map(|counts| counts.values)
+    }
+
     /// Set metadata attached to the root node. Will be written at
     /// [`Index::flush`] time.
     pub fn set_meta<B: AsRef<[u8]>>(&mut self, meta: B) {
@@ -2531,23 +3510,55 @@ impl Index {
     /// if it's just loaded from disk without modifications.
     pub fn clear_dirty(&mut self) {
         self.dirty_root = self.clean_root.clone();
-        self.dirty_radixes.clear();
-        if self.dirty_root.radix_offset.is_dirty() {
-            // In case the disk buffer is empty, a "dirty radix" entry
-            // is created automatically. Check OpenOptions::open for
-            // details.
-            assert_eq!(
-                self.dirty_root.radix_offset,
-                RadixOffset::from_dirty_index(0)
-            );
-            self.dirty_radixes.push(MemRadix::default());
-        }
+        // In case the disk buffer is empty, "dirty radix" entries
+        // are created automatically. Check OpenOptions::open for
+        // details.
+        self.dirty_radixes = self.dirty_root.new_dirty_radixes(self.fanout);
         self.dirty_leafs.clear();
         self.dirty_links.clear();
         self.dirty_keys.clear();
         self.dirty_ext_keys.clear();
     }
 
+    /// Save the dirty (in-memory) state so it can be restored later by
+    /// [`Index::rollback`].
+    ///
+    /// This copies all dirty entries. It is intended for speculative changes
+    /// that might get discarded, like inserting keys while validating data.
+    pub fn checkpoint(&self) -> IndexCheckpoint {
+        IndexCheckpoint {
+            buf_len: self.buf.len(),
+            dirty_root: self.dirty_root.clone(),
+            dirty_radixes: self.dirty_radixes.clone(),
+            dirty_leafs: self.dirty_leafs.clone(),
+            dirty_links: self.dirty_links.clone(),
+            dirty_keys: self.dirty_keys.clone(),
+            dirty_ext_keys: self.dirty_ext_keys.clone(),
+        }
+    }
+
+    /// Restore the dirty (in-memory) state saved by [`Index::checkpoint`].
+    /// Changes made after the checkpoint are discarded.
+    ///
+    /// Return an error if the index was flushed after the checkpoint. The
+    /// checkpoint must be created by the same [`Index`].
+    pub fn rollback(&mut self, checkpoint: IndexCheckpoint) -> crate::Result<()> {
+        if checkpoint.buf_len != self.buf.len() {
+            return Err(crate::Error::programming(format!(
+                "cannot rollback to a checkpoint taken before flush (len {} != {})",
+                checkpoint.buf_len,
+                self.buf.len()
+            )));
+        }
+        self.dirty_root = checkpoint.dirty_root;
+        self.dirty_radixes = checkpoint.dirty_radixes;
+        self.dirty_leafs = checkpoint.dirty_leafs;
+        self.dirty_links = checkpoint.dirty_links;
+        self.dirty_keys = checkpoint.dirty_keys;
+        self.dirty_ext_keys = checkpoint.dirty_ext_keys;
+        Ok(())
+    }
+
     /// Flush changes to disk.
     ///
     /// Take the file lock when writing.
@@ -2576,6 +3587,19 @@ impl Index {
     /// For in-memory-only indexes, this function does nothing and returns 0,
     /// unless read-only was set at open time.
     pub fn flush(&mut self) -> crate::Result<u64> {
+        let policy = if self.fsync || config::get_global_fsync() {
+            FsyncPolicy::Data
+        } else {
+            FsyncPolicy::Never
+        };
+        self.flush_with(policy)
+    }
+
+    /// Write in-memory entries to disk, with the given [`FsyncPolicy`].
+    ///
+    /// See [`Index::flush`] for details. Unlike [`Index::flush`], this ignores
+    /// [`OpenOptions::fsync`].
+    pub fn flush_with(&mut self, policy: FsyncPolicy) -> crate::Result<u64> {
         let result: crate::Result<_> = (|| {
             let span = debug_span!("Index::flush", path = self.path.to_string_lossy().as_ref());
             let _guard = span.enter();
@@ -2634,22 +3658,26 @@ impl Index {
                 let mut buf = Vec::with_capacity(estimated_dirty_bytes);
 
                 // Write in the following order:
-                // header, keys, links, leafs, radixes, root.
+                // header, inlined leafs, keys, links, leafs, radixes, root.
                 // Latter entries depend on former entries.
 
+                // The header decides whether keys use FIXED_KEY. If another process created the
+                // file after this index was opened, its header is unknown. Write KEY instead.
+                let fixed_key_len = if len > 0 && old_len == 0 {
+                    None
+                } else {
+                    self.fixed_key_len
+                };
+
                 if len == 0 {
                     buf.write_all(&[TYPE_HEAD]).infallible()?;
+                    if let Some(fixed_key_len) = fixed_key_len {
+                        buf.write_all(&[TYPE_FIXED_KEY_LEN]).infallible()?;
+                        buf.write_vlq(fixed_key_len).infallible()?;
+                    }
                 }
 
-                for (i, entry) in self.dirty_keys.iter().enumerate() {
-                    if !entry.is_unused() {
-                        let offset = buf.len() as u64 + len;
-                        offset_map.key_map[i] = offset;
-                        entry.write_to(&mut buf, &offset_map).infallible()?;
-                    };
-                }
-
-                // Inlined leafs. They might affect ExtKeys and Links. Need to write first.
+                // Inlined leafs. They might affect Keys, ExtKeys and Links. Need to write first.
                 for i in 0..self.dirty_leafs.len() {
                     let entry = self.dirty_leafs.get_mut(i).unwrap();
                     let offset = buf.len() as u64 + len;
@@ -2658,9 +3686,11 @@ impl Index {
                             &mut buf,
                             &self.buf,
                             len,
+                            &mut self.dirty_keys,
                             &mut self.dirty_ext_keys,
                             &mut self.dirty_links,
                             &mut offset_map,
+                            fixed_key_len,
                         )?
                     {
                         offset_map.leaf_map[i] = offset;
@@ -2668,6 +3698,19 @@ impl Index {
                     }
                 }
 
+                for (i, entry) in self.dirty_keys.iter().enumerate() {
+                    if !entry.is_unused() {
+                        let offset = buf.len() as u64 + len;
+                        offset_map.key_map[i] = offset;
+                        match fixed_key_len {
+                            Some(len) if len == entry.key.len() => {
+                                entry.write_fixed_to(&mut buf).infallible()?
+                            }
+                            _ => entry.write_to(&mut buf, &offset_map).infallible()?,
+                        }
+                    };
+                }
+
                 for (i, entry) in self.dirty_ext_keys.iter().enumerate() {
                     if !entry.is_unused() {
                         let offset = buf.len() as u64 + len;
@@ -2676,11 +3719,25 @@ impl Index {
                     }
                 }
 
+                // Links with the same value and next link are only written once.
+                // Inlined links can be reused too.
+                let mut written_links: HashMap<(u64, u64), u64> = HashMap::new();
+                for (i, entry) in self.dirty_links.iter().enumerate() {
+                    let offset = offset_map.link_map[i];
+                    if entry.is_unused() && offset != 0 {
+                        let next = entry.next_link_offset.to_disk(&offset_map);
+                        written_links.entry((entry.value, next)).or_insert(offset);
+                    }
+                }
                 for (i, entry) in self.dirty_links.iter().enumerate() {
                     if !entry.is_unused() {
+                        let next = entry.next_link_offset.to_disk(&offset_map);
                         let offset = buf.len() as u64 + len;
+                        let offset = *written_links.entry((entry.value, next)).or_insert(offset);
                         offset_map.link_map[i] = offset;
-                        entry.write_to(&mut buf, &offset_map).infallible()?;
+                        if offset == buf.len() as u64 + len {
+                            entry.write_to(&mut buf, &offset_map).infallible()?;
+                        }
                     }
                 }
 
@@ -2695,14 +3752,18 @@ impl Index {
                     }
                 }
 
-                // Write Radix entries in reversed order since former ones might refer to latter ones.
-                for (i, entry) in self.dirty_radixes.iter().rev().enumerate() {
+                // Write Radix entries in children-first order since parents refer to children.
+                let radix_len = self.dirty_radixes.len();
+                for i in radix_write_order(&self.dirty_radixes) {
                     let offset = buf.len() as u64 + len;
-                    entry.write_to(&mut buf, &offset_map).infallible()?;
-                    offset_map.radix_map[i] = offset;
+                    self.dirty_radixes[i]
+                        .write_to(&mut buf, &offset_map)
+                        .infallible()?;
+                    offset_map.radix_map[radix_len - 1 - i] = offset;
                 }
 
                 // Write Root.
+                let root_start = buf.len();
                 let root_len = self
                     .dirty_root
                     .write_to(&mut buf, &offset_map)
@@ -2731,12 +3792,28 @@ impl Index {
                 lock.as_mut()
                     .seek(SeekFrom::Start(len))
                     .context(&path, "cannot seek")?;
-                lock.as_mut()
-                    .write_all(&buf)
-                    .context(&path, "cannot write new data to index")?;
-
-                if self.fsync || config::get_global_fsync() {
+                if policy == FsyncPolicy::Never {
+                    lock.as_mut()
+                        .write_all(&buf)
+                        .context(&path, "cannot write new data to index")?;
+                } else {
+                    // Make sure entries are on disk before the Root entry
+                    // that refers to them.
+                    lock.as_mut()
+                        .write_all(&buf[..root_start])
+                        .context(&path, "cannot write new data to index")?;
+                    lock.as_mut().sync_data().context(&path, "cannot sync")?;
+                    lock.as_mut()
+                        .write_all(&buf[root_start..])
+                        .context(&path, "cannot write new root to index")?;
                     lock.as_mut().sync_all().context(&path, "cannot sync")?;
+                    if policy == FsyncPolicy::DataAndDirectory {
+                        if let Some(dir) = path.parent() {
+                            if !dir.as_os_str().is_empty() {
+                                utils::fsync_dir(dir).context(dir, "cannot sync directory")?;
+                            }
+                        }
+                    }
                 }
 
                 // Remap and update root since length has changed
@@ -2761,6 +3838,7 @@ impl Index {
 
                 debug_assert_eq!(checksum.end, new_checksum.end);
                 debug_assert_eq!(&checksum.xxhash_list, &new_checksum.xxhash_list);
+                self.fixed_key_len = read_fixed_key_len(&path, &self.buf, &checksum)?;
                 self.checksum = checksum;
                 self.clean_root = root;
             }
@@ -2780,6 +3858,55 @@ impl Index {
             .context(|| format!("  Index.path = {:?}", self.path))
     }
 
+    /// Write the current state, including pending changes, to a new index
+    /// file at `path`. Return the size of the new file.
+    ///
+    /// The new file only contains live entries, without the history of
+    /// previous flushes. Keys are embedded so the new file can be used without
+    /// the external key buffer. This index is not changed.
+    pub fn write_snapshot(&self, path: impl AsRef<Path>) -> crate::Result<u64> {
+        let path = path.as_ref();
+        let result: crate::Result<_> = (|| {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let tmp = tempfile::NamedTempFile::new_in(dir)
+                .context(dir, "cannot create tempfile for index snapshot")?;
+            let len = {
+                let mut index = OpenOptions::new()
+                    .checksum_enabled(self.checksum_enabled)
+                    .checksum_max_chain_len(self.checksum_max_chain_len)
+                    .checksum_chunk_size_logarithm(self.checksum.chunk_size_logarithm)
+                    .fsync(self.fsync)
+                    .radix_fanout(self.fanout)
+                    .radix_path_compression(self.path_compression)
+                    .fixed_key_len(self.fixed_key_len)
+                    .reverse_index(self.dirty_root.reverse_radix_offset.is_some())
+                    .track_counts(self.dirty_root.counts.is_some())
+                    .open(tmp.path())?;
+                for entry in self.range(..)? {
+                    let (key, link) = entry?;
+                    let values: Vec<u64> = link.values(self).collect::<crate::Result<_>>()?;
+                    // Values are iterated newest first. Insert them oldest first.
+                    for value in values.into_iter().rev() {
+                        index.insert(&key, value)?;
+                    }
+                }
+                index.set_meta(self.get_meta());
+                index.flush()?
+            };
+            let _ = utils::fix_perm_file(tmp.as_file(), false);
+            tmp.persist(path).map_err(|e| {
+                crate::Error::wrap(Box::new(e), "cannot persist tempfile for index snapshot")
+            })?;
+            Ok(len)
+        })();
+        result
+            .context(|| format!("in Index::write_snapshot({:?})", path))
+            .context(|| format!("  Index.path = {:?}", self.path))
+    }
+
     /// Lookup by `key`. Return [`LinkOffset`].
     ///
     /// To test if the key exists or not, use [Offset::is_null].
@@ -2787,12 +3914,18 @@ impl Index {
     pub fn get<K: AsRef<[u8]>>(&self, key: &K) -> crate::Result<LinkOffset> {
         let result: crate::Result<_> = (|| {
             let mut offset: Offset = self.dirty_root.radix_offset.into();
-            let mut iter = Base16Iter::from_base256(key);
+            let mut iter = KeyDigits::new(key.as_ref(), self.fanout);
 
             while !offset.is_null() {
                 // Read the entry at "offset"
                 match offset.to_typed(self)? {
                     TypedOffset::Radix(radix) => {
+                        // Skip digits of a path-compressed Radix entry.
+                        for digit in radix.prefix(self)? {
+                            if iter.next() != Some(digit) {
+                                return Ok(LinkOffset::default());
+                            }
+                        }
                         match iter.next() {
                             None => {
                                 // The key ends at this Radix entry.
@@ -2828,21 +3961,90 @@ impl Index {
 
     /// Scan entries which match the given prefix in base16 form.
     /// Return [`RangeIter`] which allows accesses to keys and values.
-    pub fn scan_prefix_base16(
+    pub fn scan_prefix_base16(&self, base16: impl Iterator<Item = u8>) -> crate::Result<RangeIter> {
+        match self.fanout {
+            RadixFanout::Base16 => {
+                self.scan_prefix_digits(self.dirty_root.radix_offset, base16, None)
+            }
+            RadixFanout::Base256 => {
+                // Pair base16 digits into bytes. A trailing unpaired digit
+                // selects a range of children in the last radix entry.
+                let mut base16: Vec<u8> = base16.collect();
+                if let Some(&x) = base16.iter().find(|&&x| x >= 16) {
+                    return Err(crate::Error::programming(format!(
+                        "invalid base16 digit {} in prefix",
+                        x
+                    )));
+                }
+                let odd = if base16.len() & 1 == 1 {
+                    base16.pop()
+                } else {
+                    None
+                };
+                let prefix = base16_to_base256(&base16);
+                let digits = KeyDigits::new(&prefix, self.fanout);
+                self.scan_prefix_digits(self.dirty_root.radix_offset, digits, odd)
+            }
+        }
+    }
+
+    /// Scan entries which match the given prefix in radix digits. `odd` is
+    /// the high 4 bits of an extra digit, used by base256 indexes to support
+    /// odd-length base16 prefixes.
+    fn scan_prefix_digits(
         &self,
-        mut base16: impl Iterator<Item = u8>,
+        root: RadixOffset,
+        mut digits: impl Iterator<Item = u8>,
+        odd: Option<u8>,
     ) -> crate::Result<RangeIter> {
-        let mut offset: Offset = self.dirty_root.radix_offset.into();
+        let mut offset: Offset = root.into();
         let mut front_stack = Vec::<IterState>::new();
+        // Count of digits consumed.
+        let mut depth = 0;
 
         while !offset.is_null() {
             // Read the entry at "offset"
             match offset.to_typed(self)? {
                 TypedOffset::Radix(radix) => {
-                    match base16.next() {
+                    // Skip digits of a path-compressed Radix entry.
+                    for digit in radix.prefix(self)? {
+                        let (matched, ended) = match digits.next() {
+                            Some(x) => (x == digit, false),
+                            // The prefix ends inside the Radix entry.
+                            None => match odd {
+                                None => (true, true),
+                                Some(x) => (digit >> 4 == x, true),
+                            },
+                        };
+                        if !matched || ended {
+                            // Either nothing, or everything in the Radix entry matches.
+                            front_stack.push(IterState::RadixStart(radix));
+                            let mut back_stack = front_stack.clone();
+                            if matched {
+                                *back_stack.last_mut().unwrap() = IterState::RadixEnd(radix);
+                            }
+                            return Ok(RangeIter::new(self, front_stack, back_stack));
+                        }
+                        depth += 1;
+                    }
+                    match digits.next() {
                         None => {
-                            let start = IterState::RadixStart(radix);
-                            let end = IterState::RadixEnd(radix);
+                            let (start, end) = match odd {
+                                None => (IterState::RadixStart(radix), IterState::RadixEnd(radix)),
+                                Some(x) => {
+                                    // Children x0 to xf match.
+                                    let first = x << 4;
+                                    let start = match first {
+                                        0 => IterState::RadixLeaf(radix),
+                                        _ => IterState::RadixChild(radix, first - 1),
+                                    };
+                                    let end = match x {
+                                        15 => IterState::RadixEnd(radix),
+                                        _ => IterState::RadixChild(radix, first + 16),
+                                    };
+                                    (start, end)
+                                }
+                            };
                             front_stack.push(start);
                             let mut back_stack = front_stack.clone();
                             *back_stack.last_mut().unwrap() = end;
@@ -2852,6 +4054,7 @@ impl Index {
                             // Follow the `x`-th child in the Radix entry.
                             front_stack.push(IterState::RadixChild(radix, x));
                             offset = radix.child(self, x)?;
+                            depth += 1;
                         }
                     }
                 }
@@ -2860,11 +4063,16 @@ impl Index {
                     let eq = {
                         let (stored_key, _link_offset) = leaf.key_and_link_offset(self)?;
                         // Remaining key matches?
-                        let remaining: Vec<u8> = base16.collect();
-                        Base16Iter::from_base256(&stored_key)
-                            .skip(front_stack.len())
+                        let remaining: Vec<u8> = digits.collect();
+                        let mut stored = KeyDigits::new(stored_key, self.fanout).skip(depth);
+                        stored
+                            .by_ref()
                             .take(remaining.len())
                             .eq(remaining.iter().cloned())
+                            && match odd {
+                                None => true,
+                                Some(x) => stored.next().map(|d| d >> 4) == Some(x),
+                            }
                     };
                     if eq {
                         let start = IterState::LeafStart(leaf);
@@ -2888,11 +4096,44 @@ impl Index {
     /// Scan entries which match the given prefix in base256 form.
     /// Return [`RangeIter`] which allows accesses to keys and values.
     pub fn scan_prefix<B: AsRef<[u8]>>(&self, prefix: B) -> crate::Result<RangeIter> {
-        self.scan_prefix_base16(Base16Iter::from_base256(&prefix))
+        let digits = KeyDigits::new(prefix.as_ref(), self.fanout);
+        self.scan_prefix_digits(self.dirty_root.radix_offset, digits, None)
             .context(|| format!("in Index::scan_prefix({:?})", prefix.as_ref()))
             .context(|| format!("  Index.path = {:?}", self.path))
     }
 
+    /// Find keys that have the given value.
+    ///
+    /// Requires the reverse index. See [`OpenOptions::reverse_index`].
+    pub fn keys_by_value(&self, value: u64) -> crate::Result<Vec<Cow<[u8]>>> {
+        let inner = || -> crate::Result<_> {
+            let root = match self.dirty_root.reverse_radix_offset {
+                Some(root) => root,
+                None => {
+                    return Err(crate::Error::programming(
+                        "keys_by_value requires the reverse index",
+                    ));
+                }
+            };
+            let prefix = value.to_be_bytes();
+            let digits = KeyDigits::new(&prefix, self.fanout);
+            let mut keys = Vec::new();
+            for entry in self.scan_prefix_digits(root, digits, None)? {
+                let (key, link) = entry?;
+                if !link.is_null() {
+                    keys.push(match key {
+                        Cow::Borrowed(key) => Cow::Borrowed(&key[prefix.len()..]),
+                        Cow::Owned(key) => Cow::Owned(key[prefix.len()..].to_vec()),
+                    });
+                }
+            }
+            Ok(keys)
+        };
+        inner()
+            .context(|| format!("in Index::keys_by_value({})", value))
+            .context(|| format!("  Index.path = {:?}", self.path))
+    }
+
     /// Scan entries which match the given prefix in hex form.
     /// Return [`RangeIter`] which allows accesses to keys and values.
     pub fn scan_prefix_hex<B: AsRef<[u8]>>(&self, prefix: B) -> crate::Result<RangeIter> {
@@ -2906,7 +4147,11 @@ impl Index {
     /// Scans entries whose keys are within the given range.
     ///
     /// Returns a double-ended iterator, which provides accesses to keys and
-    /// values.
+    /// values. Keys are in lexicographic order. Use `rev()` to iterate in the
+    /// reverse order.
+    ///
+    /// To query integers (ex. timestamps) by range, store them as big-endian
+    /// bytes so the lexicographic order matches the numeric order.
     pub fn range<'a>(&self, range: impl RangeBounds<&'a [u8]>) -> crate::Result<RangeIter> {
         let is_empty_range = match (range.start_bound(), range.end_bound()) {
             (Included(start), Included(end)) => start > end,
@@ -2938,6 +4183,17 @@ impl Index {
             .context(|| format!("  Index.path = {:?}", self.path))
     }
 
+    /// Iterate through keys with values inserted since the last flush.
+    ///
+    /// Each item contains a key and its new values, newest first. Values
+    /// flushed earlier are not included. Removals are not included either.
+    ///
+    /// Only in-memory entries are visited, so this is cheap if there are few
+    /// changes.
+    pub fn dirty_iter(&self) -> DirtyIter {
+        DirtyIter::new(self)
+    }
+
     /// Insert a key-value pair. The value will be the head of the linked list.
     /// That is, `get(key).values().first()` will return the newly inserted
     /// value.
@@ -2978,11 +4234,17 @@ impl Index {
     /// `key` could be a reference, or an embedded value. See [`InsertKey`] for
     /// details.
     ///
+    /// Multiple keys can share a linked list using [`InsertValue::Replace`].
+    ///
     /// This is a low-level API.
     pub fn insert_advanced(&mut self, key: InsertKey, value: InsertValue) -> crate::Result<()> {
-        let mut offset: Offset = self.dirty_root.radix_offset.into();
-        let mut step = 0;
-        let (key, key_buf_offset) = match key {
+        if self.readonly {
+            return Err(crate::Error::path(
+                self.path(),
+                "cannot insert: Index opened by open_readonly",
+            ));
+        }
+        let (key, key_buf_offset) = match key {
             InsertKey::Embed(k) => (k, None),
             InsertKey::Reference((start, len)) => {
                 let key = match self.key_buf.as_ref().slice(start, len) {
@@ -3000,7 +4262,128 @@ impl Index {
                 (detached_key, Some((start, len)))
             }
         };
-        let mut iter = Base16Iter::from_base256(&key);
+        if let Some(fixed_key_len) = self.fixed_key_len {
+            if key.len() != fixed_key_len {
+                return Err(crate::Error::programming(format!(
+                    "key length {} does not match the fixed key length {}",
+                    key.len(),
+                    fixed_key_len
+                )));
+            }
+        }
+        if self.dirty_root.reverse_radix_offset.is_some() {
+            self.update_reverse_index(key, value)?;
+        }
+        if self.dirty_root.counts.is_some() {
+            self.update_counts(key, value)?;
+        }
+        self.insert_resolved(key, key_buf_offset, value)
+    }
+
+    // Internal function used by [`Index::insert_advanced`].
+    // Update the reverse index for inserting `value` to `key`.
+    fn update_reverse_index(&mut self, key: &[u8], value: InsertValue) -> crate::Result<()> {
+        let values = |index: &Index, link: LinkOffset| -> crate::Result<Vec<u64>> {
+            link.values(index).collect()
+        };
+        // (key, values) pairs to remove, and values to add.
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        match value {
+            InsertValue::Prepend(value) => added.push(value),
+            InsertValue::PrependReplace(value, link) => {
+                removed.push((key.to_vec(), values(self, self.get(&key)?)?));
+                added.push(value);
+                added.extend(values(self, link)?);
+            }
+            InsertValue::Replace(link) => {
+                removed.push((key.to_vec(), values(self, self.get(&key)?)?));
+                added.extend(values(self, link)?);
+            }
+            InsertValue::Tombstone => removed.push((key.to_vec(), values(self, self.get(&key)?)?)),
+            InsertValue::TombstonePrefix => {
+                for entry in self.scan_prefix(key)? {
+                    let (key, link) = entry?;
+                    removed.push((key.to_vec(), values(self, link)?));
+                }
+            }
+        }
+
+        // Switch to the reverse tree temporarily.
+        let root = self.dirty_root.radix_offset;
+        self.dirty_root.radix_offset = self.dirty_root.reverse_radix_offset.unwrap();
+        let result = (|| -> crate::Result<()> {
+            for (key, values) in removed {
+                for value in values {
+                    let reverse_key = reverse_index_key(value, &key);
+                    self.insert_resolved(&reverse_key, None, InsertValue::Tombstone)?;
+                }
+            }
+            for value in added {
+                let reverse_key = reverse_index_key(value, key);
+                if self.get(&reverse_key)?.is_null() {
+                    self.insert_resolved(&reverse_key, None, InsertValue::Prepend(value))?;
+                }
+            }
+            Ok(())
+        })();
+        self.dirty_root.reverse_radix_offset = Some(self.dirty_root.radix_offset);
+        self.dirty_root.radix_offset = root;
+        result
+    }
+
+    // Internal function used by [`Index::insert_advanced`].
+    // Update key and value counts for inserting `value` to `key`.
+    fn update_counts(&mut self, key: &[u8], value: InsertValue) -> crate::Result<()> {
+        // (key count, value count) of a linked list.
+        let counts = |index: &Index, link: LinkOffset| -> crate::Result<(u64, u64)> {
+            let mut values = 0;
+            for value in link.values(index) {
+                value?;
+                values += 1;
+            }
+            Ok((u64::from(!link.is_null()), values))
+        };
+        // Counts before and after the change.
+        let (old, new) = match value {
+            InsertValue::Prepend(_) => {
+                // No need to count existing values.
+                let old_keys = u64::from(!self.get(&key)?.is_null());
+                ((old_keys, 0), (1, 1))
+            }
+            InsertValue::PrependReplace(_, link) => {
+                let (_, values) = counts(self, link)?;
+                (counts(self, self.get(&key)?)?, (1, values + 1))
+            }
+            InsertValue::Replace(link) => (counts(self, self.get(&key)?)?, counts(self, link)?),
+            InsertValue::Tombstone => (counts(self, self.get(&key)?)?, (0, 0)),
+            InsertValue::TombstonePrefix => {
+                let mut old = (0, 0);
+                for entry in self.scan_prefix(key)? {
+                    let (_, link) = entry?;
+                    let (keys, values) = counts(self, link)?;
+                    old = (old.0 + keys, old.1 + values);
+                }
+                (old, (0, 0))
+            }
+        };
+        let root_counts = self.dirty_root.counts.as_mut().unwrap();
+        root_counts.keys = (root_counts.keys + new.0).saturating_sub(old.0);
+        root_counts.values = (root_counts.values + new.1).saturating_sub(old.1);
+        Ok(())
+    }
+
+    // Internal function used by [`Index::insert_advanced`].
+    // Insert to the tree starting from `dirty_root.radix_offset`.
+    fn insert_resolved(
+        &mut self,
+        key: &[u8],
+        key_buf_offset: Option<(u64, u64)>,
+        value: InsertValue,
+    ) -> crate::Result<()> {
+        let mut offset: Offset = self.dirty_root.radix_offset.into();
+        let mut step = 0;
+        let mut iter = KeyDigits::new(key, self.fanout);
 
         let mut last_radix = RadixOffset::default();
         let mut last_child = 0u8;
@@ -3018,6 +4401,35 @@ impl Index {
                         last_radix.set_child(self, last_child, offset);
                     }
 
+                    // Match digits of a path-compressed radix entry.
+                    let prefix = &self.dirty_radixes[radix.dirty_index()].prefix;
+                    if !prefix.is_empty() {
+                        let matched = prefix
+                            .iter()
+                            .zip(iter)
+                            .take_while(|(a, b)| **a == *b)
+                            .count();
+                        let prefix_len = prefix.len();
+                        iter = iter.skip(matched);
+                        if matched < prefix_len {
+                            let parent = if step == 0 {
+                                None
+                            } else {
+                                Some((last_radix, last_child))
+                            };
+                            return self.split_radix_prefix(
+                                radix,
+                                matched,
+                                iter.next(),
+                                parent,
+                                key,
+                                key_buf_offset,
+                                value,
+                            );
+                        }
+                        step += matched;
+                    }
+
                     last_radix = radix;
 
                     match iter.next() {
@@ -3030,6 +4442,7 @@ impl Index {
                                 InsertValue::PrependReplace(value, link_offset) => {
                                     link_offset.create(self, value)
                                 }
+                                InsertValue::Replace(link_offset) => link_offset,
                                 InsertValue::Tombstone => LinkOffset::default(),
                                 InsertValue::TombstonePrefix => {
                                     radix.set_all_to_null(self);
@@ -3051,6 +4464,7 @@ impl Index {
                                     InsertValue::PrependReplace(value, link_offset) => {
                                         link_offset.create(self, value)
                                     }
+                                    InsertValue::Replace(link_offset) => link_offset,
                                     InsertValue::Tombstone | InsertValue::TombstonePrefix => {
                                         // No need to create a key.
                                         radix.set_child(self, x, Offset::null());
@@ -3094,6 +4508,7 @@ impl Index {
                             InsertValue::PrependReplace(value, link_offset) => {
                                 link_offset.create(self, value)
                             }
+                            InsertValue::Replace(link_offset) => link_offset,
                             InsertValue::Tombstone | InsertValue::TombstonePrefix => {
                                 // No need to copy the leaf entry.
                                 last_radix.set_child(self, last_child, Offset::null());
@@ -3111,6 +4526,7 @@ impl Index {
                             InsertValue::PrependReplace(value, link_offset) => {
                                 link_offset.create(self, value)
                             }
+                            InsertValue::Replace(link_offset) => link_offset,
                             InsertValue::Tombstone | InsertValue::TombstonePrefix => return Ok(()),
                         };
                         self.split_leaf(
@@ -3145,6 +4561,200 @@ impl Index {
         self.verify_checksum(0, self.checksum.end)
     }
 
+    /// Find on-disk byte ranges that do not match their checksums.
+    ///
+    /// Unlike [`Index::verify`], this checks all checksum chunks instead of
+    /// stopping at the first bad one, so the caller can tell which regions are
+    /// corrupted. Return an empty list if checksum is disabled.
+    pub fn corrupted_ranges(&self) -> Vec<Range<u64>> {
+        self.checksum.corrupted_ranges(&self.buf)
+    }
+
+    /// Walk the tree from the root and check its structure.
+    ///
+    /// Unlike [`Index::verify`], which only checks checksums, this checks:
+    /// - Offsets are in bounds, and on-disk entries only refer to entries
+    ///   written before them. Entries are referred at most once.
+    /// - Entries have expected types, and radix children can be resolved.
+    /// - Keys of leaf entries match the radix path leading to them.
+    /// - LINK chains do not have cycles.
+    ///
+    /// Problems are collected into the returned [`VerifyReport`] instead of
+    /// stopping at the first one.
+    pub fn verify_tree(&self) -> VerifyReport {
+        let mut report = VerifyReport::default();
+        if let Err(e) = self.verify() {
+            report.problem(Offset::null(), e);
+        }
+
+        let last_child = self.fanout.last_child();
+        let mut visited = std::collections::HashSet::new();
+        // (offset, parent offset, radix digits leading to the offset)
+        let mut stack: Vec<(Offset, Offset, Vec<u8>)> =
+            vec![(self.dirty_root.radix_offset.0, Offset::null(), Vec::new())];
+        if let Some(reverse_radix_offset) = self.dirty_root.reverse_radix_offset {
+            stack.push((reverse_radix_offset.0, Offset::null(), Vec::new()));
+        }
+        while let Some((offset, parent, path)) = stack.pop() {
+            if !self.verify_offset(offset, parent, &mut report) {
+                continue;
+            }
+            if !visited.insert(offset.0) {
+                report.problem(offset, "entry is referred more than once");
+                continue;
+            }
+            let typed = match offset.to_typed(self) {
+                Ok(typed) => typed,
+                Err(e) => {
+                    report.problem(offset, e);
+                    continue;
+                }
+            };
+            match typed {
+                TypedOffset::Radix(radix) => {
+                    report.radix_count += 1;
+                    match radix.link_offset(self) {
+                        Ok(link) => self.verify_link_chain(link, offset, &mut report),
+                        Err(e) => report.problem(offset, e),
+                    }
+                    let mut path = path;
+                    match radix.prefix(self) {
+                        Ok(prefix) => path.extend(prefix),
+                        Err(e) => {
+                            report.problem(offset, e);
+                            continue;
+                        }
+                    }
+                    for i in 0..=last_child {
+                        match radix.child(self, i) {
+                            Ok(child) if child.is_null() => {}
+                            Ok(child) => {
+                                let mut child_path = path.clone();
+                                child_path.push(i);
+                                stack.push((child, offset, child_path));
+                            }
+                            Err(e) => report.problem(offset, e),
+                        }
+                    }
+                }
+                TypedOffset::Leaf(leaf) if !parent.is_null() => {
+                    report.leaf_count += 1;
+                    match leaf.key_and_link_offset(self) {
+                        Ok((key, link)) => {
+                            let digits = KeyDigits::new(key, self.fanout);
+                            if digits.len() < path.len() || !digits.zip(&path).all(|(a, &b)| a == b)
+                            {
+                                report.problem(
+                                    offset,
+                                    format!("key {:?} does not match its path", key),
+                                );
+                            }
+                            // The LINK entry of an INLINE_LEAF is written after it.
+                            let inline = !offset.is_dirty()
+                                && self.buf[offset.0 as usize] == TYPE_INLINE_LEAF;
+                            let link_parent = if inline { Offset::null() } else { offset };
+                            self.verify_link_chain(link, link_parent, &mut report);
+                        }
+                        Err(e) => report.problem(offset, e),
+                    }
+                }
+                _ => report.problem(offset, "unexpected entry type in radix tree"),
+            }
+        }
+        report
+    }
+
+    /// Write a human-readable dump of the index for debugging.
+    ///
+    /// The index is checked by [`Index::verify_tree`] first. If it is
+    /// consistent, all entries are written with their offsets, in the same
+    /// format as `Debug`. Otherwise, the problems are written instead, since
+    /// entries cannot be reliably parsed from corrupted data.
+    pub fn dump_debug(&self, out: &mut dyn Write) -> crate::Result<()> {
+        let report = self.verify_tree();
+        (|| -> io::Result<()> {
+            if report.is_ok() {
+                write!(out, "{:?}", self)?;
+            } else {
+                writeln!(
+                    out,
+                    "Index {{ len: {}, root: {:?} }}",
+                    self.buf.len(),
+                    self.dirty_root.radix_offset
+                )?;
+                for problem in &report.problems {
+                    writeln!(out, "Problem at {}: {}", problem.offset, problem.message)?;
+                }
+            }
+            writeln!(
+                out,
+                "Reachable: {} radix, {} leaf, {} link entries",
+                report.radix_count, report.leaf_count, report.link_count
+            )
+        })()
+        .context(&self.path, "cannot write debug dump")
+    }
+
+    // Internal function used by [`Index::verify_tree`].
+    // Check that "offset" is in bounds, and refers to an older entry if both
+    // "offset" and "parent" are on disk. Return false if there is a problem.
+    fn verify_offset(&self, offset: Offset, parent: Offset, report: &mut VerifyReport) -> bool {
+        let in_bounds = if offset.is_dirty() {
+            let index = ((offset.0 - DIRTY_OFFSET) >> TYPE_BITS) as usize;
+            let len = match ((offset.0 - DIRTY_OFFSET) & ((1 << TYPE_BITS) - 1)) as u8 {
+                TYPE_RADIX => self.dirty_radixes.len(),
+                TYPE_LEAF => self.dirty_leafs.len(),
+                TYPE_LINK => self.dirty_links.len(),
+                TYPE_KEY => self.dirty_keys.len(),
+                TYPE_EXT_KEY => self.dirty_ext_keys.len(),
+                _ => 0,
+            };
+            index < len
+        } else {
+            (offset.0 as usize) < self.buf.len()
+        };
+        if !in_bounds {
+            report.problem(offset, "offset is out of bounds");
+        } else if !parent.is_dirty() && !parent.is_null() && offset.0 >= parent.0 {
+            report.problem(
+                offset,
+                format!("entry is not written before its parent {}", parent.0),
+            );
+        } else {
+            return true;
+        }
+        false
+    }
+
+    // Internal function used by [`Index::verify_tree`].
+    // Follow a LINK chain starting from "link", referred by "parent".
+    fn verify_link_chain(&self, link: LinkOffset, parent: Offset, report: &mut VerifyReport) {
+        let mut visited = std::collections::HashSet::new();
+        let mut link = link;
+        let mut parent = parent;
+        while !link.is_null() {
+            let offset = link.0;
+            if !self.verify_offset(offset, parent, report) {
+                return;
+            }
+            if !visited.insert(offset.0) {
+                report.problem(offset, "cycle in LINK chain");
+                return;
+            }
+            report.link_count += 1;
+            match link.value_and_next(self) {
+                Ok((_value, next)) => {
+                    parent = offset;
+                    link = next;
+                }
+                Err(e) => {
+                    report.problem(offset, e);
+                    return;
+                }
+            }
+        }
+    }
+
     // Internal function used by [`Index::range`].
     // Calculate the [`IterState`] stack used by [`RangeIter`].
     // `side` is the side of the `bound`, starting side of the iteration,
@@ -3155,51 +4765,83 @@ impl Index {
         side: Side,
     ) -> crate::Result<Vec<IterState>> {
         let root_radix = self.dirty_root.radix_offset;
-        let (inclusive, mut base16iter) = match bound {
+        let (inclusive, mut digits) = match bound {
             Unbounded => {
                 return Ok(match side {
                     Front => vec![IterState::RadixStart(root_radix)],
                     Back => vec![IterState::RadixEnd(root_radix)],
                 });
             }
-            Included(ref key) => (true, Base16Iter::from_base256(key)),
-            Excluded(ref key) => (false, Base16Iter::from_base256(key)),
+            Included(key) => (true, KeyDigits::new(key, self.fanout)),
+            Excluded(key) => (false, KeyDigits::new(key, self.fanout)),
         };
 
         let mut offset: Offset = root_radix.into();
         let mut stack = Vec::<IterState>::new();
+        // Count of digits consumed.
+        let mut depth = 0;
 
         while !offset.is_null() {
             match offset.to_typed(self)? {
-                TypedOffset::Radix(radix) => match base16iter.next() {
-                    None => {
-                        // The key ends at this Radix entry.
-                        let state = IterState::RadixLeaf(radix);
-                        let state = if inclusive {
-                            state.step(side).unwrap()
-                        } else {
-                            state
+                TypedOffset::Radix(radix) => {
+                    // Compare with digits of a path-compressed Radix entry.
+                    let mut prefix_cmp = Equal;
+                    for digit in radix.prefix(self)? {
+                        prefix_cmp = match digits.next() {
+                            None => Greater,
+                            Some(x) => digit.cmp(&x),
                         };
-                        stack.push(state);
+                        if prefix_cmp != Equal {
+                            break;
+                        }
+                        depth += 1;
+                    }
+                    if prefix_cmp != Equal {
+                        // The key is not in this Radix entry. Keys in the Radix entry are all
+                        // greater, or all less than the key.
+                        if stack.is_empty() {
+                            return Err(self.corruption("unexpected prefix in root radix entry"));
+                        }
+                        // The last stack frame points to the Radix entry, which excludes it.
+                        // To include it, move the (exclusive) frame one step further so both
+                        // bounds stay at the parent level.
+                        if let (Greater, Front) | (Less, Back) = (prefix_cmp, side) {
+                            let state = stack.pop().unwrap();
+                            stack.push(state.step(side, self.fanout.last_child()).unwrap());
+                        }
                         return Ok(stack);
                     }
-                    Some(x) => {
-                        // Follow the `x`-th child in the Radix entry.
-                        stack.push(IterState::RadixChild(radix, x));
-                        offset = radix.child(self, x)?;
+                    match digits.next() {
+                        None => {
+                            // The key ends at this Radix entry.
+                            let state = IterState::RadixLeaf(radix);
+                            let state = if inclusive {
+                                state.step(side, self.fanout.last_child()).unwrap()
+                            } else {
+                                state
+                            };
+                            stack.push(state);
+                            return Ok(stack);
+                        }
+                        Some(x) => {
+                            // Follow the `x`-th child in the Radix entry.
+                            stack.push(IterState::RadixChild(radix, x));
+                            offset = radix.child(self, x)?;
+                            depth += 1;
+                        }
                     }
-                },
+                }
                 TypedOffset::Leaf(leaf) => {
                     let stored_cmp_key = {
                         let (stored_key, _link_offset) = leaf.key_and_link_offset(self)?;
-                        Base16Iter::from_base256(&stored_key)
-                            .skip(stack.len())
-                            .cmp(base16iter)
+                        KeyDigits::new(stored_key, self.fanout)
+                            .skip(depth)
+                            .cmp(digits)
                     };
                     let state = IterState::Leaf(leaf);
                     let state = match (stored_cmp_key, side, inclusive) {
                         (Equal, _, true) | (Less, Back, _) | (Greater, Front, _) => {
-                            state.step(side).unwrap()
+                            state.step(side, self.fanout.last_child()).unwrap()
                         }
                         (Equal, _, false) | (Greater, Back, _) | (Less, Front, _) => state,
                     };
@@ -3215,6 +4857,71 @@ impl Index {
         Ok(stack)
     }
 
+    #[allow(clippy::too_many_arguments)]
+    /// Split a path-compressed radix entry, whose prefix digits only match the
+    /// first `matched` digits of the key. Separated from `insert_advanced`,
+    /// similar to `split_leaf`.
+    ///
+    /// `digit` is the key digit following the matched digits, or `None` if the
+    /// key ends. `parent` is the radix entry and child referring to
+    /// `radix_offset`, or `None` if `radix_offset` is the root.
+    fn split_radix_prefix(
+        &mut self,
+        radix_offset: RadixOffset,
+        matched: usize,
+        digit: Option<u8>,
+        parent: Option<(RadixOffset, u8)>,
+        key: &[u8],
+        key_buf_offset: Option<(u64, u64)>,
+        value: InsertValue,
+    ) -> crate::Result<()> {
+        // Example. prefix = "123", key = "1256". matched = 2, digit = Some(5).
+        //
+        //      Offset | Before                     | After
+        //           A | Radix(1: B)                | Radix(1: C)
+        //           B | Radix(prefix: 123, 4: ...) | Radix(prefix: 3, 4: ...)
+        //           C |                            | Radix(prefix: 12, 3: B, 5: D)
+        //           D |                            | Leaf("1256")
+        let new_link_offset = match value {
+            InsertValue::Prepend(value) => LinkOffset::default().create(self, value),
+            InsertValue::PrependReplace(value, link_offset) => link_offset.create(self, value),
+            InsertValue::Replace(link_offset) => link_offset,
+            // The key does not exist.
+            InsertValue::Tombstone => return Ok(()),
+            InsertValue::TombstonePrefix => {
+                if digit.is_none() {
+                    // All keys in the radix entry start with the key.
+                    match parent {
+                        Some((parent, child)) => parent.set_child(self, child, Offset::null()),
+                        None => radix_offset.set_all_to_null(self),
+                    }
+                }
+                return Ok(());
+            }
+        };
+
+        let prefix = std::mem::take(&mut self.dirty_radixes[radix_offset.dirty_index()].prefix);
+        self.dirty_radixes[radix_offset.dirty_index()].prefix = prefix[matched + 1..].into();
+        let mut radix = MemRadix::new(self.fanout);
+        radix.prefix = prefix[..matched].into();
+        radix.offsets[prefix[matched] as usize] = radix_offset.into();
+        match digit {
+            None => radix.link_offset = new_link_offset,
+            Some(x) => {
+                let key_offset = self.create_key(key, key_buf_offset);
+                let leaf_offset = LeafOffset::create(self, new_link_offset, key_offset);
+                radix.offsets[x as usize] = leaf_offset.into();
+            }
+        }
+
+        let new_radix_offset = RadixOffset::create(self, radix);
+        match parent {
+            Some((parent, child)) => parent.set_child(self, child, new_radix_offset.into()),
+            None => self.dirty_root.radix_offset = new_radix_offset,
+        }
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     /// Split a leaf entry. Separated from `insert_advanced` to make `insert_advanced`
     /// shorter.  The parameters are internal states inside `insert_advanced`. Calling this
@@ -3279,19 +4986,33 @@ impl Index {
         // `self.dirty_keys` or `self.dirty_ext_keys`. That's true here since we won't read
         // `old_iter` after creating new keys. But be aware of the constraint when modifying the
         // code.
-        let mut old_iter = Base16Iter::from_base256(&old_key).skip(step);
-        let mut new_iter = Base16Iter::from_base256(&new_key).skip(step);
+        let mut old_iter = KeyDigits::new(old_key, self.fanout).skip(step);
+        let mut new_iter = KeyDigits::new(new_key, self.fanout).skip(step);
 
         let mut last_radix_offset = radix_offset;
         let mut last_radix_child = child;
 
+        // With path compression, common digits are stored in the first Radix entry, instead of
+        // a chain of Radix entries. Then the loop below runs only once.
+        let mut prefix: Box<[u8]> = Default::default();
+        if self.path_compression {
+            let common = old_iter
+                .zip(new_iter)
+                .take_while(|(b1, b2)| b1 == b2)
+                .count();
+            prefix = old_iter.take(common).collect();
+            old_iter = old_iter.skip(common);
+            new_iter = new_iter.skip(common);
+        }
+
         let mut completed = false;
 
         loop {
             let b1 = old_iter.next();
             let b2 = new_iter.next();
 
-            let mut radix = MemRadix::default();
+            let mut radix = MemRadix::new(self.fanout);
+            radix.prefix = std::mem::take(&mut prefix);
 
             if let Some(b1) = b1 {
                 // Initial value for the b1-th child. Could be rewritten by
@@ -3346,6 +5067,21 @@ impl Index {
     }
 }
 
+/// Specify how [`Index::flush_with`] makes written data durable.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Do not fsync. Written data might be lost on power loss.
+    Never,
+
+    /// Fsync new entries before writing the Root entry, then fsync the Root
+    /// entry. The Root entry never refers to entries not on disk.
+    Data,
+
+    /// Like `Data`. Also fsync the directory so a newly created index file
+    /// survives power loss.
+    DataAndDirectory,
+}
+
 /// Specify value to insert. Used by `insert_advanced`.
 #[derive(Copy, Clone)]
 pub enum InsertValue {
@@ -3355,6 +5091,12 @@ pub enum InsertValue {
     /// Replace the linked list. Then insert as a head.
     PrependReplace(u64, LinkOffset),
 
+    /// Replace the linked list with an existing one.
+    ///
+    /// Keys replaced with the same [`LinkOffset`] share the values without
+    /// creating new link entries.
+    Replace(LinkOffset),
+
     /// Effectively delete associated values for the specified key.
     Tombstone,
 
@@ -3389,6 +5131,9 @@ impl Debug for Offset {
 impl Debug for MemRadix {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         write!(f, "Radix {{ link: {:?}", self.link_offset)?;
+        if !self.prefix.is_empty() {
+            write!(f, ", prefix: {:?}", self.prefix)?;
+        }
         for (i, v) in self.offsets.iter().cloned().enumerate() {
             if !v.is_null() {
                 write!(f, ", {}: {:?}", i, v)?;
@@ -3448,15 +5193,17 @@ impl Debug for MemExtKey {
 
 impl Debug for MemRoot {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
-        if self.meta.is_empty() {
-            write!(f, "Root {{ radix: {:?} }}", self.radix_offset)
-        } else {
-            write!(
-                f,
-                "Root {{ radix: {:?}, meta: {:?} }}",
-                self.radix_offset, self.meta
-            )
+        write!(f, "Root {{ radix: {:?}", self.radix_offset)?;
+        if let Some(reverse_radix_offset) = self.reverse_radix_offset {
+            write!(f, ", reverse: {:?}", reverse_radix_offset)?;
+        }
+        if let Some(counts) = self.counts {
+            write!(f, ", keys: {}, values: {}", counts.keys, counts.values)?;
+        }
+        if !self.meta.is_empty() {
+            write!(f, ", meta: {:?}", self.meta)?;
         }
+        write!(f, " }}")
     }
 }
 impl Debug for MemChecksum {
@@ -3521,12 +5268,24 @@ impl Debug for Index {
                     e.write_to(&mut buf, &offset_map).expect("write");
                     writeln!(f, "{:?}", e)?;
                 }
+                TYPE_FIXED_KEY_LEN if i == TYPE_BYTES as u64 => {
+                    let len = self.fixed_key_len.expect("fixed_key_len");
+                    buf.push(TYPE_FIXED_KEY_LEN);
+                    buf.write_vlq(len).expect("write");
+                    writeln!(f, "KeyLen {{ len: {} }}", len)?;
+                }
+                TYPE_FIXED_KEY => {
+                    let key = KeyOffset(Offset(i)).key_content(self).expect("read");
+                    let e = MemKey { key: key.into() };
+                    e.write_fixed_to(&mut buf).expect("write");
+                    writeln!(f, "{:?}", e)?;
+                }
                 TYPE_EXT_KEY => {
                     let e = MemExtKey::read_from(self, i).expect("read");
                     e.write_to(&mut buf, &offset_map).expect("write");
                     writeln!(f, "{:?}", e)?;
                 }
-                TYPE_ROOT => {
+                TYPE_ROOT | TYPE_EXT_ROOT => {
                     root_offset = i as usize;
                     let e = MemRoot::read_from(self, i).expect("read").0;
                     e.write_to(&mut buf, &offset_map).expect("write");
@@ -3662,6 +5421,170 @@ mod tests {
         assert_eq!(index.scan_prefix_hex(b"31").unwrap().count(), 0);
     }
 
+    #[test]
+    fn test_radix_fanout_base256() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let mut index = open_opts()
+            .radix_fanout(RadixFanout::Base256)
+            .open(&path)
+            .unwrap();
+        let keys: Vec<&[u8]> = vec![b"", b"a", b"ab", b"abc", b"b", b"ba\xff", b"c"];
+        for (i, key) in keys.iter().enumerate() {
+            index.insert(key, i as u64).unwrap();
+        }
+        assert_eq!(
+            format!("{:?}", index),
+            r#"Index { len: 0, root: Radix[0] }
+Radix[0]: Radix { link: Link[0], 97: Radix[1], 98: Radix[3], 99: Leaf[5] }
+Radix[1]: Radix { link: Link[1], 98: Radix[2] }
+Radix[2]: Radix { link: Link[2], 99: Leaf[2] }
+Radix[3]: Radix { link: Link[4], 97: Leaf[4] }
+Leaf[0]: Leaf (unused)
+Leaf[1]: Leaf (unused)
+Leaf[2]: Leaf { key: Key[2], link: Link[3] }
+Leaf[3]: Leaf (unused)
+Leaf[4]: Leaf { key: Key[4], link: Link[5] }
+Leaf[5]: Leaf { key: Key[5], link: Link[6] }
+Link[0]: Link { value: 0, next: None }
+Link[1]: Link { value: 1, next: None }
+Link[2]: Link { value: 2, next: None }
+Link[3]: Link { value: 3, next: None }
+Link[4]: Link { value: 4, next: None }
+Link[5]: Link { value: 5, next: None }
+Link[6]: Link { value: 6, next: None }
+Key[0]: Key (unused)
+Key[1]: Key (unused)
+Key[2]: Key { key: 61 62 63 }
+Key[3]: Key (unused)
+Key[4]: Key { key: 62 61 FF }
+Key[5]: Key { key: 63 }
+"#
+        );
+
+        // The fanout is decided by the file, not by the open options.
+        index.flush().unwrap();
+        let index = open_opts().open(&path).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            let link = index.get(key).unwrap();
+            assert_eq!(link.values(&index).next().unwrap().unwrap(), i as u64);
+        }
+        assert!(index.get(b"abcd").unwrap().is_null());
+        assert!(index.get(b"ba").unwrap().is_null());
+
+        let scan_keys = |prefix: &[u8]| -> Vec<Vec<u8>> {
+            let iter = index.scan_prefix(prefix).unwrap();
+            iter_to_keys(&index, &keys, &iter)
+        };
+        assert_eq!(scan_keys(b""), keys);
+        assert_eq!(scan_keys(b"a"), vec![&b"a"[..], b"ab", b"abc"]);
+        assert_eq!(scan_keys(b"ba"), vec![b"ba\xff"]);
+        assert!(scan_keys(b"bb").is_empty());
+        assert!(scan_keys(b"d").is_empty());
+
+        // Odd-length hex prefixes select part of a radix entry.
+        // 0x61 = b'a', 0x62 = b'b', 0x63 = b'c'
+        assert_eq!(index.scan_prefix_hex(b"6").unwrap().count(), keys.len() - 1);
+        assert_eq!(index.scan_prefix_hex(b"626").unwrap().count(), 1);
+        assert_eq!(index.scan_prefix_hex(b"626f").unwrap().count(), 0);
+        assert_eq!(index.scan_prefix_hex(b"6261f").unwrap().count(), 1);
+        assert_eq!(index.scan_prefix_hex(b"6261e").unwrap().count(), 0);
+        assert!(index.scan_prefix_hex(b"z").is_err());
+
+        let range_keys: Vec<_> = index
+            .range(&b"ab"[..]..&b"c"[..])
+            .unwrap()
+            .map(|e| e.unwrap().0.to_vec())
+            .collect();
+        assert_eq!(range_keys, vec![&b"ab"[..], b"abc", b"b", b"ba\xff"]);
+    }
+
+    #[test]
+    fn test_radix_path_compression() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let mut opts = open_opts();
+        opts.radix_path_compression(true);
+        let mut index = opts.open(&path).unwrap();
+        index.insert(b"abcd1", 1).unwrap();
+        index.insert(b"abcd2", 2).unwrap();
+        assert_eq!(
+            format!("{:?}", index),
+            r#"Index { len: 0, root: Radix[0] }
+Radix[0]: Radix { link: None, 6: Radix[1] }
+Radix[1]: Radix { link: None, prefix: [1, 6, 2, 6, 3, 6, 4, 3], 1: Leaf[0], 2: Leaf[1] }
+Leaf[0]: Leaf { key: Key[0], link: Link[0] }
+Leaf[1]: Leaf { key: Key[1], link: Link[1] }
+Link[0]: Link { value: 1, next: None }
+Link[1]: Link { value: 2, next: None }
+Key[0]: Key { key: 61 62 63 64 31 }
+Key[1]: Key { key: 61 62 63 64 32 }
+"#
+        );
+
+        // Path-compressed entries can be read from disk.
+        index.flush().unwrap();
+        let mut index = opts.open(&path).unwrap();
+        assert_eq!(index.get(b"abcd1").unwrap().values(&index).count(), 1);
+        assert!(index.get(b"abcd").unwrap().is_null());
+        assert!(index.get(b"abce1").unwrap().is_null());
+
+        // Split the prefix in the middle. The key can end inside the prefix, or have a
+        // different digit.
+        // `keys[i]` has value `i`, as `iter_to_keys` expects.
+        let keys: Vec<&[u8]> = vec![b"", b"abcd1", b"abcd2", b"ab", b"abx"];
+        index.insert(b"ab", 3).unwrap();
+        index.insert(b"abx", 4).unwrap();
+        assert_eq!(
+            format!("{:?}", index),
+            r#"Index { len: 91, root: Radix[0] }
+Disk[1]: InlineLeaf { key: Disk[2], link: Disk[9] }
+Disk[2]: Key { key: 61 62 63 64 31 }
+Disk[9]: Link { value: 1, next: None }
+Disk[12]: InlineLeaf { key: Disk[13], link: Disk[20] }
+Disk[13]: Key { key: 61 62 63 64 32 }
+Disk[20]: Link { value: 2, next: None }
+Disk[23]: Radix { link: None, prefix: [1, 6, 2, 6, 3, 6, 4, 3], 1: Disk[1], 2: Disk[12] }
+Disk[40]: Radix { link: None, 6: Disk[23] }
+Disk[48]: Root { radix: Disk[40] }
+Disk[51]: Checksum { start: 0, end: 51, chunk_size_logarithm: 4, checksums.len(): 4 }
+Radix[0]: Radix { link: None, 6: Radix[2] }
+Radix[1]: Radix { link: None, prefix: [3, 6, 4, 3], 1: Disk[1], 2: Disk[12] }
+Radix[2]: Radix { link: Link[0], prefix: [1, 6, 2], 6: Radix[1], 7: Leaf[0] }
+Leaf[0]: Leaf { key: Key[0], link: Link[1] }
+Link[0]: Link { value: 3, next: None }
+Link[1]: Link { value: 4, next: None }
+Key[0]: Key { key: 61 62 78 }
+"#
+        );
+
+        index.flush().unwrap();
+        let index = opts.open(&path).unwrap();
+        let scan_keys = |prefix: &[u8]| -> Vec<Vec<u8>> {
+            let iter = index.scan_prefix(prefix).unwrap();
+            iter_to_keys(&index, &keys, &iter)
+        };
+        let sorted_keys = vec![&b"ab"[..], b"abcd1", b"abcd2", b"abx"];
+        assert_eq!(scan_keys(b""), sorted_keys);
+        assert_eq!(scan_keys(b"a"), sorted_keys);
+        assert_eq!(scan_keys(b"abc"), vec![&b"abcd1"[..], b"abcd2"]);
+        assert_eq!(scan_keys(b"abcd2"), vec![b"abcd2"]);
+        assert!(scan_keys(b"abd").is_empty());
+        assert!(scan_keys(b"b").is_empty());
+        assert_eq!(index.scan_prefix_hex(b"6162636").unwrap().count(), 2);
+        assert_eq!(index.scan_prefix_hex(b"6162637").unwrap().count(), 0);
+
+        // Remove keys by a prefix ending inside a path-compressed entry.
+        let mut index = opts.open(&path).unwrap();
+        index.remove_prefix(b"abc").unwrap();
+        let keys: Vec<_> = index
+            .range(..)
+            .unwrap()
+            .map(|e| e.unwrap().0.to_vec())
+            .collect();
+        assert_eq!(keys, vec![&b"ab"[..], b"abx"]);
+    }
+
     #[test]
     fn test_remove() {
         let dir = tempdir().unwrap();
@@ -3830,20 +5753,20 @@ Key[0]: Key { key: 12 }
         index.flush().expect("flush");
         assert_eq!(
             format!("{:?}", index),
-            r#"Index { len: 104, root: Disk[45] }
+            r#"Index { len: 100, root: Disk[41] }
 Disk[1]: Radix { link: None }
 Disk[5]: Root { radix: Disk[1] }
 Disk[8]: Checksum { start: 0, end: 8, chunk_size_logarithm: 4, checksums.len(): 1 }
-Disk[24]: Key { key: 12 }
-Disk[27]: Key { key: 34 }
-Disk[30]: Link { value: 55, next: None }
-Disk[33]: Link { value: 77, next: None }
-Disk[36]: Link { value: 99, next: Disk[33] }
-Disk[39]: Leaf { key: Disk[24], link: Disk[33] }
-Disk[42]: Leaf { key: Disk[27], link: Disk[36] }
-Disk[45]: Radix { link: Disk[30], 1: Disk[39], 3: Disk[42] }
-Disk[61]: Root { radix: Disk[45] }
-Disk[64]: Checksum { start: 0, end: 64, chunk_size_logarithm: 4, checksums.len(): 4 }
+Disk[24]: InlineLeaf { key: Disk[25], link: Disk[28] }
+Disk[25]: Key { key: 12 }
+Disk[28]: Link { value: 77, next: None }
+Disk[31]: InlineLeaf { key: Disk[32], link: Disk[35] }
+Disk[32]: Key { key: 34 }
+Disk[35]: Link { value: 99, next: Disk[28] }
+Disk[38]: Link { value: 55, next: None }
+Disk[41]: Radix { link: Disk[38], 1: Disk[24], 3: Disk[31] }
+Disk[57]: Root { radix: Disk[41] }
+Disk[60]: Checksum { start: 0, end: 60, chunk_size_logarithm: 4, checksums.len(): 4 }
 "#
         );
     }
@@ -3944,28 +5867,28 @@ Key[0]: Key { key: 12 }
         index.flush().expect("flush");
         assert_eq!(
             format!("{:?}", index),
-            r#"Index { len: 46, root: Disk[11] }
-Disk[1]: Key { key: 12 34 }
-Disk[5]: Link { value: 5, next: None }
-Disk[8]: Leaf { key: Disk[1], link: Disk[5] }
-Disk[11]: Radix { link: None, 1: Disk[8] }
-Disk[19]: Root { radix: Disk[11] }
-Disk[22]: Checksum { start: 0, end: 22, chunk_size_logarithm: 4, checksums.len(): 2 }
+            r#"Index { len: 44, root: Disk[9] }
+Disk[1]: InlineLeaf { key: Disk[2], link: Disk[6] }
+Disk[2]: Key { key: 12 34 }
+Disk[6]: Link { value: 5, next: None }
+Disk[9]: Radix { link: None, 1: Disk[1] }
+Disk[17]: Root { radix: Disk[9] }
+Disk[20]: Checksum { start: 0, end: 20, chunk_size_logarithm: 4, checksums.len(): 2 }
 "#
         );
         index.insert(&[0x12, 0x78], 7).expect("insert");
         assert_eq!(
             format!("{:?}", index),
-            r#"Index { len: 46, root: Radix[0] }
-Disk[1]: Key { key: 12 34 }
-Disk[5]: Link { value: 5, next: None }
-Disk[8]: Leaf { key: Disk[1], link: Disk[5] }
-Disk[11]: Radix { link: None, 1: Disk[8] }
-Disk[19]: Root { radix: Disk[11] }
-Disk[22]: Checksum { start: 0, end: 22, chunk_size_logarithm: 4, checksums.len(): 2 }
+            r#"Index { len: 44, root: Radix[0] }
+Disk[1]: InlineLeaf { key: Disk[2], link: Disk[6] }
+Disk[2]: Key { key: 12 34 }
+Disk[6]: Link { value: 5, next: None }
+Disk[9]: Radix { link: None, 1: Disk[1] }
+Disk[17]: Root { radix: Disk[9] }
+Disk[20]: Checksum { start: 0, end: 20, chunk_size_logarithm: 4, checksums.len(): 2 }
 Radix[0]: Radix { link: None, 1: Radix[1] }
 Radix[1]: Radix { link: None, 2: Radix[2] }
-Radix[2]: Radix { link: None, 3: Disk[8], 7: Leaf[0] }
+Radix[2]: Radix { link: None, 3: Disk[1], 7: Leaf[0] }
 Leaf[0]: Leaf { key: Key[0], link: Link[0] }
 Link[0]: Link { value: 7, next: None }
 Key[0]: Key { key: 12 78 }
@@ -3979,16 +5902,16 @@ Key[0]: Key { key: 12 78 }
         index.insert(&[0x12], 7).expect("insert");
         assert_eq!(
             format!("{:?}", index),
-            r#"Index { len: 46, root: Radix[0] }
-Disk[1]: Key { key: 12 34 }
-Disk[5]: Link { value: 5, next: None }
-Disk[8]: Leaf { key: Disk[1], link: Disk[5] }
-Disk[11]: Radix { link: None, 1: Disk[8] }
-Disk[19]: Root { radix: Disk[11] }
-Disk[22]: Checksum { start: 0, end: 22, chunk_size_logarithm: 4, checksums.len(): 2 }
+            r#"Index { len: 44, root: Radix[0] }
+Disk[1]: InlineLeaf { key: Disk[2], link: Disk[6] }
+Disk[2]: Key { key: 12 34 }
+Disk[6]: Link { value: 5, next: None }
+Disk[9]: Radix { link: None, 1: Disk[1] }
+Disk[17]: Root { radix: Disk[9] }
+Disk[20]: Checksum { start: 0, end: 20, chunk_size_logarithm: 4, checksums.len(): 2 }
 Radix[0]: Radix { link: None, 1: Radix[1] }
 Radix[1]: Radix { link: None, 2: Radix[2] }
-Radix[2]: Radix { link: Link[0], 3: Disk[8] }
+Radix[2]: Radix { link: Link[0], 3: Disk[1] }
 Link[0]: Link { value: 7, next: None }
 "#
         );
@@ -4001,16 +5924,16 @@ Link[0]: Link { value: 7, next: None }
         index.flush().expect("flush");
         assert_eq!(
             format!("{:?}", index),
-            r#"Index { len: 77, root: Disk[34] }
-Disk[1]: Key { key: 12 78 }
-Disk[5]: Link { value: 5, next: None }
-Disk[8]: Link { value: 7, next: None }
-Disk[11]: Leaf { key: Disk[1], link: Disk[8] }
-Disk[14]: Radix { link: Disk[5], 7: Disk[11] }
-Disk[26]: Radix { link: None, 2: Disk[14] }
-Disk[34]: Radix { link: None, 1: Disk[26] }
-Disk[42]: Root { radix: Disk[34] }
-Disk[45]: Checksum { start: 0, end: 45, chunk_size_logarithm: 4, checksums.len(): 3 }
+            r#"Index { len: 75, root: Disk[32] }
+Disk[1]: InlineLeaf { key: Disk[2], link: Disk[6] }
+Disk[2]: Key { key: 12 78 }
+Disk[6]: Link { value: 7, next: None }
+Disk[9]: Link { value: 5, next: None }
+Disk[12]: Radix { link: Disk[9], 7: Disk[1] }
+Disk[24]: Radix { link: None, 2: Disk[12] }
+Disk[32]: Radix { link: None, 1: Disk[24] }
+Disk[40]: Root { radix: Disk[32] }
+Disk[43]: Checksum { start: 0, end: 43, chunk_size_logarithm: 4, checksums.len(): 3 }
 "#
         );
 
@@ -4021,16 +5944,16 @@ Disk[45]: Checksum { start: 0, end: 45, chunk_size_logarithm: 4, checksums.len()
         index.insert(&[0x12, 0x78], 7).expect("insert");
         assert_eq!(
             format!("{:?}", index),
-            r#"Index { len: 45, root: Radix[0] }
-Disk[1]: Key { key: 12 }
-Disk[4]: Link { value: 5, next: None }
-Disk[7]: Leaf { key: Disk[1], link: Disk[4] }
-Disk[10]: Radix { link: None, 1: Disk[7] }
-Disk[18]: Root { radix: Disk[10] }
-Disk[21]: Checksum { start: 0, end: 21, chunk_size_logarithm: 4, checksums.len(): 2 }
+            r#"Index { len: 43, root: Radix[0] }
+Disk[1]: InlineLeaf { key: Disk[2], link: Disk[5] }
+Disk[2]: Key { key: 12 }
+Disk[5]: Link { value: 5, next: None }
+Disk[8]: Radix { link: None, 1: Disk[1] }
+Disk[16]: Root { radix: Disk[8] }
+Disk[19]: Checksum { start: 0, end: 19, chunk_size_logarithm: 4, checksums.len(): 2 }
 Radix[0]: Radix { link: None, 1: Radix[1] }
 Radix[1]: Radix { link: None, 2: Radix[2] }
-Radix[2]: Radix { link: Disk[4], 7: Leaf[0] }
+Radix[2]: Radix { link: Disk[5], 7: Leaf[0] }
 Leaf[0]: Leaf { key: Key[0], link: Link[0] }
 Link[0]: Link { value: 7, next: None }
 Key[0]: Key { key: 12 78 }
@@ -4044,16 +5967,16 @@ Key[0]: Key { key: 12 78 }
         index.insert(&[0x12], 7).expect("insert");
         assert_eq!(
             format!("{:?}", index),
-            r#"Index { len: 45, root: Radix[0] }
-Disk[1]: Key { key: 12 }
-Disk[4]: Link { value: 5, next: None }
-Disk[7]: Leaf { key: Disk[1], link: Disk[4] }
-Disk[10]: Radix { link: None, 1: Disk[7] }
-Disk[18]: Root { radix: Disk[10] }
-Disk[21]: Checksum { start: 0, end: 21, chunk_size_logarithm: 4, checksums.len(): 2 }
+            r#"Index { len: 43, root: Radix[0] }
+Disk[1]: InlineLeaf { key: Disk[2], link: Disk[5] }
+Disk[2]: Key { key: 12 }
+Disk[5]: Link { value: 5, next: None }
+Disk[8]: Radix { link: None, 1: Disk[1] }
+Disk[16]: Root { radix: Disk[8] }
+Disk[19]: Checksum { start: 0, end: 19, chunk_size_logarithm: 4, checksums.len(): 2 }
 Radix[0]: Radix { link: None, 1: Leaf[0] }
-Leaf[0]: Leaf { key: Disk[1], link: Link[0] }
-Link[0]: Link { value: 7, next: Disk[4] }
+Leaf[0]: Leaf { key: Disk[2], link: Link[0] }
+Link[0]: Link { value: 7, next: Disk[5] }
 "#
         );
     }
@@ -4204,6 +6127,37 @@ Disk[201]: Checksum { start: 126, end: 201, chunk_size_logarithm: 4, checksums.l
         assert_eq!(format!("{:?}", index3), format!("{:?}", index4));
     }
 
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Index>();
+    }
+
+    #[test]
+    fn test_clone_shares_buffer() {
+        let dir = tempdir().unwrap();
+        let mut index = open_opts().open(dir.path().join("a")).expect("open");
+        for i in 0..100u8 {
+            index.insert(&[i], i as u64).expect("insert");
+        }
+        index.flush().expect("flush");
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let clone = index.try_clone_without_dirty().unwrap();
+                assert_eq!(clone.buf.as_ptr(), index.buf.as_ptr());
+                std::thread::spawn(move || {
+                    (0..100u8)
+                        .map(|i| clone.get(&[i]).unwrap().values(&clone).count())
+                        .sum::<usize>()
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 100);
+        }
+    }
+
     #[test]
     fn test_open_options_write() {
         let dir = tempdir().unwrap();
@@ -4365,6 +6319,78 @@ Disk[201]: Checksum { start: 126, end: 201, chunk_size_logarithm: 4, checksums.l
         }
     }
 
+    #[test]
+    fn test_corrupted_ranges() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let opts = open_opts().checksum_chunk_size_logarithm(4).clone();
+        let mut index = opts.open(&path).unwrap();
+        for i in 0..20u8 {
+            index.insert(&[i, i], i as u64).unwrap();
+        }
+        index.flush().unwrap();
+        assert!(index.corrupted_ranges().is_empty());
+        assert!(index.buf.len() > 96);
+        drop(index);
+
+        // Corrupt 2 bytes in adjacent chunks, and 1 byte in another chunk.
+        let mut bytes = fs::read(&path).unwrap();
+        for offset in [20, 40, 90] {
+            bytes[offset] ^= 1;
+        }
+        fs::write(&path, &bytes).unwrap();
+
+        let index = opts.open(&path).unwrap();
+        assert!(index.verify().is_err());
+        assert_eq!(index.corrupted_ranges(), [16..48, 80..96]);
+
+        // No checksum.
+        let index = opts.clone().checksum_enabled(false).open(&path).unwrap();
+        assert!(index.corrupted_ranges().is_empty());
+    }
+
+    #[test]
+    fn test_dump_debug() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let opts = open_opts().checksum_chunk_size_logarithm(4).clone();
+        let mut index = opts.open(&path).unwrap();
+        index.insert(&[1, 2], 3).unwrap();
+        index.flush().unwrap();
+
+        let mut out = Vec::new();
+        index.dump_debug(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"Index { len: 44, root: Disk[9] }
+Disk[1]: InlineLeaf { key: Disk[2], link: Disk[6] }
+Disk[2]: Key { key: 1 2 }
+Disk[6]: Link { value: 3, next: None }
+Disk[9]: Radix { link: None, 0: Disk[1] }
+Disk[17]: Root { radix: Disk[9] }
+Disk[20]: Checksum { start: 0, end: 20, chunk_size_logarithm: 4, checksums.len(): 2 }
+Reachable: 1 radix, 1 leaf, 1 link entries
+"#
+        );
+
+        // Corrupted data is reported instead of dumped.
+        let path = dir.path().join("b");
+        let mut index = opts.open(&path).unwrap();
+        for i in 0..20u8 {
+            index.insert(&[i, i], i as u64).unwrap();
+        }
+        index.flush().unwrap();
+        drop(index);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[20] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        let index = opts.open(&path).unwrap();
+        let mut out = Vec::new();
+        index.dump_debug(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Problem at 0: "), "{}", out);
+    }
+
     #[test]
     fn test_checksum_toggle() {
         let dir = tempdir().unwrap();
@@ -4401,44 +6427,44 @@ Disk[201]: Checksum { start: 126, end: 201, chunk_size_logarithm: 4, checksums.l
 
         assert_eq!(
             format!("{:?}", index),
-            r#"Index { len: 415, root: Disk[402] }
-Disk[1]: Key { key: 61 62 63 64 65 66 67 }
-Disk[10]: Link { value: 4660, next: None }
-Disk[14]: Leaf { key: Disk[1], link: Disk[10] }
-Disk[17]: Radix { link: None, 6: Disk[14] }
-Disk[25]: Root { radix: Disk[17] }
-Disk[29]: Key { key: 62 63 64 65 66 67 68 }
-Disk[38]: Link { value: 9029, next: None }
-Disk[42]: Leaf { key: Disk[29], link: Disk[38] }
-Disk[45]: Radix { link: None, 1: Disk[14], 2: Disk[42] }
-Disk[57]: Radix { link: None, 6: Disk[45] }
-Disk[65]: Root { radix: Disk[57] }
-Disk[69]: Key { key: 63 64 65 66 67 68 69 }
-Disk[78]: Link { value: 13398, next: None }
-Disk[82]: Leaf { key: Disk[69], link: Disk[78] }
-Disk[85]: Radix { link: None, 1: Disk[14], 2: Disk[42], 3: Disk[82] }
-Disk[101]: Radix { link: None, 6: Disk[85] }
-Disk[109]: Root { radix: Disk[101] }
-Disk[112]: Checksum { start: 0, end: 112, chunk_size_logarithm: 4, checksums.len(): 7 }
-Disk[176]: Key { key: 64 65 66 67 68 69 6A }
-Disk[185]: Link { value: 17767, next: None }
-Disk[190]: Leaf { key: Disk[176], link: Disk[185] }
-Disk[195]: Radix { link: None, 1: Disk[14], 2: Disk[42], 3: Disk[82], 4: Disk[190] }
-Disk[215]: Radix { link: None, 6: Disk[195] }
-Disk[223]: Root { radix: Disk[215] }
-Disk[227]: Checksum { start: 112, end: 227, chunk_size_logarithm: 4, checksums.len(): 8 }
-Disk[299]: Key { key: 65 66 67 68 69 6A 68 }
-Disk[308]: Link { value: 22136, next: None }
-Disk[313]: Leaf { key: Disk[299], link: Disk[308] }
-Disk[318]: Radix { link: None, 1: Disk[14], 2: Disk[42], 3: Disk[82], 4: Disk[190], 5: Disk[313] }
-Disk[342]: Radix { link: None, 6: Disk[318] }
-Disk[350]: Root { radix: Disk[342] }
-Disk[355]: Key { key: 66 67 68 69 6A 6B 6C }
-Disk[364]: Link { value: 30864, next: None }
-Disk[369]: Leaf { key: Disk[355], link: Disk[364] }
-Disk[374]: Radix { link: None, 1: Disk[14], 2: Disk[42], 3: Disk[82], 4: Disk[190], 5: Disk[313], 6: Disk[369] }
-Disk[402]: Radix { link: None, 6: Disk[374] }
-Disk[410]: Root { radix: Disk[402] }
+            r#"Index { len: 397, root: Disk[384] }
+Disk[1]: InlineLeaf { key: Disk[2], link: Disk[11] }
+Disk[2]: Key { key: 61 62 63 64 65 66 67 }
+Disk[11]: Link { value: 4660, next: None }
+Disk[15]: Radix { link: None, 6: Disk[1] }
+Disk[23]: Root { radix: Disk[15] }
+Disk[27]: InlineLeaf { key: Disk[28], link: Disk[37] }
+Disk[28]: Key { key: 62 63 64 65 66 67 68 }
+Disk[37]: Link { value: 9029, next: None }
+Disk[41]: Radix { link: None, 1: Disk[1], 2: Disk[27] }
+Disk[53]: Radix { link: None, 6: Disk[41] }
+Disk[61]: Root { radix: Disk[53] }
+Disk[65]: InlineLeaf { key: Disk[66], link: Disk[75] }
+Disk[66]: Key { key: 63 64 65 66 67 68 69 }
+Disk[75]: Link { value: 13398, next: None }
+Disk[79]: Radix { link: None, 1: Disk[1], 2: Disk[27], 3: Disk[65] }
+Disk[95]: Radix { link: None, 6: Disk[79] }
+Disk[103]: Root { radix: Disk[95] }
+Disk[106]: Checksum { start: 0, end: 106, chunk_size_logarithm: 4, checksums.len(): 7 }
+Disk[170]: InlineLeaf { key: Disk[171], link: Disk[180] }
+Disk[171]: Key { key: 64 65 66 67 68 69 6A }
+Disk[180]: Link { value: 17767, next: None }
+Disk[185]: Radix { link: None, 1: Disk[1], 2: Disk[27], 3: Disk[65], 4: Disk[170] }
+Disk[205]: Radix { link: None, 6: Disk[185] }
+Disk[213]: Root { radix: Disk[205] }
+Disk[217]: Checksum { start: 106, end: 217, chunk_size_logarithm: 4, checksums.len(): 8 }
+Disk[289]: InlineLeaf { key: Disk[290], link: Disk[299] }
+Disk[290]: Key { key: 65 66 67 68 69 6A 68 }
+Disk[299]: Link { value: 22136, next: None }
+Disk[304]: Radix { link: None, 1: Disk[1], 2: Disk[27], 3: Disk[65], 4: Disk[170], 5: Disk[289] }
+Disk[328]: Radix { link: None, 6: Disk[304] }
+Disk[336]: Root { radix: Disk[328] }
+Disk[341]: InlineLeaf { key: Disk[342], link: Disk[351] }
+Disk[342]: Key { key: 66 67 68 69 6A 6B 6C }
+Disk[351]: Link { value: 30864, next: None }
+Disk[356]: Radix { link: None, 1: Disk[1], 2: Disk[27], 3: Disk[65], 4: Disk[170], 5: Disk[289], 6: Disk[341] }
+Disk[384]: Radix { link: None, 6: Disk[356] }
+Disk[392]: Root { radix: Disk[384] }
 "#
         );
     }
@@ -4489,64 +6515,551 @@ Disk[410]: Root { radix: Disk[402] }
             show_checksums(&index)
         };
 
-        // Unlimited chain. Chain: 1358 -> 1167 -> 1071 -> 818 -> 738 -> ...
+        // Unlimited chain. Chain: 1332 -> 1057 -> 804 -> 728 -> 553 -> ...
         assert_eq!(
             t(0),
             r#"
-                Disk[21]: Checksum { start: 0, end: 21, chunk_size_logarithm: 7, checksums.len(): 1 }
-                Disk[54]: Checksum { start: 0, end: 54, chunk_size_logarithm: 7, checksums.len(): 1 }
-                Disk[203]: Checksum { start: 0, end: 203, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[266]: Checksum { start: 203, end: 266, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[433]: Checksum { start: 266, end: 433, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[499]: Checksum { start: 433, end: 499, chunk_size_logarithm: 7, checksums.len(): 1 }
-                Disk[563]: Checksum { start: 433, end: 563, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[738]: Checksum { start: 563, end: 738, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[818]: Checksum { start: 738, end: 818, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[896]: Checksum { start: 818, end: 896, chunk_size_logarithm: 7, checksums.len(): 1 }
-                Disk[1071]: Checksum { start: 818, end: 1071, chunk_size_logarithm: 7, checksums.len(): 3 }
-                Disk[1167]: Checksum { start: 1071, end: 1167, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[1358]: Checksum { start: 1167, end: 1358, chunk_size_logarithm: 7, checksums.len(): 2 }"#
+                Disk[19]: Checksum { start: 0, end: 19, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[52]: Checksum { start: 0, end: 52, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[201]: Checksum { start: 0, end: 201, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[260]: Checksum { start: 201, end: 260, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[427]: Checksum { start: 260, end: 427, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[493]: Checksum { start: 427, end: 493, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[553]: Checksum { start: 427, end: 553, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[728]: Checksum { start: 553, end: 728, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[804]: Checksum { start: 728, end: 804, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[882]: Checksum { start: 804, end: 882, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[1057]: Checksum { start: 804, end: 1057, chunk_size_logarithm: 7, checksums.len(): 3 }
+                Disk[1149]: Checksum { start: 1057, end: 1149, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[1332]: Checksum { start: 1057, end: 1332, chunk_size_logarithm: 7, checksums.len(): 3 }"#
         );
 
         // Max chain len = 2. Chain: 1331 -> 1180 -> 0; 872 -> 761 -> 0; ...
         assert_eq!(
             t(2),
             r#"
-                Disk[21]: Checksum { start: 0, end: 21, chunk_size_logarithm: 7, checksums.len(): 1 }
-                Disk[54]: Checksum { start: 0, end: 54, chunk_size_logarithm: 7, checksums.len(): 1 }
-                Disk[203]: Checksum { start: 0, end: 203, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[266]: Checksum { start: 203, end: 266, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[433]: Checksum { start: 0, end: 433, chunk_size_logarithm: 7, checksums.len(): 4 }
-                Disk[514]: Checksum { start: 433, end: 514, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[586]: Checksum { start: 433, end: 586, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[761]: Checksum { start: 0, end: 761, chunk_size_logarithm: 7, checksums.len(): 6 }
-                Disk[872]: Checksum { start: 761, end: 872, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[950]: Checksum { start: 0, end: 950, chunk_size_logarithm: 7, checksums.len(): 8 }
-                Disk[1180]: Checksum { start: 0, end: 1180, chunk_size_logarithm: 7, checksums.len(): 10 }
-                Disk[1331]: Checksum { start: 1180, end: 1331, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[1522]: Checksum { start: 0, end: 1522, chunk_size_logarithm: 7, checksums.len(): 12 }"#
+                Disk[19]: Checksum { start: 0, end: 19, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[52]: Checksum { start: 0, end: 52, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[201]: Checksum { start: 0, end: 201, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[260]: Checksum { start: 201, end: 260, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[427]: Checksum { start: 0, end: 427, chunk_size_logarithm: 7, checksums.len(): 4 }
+                Disk[508]: Checksum { start: 427, end: 508, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[568]: Checksum { start: 427, end: 568, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[743]: Checksum { start: 0, end: 743, chunk_size_logarithm: 7, checksums.len(): 6 }
+                Disk[850]: Checksum { start: 743, end: 850, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[928]: Checksum { start: 0, end: 928, chunk_size_logarithm: 7, checksums.len(): 8 }
+                Disk[1158]: Checksum { start: 0, end: 1158, chunk_size_logarithm: 7, checksums.len(): 10 }
+                Disk[1305]: Checksum { start: 1158, end: 1305, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[1496]: Checksum { start: 0, end: 1496, chunk_size_logarithm: 7, checksums.len(): 12 }"#
         );
 
         // Max chain len = 1. All have start: 0.
         assert_eq!(
             t(1),
             r#"
-                Disk[21]: Checksum { start: 0, end: 21, chunk_size_logarithm: 7, checksums.len(): 1 }
-                Disk[54]: Checksum { start: 0, end: 54, chunk_size_logarithm: 7, checksums.len(): 1 }
-                Disk[203]: Checksum { start: 0, end: 203, chunk_size_logarithm: 7, checksums.len(): 2 }
-                Disk[266]: Checksum { start: 0, end: 266, chunk_size_logarithm: 7, checksums.len(): 3 }
-                Disk[440]: Checksum { start: 0, end: 440, chunk_size_logarithm: 7, checksums.len(): 4 }
-                Disk[521]: Checksum { start: 0, end: 521, chunk_size_logarithm: 7, checksums.len(): 5 }
-                Disk[616]: Checksum { start: 0, end: 616, chunk_size_logarithm: 7, checksums.len(): 5 }
-                Disk[814]: Checksum { start: 0, end: 814, chunk_size_logarithm: 7, checksums.len(): 7 }
-                Disk[933]: Checksum { start: 0, end: 933, chunk_size_logarithm: 7, checksums.len(): 8 }
-                Disk[1058]: Checksum { start: 0, end: 1058, chunk_size_logarithm: 7, checksums.len(): 9 }
-                Disk[1296]: Checksum { start: 0, end: 1296, chunk_size_logarithm: 7, checksums.len(): 11 }
-                Disk[1455]: Checksum { start: 0, end: 1455, chunk_size_logarithm: 7, checksums.len(): 12 }
-                Disk[1725]: Checksum { start: 0, end: 1725, chunk_size_logarithm: 7, checksums.len(): 14 }"#
+                Disk[19]: Checksum { start: 0, end: 19, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[52]: Checksum { start: 0, end: 52, chunk_size_logarithm: 7, checksums.len(): 1 }
+                Disk[201]: Checksum { start: 0, end: 201, chunk_size_logarithm: 7, checksums.len(): 2 }
+                Disk[260]: Checksum { start: 0, end: 260, chunk_size_logarithm: 7, checksums.len(): 3 }
+                Disk[434]: Checksum { start: 0, end: 434, chunk_size_logarithm: 7, checksums.len(): 4 }
+                Disk[515]: Checksum { start: 0, end: 515, chunk_size_logarithm: 7, checksums.len(): 5 }
+                Disk[606]: Checksum { start: 0, end: 606, chunk_size_logarithm: 7, checksums.len(): 5 }
+                Disk[804]: Checksum { start: 0, end: 804, chunk_size_logarithm: 7, checksums.len(): 7 }
+                Disk[919]: Checksum { start: 0, end: 919, chunk_size_logarithm: 7, checksums.len(): 8 }
+                Disk[1044]: Checksum { start: 0, end: 1044, chunk_size_logarithm: 7, checksums.len(): 9 }
+                Disk[1282]: Checksum { start: 0, end: 1282, chunk_size_logarithm: 7, checksums.len(): 11 }
+                Disk[1437]: Checksum { start: 0, end: 1437, chunk_size_logarithm: 7, checksums.len(): 12 }
+                Disk[1707]: Checksum { start: 0, end: 1707, chunk_size_logarithm: 7, checksums.len(): 14 }"#
         );
     }
 
+    #[test]
+    fn test_inline_key_leaf() {
+        let dir = tempdir().unwrap();
+        let mut index = open_opts().open(dir.path().join("a")).unwrap();
+        let short_key = [0x12u8; INLINE_KEY_MAX_LEN];
+        let long_key = [0x34u8; INLINE_KEY_MAX_LEN + 1];
+        index.insert(&short_key, 1).unwrap();
+        index.insert(&long_key, 2).unwrap();
+        index.flush().unwrap();
+
+        // Only the short key is inlined.
+        let debug = format!("{:?}", index);
+        assert_eq!(debug.matches("InlineLeaf").count(), 1, "{}", debug);
+        assert!(debug.contains("Disk[1]: InlineLeaf { key: Disk[2], link: Disk[36] }"));
+
+        let index = open_opts().open(dir.path().join("a")).unwrap();
+        for (key, value) in [(&short_key[..], 1), (&long_key[..], 2)] {
+            let link = index.get(&key).unwrap();
+            assert_eq!(
+                link.values(&index).map(|v| v.unwrap()).collect::<Vec<_>>(),
+                [value]
+            );
+        }
+        assert_eq!(index.range(..).unwrap().count(), 2);
+        assert!(index.verify_tree().is_ok());
+    }
+
+    #[test]
+    fn test_fixed_key_len() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let mut index = open_opts().fixed_key_len(Some(2)).open(&path).unwrap();
+        index.insert(&[0x12, 0x34], 5).unwrap();
+        index.insert(&[0x12, 0x56], 6).unwrap();
+        assert!(index.insert(&[0x12], 7).is_err());
+        index.flush().unwrap();
+        index.insert(&[0x78, 0x9a], 8).unwrap();
+        index.flush().unwrap();
+
+        // The key length is read from the header.
+        let mut index = open_opts().open(&path).unwrap();
+        assert!(index.insert(&[0x12, 0x34, 0x56], 7).is_err());
+        assert_eq!(
+            format!("{:?}", index),
+            r#"Index { len: 142, root: Disk[87] }
+Disk[1]: KeyLen { len: 2 }
+Disk[3]: InlineLeaf { key: Disk[4], link: Disk[7] }
+Disk[4]: Key { key: 12 34 }
+Disk[7]: Link { value: 5, next: None }
+Disk[10]: InlineLeaf { key: Disk[11], link: Disk[14] }
+Disk[11]: Key { key: 12 56 }
+Disk[14]: Link { value: 6, next: None }
+Disk[17]: Radix { link: None, 3: Disk[3], 5: Disk[10] }
+Disk[29]: Radix { link: None, 2: Disk[17] }
+Disk[37]: Radix { link: None, 1: Disk[29] }
+Disk[45]: Root { radix: Disk[37] }
+Disk[48]: Checksum { start: 0, end: 48, chunk_size_logarithm: 4, checksums.len(): 3 }
+Disk[80]: InlineLeaf { key: Disk[81], link: Disk[84] }
+Disk[81]: Key { key: 78 9A }
+Disk[84]: Link { value: 8, next: None }
+Disk[87]: Radix { link: None, 1: Disk[29], 7: Disk[80] }
+Disk[99]: Root { radix: Disk[87] }
+Disk[102]: Checksum { start: 48, end: 102, chunk_size_logarithm: 4, checksums.len(): 4 }
+"#
+        );
+        for (key, value) in [([0x12, 0x34], 5), ([0x12, 0x56], 6), ([0x78, 0x9a], 8)] {
+            let link = index.get(&key).unwrap();
+            assert_eq!(
+                link.values(&index).map(|v| v.unwrap()).collect::<Vec<_>>(),
+                [value]
+            );
+        }
+        assert!(index.verify_tree().is_ok());
+
+        // Keys longer than INLINE_KEY_MAX_LEN are not inlined.
+        let long_key = [0x42u8; INLINE_KEY_MAX_LEN + 1];
+        let path = dir.path().join("b");
+        let mut index = open_opts()
+            .fixed_key_len(Some(long_key.len()))
+            .open(&path)
+            .unwrap();
+        index.insert(&long_key, 1).unwrap();
+        index.flush().unwrap();
+        let index = open_opts().open(&path).unwrap();
+        assert!(format!("{:?}", index).contains("Disk[3]: Key { key: 42 42"));
+        assert_eq!(index.get(&long_key).unwrap().values(&index).count(), 1);
+
+        // Existing indexes without KEY_LEN keep supporting variable-length keys.
+        let path = dir.path().join("c");
+        let mut index = open_opts().open(&path).unwrap();
+        index.insert(&[1], 1).unwrap();
+        index.flush().unwrap();
+        let mut index = open_opts().fixed_key_len(Some(2)).open(&path).unwrap();
+        index.insert(&[1, 2, 3], 2).unwrap();
+        index.flush().unwrap();
+    }
+
+    #[test]
+    fn test_range_borrowed_keys() {
+        let dir = tempdir().unwrap();
+        let mut index = open_opts().open(dir.path().join("a")).unwrap();
+        index.insert(b"ab", 1).unwrap();
+        index.insert(b"abc", 2).unwrap();
+        index.flush().unwrap();
+
+        let buf_range = index.buf.as_ptr_range();
+        let keys: Vec<_> = index.range(..).unwrap().map(|e| e.unwrap().0).collect();
+        assert_eq!(keys, [&b"ab"[..], b"abc"]);
+        // "ab" ends at a radix entry and has to be reconstructed.
+        assert!(matches!(keys[0], Cow::Owned(_)));
+        // "abc" is borrowed from the mmap buffer.
+        match keys[1] {
+            Cow::Borrowed(key) => assert!(buf_range.contains(&key.as_ptr())),
+            Cow::Owned(_) => panic!("key should be borrowed"),
+        }
+    }
+
+    #[test]
+    fn test_track_counts() {
+        // Count keys and values by walking the tree.
+        let walk_counts = |index: &Index| -> (u64, u64) {
+            let mut counts = (0, 0);
+            for entry in index.range(..).unwrap() {
+                let (_, link) = entry.unwrap();
+                let values = link.values(index).count() as u64;
+                if values > 0 {
+                    counts = (counts.0 + 1, counts.1 + values);
+                }
+            }
+            counts
+        };
+        let check = |index: &Index| {
+            let counts = (index.key_count().unwrap(), index.value_count().unwrap());
+            assert_eq!(counts, walk_counts(index));
+        };
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let mut index = open_opts().track_counts(true).open(&path).unwrap();
+        check(&index);
+        index.insert(b"ab", 1).unwrap();
+        index.insert(b"ab", 2).unwrap();
+        index.insert(b"a", 3).unwrap();
+        index.insert(b"cd", 4).unwrap();
+        check(&index);
+        index.flush().unwrap();
+
+        let mut index = open_opts().open(&path).unwrap();
+        assert!(format!("{:?}", index).contains("keys: 3, values: 4"));
+        check(&index);
+        index.remove(b"ab").unwrap();
+        index.remove(b"xy").unwrap();
+        check(&index);
+        index.insert(b"ce", 5).unwrap();
+        index.remove_prefix(b"c").unwrap();
+        check(&index);
+
+        let link = index.get(b"a").unwrap().create(&mut index, 6);
+        index
+            .insert_advanced(InsertKey::Embed(b"a"), InsertValue::PrependReplace(7, link))
+            .unwrap();
+        index
+            .insert_advanced(InsertKey::Embed(b"ef"), InsertValue::Replace(link))
+            .unwrap();
+        check(&index);
+        index
+            .insert_advanced(
+                InsertKey::Embed(b"ef"),
+                InsertValue::Replace(LinkOffset::default()),
+            )
+            .unwrap();
+        check(&index);
+        index.flush().unwrap();
+        check(&index);
+
+        // Counts are not tracked by default.
+        let index = open_opts().open(dir.path().join("b")).unwrap();
+        assert_eq!(index.key_count(), None);
+        assert_eq!(index.value_count(), None);
+    }
+    #[test]
+    fn test_dirty_iter() {
+        let dirty_entries = |index: &Index| -> Vec<(Vec<u8>, Vec<u64>)> {
+            index
+                .dirty_iter()
+                .map(|e| {
+                    let (key, values) = e.unwrap();
+                    (key.to_vec(), values)
+                })
+                .collect()
+        };
+        let dir = tempdir().unwrap();
+        for fanout in [RadixFanout::Base16, RadixFanout::Base256] {
+            let path = dir.path().join(format!("{:?}", fanout));
+            let mut index = open_opts().radix_fanout(fanout).open(&path).unwrap();
+            assert!(dirty_entries(&index).is_empty());
+            index.insert(b"ab", 1).unwrap();
+            index.insert(b"cd", 2).unwrap();
+            index.flush().unwrap();
+            assert!(dirty_entries(&index).is_empty());
+
+            index.insert(b"cd", 3).unwrap();
+            index.insert(b"cd", 4).unwrap();
+            index.insert(b"a", 5).unwrap();
+            index.insert(b"xyz", 6).unwrap();
+            index.remove(b"ab").unwrap();
+            assert_eq!(
+                dirty_entries(&index),
+                [
+                    (b"a".to_vec(), vec![5]),
+                    (b"cd".to_vec(), vec![4, 3]),
+                    (b"xyz".to_vec(), vec![6]),
+                ]
+            );
+
+            index.flush().unwrap();
+            assert!(dirty_entries(&index).is_empty());
+        }
+    }
+    #[test]
+    fn test_open_readonly() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        assert!(Index::open_readonly(&path).is_err());
+
+        let mut index = open_opts()
+            .radix_fanout(RadixFanout::Base256)
+            .open(&path)
+            .unwrap();
+        index.insert(b"ab", 1).unwrap();
+        index.flush().unwrap();
+
+        let mut index = Index::open_readonly(&path).unwrap();
+        assert_eq!(index.get(b"ab").unwrap().values(&index).count(), 1);
+        assert_eq!(index.fanout, RadixFanout::Base256);
+        assert!(index.insert(b"cd", 2).is_err());
+        assert!(index.remove(b"ab").is_err());
+        assert!(index.flush().is_err());
+        assert!(index.try_clone().unwrap().insert(b"cd", 2).is_err());
+    }
+    #[test]
+    fn test_flush_with_fsync_policy() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let mut index = open_opts().open(&path).unwrap();
+        let policies = [
+            FsyncPolicy::Never,
+            FsyncPolicy::Data,
+            FsyncPolicy::DataAndDirectory,
+        ];
+        for (i, policy) in policies.into_iter().enumerate() {
+            index.insert(&[i as u8], i as u64).unwrap();
+            let len = index.flush_with(policy).unwrap();
+            assert_eq!(len, index.buf.len() as u64);
+        }
+
+        let index = open_opts().open(&path).unwrap();
+        for i in 0..policies.len() {
+            assert_eq!(index.get(&[i as u8]).unwrap().values(&index).count(), 1);
+        }
+        assert!(index.verify_tree().is_ok());
+    }
+    #[test]
+    fn test_write_snapshot() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let mut index = open_opts().open(&path).unwrap();
+        for i in 0..10u64 {
+            index.insert(&[i as u8], i).unwrap();
+            index.flush().unwrap();
+        }
+        index.insert(&[1], 11).unwrap();
+        index.remove([2]).unwrap();
+        index.insert(&[20, 21], 20).unwrap();
+        index.set_meta(b"meta");
+
+        let snapshot_path = dir.path().join("b");
+        let len = index.write_snapshot(&snapshot_path).unwrap();
+        assert!(len < index.buf.len() as u64);
+
+        // The original index is not changed.
+        assert_eq!(index.get_original_meta(), b"");
+        assert_eq!(index.get(&[20, 21]).unwrap().values(&index).count(), 1);
+
+        let snapshot = open_opts().open(&snapshot_path).unwrap();
+        assert_eq!(snapshot.buf.len() as u64, len);
+        assert_eq!(snapshot.get_meta(), b"meta");
+        let entries = |index: &Index| -> Vec<(Vec<u8>, Vec<u64>)> {
+            index
+                .range(..)
+                .unwrap()
+                .map(|e| {
+                    let (key, link) = e.unwrap();
+                    let values = link.values(index).map(|v| v.unwrap()).collect();
+                    (key.to_vec(), values)
+                })
+                .filter(|(_, values): &(_, Vec<u64>)| !values.is_empty())
+                .collect()
+        };
+        assert_eq!(entries(&snapshot), entries(&index));
+        assert_eq!(
+            snapshot
+                .get(&[1])
+                .unwrap()
+                .values(&snapshot)
+                .map(|v| v.unwrap())
+                .collect::<Vec<_>>(),
+            [11, 1]
+        );
+        assert!(snapshot.get(&[2]).unwrap().is_null());
+        assert!(snapshot.verify_tree().is_ok());
+    }
+    #[test]
+    fn test_shared_links() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let mut index = open_opts().open(&path).unwrap();
+        index.insert(b"a", 1).unwrap();
+        index.insert(b"a", 2).unwrap();
+        let link = index.get(b"a").unwrap();
+        index
+            .insert_advanced(InsertKey::Embed(b"b"), InsertValue::Replace(link))
+            .unwrap();
+        let values = |index: &Index, key: &[u8]| -> Vec<u64> {
+            let link = index.get(&key).unwrap();
+            link.values(index).map(|v| v.unwrap()).collect()
+        };
+        assert_eq!(values(&index, b"b"), [2, 1]);
+        index.flush().unwrap();
+        assert_eq!(values(&index, b"a"), [2, 1]);
+        assert_eq!(values(&index, b"b"), [2, 1]);
+        assert_eq!(index.get(b"a").unwrap(), index.get(b"b").unwrap());
+
+        // Identical links created for different keys are written once.
+        let keys = [[1u8; INLINE_KEY_MAX_LEN + 1], [2u8; INLINE_KEY_MAX_LEN + 1]];
+        for key in &keys {
+            index.insert(key, 7).unwrap();
+        }
+        index.flush().unwrap();
+        let links = format!("{:?}", index).matches("Link { value: 7,").count();
+        assert_eq!(links, 1);
+        for key in &keys {
+            assert_eq!(values(&index, key), [7]);
+        }
+        assert!(index.verify_tree().is_ok());
+    }
+    #[test]
+    fn test_reverse_index() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a");
+        let keys_by_value = |index: &Index, value: u64| -> Vec<Vec<u8>> {
+            let mut keys: Vec<Vec<u8>> = index
+                .keys_by_value(value)
+                .unwrap()
+                .into_iter()
+                .map(|k| k.to_vec())
+                .collect();
+            keys.sort();
+            keys
+        };
+
+        let mut index = open_opts().reverse_index(true).open(&path).unwrap();
+        index.insert(b"ab", 1).unwrap();
+        index.insert(b"cd", 1).unwrap();
+        index.insert(b"cd", 2).unwrap();
+        index.insert(b"cd", 2).unwrap();
+        assert_eq!(keys_by_value(&index, 1), [b"ab", b"cd"]);
+        assert_eq!(keys_by_value(&index, 2), [b"cd"]);
+        assert!(keys_by_value(&index, 3).is_empty());
+        index.flush().unwrap();
+
+        // The reverse index is read from disk.
+        let mut index = open_opts().open(&path).unwrap();
+        assert!(format!("{:?}", index).contains("reverse: Disk["));
+        assert_eq!(keys_by_value(&index, 1), [b"ab", b"cd"]);
+        assert!(index.verify_tree().is_ok());
+
+        index.remove(b"cd").unwrap();
+        assert_eq!(keys_by_value(&index, 1), [b"ab"]);
+        assert!(keys_by_value(&index, 2).is_empty());
+        index.insert(b"cd", 1).unwrap();
+        index.insert(b"ce", 1).unwrap();
+        index.remove_prefix(b"c").unwrap();
+        assert_eq!(keys_by_value(&index, 1), [b"ab"]);
+
+        let link = index.get(b"ab").unwrap().create(&mut index, 4);
+        index
+            .insert_advanced(
+                InsertKey::Embed(b"ef"),
+                InsertValue::PrependReplace(5, link),
+            )
+            .unwrap();
+        assert_eq!(keys_by_value(&index, 1), [b"ab", b"ef"]);
+        assert_eq!(keys_by_value(&index, 4), [b"ef"]);
+        assert_eq!(keys_by_value(&index, 5), [b"ef"]);
+        index.flush().unwrap();
+        assert!(index.verify_tree().is_ok());
+
+        // Indexes without the reverse index do not support keys_by_value.
+        let mut index = open_opts().open(dir.path().join("b")).unwrap();
+        index.insert(b"ab", 1).unwrap();
+        assert!(index.keys_by_value(1).is_err());
+        assert!(!format!("{:?}", index).contains("reverse"));
+    }
+
+    #[test]
+    fn test_verify_tree() {
+        for fanout in [RadixFanout::Base16, RadixFanout::Base256] {
+            for path_compression in [false, true] {
+                let dir = tempdir().unwrap();
+                let mut opts = open_opts();
+                opts.radix_fanout(fanout)
+                    .radix_path_compression(path_compression);
+                let mut index = opts.open(dir.path().join("a")).unwrap();
+                let report = index.verify_tree();
+                assert!(report.is_ok(), "{:?}", report);
+                assert_eq!(report.radix_count, 1);
+
+                for key in [&b"abc"[..], b"abd", b"a", b"xyz"] {
+                    index.insert(&key, 1).unwrap();
+                }
+                index.insert(&b"abc", 2).unwrap();
+                index.flush().unwrap();
+                index.insert(&b"abe", 3).unwrap();
+                index.insert(&b"xyz", 4).unwrap();
+
+                for index in [index.try_clone_without_dirty().unwrap(), index] {
+                    let report = index.verify_tree();
+                    assert!(report.is_ok(), "{:?}", report);
+                    let value_count: usize = index
+                        .range(..)
+                        .unwrap()
+                        .map(|e| e.unwrap().1.values(&index).count())
+                        .sum();
+                    assert_eq!(report.link_count, value_count);
+                }
+            }
+        }
+
+        // External keys use INLINE_LEAF entries.
+        let buf = Arc::new(vec![0x12u8, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+        let dir = tempdir().unwrap();
+        let mut index = open_opts()
+            .key_buf(Some(buf))
+            .open(dir.path().join("a"))
+            .unwrap();
+        for (start, len) in [(1, 2), (1, 3), (2, 1)] {
+            index
+                .insert_advanced(InsertKey::Reference((start, len)), InsertValue::Prepend(5))
+                .unwrap();
+        }
+        index.flush().unwrap();
+        let report = index.verify_tree();
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!((report.leaf_count, report.link_count), (2, 3));
+    }
+
+    #[test]
+    fn test_verify_tree_problems() {
+        let mut index = in_memory_index();
+        index.insert(&b"ab", 1).unwrap();
+        index.insert(&b"ab", 2).unwrap();
+        index.insert(&b"ac", 3).unwrap();
+        assert!(index.verify_tree().is_ok());
+
+        // Key does not match its path.
+        let mut broken = index.try_clone().unwrap();
+        broken.dirty_keys[0].key = b"xy".to_vec().into_boxed_slice();
+        let report = broken.verify_tree();
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].message.contains("does not match"));
+
+        // Cycle in the LINK chain.
+        let mut broken = index.try_clone().unwrap();
+        broken.dirty_links[0].next_link_offset = LinkOffset::from_dirty_index(1);
+        let report = broken.verify_tree();
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].message.contains("cycle"));
+
+        // Out of bounds child.
+        let mut broken = index.try_clone().unwrap();
+        broken.dirty_radixes[0].offsets[9] = LeafOffset::from_dirty_index(100).0;
+        let report = broken.verify_tree();
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].message.contains("out of bounds"));
+    }
+
     #[test]
     fn test_root_meta() {
         let dir = tempdir().unwrap();
@@ -4556,8 +7069,15 @@ Disk[410]: Root { radix: Disk[402] }
         index.set_meta(&meta);
         assert_eq!(index.get_meta(), &meta[..]);
         index.flush().expect("flush");
-        let index = open_opts().open(dir.path().join("a")).expect("open");
+        let mut index = open_opts().open(dir.path().join("a")).expect("open");
         assert_eq!(index.get_meta(), &meta[..]);
+        assert_eq!(index.root_metadata(), &meta[..]);
+
+        // root_metadata() only reflects flushed ROOT entries.
+        index.set_meta(b"offset 10");
+        assert_eq!(index.root_metadata(), &meta[..]);
+        index.flush().expect("flush");
+        assert_eq!(index.root_metadata(), b"offset 10");
     }
 
     impl<'a> RangeIter<'a> {
@@ -4614,8 +7134,19 @@ Disk[410]: Root { radix: Disk[402] }
 
     /// Test `Index::range` against `BTreeSet::range`. `tree` specifies keys.
     fn test_range_against_btreeset(tree: BTreeSet<&[u8]>) {
+        for fanout in [RadixFanout::Base16, RadixFanout::Base256] {
+            for path_compression in [false, true] {
+                let mut opts = open_opts();
+                opts.radix_fanout(fanout)
+                    .radix_path_compression(path_compression);
+                test_range_against_btreeset_with_opts(tree.clone(), &opts);
+            }
+        }
+    }
+
+    fn test_range_against_btreeset_with_opts(tree: BTreeSet<&[u8]>, opts: &OpenOptions) {
         let dir = tempdir().unwrap();
-        let mut index = open_opts().open(dir.path().join("a")).unwrap();
+        let mut index = opts.open(dir.path().join("a")).unwrap();
         let keys: Vec<&[u8]> = tree.iter().cloned().collect();
         for (i, key) in keys.iter().enumerate() {
             index.insert(key, i as u64).unwrap();
@@ -4706,6 +7237,45 @@ Disk[410]: Root { radix: Disk[402] }
         );
     }
 
+    #[test]
+    fn test_range_integer_keys() {
+        let dir = tempdir().unwrap();
+        let mut index = open_opts().open(dir.path().join("a")).unwrap();
+        for i in [3u64, 256, 1000, 70000, 1 << 40] {
+            index.insert(&i.to_be_bytes(), i).unwrap();
+        }
+        index.flush().unwrap();
+
+        let query = |range: (Bound<u64>, Bound<u64>), rev: bool| -> Vec<u64> {
+            let start = range.0.map(u64::to_be_bytes);
+            let end = range.1.map(u64::to_be_bytes);
+            let start = start.as_ref().map(|v| &v[..]);
+            let end = end.as_ref().map(|v| &v[..]);
+            let iter = index.range((start, end)).unwrap();
+            let extract = |e: crate::Result<(Cow<[u8]>, LinkOffset)>| {
+                let (key, _) = e.unwrap();
+                u64::from_be_bytes(<[u8; 8]>::try_from(key.as_ref()).unwrap())
+            };
+            if rev {
+                iter.rev().map(extract).collect()
+            } else {
+                iter.map(extract).collect()
+            }
+        };
+
+        assert_eq!(query((Included(256), Excluded(70000)), false), [256, 1000]);
+        assert_eq!(
+            query((Excluded(256), Included(70000)), false),
+            [1000, 70000]
+        );
+        assert_eq!(
+            query((Included(4), Unbounded), true),
+            [1 << 40, 70000, 1000, 256]
+        );
+        assert_eq!(query((Unbounded, Excluded(1000)), true), [256, 3]);
+        assert!(query((Excluded(3), Excluded(256)), false).is_empty());
+    }
+
     #[test]
     fn test_clear_dirty() {
         let dir = tempdir().unwrap();
@@ -4731,6 +7301,49 @@ Disk[410]: Root { radix: Disk[402] }
         assert!(index.get(&"bar").unwrap().is_null());
     }
 
+    #[test]
+    fn test_checkpoint_rollback() {
+        let dir = tempdir().unwrap();
+        let mut index = open_opts().open(dir.path().join("a")).unwrap();
+
+        index.insert(&"foo", 1).unwrap();
+        index.set_meta(b"x");
+        let checkpoint = index.checkpoint();
+
+        index.insert(&"foo", 2).unwrap();
+        index.insert(&"foobar", 3).unwrap();
+        index.remove("foo").unwrap();
+        index.set_meta(b"y");
+        index.rollback(checkpoint).unwrap();
+
+        assert_eq!(index.get_meta(), b"x");
+        assert_eq!(
+            index
+                .get(&"foo")
+                .unwrap()
+                .values(&index)
+                .collect::<crate::Result<Vec<_>>>()
+                .unwrap(),
+            [1]
+        );
+        assert!(index.get(&"foobar").unwrap().is_null());
+
+        // Checkpoints taken before flush cannot be used.
+        let checkpoint = index.checkpoint();
+        index.flush().unwrap();
+        index.insert(&"bar", 4).unwrap();
+        assert!(index.rollback(checkpoint).is_err());
+        assert!(!index.get(&"bar").unwrap().is_null());
+
+        // Checkpoints taken after flush work.
+        let checkpoint = index.checkpoint();
+        index.insert(&"baz", 5).unwrap();
+        index.rollback(checkpoint).unwrap();
+        assert!(!index.get(&"bar").unwrap().is_null());
+        assert!(index.get(&"baz").unwrap().is_null());
+        assert!(!index.get(&"foo").unwrap().is_null());
+    }
+
     #[test]
     fn test_meta_only_flush() {
         let dir = tempdir().unwrap();
@@ -4810,10 +7423,13 @@ Disk[410]: Root { radix: Disk[402] }
             true
         }
 
-        fn test_deletion(keys_deleted: Vec<(Vec<u8>, bool)>) -> bool {
+        fn test_deletion(keys_deleted: Vec<(Vec<u8>, bool)>, path_compression: bool) -> bool {
             // Compare Index with BTreeSet
             let mut set = BTreeSet::<Vec<u8>>::new();
-            let mut index = in_memory_index();
+            let mut index = OpenOptions::new()
+                .radix_path_compression(path_compression)
+                .create_in_memory()
+                .unwrap();
             keys_deleted.into_iter().all(|(key, deleted)| {
                 if deleted {
                     set.remove(&key);
@@ -4831,9 +7447,12 @@ Disk[410]: Root { radix: Disk[402] }
             })
         }
 
-        fn test_deletion_prefix(keys_deleted: Vec<(Vec<u8>, bool)>) -> bool {
+        fn test_deletion_prefix(keys_deleted: Vec<(Vec<u8>, bool)>, path_compression: bool) -> bool {
             let mut set = BTreeSet::<Vec<u8>>::new();
-            let mut index = in_memory_index();
+            let mut index = OpenOptions::new()
+                .radix_path_compression(path_compression)
+                .create_in_memory()
+                .unwrap();
             keys_deleted.into_iter().all(|(key, deleted)| {
                 if deleted {
                     // BTreeSet does not have remove_prefix. Emulate it.
@@ -441,6 +441,10 @@ impl Log {
     /// Even if [`Log::append`] is never called, this function has a side effect
     /// updating the [`Log`] to contain latest entries on disk.
     ///
+    /// Entries appended by other [`Log`]s (possibly in other processes) since
+    /// the last sync are ordered before the in-memory entries of this [`Log`].
+    /// Neither side is lost.
+    ///
     /// Other [`Log`] instances living in a same process or other processes won't
     /// be notified about the change and they can only access the data
     /// "snapshotted" at open time.
@@ -644,7 +648,7 @@ impl Log {
                     // This is needed because `Log::append` updated indexes in-memory but
                     // did not update their metadata for performance. This is to update
                     // the metadata stored in Indexes.
-                    Self::set_index_log_len(self.indexes.iter_mut(), meta.primary_len);
+                    Self::set_index_log_len(self.indexes.iter_mut(), meta.primary_len, meta.epoch);
                     Some(&self.indexes)
                 },
                 self.open_options.fsync,
@@ -893,6 +897,8 @@ impl Log {
                             def,
                             &self.disk_buf,
                             self.meta.primary_len,
+                            self.meta.epoch,
+                            self.open_options.verify_checksum,
                         )?;
                         index.flush()?
                     };
@@ -1203,6 +1209,8 @@ impl Log {
                 def,
                 &self.disk_buf,
                 self.meta.primary_len,
+                self.meta.epoch,
+                self.open_options.verify_checksum,
             )?;
         }
         Ok(())
@@ -1214,6 +1222,8 @@ impl Log {
         def: &IndexDef,
         disk_buf: &Bytes,
         primary_len: u64,
+        epoch: u64,
+        verify: bool,
     ) -> crate::Result<usize> {
         // The index meta is used to store the next offset the index should be built.
         let mut offset = Self::get_index_log_len(index, true)?;
@@ -1221,7 +1231,7 @@ impl Log {
         let mut count = 0;
         // PERF: might be worthwhile to cache xxhash verification result.
         while let Some(entry_result) =
-            Self::read_entry_from_buf(path, disk_buf, offset).context(|| {
+            Self::read_entry_from_buf(path, disk_buf, offset, verify).context(|| {
                 format!(
                     "while updating index {:?} for on-disk entry at {}",
                     def.name, offset
@@ -1255,7 +1265,7 @@ impl Log {
             offset = entry_result.next_offset;
         }
         // The index now contains all entries. Write "next_offset" as the index meta.
-        Self::set_index_log_len(std::iter::once(index), primary_len);
+        Self::set_index_log_len(std::iter::once(index), primary_len, epoch);
 
         Ok(count)
     }
@@ -1336,13 +1346,16 @@ impl Log {
                 let mut indexes = Vec::with_capacity(index_defs.len());
                 for def in index_defs.iter() {
                     let index_len = meta.indexes.get(&def.metaname()).cloned().unwrap_or(0);
-                    indexes.push(Self::load_index(
-                        dir,
-                        def,
-                        index_len,
-                        key_buf.clone(),
-                        fsync,
-                    )?);
+                    let mut index = Self::load_index(dir, def, index_len, key_buf.clone(), fsync)?;
+                    if let Some(epoch) = Self::get_index_epoch(&index) {
+                        if epoch != meta.epoch {
+                            // The index was built for a different version of
+                            // the log (ex. before repair). Rebuild it like a
+                            // new index.
+                            index = Self::load_index(dir, def, 0, key_buf.clone(), fsync)?;
+                        }
+                    }
+                    indexes.push(index);
                 }
                 indexes
             }
@@ -1415,7 +1428,8 @@ impl Log {
     /// integrity-check failed.
     fn read_entry(&self, offset: u64) -> crate::Result<Option<EntryResult>> {
         let result = if offset < self.meta.primary_len {
-            let entry = Self::read_entry_from_buf(&self.dir, &self.disk_buf, offset)?;
+            let verify = self.open_options.verify_checksum;
+            let entry = Self::read_entry_from_buf(&self.dir, &self.disk_buf, offset, verify)?;
             if let Some(ref entry) = entry {
                 crate::page_out::adjust_available(-(entry.data.len() as i64));
             }
@@ -1425,19 +1439,21 @@ impl Log {
             if offset >= self.mem_buf.len() as u64 {
                 return Ok(None);
             }
-            Self::read_entry_from_buf(&self.dir, &self.mem_buf, offset)?
+            let verify = self.open_options.verify_checksum;
+            Self::read_entry_from_buf(&self.dir, &self.mem_buf, offset, verify)?
                 .map(|entry_result| entry_result.offset(self.meta.primary_len))
         };
         Ok(result)
     }
 
-    /// Read an entry at the given offset of the given buffer. Verify its integrity. Return the
-    /// data, the real data offset, and the next entry offset. Return None if the offset is at
-    /// the end of the buffer.  Raise errors if there are integrity check issues.
+    /// Read an entry at the given offset of the given buffer. Verify its integrity if `verify`
+    /// is set. Return the data, the real data offset, and the next entry offset. Return None if
+    /// the offset is at the end of the buffer.  Raise errors if there are integrity check issues.
     fn read_entry_from_buf<'a>(
         path: &GenericPath,
         buf: &'a [u8],
         offset: u64,
+        verify: bool,
     ) -> crate::Result<Option<EntryResult<'a>>> {
         let data_error = |msg: String| -> crate::Error {
             match path.as_opt_path() {
@@ -1511,6 +1527,7 @@ impl Log {
         let data = &buf[offset as usize..end as usize];
 
         let verified = match checksum_flags {
+            _ if !verify => true,
             0 => true,
             ENTRY_FLAG_HAS_XXHASH64 => xxhash(data) == checksum,
             ENTRY_FLAG_HAS_XXHASH32 => xxhash32(data) as u64 == checksum,
@@ -1581,12 +1598,24 @@ impl Log {
         })
     }
 
-    /// Update the log length (in bytes) covered by the given indexes.
+    /// Get the log epoch the index was built for.
+    ///
+    /// Return `None` if the index is new, or was written by an older version
+    /// that does not record the epoch.
+    fn get_index_epoch(index: &Index) -> Option<u64> {
+        let index_meta = index.get_meta();
+        let (_len, vlq_len): (u64, _) = index_meta.read_vlq_at(0).ok()?;
+        let epoch = index_meta.get(vlq_len..vlq_len + 8)?;
+        Some(LittleEndian::read_u64(epoch))
+    }
+
+    /// Update the log length (in bytes) and epoch covered by the given indexes.
     ///
-    /// `len` is usually `meta.primary_len`.
-    fn set_index_log_len<'a>(indexes: impl Iterator<Item = &'a mut Index>, len: u64) {
+    /// `len` and `epoch` are usually `meta.primary_len` and `meta.epoch`.
+    fn set_index_log_len<'a>(indexes: impl Iterator<Item = &'a mut Index>, len: u64, epoch: u64) {
         let mut index_meta = Vec::new();
         index_meta.write_vlq(len).unwrap();
+        index_meta.write_u64::<LittleEndian>(epoch).unwrap();
         for index in indexes {
             index.set_meta(&index_meta);
         }
@@ -123,6 +123,7 @@ pub struct OpenOptions {
     pub(crate) fold_defs: Vec<FoldDef>,
     pub(crate) create: bool,
     pub(crate) checksum_type: ChecksumType,
+    pub(crate) verify_checksum: bool,
     pub(crate) flush_filter: Option<FlushFilterFunc>,
     pub(crate) fsync: bool,
     pub(crate) auto_sync_threshold: Option<u64>,
@@ -242,6 +243,7 @@ impl OpenOptions {
             index_defs: Vec::new(),
             fold_defs: Vec::new(),
             checksum_type: ChecksumType::Auto,
+            verify_checksum: true,
             flush_filter: None,
             fsync: false,
             auto_sync_threshold: None,
@@ -308,6 +310,16 @@ impl OpenOptions {
         self
     }
 
+    /// Sets whether to verify entry checksums on read.
+    ///
+    /// Verification is enabled by default. Disabling it saves hashing cost
+    /// for trusted data on hot paths, at the cost of not detecting silent
+    /// corruption. [`OpenOptions::repair`] always verifies checksums.
+    pub fn verify_checksum(mut self, verify_checksum: bool) -> Self {
+        self.verify_checksum = verify_checksum;
+        self
+    }
+
     /// Sets the flush filter function.
     ///
     /// The function will be called at [`Log::sync`] time, if there are
@@ -536,6 +548,7 @@ impl fmt::Debug for OpenOptions {
         write!(f, "fsync: {}, ", self.fsync)?;
         write!(f, "create: {}, ", self.create)?;
         write!(f, "checksum_type: {:?}, ", self.checksum_type)?;
+        write!(f, "verify_checksum: {}, ", self.verify_checksum)?;
         write!(f, "auto_sync_threshold: {:?}, ", self.auto_sync_threshold)?;
         let flush_filter_desc = match self.flush_filter {
             Some(ref _buf) => "Some(_)",
@@ -150,6 +150,8 @@ impl OpenOptions {
                 })
                 .context("cannot open log for repair")?;
 
+            // Repair is about finding corruption. Always verify checksums.
+            log.open_options.verify_checksum = true;
             let mut iter = log.iter();
 
             // Read entries until hitting a checksum error.
@@ -150,6 +150,34 @@ fn test_checksum_type() {
     );
 }
 
+#[test]
+fn test_verify_checksum() {
+    let dir = tempdir().unwrap();
+    let log_path = dir.path().join("log");
+    let mut log = Log::open(&log_path, Vec::new()).unwrap();
+    log.append(b"abc").unwrap();
+    log.sync().unwrap();
+    pwrite(&log_path.join(PRIMARY_FILE), -1, b"d");
+
+    // Corrupted data is detected by default.
+    let log = Log::open(&log_path, Vec::new()).unwrap();
+    assert!(log.iter().next().unwrap().is_err());
+
+    // Skip verification. The corrupted data is returned as-is.
+    let log = OpenOptions::new()
+        .verify_checksum(false)
+        .open(&log_path)
+        .unwrap();
+    assert_eq!(log.iter().next().unwrap().unwrap(), b"abd");
+
+    // Repair still verifies checksums.
+    let message = OpenOptions::new()
+        .verify_checksum(false)
+        .repair(&log_path)
+        .unwrap();
+    assert!(message.contains("Verified first 0 entries"), "{}", message);
+}
+
 #[test]
 fn test_iter_and_iter_dirty() {
     let dir = tempdir().unwrap();
@@ -622,7 +650,7 @@ fn test_update_index_upon_open() {
     log.sync().unwrap();
     assert_eq!(
         get_index_size(),
-        47,
+        55,
         "index should not be empty as 4 entries exceed lag threshold 3"
     );
 
@@ -631,7 +659,7 @@ fn test_update_index_upon_open() {
     log.sync().unwrap();
     assert_eq!(
         get_index_size(),
-        47,
+        55,
         "index should not be changed because 2 new entries are within lag threshold 3"
     );
 
@@ -641,7 +669,7 @@ fn test_update_index_upon_open() {
     let _log = open_opts.open(dir.path()).unwrap();
     assert_eq!(
         get_index_size(),
-        84,
+        100,
         "index should be changed because 2 pending entries exceeds lag threshold 1"
     );
 
@@ -653,7 +681,7 @@ fn test_update_index_upon_open() {
     let _log = open_opts.open(dir.path()).unwrap();
     assert_eq!(
         dir.path().join(index_filename).metadata().unwrap().len(),
-        53,
+        61,
         "new index should be built at open time since 6 entries exceeds threshold 4"
     );
 
@@ -669,6 +697,72 @@ fn test_update_index_upon_open() {
     );
 }
 
+#[test]
+fn test_independent_index_lag() {
+    // Each index tracks its own progress. A lagging index does not affect
+    // others, and a new index catches up on open.
+    let dir = tempdir().unwrap();
+    let def_a = IndexDef::new("a", |_| vec![IndexOutput::Reference(0..1)]).lag_threshold(0);
+    let def_b = IndexDef::new("b", |_| vec![IndexOutput::Reference(1..2)]).lag_threshold(1 << 20);
+    let open_opts = OpenOptions::new()
+        .create(true)
+        .index_defs(vec![def_a.clone(), def_b.clone()]);
+    let mut log = open_opts.open(dir.path()).unwrap();
+    log.append(b"xy").unwrap();
+    log.append(b"yz").unwrap();
+    log.sync().unwrap();
+    let index_a_path = dir.path().join(def_a.filename());
+    let index_b_path = dir.path().join(def_b.filename());
+    assert!(index_a_path.metadata().unwrap().len() > 0);
+    assert_eq!(index_b_path.metadata().unwrap().len(), 0);
+
+    // The lagging index "b" still answers queries after reopen.
+    drop(log);
+    let log = open_opts.open(dir.path()).unwrap();
+    assert_eq!(log.lookup(0, b"y").unwrap().count(), 1);
+    assert_eq!(log.lookup(1, b"y").unwrap().count(), 1);
+    assert_eq!(index_b_path.metadata().unwrap().len(), 0);
+
+    // Add index "c" to the existing log.
+    drop(log);
+    let def_c = IndexDef::new("c", |_| vec![IndexOutput::Reference(0..2)]).lag_threshold(0);
+    let open_opts = OpenOptions::new()
+        .create(true)
+        .index_defs(vec![def_a, def_b, def_c]);
+    let log = open_opts.open(dir.path()).unwrap();
+    assert_eq!(log.lookup(2, b"yz").unwrap().count(), 1);
+}
+
+#[test]
+fn test_rebuild_index_on_epoch_change() {
+    let dir = tempdir().unwrap();
+    let def = IndexDef::new("a", |_| vec![IndexOutput::Reference(0..1)]).lag_threshold(0);
+    let index_path = dir.path().join(def.filename());
+    let open_opts = OpenOptions::new().create(true).index_defs(vec![def]);
+    let mut log = open_opts.open(dir.path()).unwrap();
+    log.append(b"ab").unwrap();
+    log.append(b"bc").unwrap();
+    log.sync().unwrap();
+    drop(log);
+    let index_size = index_path.metadata().unwrap().len();
+
+    // Pretend the log was replaced. The index no longer matches the log.
+    let meta_path = dir.path().join(META_FILE);
+    let mut meta = LogMetadata::read_file(&meta_path).unwrap();
+    meta.epoch ^= 1;
+    meta.write_file(&meta_path, false).unwrap();
+
+    // The index is rebuilt (appended) at open time.
+    let log = open_opts.open(dir.path()).unwrap();
+    assert!(index_path.metadata().unwrap().len() > index_size);
+    assert_eq!(log.lookup(0, b"b").unwrap().count(), 1);
+
+    // Once rebuilt, it is reused.
+    let index_size = index_path.metadata().unwrap().len();
+    let _log = open_opts.open(dir.path()).unwrap();
+    assert_eq!(index_path.metadata().unwrap().len(), index_size);
+}
+
 #[test]
 fn test_flush_filter() {
     let dir = tempdir().unwrap();
@@ -797,6 +891,31 @@ fn test_sync_fast_paths() {
     }
 }
 
+#[test]
+fn test_sync_merge_order() {
+    // Entries synced by another Log come before local in-memory entries.
+    let dir = tempdir().unwrap();
+    let open_opts = OpenOptions::new().create(true).index_defs(vec![
+        IndexDef::new("i", |_| vec![IndexOutput::Reference(0..2)]),
+    ]);
+    let mut log1 = open_opts.open(dir.path()).unwrap();
+    let mut log2 = open_opts.open(dir.path()).unwrap();
+
+    log1.append(b"1a").unwrap();
+    log2.append(b"2a").unwrap();
+    log2.append(b"2b").unwrap();
+    log2.sync().unwrap();
+    log1.append(b"1b").unwrap();
+    log1.sync().unwrap();
+    log2.sync().unwrap();
+
+    for log in [&log1, &log2] {
+        let entries: Vec<&[u8]> = log.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(entries, [b"2a", b"2b", b"1a", b"1b"]);
+        assert_eq!(log.lookup_range(0, ..).unwrap().count(), 4);
+    }
+}
+
 #[test]
 fn test_auto_sync_threshold() {
     let dir = tempdir().unwrap();
@@ -1281,12 +1400,12 @@ Rebuilt index "c""#
         log.append(&[b'z'; 50_000][..]).unwrap();
         log.sync().unwrap();
         assert_eq!(len(PRIMARY_FILE), PRIMARY_START_OFFSET + 150036);
-        assert_eq!(len(index_file), 100);
+        assert_eq!(len(index_file), 116);
     };
     let delete_content = || {
         open_opts.delete_content(path).unwrap();
         assert_eq!(len(PRIMARY_FILE), PRIMARY_START_OFFSET);
-        assert_eq!(len(index_file), 25);
+        assert_eq!(len(index_file), 33);
         // Check SIGBUS
         try_trigger_sigbus();
         // Check log is empty
@@ -6,6 +6,13 @@
  */
 
 //! Atomic `sync` support for multiple [`Log`]s.
+//!
+//! Each [`Log`] has its own metadata (length, epoch, index lengths). A
+//! [`MultiLog`] keeps a copy of them in a single "multimeta" that is written
+//! atomically by [`MultiLog::write_meta`]. Readers load [`Log`]s using the
+//! multimeta, not per-log metadata. So changes to several [`Log`]s become
+//! visible together, or not at all if the process crashes before
+//! `write_meta`.
 
 use std::collections::BTreeMap;
 use std::collections::HashMap;
@@ -712,6 +719,50 @@ mod tests {
         assert_eq!(mlog2[1].iter().count(), 0);
     }
 
+    #[test]
+    fn test_interrupted_transaction_is_invisible() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut mlog = simple_multilog(path);
+        mlog[0].append(b"1").unwrap();
+        mlog[1].append(b"x").unwrap();
+        mlog.sync().unwrap();
+
+        // Start a transaction touching both logs, but "crash" before
+        // write_meta.
+        let lock = mlog.lock().unwrap();
+        mlog[0].append(b"2").unwrap();
+        mlog[0].sync().unwrap();
+        mlog[1].append(b"y").unwrap();
+        mlog[1].sync().unwrap();
+        drop(lock);
+        drop(mlog);
+
+        // Neither change is visible.
+        let mut mlog = simple_multilog(path);
+        assert_eq!(mlog[0].iter().count(), 1);
+        assert_eq!(mlog[1].iter().count(), 1);
+
+        // A later transaction commits both changes.
+        let lock = mlog.lock().unwrap();
+        mlog[0].append(b"3").unwrap();
+        mlog[0].sync().unwrap();
+        mlog[1].append(b"z").unwrap();
+        mlog[1].sync().unwrap();
+        mlog.write_meta(&lock).unwrap();
+        drop(lock);
+
+        let mlog = simple_multilog(path);
+        assert_eq!(
+            mlog[0].iter().collect::<Result<Vec<_>, _>>().unwrap(),
+            [b"1", b"3"]
+        );
+        assert_eq!(
+            mlog[1].iter().collect::<Result<Vec<_>, _>>().unwrap(),
+            [b"x", b"z"]
+        );
+    }
+
     #[test]
     fn test_version() {
         let dir = tempfile::tempdir().unwrap();
@@ -794,11 +845,11 @@ mod tests {
 
         // Open one time, index is built on demand.
         let _mlog = mopts.open(path).unwrap();
-        assert_eq!(index_size(), 36);
+        assert_eq!(index_size(), 44);
 
         // Open another time, index is reused.
         let mut mlog = mopts.open(path).unwrap();
-        assert_eq!(index_size(), 36);
+        assert_eq!(index_size(), 44);
 
         // Force updating epoch to make multimeta and per-log meta incompatible.
         let lock = LockGuard(ScopedDirLock::new(path).unwrap());
@@ -811,7 +862,7 @@ mod tests {
 
         // The index is rebuilt (appended) at open time because of incompatible meta.
         let _mlog = mopts.open(path).unwrap();
-        assert_eq!(index_size(), 71);
+        assert_eq!(index_size(), 87);
     }
 
     #[test]
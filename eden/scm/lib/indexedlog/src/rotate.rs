@@ -65,6 +65,7 @@ const LATEST_FILE: &str = "latest";
 #[derive(Clone)]
 pub struct OpenOptions {
     pub(crate) max_bytes_per_log: u64,
+    pub(crate) max_entries_per_log: Option<u64>,
     pub(crate) max_log_count: u8,
     pub(crate) log_open_options: log::OpenOptions,
     pub(crate) auto_sync_threshold: Option<u64>,
@@ -76,7 +77,7 @@ impl OpenOptions {
     ///
     /// The default values are:
     /// - Keep 2 logs.
-    /// - A log gets rotated when it exceeds 2GB.
+    /// - A log gets rotated when it exceeds 2GB. No limit on entry count.
     /// - No indexes.
     /// - Do not create on demand.
     /// - Do not sync automatically on append().
@@ -86,6 +87,7 @@ impl OpenOptions {
         let max_bytes_per_log = 2_000_000_000; // 2 GB
         Self {
             max_bytes_per_log,
+            max_entries_per_log: None,
             max_log_count,
             log_open_options: log::OpenOptions::new(),
             auto_sync_threshold: None,
@@ -108,6 +110,18 @@ impl OpenOptions {
         self
     }
 
+    /// Set the maximum entry count per [`Log`].
+    ///
+    /// A log gets rotated when it exceeds either this or
+    /// [`OpenOptions::max_bytes_per_log`]. Counting entries requires scanning
+    /// the writable log on sync.
+    pub fn max_entries_per_log(mut self, count: impl Into<Option<u64>>) -> Self {
+        let count = count.into();
+        assert!(count != Some(0));
+        self.max_entries_per_log = count;
+        self
+    }
+
     /// Sets the checksum type.
     ///
     /// See [log::ChecksumType] for details.
@@ -116,6 +130,14 @@ impl OpenOptions {
         self
     }
 
+    /// Sets whether to verify entry checksums on read.
+    ///
+    /// See [log::OpenOptions::verify_checksum] for details.
+    pub fn verify_checksum(mut self, verify_checksum: bool) -> Self {
+        self.log_open_options = self.log_open_options.verify_checksum(verify_checksum);
+        self
+    }
+
     /// Set whether create the [`RotateLog`] structure if it does not exist.
     pub fn create(mut self, create: bool) -> Self {
         self.log_open_options = self.log_open_options.create(create);
@@ -341,6 +363,7 @@ impl fmt::Debug for OpenOptions {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "OpenOptions {{ ")?;
         write!(f, "max_bytes_per_log: {}, ", self.max_bytes_per_log)?;
+        write!(f, "max_entries_per_log: {:?}, ", self.max_entries_per_log)?;
         write!(f, "max_log_count: {}, ", self.max_log_count)?;
         write!(f, "auto_sync_threshold: {:?}, ", self.auto_sync_threshold)?;
         write!(f, "log_open_options: {:?} }}", &self.log_open_options)?;
@@ -517,7 +540,11 @@ impl RotateLog {
                     func();
                 }
 
-                if size >= self.open_options.max_bytes_per_log {
+                let too_many_entries = match self.open_options.max_entries_per_log {
+                    Some(max_entries) => self.writable_log().iter().count() as u64 >= max_entries,
+                    None => false,
+                };
+                if size >= self.open_options.max_bytes_per_log || too_many_entries {
                     // `self.writable_log()` will be rotated (i.e., becomes immutable).
                     // Make sure indexes are up-to-date so reading it would not require
                     // building missing indexes in-memory.
@@ -1059,6 +1086,36 @@ mod tests {
         assert!(!dir.path().join("0").exists());
     }
 
+    #[test]
+    fn test_rotate_by_entry_count() {
+        let dir = tempdir().unwrap();
+        let mut rotate = OpenOptions::new()
+            .create(true)
+            .max_entries_per_log(3)
+            .max_log_count(2)
+            .index("first-byte", |_| vec![IndexOutput::Reference(0..1)])
+            .open(&dir)
+            .unwrap();
+
+        // No rotate.
+        rotate.append(b"a").unwrap();
+        rotate.append(b"a").unwrap();
+        assert_eq!(rotate.sync().unwrap(), 0);
+
+        // Trigger rotate. "a" is still accessible.
+        rotate.append(b"b").unwrap();
+        assert_eq!(rotate.sync().unwrap(), 1);
+        assert_eq!(lookup(&rotate, b"a").len(), 2);
+
+        // Trigger rotate again. Older entries are dropped.
+        for _ in 0..3 {
+            rotate.append(b"c").unwrap();
+        }
+        assert_eq!(rotate.sync().unwrap(), 2);
+        assert_eq!(lookup(&rotate, b"a").len(), 0);
+        assert_eq!(lookup(&rotate, b"c").len(), 3);
+    }
+
     #[test]
     fn test_manual_remove_old_logs() {
         let dir = tempdir().unwrap();
@@ -1506,7 +1563,7 @@ mod tests {
         // The "current" log is still mutable. Its index respects lag_threshold,
         // and is logically empty (because side effect of delete_content, the
         // index has some bytes in it).
-        assert_eq!(size("2/index2-idx"), 25);
+        assert_eq!(size("2/index2-idx"), 33);
         assert!(size("2/log") < 100);
     }
 
@@ -114,6 +114,21 @@ pub fn open_dir(lock_path: impl AsRef<Path>) -> io::Result<File> {
     }
 }
 
+/// Fsync a directory so entries created in it are durable.
+///
+/// This is a no-op on Windows, which does not support fsync on directories.
+pub(crate) fn fsync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        File::open(dir)?.sync_all()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        Ok(())
+    }
+}
+
 #[inline]
 pub fn xxhash<T: AsRef<[u8]>>(buf: T) -> u64 {
     let mut xx = XxHash::default();
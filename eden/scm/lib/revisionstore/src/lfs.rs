@@ -1521,6 +1521,8 @@ impl LfsRemoteInner {
                     )
                     .map(Some)
                     .right_future(),
+                    // A successful upload is all we need, so there is nothing to verify.
+                    Operation::Verify => continue,
                 };
 
                 futures.push(with_client_request_info_scope(
@@ -89,6 +89,32 @@ pub trait VLQDecode<T> {
     /// assert_eq!(x.unwrap(), 1000i32);
     /// ```
     fn read_vlq(&mut self) -> io::Result<T>;
+
+    /// Similar to `read_vlq`, but return `Ok(None)` if the stream ends before
+    /// the first byte. This is useful for scanning a stream of VLQ integers
+    /// until its end.
+    ///
+    /// Reaching the end of the stream in the middle of an integer is still an
+    /// `UnexpectedEof` error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vlqencoding::VLQDecode;
+    /// use std::io::{Cursor,ErrorKind};
+    ///
+    /// let mut c = Cursor::new(vec![120u8, 211]);
+    ///
+    /// let x: Option<u64> = c.try_read_vlq().unwrap();
+    /// assert_eq!(x, Some(120));
+    ///
+    /// let x: Result<Option<u64>, _> = c.try_read_vlq();
+    /// assert_eq!(x.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    ///
+    /// let x: Option<u64> = c.try_read_vlq().unwrap();
+    /// assert_eq!(x, None);
+    /// ```
+    fn try_read_vlq(&mut self) -> io::Result<Option<T>>;
 }
 
 pub trait VLQDecodeAt<T> {
@@ -147,12 +173,24 @@ macro_rules! impl_unsigned_primitive {
 
         impl<R: Read + ?Sized> VLQDecode<$T> for R {
             fn read_vlq(&mut self) -> io::Result<$T> {
+                self.try_read_vlq()?
+                    .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+            }
+
+            fn try_read_vlq(&mut self) -> io::Result<Option<$T>> {
                 let mut buf = [0u8];
                 let mut value = 0 as $T;
                 let mut base = 1 as $T;
                 let base_multiplier = (1 << 7) as $T;
+                let mut first = true;
                 loop {
-                    self.read_exact(&mut buf)?;
+                    match self.read_exact(&mut buf) {
+                        Err(e) if first && e.kind() == io::ErrorKind::UnexpectedEof => {
+                            return Ok(None);
+                        }
+                        result => result?,
+                    }
+                    first = false;
                     let byte = buf[0];
                     value = ($T::from(byte & 127))
                         .checked_mul(base)
@@ -165,7 +203,7 @@ macro_rules! impl_unsigned_primitive {
                         .checked_mul(base_multiplier)
                         .ok_or(io::ErrorKind::InvalidData)?;
                 }
-                Ok(value)
+                Ok(Some(value))
             }
         }
 
@@ -217,6 +255,11 @@ macro_rules! impl_signed_primitive {
             fn read_vlq(&mut self) -> io::Result<$T> {
                 (self.read_vlq() as Result<$U, _>).map(|n| ((n >> 1) as $T) ^ -((n & 1) as $T))
             }
+
+            fn try_read_vlq(&mut self) -> io::Result<Option<$T>> {
+                (self.try_read_vlq() as Result<Option<$U>, _>)
+                    .map(|n| n.map(|n| ((n >> 1) as $T) ^ -((n & 1) as $T)))
+            }
         }
 
         impl<R: AsRef<[u8]>> VLQDecodeAt<$T> for R {
@@ -306,6 +349,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_try_read_vlq() {
+        let mut v = vec![];
+        for i in [0u64, 127, 128, 22742734291] {
+            v.write_vlq(i).unwrap();
+        }
+        let mut c = Cursor::new(v);
+        let mut values: Vec<u64> = Vec::new();
+        while let Some(value) = c.try_read_vlq().unwrap() {
+            values.push(value);
+        }
+        assert_eq!(values, [0, 127, 128, 22742734291]);
+
+        let mut c = Cursor::new(vec![3u8, 255]);
+        assert_eq!(
+            (c.try_read_vlq() as io::Result<Option<i32>>).unwrap(),
+            Some(-2)
+        );
+        assert_eq!(
+            (c.try_read_vlq() as io::Result<Option<i32>>)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
     #[test]
     fn test_zig_zag() {
         let mut c = Cursor::new(vec![]);